@@ -0,0 +1,202 @@
+//! Captures the fava responses the parsers depend on, for use as test
+//! fixtures: the query_result envelope, an account journal page, the changed
+//! API and the errors page.
+//!
+//! Run with
+//! `cargo run --example capture_fixtures -- --url http://fava:5000/beancount --out tests/fixtures/`,
+//! optionally with `--account Assets:Bank`, `--query 'SELECT ...'`,
+//! `--fava-version 1.27` and `--replacements scrub.toml`.
+//!
+//! The replacement file maps personal strings to stand-ins, e.g.
+//!
+//! ```toml
+//! [replace]
+//! "Assets:Bank:Chase" = "Assets:Bank:Checking"
+//! "Dr. Smith" = "Doctor"
+//! ```
+//!
+//! Fixtures go to one directory per fava version below `--out`, listed in
+//! `manifest.toml` there, so that the fixtures of several versions can live
+//! side by side. Capturing the same version again replaces its fixtures.
+//! The tests run against every set the manifest in `tests/fixtures` lists.
+
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, env, fs, path::PathBuf, process, time::SystemTime};
+
+const DEFAULT_QUERY: &str = "SELECT date, flag, payee, narration, account, position LIMIT 20";
+
+const DEFAULT_ACCOUNT: &str = "Assets";
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Manifest {
+    #[serde(default)]
+    fixtures: Vec<FixtureSet>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct FixtureSet {
+    fava_version: String,
+    /// Directory of the fixtures, relative to the manifest.
+    dir: String,
+    captured: String,
+    query: String,
+    account: String,
+    /// Fixture kind to file name.
+    files: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Replacements {
+    #[serde(default)]
+    replace: BTreeMap<String, String>,
+}
+
+#[tokio::main]
+async fn main() {
+    if let Err(e) = run(env::args().skip(1).collect()).await {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+async fn run(args: Vec<String>) -> Result<(), String> {
+    let mut flags: BTreeMap<&str, &str> = BTreeMap::new();
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let name = flag
+            .strip_prefix("--")
+            .ok_or_else(|| format!("unexpected argument {}", flag))?;
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{} needs a value", flag))?;
+        flags.insert(name, value);
+    }
+    let url = flags
+        .get("url")
+        .ok_or("--url is required")?
+        .trim_end_matches('/');
+    let out = PathBuf::from(flags.get("out").copied().unwrap_or("tests/fixtures"));
+    let query = flags.get("query").copied().unwrap_or(DEFAULT_QUERY);
+    let account = flags.get("account").copied().unwrap_or(DEFAULT_ACCOUNT);
+    let replacements = match flags.get("replacements") {
+        Some(path) => {
+            let text = fs::read_to_string(path)
+                .map_err(|e| format!("can not read replacements {}: {}", path, e))?;
+            toml::from_str::<Replacements>(&text)
+                .map_err(|e| format!("invalid replacements {}: {}", path, e))?
+                .replace
+        }
+        None => BTreeMap::new(),
+    };
+
+    let http = reqwest::Client::new();
+    let fetch = |path: String, query: Vec<(&'static str, String)>| {
+        let request = http.get(format!("{}/{}", url, path)).query(&query);
+        async move {
+            let response = request
+                .send()
+                .await
+                .map_err(|e| format!("{}: {}", path, e))?;
+            println!("{} {}", response.status(), path);
+            response
+                .text()
+                .await
+                .map_err(|e| format!("{}: {}", path, e))
+        }
+    };
+    let captures = [
+        (
+            "query_result",
+            "query_result.json",
+            fetch(
+                "api/query_result".into(),
+                vec![("query_string", query.into())],
+            )
+            .await?,
+        ),
+        (
+            "account",
+            "account.html",
+            fetch(format!("account/{}/", account), Vec::new()).await?,
+        ),
+        (
+            "changed",
+            "changed.json",
+            fetch("api/changed".into(), Vec::new()).await?,
+        ),
+        (
+            "errors",
+            "errors.html",
+            fetch("errors/".into(), Vec::new()).await?,
+        ),
+    ];
+    let fava_version = match flags.get("fava-version") {
+        Some(version) => version.to_string(),
+        None => fava_version(&fetch("help/".into(), Vec::new()).await?)
+            .ok_or("can not tell the fava version, pass --fava-version")?,
+    };
+
+    let dir = out.join(&fava_version);
+    fs::create_dir_all(&dir).map_err(|e| format!("can not create {}: {}", dir.display(), e))?;
+    let mut files = BTreeMap::new();
+    for (kind, name, body) in captures {
+        let path = dir.join(name);
+        fs::write(&path, scrub(&body, &replacements))
+            .map_err(|e| format!("can not write {}: {}", path.display(), e))?;
+        files.insert(kind.to_string(), name.to_string());
+    }
+
+    let manifest_path = out.join("manifest.toml");
+    let mut manifest: Manifest = match fs::read_to_string(&manifest_path) {
+        Ok(text) => toml::from_str(&text)
+            .map_err(|e| format!("invalid manifest {}: {}", manifest_path.display(), e))?,
+        Err(_) => Manifest::default(),
+    };
+    manifest
+        .fixtures
+        .retain(|set| set.fava_version != fava_version);
+    manifest.fixtures.push(FixtureSet {
+        dir: fava_version.clone(),
+        fava_version,
+        captured: httpdate::fmt_http_date(SystemTime::now()),
+        query: scrub(query, &replacements),
+        account: scrub(account, &replacements),
+        files,
+    });
+    manifest
+        .fixtures
+        .sort_by(|a, b| a.fava_version.cmp(&b.fava_version));
+    let text = toml::to_string(&manifest).map_err(|e| e.to_string())?;
+    fs::write(&manifest_path, text)
+        .map_err(|e| format!("can not write {}: {}", manifest_path.display(), e))?;
+    println!("wrote {}", dir.display());
+    Ok(())
+}
+
+/// Replaces the personal strings, longest first so that a name is not
+/// half-replaced by a shorter one it contains.
+fn scrub(text: &str, replacements: &BTreeMap<String, String>) -> String {
+    let mut pairs: Vec<(&String, &String)> = replacements.iter().collect();
+    pairs.sort_by_key(|(from, _)| std::cmp::Reverse(from.len()));
+    pairs
+        .into_iter()
+        .fold(text.to_string(), |text, (from, to)| text.replace(from, to))
+}
+
+/// The version fava prints on its help page, such as `Fava v1.27.3`.
+fn fava_version(help: &str) -> Option<String> {
+    let help = help.to_lowercase();
+    help.match_indices("fava")
+        .filter_map(|(i, _)| {
+            let rest = help[i + 4..].trim_start();
+            let rest = rest.strip_prefix("version").unwrap_or(rest).trim_start();
+            let rest = rest.strip_prefix('v').unwrap_or(rest);
+            let version: String = rest
+                .chars()
+                .take_while(|c| c.is_ascii_digit() || *c == '.')
+                .collect();
+            let version = version.trim_end_matches('.');
+            version.contains('.').then(|| version.to_string())
+        })
+        .next()
+}
@@ -0,0 +1,38 @@
+//! Mounts fava-query under `/finance` of a host application that checks a
+//! bearer token on every request.
+//!
+//! Run with `url=http://fava:5000/beancount token=secret cargo run --example embed`,
+//! then `curl -H 'Authorization: Bearer secret' localhost:3000/finance/api/status`.
+
+use axum::{
+    http::{header::AUTHORIZATION, Request, StatusCode},
+    middleware::{self, Next},
+    response::Response,
+    routing::get,
+    Router,
+};
+use std::{env, net::SocketAddr};
+
+#[tokio::main]
+async fn main() {
+    let url = env::var("url").expect("url not set");
+    let config = fava_query::Config::new(url).refresh_path("none");
+    let app = Router::new()
+        .route("/", get(|| async { "home api" }))
+        .nest("/finance", fava_query::router(config))
+        .layer(middleware::from_fn(auth));
+    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
+    println!("listening on {}", addr);
+    axum::Server::bind(&addr)
+        .serve(app.into_make_service())
+        .await
+        .unwrap();
+}
+
+async fn auth<B>(request: Request<B>, next: Next<B>) -> Result<Response, StatusCode> {
+    let expected = format!("Bearer {}", env::var("token").unwrap_or_default());
+    match request.headers().get(AUTHORIZATION) {
+        Some(value) if value.as_bytes() == expected.as_bytes() => Ok(next.run(request).await),
+        _ => Err(StatusCode::UNAUTHORIZED),
+    }
+}
@@ -0,0 +1,164 @@
+use axum::{
+    http::{
+        header::{ACCEPT, VARY},
+        HeaderMap, HeaderValue,
+    },
+    response::Response,
+};
+
+/// The media types a `format` is asked for by in an `Accept` header.
+const MEDIA_TYPES: &[(&str, &str)] = &[
+    ("json", "application/json"),
+    ("csv", "text/csv"),
+    ("ndjson", "application/x-ndjson"),
+    ("ndjson", "application/ndjson"),
+    ("arrow", "application/vnd.apache.arrow.stream"),
+    (
+        "xlsx",
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    ),
+    ("beancount", "text/plain"),
+];
+
+/// The one of `formats` that the `Accept` header of a request without a
+/// `format` prefers, or else the first of them.
+///
+/// Each format takes the quality of the most specific media range matching
+/// it, so `text/csv;q=0` turns CSV down even next to `*/*`. Formats of the
+/// same quality go by the order of the header, then by that of `formats`.
+pub fn format<'a>(headers: &HeaderMap, formats: &[&'a str]) -> &'a str {
+    let header = headers
+        .get(ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .unwrap_or_default();
+    let ranges: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let range = parts.next()?.trim().to_ascii_lowercase();
+            let quality = parts
+                .find_map(|part| part.trim().strip_prefix("q="))
+                .and_then(|q| q.trim().parse().ok())
+                .unwrap_or(1.0);
+            Some((range, quality)).filter(|(range, _)| range.contains('/'))
+        })
+        .collect();
+
+    let mut best: Option<(f32, usize, &'a str)> = None;
+    for format in formats {
+        let chosen = MEDIA_TYPES
+            .iter()
+            .filter(|(name, _)| name == format)
+            .filter_map(|(_, media_type)| matching(&ranges, media_type))
+            .max_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+        let (quality, position) = match chosen {
+            Some((_, position)) => (ranges[position].1, position),
+            None => continue,
+        };
+        let better = match best {
+            Some((best_quality, best_position, _)) => {
+                quality > best_quality || (quality == best_quality && position < best_position)
+            }
+            None => true,
+        };
+        if quality > 0.0 && better {
+            best = Some((quality, position, format));
+        }
+    }
+    best.map_or(formats[0], |(_, _, format)| format)
+}
+
+/// The specificity and header position of the most specific range that
+/// matches `media_type`: 2 for the type itself, 1 for `type/*` and 0 for
+/// `*/*`.
+fn matching(ranges: &[(String, f32)], media_type: &str) -> Option<(u8, usize)> {
+    let kind = media_type.split('/').next().unwrap_or_default();
+    ranges
+        .iter()
+        .enumerate()
+        .filter_map(|(position, (range, _))| {
+            let specificity = match range.split_once('/') {
+                _ if range == media_type => 2,
+                Some((range_kind, "*")) if range_kind == kind => 1,
+                Some(("*", "*")) => 0,
+                _ => return None,
+            };
+            Some((specificity, position))
+        })
+        .max_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)))
+}
+
+/// Marks a response as chosen by the `Accept` header, so that caches keep
+/// one per format.
+pub fn vary(mut response: Response) -> Response {
+    response
+        .headers_mut()
+        .append(VARY, HeaderValue::from_static("accept"));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::CONTENT_TYPE, Request},
+        routing::get,
+        Router,
+    };
+
+    use super::*;
+    use crate::{
+        backend::Backend,
+        config::Config,
+        testing::{self, table},
+        AppState,
+    };
+
+    const FORMATS: &[&str] = &["json", "csv", "xlsx", "arrow", "ndjson"];
+
+    fn chosen(accept: &str) -> &'static str {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_str(accept).unwrap());
+        format(&headers, FORMATS)
+    }
+
+    #[test]
+    fn prefers_the_best_quality_then_the_header_order() {
+        assert_eq!(format(&HeaderMap::new(), FORMATS), "json");
+        assert_eq!(chosen("text/csv"), "csv");
+        assert_eq!(chosen("application/ndjson, text/csv"), "ndjson");
+        assert_eq!(chosen("text/csv;q=0.5, application/x-ndjson"), "ndjson");
+        assert_eq!(chosen("text/*, application/json;q=0.9"), "csv");
+        assert_eq!(chosen("image/png"), "json");
+    }
+
+    #[test]
+    fn lets_a_specific_range_turn_a_format_down() {
+        assert_eq!(chosen("application/json;q=0, */*"), "csv");
+        assert_eq!(chosen("application/json;q=0, text/csv;q=0, */*"), "xlsx");
+    }
+
+    #[tokio::test]
+    async fn answers_in_the_accepted_format() {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|| async { table(&["account"], &[&["Assets:Bank"]]) }),
+        );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        let state = AppState::new(config);
+        let request = Request::get("/api/query_result?query_string=SELECT%20account")
+            .header(ACCEPT, "text/csv")
+            .body(Body::empty())
+            .unwrap();
+        let (status, headers, body) = testing::send(&state, request).await;
+        assert_eq!(status, 200);
+        assert!(headers[CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/csv"));
+        assert_eq!(headers[VARY], "accept");
+        assert_eq!(body, b"account\r\nAssets:Bank\r\n");
+    }
+}
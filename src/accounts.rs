@@ -0,0 +1,223 @@
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    empty_string_as_none,
+    i18n::{Lang, Message},
+    params::{QueryFields, StrictQuery},
+    AppState, ErrorResult, GeneratedQuery, Meta,
+};
+
+const ACCOUNTS_QUERY: &str = "SELECT DISTINCT account, open_date(account) AS open, \
+    close_date(account) AS close, currency ORDER BY account";
+
+/// The accounts with postings as a tree below their root accounts, with
+/// the currencies posted to them.
+///
+/// Parents that only exist as a prefix, such as `Assets`, are part of the
+/// tree without dates or currencies of their own.
+pub async fn accounts(
+    State(state): State<AppState>,
+    StrictQuery(params): StrictQuery<AccountsParams>,
+    lang: Lang,
+) -> Result<AccountsResult, ErrorResult> {
+    let parsed = state
+        .session()
+        .rows(ACCOUNTS_QUERY)
+        .await
+        .map_err(|e| e.localize(lang))?;
+
+    let mut accounts: BTreeMap<String, Details> = BTreeMap::new();
+    for row in &parsed.rows {
+        let account = match row.get("account") {
+            Some(account) if !account.is_empty() => account,
+            _ => continue,
+        };
+        let details = accounts.entry(account.clone()).or_default();
+        let date = |column: &str| row.get(column).filter(|date| !date.is_empty()).cloned();
+        details.open = details.open.take().or_else(|| date("open"));
+        details.close = details.close.take().or_else(|| date("close"));
+        if let Some(currency) = row.get("currency").filter(|currency| !currency.is_empty()) {
+            details.currencies.insert(currency.clone());
+        }
+    }
+    let roots: BTreeSet<&str> = accounts
+        .keys()
+        .map(|account| account.split(':').next().unwrap_or(account))
+        .collect();
+    let roots = roots
+        .into_iter()
+        .map(|root| node(root, None, &accounts))
+        .collect();
+
+    let generated = GeneratedQuery {
+        query: ACCOUNTS_QUERY.into(),
+        filters: Vec::new(),
+        feeds: "data.roots".into(),
+    };
+    Ok(AccountsResult {
+        success: true,
+        data: Accounts { roots },
+        warnings: parsed
+            .warnings
+            .into_iter()
+            .map(|warning| warning.localize(lang))
+            .collect(),
+        meta: Meta::explain(params.explain, vec![generated]),
+    })
+}
+
+#[derive(Debug, Default)]
+struct Details {
+    open: Option<String>,
+    close: Option<String>,
+    currencies: BTreeSet<String>,
+}
+
+fn node(account: &str, parent: Option<&str>, accounts: &BTreeMap<String, Details>) -> AccountNode {
+    let prefix = format!("{}:", account);
+    let mut names: Vec<&str> = accounts
+        .keys()
+        .filter_map(|name| name.strip_prefix(&prefix))
+        .map(|rest| rest.split(':').next().unwrap_or(rest))
+        .collect();
+    names.dedup();
+    let children = names
+        .into_iter()
+        .map(|name| node(&format!("{}{}", prefix, name), Some(account), accounts))
+        .collect();
+    let details = accounts.get(account);
+    AccountNode {
+        name: account.to_string(),
+        parent: parent.map(str::to_string),
+        open: details.and_then(|details| details.open.clone()),
+        close: details.and_then(|details| details.close.clone()),
+        currencies: details
+            .map(|details| details.currencies.iter().cloned().collect())
+            .unwrap_or_default(),
+        children,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AccountsParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    explain: Option<bool>,
+}
+
+impl QueryFields for AccountsParams {
+    const FIELDS: &[&str] = &["explain"];
+}
+
+#[derive(Debug, Serialize)]
+struct AccountNode {
+    name: String,
+    parent: Option<String>,
+    open: Option<String>,
+    close: Option<String>,
+    currencies: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    children: Vec<AccountNode>,
+}
+
+#[derive(Debug, Serialize)]
+struct Accounts {
+    roots: Vec<AccountNode>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AccountsResult {
+    success: bool,
+    data: Accounts,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<Meta>,
+}
+
+impl IntoResponse for AccountsResult {
+    fn into_response(self) -> Response {
+        let body = Json(self);
+        (StatusCode::OK, body).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::get, Router};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{backend::Backend, config::Config, testing, testing::table};
+
+    #[tokio::test]
+    async fn builds_the_tree_below_each_root() {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|| async {
+                table(
+                    &["account", "open", "close", "currency"],
+                    &[
+                        &["Assets:Bank", "2020-01-01", "", "CNY"],
+                        &["Assets:Bank", "2020-01-01", "", "USD"],
+                        &["Assets:Bank:Old", "2019-05-01", "2021-12-31", "CNY"],
+                        &["Expenses:Food", "2020-01-01", "", "CNY"],
+                    ],
+                )
+            }),
+        );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        let state = AppState::new(config);
+        let (status, body) = testing::get(&state, "/api/accounts").await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["data"]["roots"],
+            json!([
+                {
+                    "name": "Assets",
+                    "parent": null,
+                    "open": null,
+                    "close": null,
+                    "currencies": [],
+                    "children": [{
+                        "name": "Assets:Bank",
+                        "parent": "Assets",
+                        "open": "2020-01-01",
+                        "close": null,
+                        "currencies": ["CNY", "USD"],
+                        "children": [{
+                            "name": "Assets:Bank:Old",
+                            "parent": "Assets:Bank",
+                            "open": "2019-05-01",
+                            "close": "2021-12-31",
+                            "currencies": ["CNY"],
+                        }],
+                    }],
+                },
+                {
+                    "name": "Expenses",
+                    "parent": null,
+                    "open": null,
+                    "close": null,
+                    "currencies": [],
+                    "children": [{
+                        "name": "Expenses:Food",
+                        "parent": "Expenses",
+                        "open": "2020-01-01",
+                        "close": null,
+                        "currencies": ["CNY"],
+                    }],
+                },
+            ])
+        );
+    }
+}
@@ -0,0 +1,216 @@
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+
+use crate::{
+    amount::{self, Amount, Scales},
+    Row,
+};
+
+/// How `aggregate` combines the rows of a group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregate {
+    Sum,
+    Count,
+    Avg,
+}
+
+impl Aggregate {
+    pub fn parse(text: &str) -> Result<Aggregate, String> {
+        match text {
+            "sum" => Ok(Aggregate::Sum),
+            "count" => Ok(Aggregate::Count),
+            "avg" => Ok(Aggregate::Avg),
+            _ => Err(format!(
+                "invalid aggregate {}, expected sum, count or avg",
+                text
+            )),
+        }
+    }
+}
+
+/// Per commodity, `None` for bare numbers, the sum of a column's cells and
+/// how many cells added to it.
+type Totals = BTreeMap<Option<String>, (Decimal, usize)>;
+
+/// One row per distinct value of the comma separated `group_by` columns,
+/// in the order the values first appear, or a single row for all of them
+/// without any.
+///
+/// `count` gives the rows of each group in a `count` column. `sum` and
+/// `avg` combine every other column whose cells all read as numbers,
+/// amounts or inventories, per commodity, and leave out the rest.
+pub fn aggregate(rows: Vec<Row>, group_by: &str, aggregate: Aggregate) -> Result<Vec<Row>, String> {
+    let group_by: Vec<&str> = group_by
+        .split(',')
+        .map(str::trim)
+        .filter(|column| !column.is_empty())
+        .collect();
+    if let Some(unknown) = group_by
+        .iter()
+        .find(|column| !rows.is_empty() && !rows.iter().any(|row| row.contains_key(**column)))
+    {
+        return Err(format!("unknown group_by column {}", unknown));
+    }
+    let mut numeric: Vec<&str> = Vec::new();
+    if aggregate != Aggregate::Count {
+        numeric = rows
+            .iter()
+            .flat_map(|row| row.keys())
+            .map(String::as_str)
+            .filter(|column| !group_by.contains(column))
+            .collect();
+        numeric.sort_unstable();
+        numeric.dedup();
+        numeric.retain(|column| {
+            rows.iter().all(|row| {
+                row.get(*column)
+                    .is_none_or(|text| text.trim().is_empty() || !cell(text).is_empty())
+            })
+        });
+    }
+
+    let mut scales = Scales::default();
+    let mut order: Vec<Vec<String>> = Vec::new();
+    let mut groups: BTreeMap<Vec<String>, (usize, BTreeMap<&str, Totals>)> = BTreeMap::new();
+    for row in &rows {
+        let key: Vec<String> = group_by
+            .iter()
+            .map(|column| row.get(*column).cloned().unwrap_or_default())
+            .collect();
+        let (count, columns) = groups.entry(key.clone()).or_insert_with(|| {
+            order.push(key);
+            Default::default()
+        });
+        *count += 1;
+        for column in &numeric {
+            let totals = columns.entry(column).or_default();
+            let text = row.get(*column).map_or("", String::as_str);
+            for (number, currency) in cell(text) {
+                scales.observe(currency.as_deref(), number);
+                let (sum, cells) = totals.entry(currency).or_default();
+                *sum += number;
+                *cells += 1;
+            }
+        }
+    }
+
+    Ok(order
+        .into_iter()
+        .map(|key| {
+            let (count, columns) = groups.remove(&key).unwrap_or_default();
+            let mut row: Row = group_by
+                .iter()
+                .map(|column| column.to_string())
+                .zip(key)
+                .collect();
+            if aggregate == Aggregate::Count {
+                row.insert("count".into(), count.to_string());
+            }
+            for (column, totals) in columns {
+                row.insert(column.to_string(), render(&totals, aggregate, &scales));
+            }
+            row
+        })
+        .collect())
+}
+
+/// The numbers of a cell, which may hold an amount, an inventory or a bare
+/// number.
+fn cell(text: &str) -> Vec<(Decimal, Option<String>)> {
+    let inventory = amount::parse_inventory(text);
+    if !inventory.is_empty() {
+        return inventory
+            .into_iter()
+            .map(|(number, currency)| (number, Some(currency)))
+            .collect();
+    }
+    Amount::parse_number(text).into_iter().collect()
+}
+
+/// Renders totals like fava renders an inventory, bare numbers first.
+fn render(totals: &Totals, aggregate: Aggregate, scales: &Scales) -> String {
+    totals
+        .iter()
+        .map(|(currency, (sum, cells))| {
+            let scale = scales.common(currency.as_deref());
+            let mut number = match aggregate {
+                Aggregate::Avg => (*sum / Decimal::from(*cells)).round_dp(scale),
+                _ => *sum,
+            };
+            number.rescale(number.scale().max(scale));
+            match currency {
+                Some(currency) => format!("{} {}", amount::format_number(number), currency),
+                None => amount::format_number(number),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows() -> Vec<Row> {
+        [
+            ("Expenses:Food", "12.50 CNY", "2", "lunch"),
+            ("Expenses:Rent", "3000.00 CNY, 10 USD", "1", "rent"),
+            ("Expenses:Food", "7.50 CNY", "4", "dinner"),
+        ]
+        .iter()
+        .map(|(account, position, weight, narration)| {
+            Row::from([
+                ("account".to_string(), account.to_string()),
+                ("position".to_string(), position.to_string()),
+                ("weight".to_string(), weight.to_string()),
+                ("narration".to_string(), narration.to_string()),
+            ])
+        })
+        .collect()
+    }
+
+    fn cells(rows: &[Row], column: &str) -> Vec<String> {
+        rows.iter()
+            .map(|row| row.get(column).cloned().unwrap_or_default())
+            .collect()
+    }
+
+    #[test]
+    fn sums_per_group_and_commodity() {
+        let rows = aggregate(rows(), "account", Aggregate::Sum).unwrap();
+        assert_eq!(cells(&rows, "account"), ["Expenses:Food", "Expenses:Rent"]);
+        assert_eq!(
+            cells(&rows, "position"),
+            ["20.00 CNY", "3000.00 CNY, 10 USD"]
+        );
+        assert_eq!(cells(&rows, "weight"), ["6", "1"]);
+        assert!(rows.iter().all(|row| !row.contains_key("narration")));
+    }
+
+    #[test]
+    fn averages_to_the_common_scale() {
+        let rows = aggregate(rows(), "", Aggregate::Avg).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["position"], "1006.67 CNY, 10 USD");
+        assert_eq!(rows[0]["weight"], "2");
+    }
+
+    #[test]
+    fn counts_the_rows_of_each_group() {
+        let rows = aggregate(rows(), "account", Aggregate::Count).unwrap();
+        assert_eq!(cells(&rows, "count"), ["2", "1"]);
+        assert!(rows.iter().all(|row| row.len() == 2));
+    }
+
+    #[test]
+    fn refuses_unknown_columns_and_aggregates() {
+        assert_eq!(
+            aggregate(rows(), "payee", Aggregate::Sum).unwrap_err(),
+            "unknown group_by column payee"
+        );
+        assert!(aggregate(Vec::new(), "payee", Aggregate::Sum)
+            .unwrap()
+            .is_empty());
+        assert!(Aggregate::parse("max").is_err());
+    }
+}
@@ -0,0 +1,409 @@
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
+use reqwest::StatusCode;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::{collections::BTreeMap, str::FromStr, sync::RwLock, time::Duration, time::SystemTime};
+
+use crate::{
+    amount::Amount,
+    config::{AlertConfig, Comparison, ComponentConfig},
+    get_account_data, schedule, AccountParams, AppState,
+};
+
+/// Evaluation interval for rules without an `interval`.
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(300);
+
+/// How long a webhook may take to answer a notification.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The current status of every configured alert rule, and the client
+/// their webhooks are notified with.
+#[derive(Debug)]
+pub struct AlertStore {
+    statuses: RwLock<BTreeMap<String, AlertStatus>>,
+    webhooks: reqwest::Client,
+}
+
+impl Default for AlertStore {
+    fn default() -> AlertStore {
+        AlertStore {
+            statuses: Default::default(),
+            webhooks: reqwest::Client::builder()
+                .timeout(WEBHOOK_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum AlertState {
+    Pending,
+    Resolved,
+    Firing,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct AlertStatus {
+    state: AlertState,
+    value: Option<Decimal>,
+    threshold: Decimal,
+    /// BQL that reproduces the watched value in fava.
+    query: String,
+    since: Option<String>,
+    last_evaluated: Option<String>,
+    /// Why the last evaluation failed; the state is kept from before it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Notification<'a> {
+    rule: &'a str,
+    state: AlertState,
+    value: Decimal,
+    threshold: Decimal,
+    query: &'a str,
+}
+
+/// Starts one background evaluator per configured alert rule.
+pub fn spawn(state: &AppState) {
+    for (name, rule) in state.config.alerts.clone() {
+        state
+            .alerts
+            .statuses
+            .write()
+            .unwrap()
+            .insert(name.clone(), pending(&rule));
+        let interval = rule
+            .interval
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_INTERVAL);
+        let task_state = state.clone();
+        schedule::on_change(state.clone(), interval, move || {
+            let state = task_state.clone();
+            let name = name.clone();
+            let rule = rule.clone();
+            async move { evaluate(&state, &name, &rule).await }
+        });
+    }
+}
+
+fn pending(rule: &AlertConfig) -> AlertStatus {
+    AlertStatus {
+        state: AlertState::Pending,
+        value: None,
+        threshold: rule.threshold,
+        query: source_query(&rule.source),
+        since: None,
+        last_evaluated: None,
+        error: None,
+    }
+}
+
+/// The BQL of a source; an account was checked to be one on config load.
+fn source_query(source: &ComponentConfig) -> String {
+    match source {
+        ComponentConfig::Query { query } => query.clone(),
+        ComponentConfig::Account { account, .. } => format!(
+            "SELECT date, position, balance WHERE account = '{}'",
+            account
+        ),
+    }
+}
+
+async fn evaluate(state: &AppState, name: &str, rule: &AlertConfig) {
+    let observed = observe(state, rule).await;
+    let now = httpdate::fmt_http_date(SystemTime::now());
+    let previous = match state.alerts.statuses.read().unwrap().get(name) {
+        Some(status) => status.clone(),
+        None => return,
+    };
+    let mut status = AlertStatus {
+        last_evaluated: Some(now.clone()),
+        error: None,
+        ..previous.clone()
+    };
+    match observed {
+        Ok(value) => {
+            status.value = Some(value);
+            status.state = next_state(rule, &previous, value);
+            if status.state != previous.state {
+                status.since = Some(now);
+                if status.state == AlertState::Firing || previous.state == AlertState::Firing {
+                    notify(state, name, rule, &status, value).await;
+                }
+            }
+        }
+        Err(error) => {
+            println!("alert {} evaluation failed: {}", name, error);
+            status.error = Some(error);
+        }
+    }
+    state
+        .alerts
+        .statuses
+        .write()
+        .unwrap()
+        .insert(name.to_string(), status);
+}
+
+/// The state of a rule that was `previous` once its value is `value`. A
+/// firing rule only resolves once the value is back past the threshold by
+/// the hysteresis, so a value around the threshold does not flap.
+fn next_state(rule: &AlertConfig, previous: &AlertStatus, value: Decimal) -> AlertState {
+    let metric = match rule.comparison {
+        Comparison::AbsChange => match previous.value {
+            Some(last) => (value - last).abs(),
+            None => return previous.state,
+        },
+        _ => value,
+    };
+    let firing = previous.state == AlertState::Firing;
+    let threshold = rule.threshold;
+    let hysteresis = rule.hysteresis;
+    let fires = match rule.comparison {
+        Comparison::Gt | Comparison::AbsChange if firing => metric > threshold - hysteresis,
+        Comparison::Gt | Comparison::AbsChange => metric > threshold,
+        Comparison::Lt if firing => metric < threshold + hysteresis,
+        Comparison::Lt => metric < threshold,
+    };
+    match fires {
+        true => AlertState::Firing,
+        false => AlertState::Resolved,
+    }
+}
+
+/// Fetches the current value of the rule's query or account balance.
+async fn observe(state: &AppState, rule: &AlertConfig) -> Result<Decimal, String> {
+    let text = match &rule.source {
+        ComponentConfig::Query { query } => {
+            let parsed = state.session().rows(query).await.map_err(|e| e.error)?;
+            let row = parsed
+                .rows
+                .into_iter()
+                .next()
+                .ok_or("query returned no rows")?;
+            match &rule.column {
+                Some(column) => row
+                    .get(column)
+                    .cloned()
+                    .ok_or(format!("query has no column {}", column))?,
+                None if row.len() == 1 => row.into_values().next().unwrap_or_default(),
+                None => return Err("query selects several columns, set `column`".into()),
+            }
+        }
+        ComponentConfig::Account { account, negate } => {
+            let journal = state
+                .session()
+                .account_journal(account)
+                .await
+                .map_err(|e| e.error)?;
+            let params = AccountParams {
+                negate: Some(*negate),
+                ..Default::default()
+            };
+            let parsed = get_account_data(&journal.entries, &params);
+            let row = parsed
+                .rows
+                .into_iter()
+                .last()
+                .ok_or("account has no entries")?;
+            row.get("balance").cloned().unwrap_or_default()
+        }
+    };
+    Amount::parse(&text)
+        .map(|amount| amount.number)
+        .or_else(|| Decimal::from_str(text.trim()).ok())
+        .ok_or(format!("value '{}' is not a number", text))
+}
+
+async fn notify(
+    state: &AppState,
+    name: &str,
+    rule: &AlertConfig,
+    status: &AlertStatus,
+    value: Decimal,
+) {
+    let notification = Notification {
+        rule: name,
+        state: status.state,
+        value,
+        threshold: rule.threshold,
+        query: &status.query,
+    };
+    println!(
+        "alert {} is {:?}: {} vs threshold {}",
+        name, status.state, value, rule.threshold
+    );
+    if let Some(webhook) = &rule.webhook {
+        let sent = state
+            .alerts
+            .webhooks
+            .post(webhook)
+            .json(&notification)
+            .send()
+            .await;
+        if let Err(e) = sent {
+            println!("alert {} notification failed: {}", name, e);
+        }
+    }
+}
+
+pub async fn alerts(State(state): State<AppState>) -> AlertsResult {
+    AlertsResult {
+        success: true,
+        data: state.alerts.statuses.read().unwrap().clone(),
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AlertsResult {
+    success: bool,
+    data: BTreeMap<String, AlertStatus>,
+}
+
+impl IntoResponse for AlertsResult {
+    fn into_response(self) -> Response {
+        let body = Json(self);
+        (StatusCode::OK, body).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        extract::RawQuery,
+        routing::{get, post},
+        Router,
+    };
+    use serde_json::{json, Value};
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::{backend::Backend, config::Config, testing, testing::table};
+
+    fn rule(comparison: Comparison, threshold: i64, hysteresis: i64) -> AlertConfig {
+        AlertConfig {
+            source: ComponentConfig::Query {
+                query: "SELECT value".into(),
+            },
+            column: None,
+            comparison,
+            threshold: threshold.into(),
+            hysteresis: hysteresis.into(),
+            webhook: None,
+            interval: None,
+        }
+    }
+
+    /// The states a rule goes through for `values`, from pending.
+    fn states(rule: &AlertConfig, values: &[i64]) -> Vec<AlertState> {
+        let mut status = pending(rule);
+        values
+            .iter()
+            .map(|value| {
+                status.state = next_state(rule, &status, (*value).into());
+                status.value = Some((*value).into());
+                status.state
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fires_past_the_threshold_and_resolves_past_the_hysteresis() {
+        use AlertState::{Firing, Pending, Resolved};
+        assert_eq!(
+            states(
+                &rule(Comparison::Gt, 100, 10),
+                &[95, 101, 100, 91, 101, 90, 100, 101]
+            ),
+            [Resolved, Firing, Firing, Firing, Firing, Resolved, Resolved, Firing]
+        );
+        assert_eq!(
+            states(&rule(Comparison::Lt, 0, 5), &[1, 0, -1, 4, 5, -1]),
+            [Resolved, Resolved, Firing, Firing, Resolved, Firing]
+        );
+        // A change needs a value before it.
+        assert_eq!(
+            states(
+                &rule(Comparison::AbsChange, 50, 0),
+                &[1000, 1020, 1100, 1090]
+            ),
+            [Pending, Resolved, Firing, Resolved]
+        );
+    }
+
+    #[tokio::test]
+    async fn notifies_the_webhook_and_keeps_the_state_on_bad_values() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let hook = received.clone();
+        let fava = Router::new()
+            .route(
+                "/api/query_result",
+                get(|RawQuery(query): RawQuery| async move {
+                    let value = match query.unwrap_or_default().contains("text") {
+                        true => "n/a",
+                        false => "150.5 CNY",
+                    };
+                    table(&["value"], &[&[value]])
+                }),
+            )
+            .route(
+                "/hook",
+                post(move |axum::Json(body): axum::Json<Value>| async move {
+                    hook.lock().unwrap().push(body);
+                }),
+            );
+        let url = testing::fava(fava).await;
+        let state = AppState::new(
+            Config::new(url.clone())
+                .backend(Backend::Html)
+                .refresh_path("none"),
+        );
+        let cash = AlertConfig {
+            webhook: Some(format!("{}/hook", url)),
+            ..rule(Comparison::Gt, 100, 0)
+        };
+        let text = AlertConfig {
+            source: ComponentConfig::Query {
+                query: "SELECT text".into(),
+            },
+            ..cash.clone()
+        };
+        for (name, rule) in [("cash", &cash), ("text", &text)] {
+            let status = pending(rule);
+            state
+                .alerts
+                .statuses
+                .write()
+                .unwrap()
+                .insert(name.into(), status);
+            evaluate(&state, name, rule).await;
+        }
+
+        assert_eq!(
+            *received.lock().unwrap(),
+            [json!({
+                "rule": "cash",
+                "state": "firing",
+                "value": "150.5",
+                "threshold": "100",
+                "query": "SELECT value",
+            })]
+        );
+        let statuses = state.alerts.statuses.read().unwrap();
+        assert_eq!(statuses["cash"].state, AlertState::Firing);
+        assert!(statuses["cash"].since.is_some());
+        assert_eq!(statuses["text"].state, AlertState::Pending);
+        assert_eq!(
+            statuses["text"].error.as_deref(),
+            Some("value 'n/a' is not a number")
+        );
+    }
+}
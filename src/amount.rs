@@ -0,0 +1,231 @@
+use rust_decimal::Decimal;
+use std::{collections::HashMap, str::FromStr};
+
+/// A number together with its commodity, as rendered by fava (`-12.00 CNY`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Amount {
+    pub number: Decimal,
+    pub currency: String,
+}
+
+impl Amount {
+    /// Parses the leading `number currency` pair of a rendered position,
+    /// ignoring any cost or price annotation that follows it.
+    pub fn parse(text: &str) -> Option<Amount> {
+        let mut parts = text.split_whitespace();
+        let number = Decimal::from_str(&parts.next()?.replace(',', "")).ok()?;
+        let currency = parts.next()?;
+        if !currency.starts_with(|c: char| c.is_ascii_uppercase()) {
+            return None;
+        }
+        Some(Amount {
+            number,
+            currency: currency.to_string(),
+        })
+    }
+
+    /// Parses a rendered amount, also accepting a bare number without its
+    /// commodity.
+    pub fn parse_number(text: &str) -> Option<(Decimal, Option<String>)> {
+        match Amount::parse(text) {
+            Some(amount) => Some((amount.number, Some(amount.currency))),
+            None => Decimal::from_str(&text.trim().replace(',', ""))
+                .ok()
+                .map(|number| (number, None)),
+        }
+    }
+}
+
+/// The `number commodity` pairs of a rendered inventory such as
+/// `1,200.00 CNY, 3 USD`.
+pub fn parse_inventory(text: &str) -> Vec<(Decimal, String)> {
+    let tokens: Vec<&str> = text
+        .split_whitespace()
+        .map(|token| token.trim_end_matches(','))
+        .filter(|token| !token.is_empty())
+        .collect();
+    tokens
+        .windows(2)
+        .filter_map(|pair| Amount::parse(&pair.join(" ")))
+        .map(|amount| (amount.number, amount.currency))
+        .collect()
+}
+
+/// The positions of a cell that is nothing but amounts, of one commodity
+/// or several, or a bare number without any.
+pub fn parse_positions(text: &str) -> Option<Vec<(Decimal, Option<String>)>> {
+    let tokens = text.split_whitespace().count();
+    if tokens == 1 {
+        return Decimal::from_str(&text.trim().replace(',', ""))
+            .ok()
+            .map(|number| vec![(number, None)]);
+    }
+    let positions = parse_inventory(text);
+    if positions.is_empty() || tokens != positions.len() * 2 {
+        return None;
+    }
+    Some(
+        positions
+            .into_iter()
+            .map(|(number, currency)| (number, Some(currency)))
+            .collect(),
+    )
+}
+
+/// Renders positions like fava does an inventory, `1200.00 CNY, 3 USD`,
+/// the number of one without a commodity bare.
+pub fn format_positions(positions: &[(Decimal, Option<String>)]) -> String {
+    positions
+        .iter()
+        .map(|(number, currency)| match currency {
+            Some(currency) => format!("{} {}", format_number(*number), currency),
+            None => format_number(*number),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Whether `text` can be a beancount commodity, such as `CNY` or `VT.X`.
+pub fn is_currency(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_uppercase())
+        && text
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || "'._-".contains(c))
+}
+
+/// Formats a number for output, keeping its scale but never emitting `-0`.
+pub fn format_number(mut number: Decimal) -> String {
+    if number.is_zero() {
+        number.set_sign_positive(true);
+    }
+    number.to_string()
+}
+
+/// Counts the decimal scales seen per commodity in a response, so that
+/// numbers without a source text of their own can be formatted alike.
+#[derive(Debug, Default)]
+pub struct Scales {
+    counts: HashMap<Option<String>, HashMap<u32, usize>>,
+}
+
+impl Scales {
+    pub fn observe(&mut self, currency: Option<&str>, number: Decimal) {
+        for key in [currency.map(str::to_string), None] {
+            *self
+                .counts
+                .entry(key)
+                .or_default()
+                .entry(number.scale())
+                .or_default() += 1;
+            if currency.is_none() {
+                break;
+            }
+        }
+    }
+
+    /// The most common scale of the commodity, or of all numbers if the
+    /// commodity is unknown or was never seen.
+    pub fn common(&self, currency: Option<&str>) -> u32 {
+        let counts = self
+            .counts
+            .get(&currency.map(str::to_string))
+            .or_else(|| self.counts.get(&None));
+        counts
+            .and_then(|counts| {
+                counts
+                    .iter()
+                    .max_by_key(|(scale, count)| (**count, std::cmp::Reverse(**scale)))
+                    .map(|(scale, _)| *scale)
+            })
+            .unwrap_or(0)
+    }
+
+    /// The largest scale of all numbers, at which each of them is exact.
+    pub fn widest(&self) -> u32 {
+        self.counts
+            .get(&None)
+            .and_then(|counts| counts.keys().max().copied())
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+    use super::*;
+
+    const CASES: usize = 1000;
+    const CURRENCIES: &[&str] = &["CNY", "USD", "VT.X", "A1", "EUR"];
+
+    /// A number of up to 18 digits at a scale of up to 8, zero a tenth of
+    /// the time, negative zero included.
+    fn number(rng: &mut StdRng) -> Decimal {
+        let scale = rng.gen_range(0..=8);
+        let mantissa = match rng.gen_ratio(1, 10) {
+            true => 0,
+            false => rng.gen_range(-10i64.pow(18)..10i64.pow(18)) >> rng.gen_range(0..60),
+        };
+        let mut number = Decimal::new(mantissa, scale);
+        if rng.gen() {
+            number.set_sign_negative(true);
+        }
+        number
+    }
+
+    #[test]
+    fn formats_numbers_at_their_scale_without_negative_zero() {
+        let mut rng = StdRng::seed_from_u64(221);
+        for _ in 0..CASES {
+            let number = number(&mut rng);
+            let text = format_number(number);
+            assert_ne!(text.trim_start_matches('-').chars().next(), Some('.'));
+            assert!(!(number.is_zero() && text.starts_with('-')), "{}", text);
+            let fraction = text
+                .split_once('.')
+                .map_or(0, |(_, fraction)| fraction.len());
+            assert_eq!(fraction as u32, number.scale(), "{}", text);
+            assert_eq!(Decimal::from_str(&text).unwrap(), number);
+        }
+    }
+
+    #[test]
+    fn reads_back_formatted_inventories() {
+        let mut rng = StdRng::seed_from_u64(221);
+        for _ in 0..CASES {
+            let positions: Vec<(Decimal, Option<String>)> = (0..rng.gen_range(1..4))
+                .map(|_| {
+                    let currency = CURRENCIES.choose(&mut rng).unwrap();
+                    (number(&mut rng), Some(currency.to_string()))
+                })
+                .collect();
+            let text = format_positions(&positions);
+            let read: Vec<(Decimal, Option<String>)> = parse_inventory(&text)
+                .into_iter()
+                .map(|(number, currency)| (number, Some(currency)))
+                .collect();
+            assert_eq!(read, positions, "{}", text);
+            assert_eq!(parse_positions(&text), Some(positions));
+        }
+    }
+
+    #[test]
+    fn takes_the_most_common_scale_of_a_commodity() {
+        let mut rng = StdRng::seed_from_u64(221);
+        for _ in 0..CASES {
+            let mut scales = Scales::default();
+            let mut counts = [0usize; 4];
+            for _ in 0..rng.gen_range(1..20) {
+                let scale = rng.gen_range(0..4u32);
+                counts[scale as usize] += 1;
+                scales.observe(Some("CNY"), Decimal::new(1, scale));
+                scales.observe(Some("USD"), Decimal::new(1, 8));
+            }
+            // Ties go to the smaller scale.
+            let most = counts.iter().max().unwrap();
+            let expected = counts.iter().position(|count| count == most).unwrap();
+            assert_eq!(scales.common(Some("CNY")), expected as u32);
+            assert_eq!(scales.common(Some("USD")), 8);
+        }
+    }
+}
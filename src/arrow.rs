@@ -0,0 +1,754 @@
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use std::str::FromStr;
+
+use crate::{
+    amount::{Amount, Scales},
+    interval, Row,
+};
+
+/// `MetadataVersion.V5` of Arrow's `Schema.fbs`.
+const METADATA_VERSION: i16 = 4;
+/// Members of the `MessageHeader` union.
+const HEADER_SCHEMA: u8 = 1;
+const HEADER_RECORD_BATCH: u8 = 3;
+/// Members of the `Type` union.
+const TYPE_INT: u8 = 2;
+const TYPE_FLOATING_POINT: u8 = 3;
+const TYPE_UTF8: u8 = 5;
+const TYPE_DECIMAL: u8 = 7;
+const TYPE_DATE: u8 = 8;
+/// `Precision.DOUBLE` and `DateUnit.DAY`.
+const DOUBLE: i16 = 2;
+const DAY: i16 = 0;
+/// The largest precision of a 128 bit `Decimal`.
+const DECIMAL_PRECISION: i32 = 38;
+
+/// The Arrow type a column of text cells reads as. Every cell of it but
+/// the empty ones, which become nulls, has to read as the type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Date,
+    Int,
+    Float,
+    /// A single amount per cell, split into a decimal and a currency
+    /// column.
+    Amount,
+    Text,
+}
+
+pub(crate) fn is_date(text: &str) -> bool {
+    text.len() == 10
+        && text.char_indices().all(|(i, c)| {
+            matches!((i, c), (4 | 7, '-')) || (i != 4 && i != 7 && c.is_ascii_digit())
+        })
+        && interval::parse_date(text).is_some()
+}
+
+pub(crate) fn number(text: &str) -> Option<Decimal> {
+    if text.split_whitespace().count() != 1 {
+        return None;
+    }
+    Decimal::from_str(&text.replace(',', "")).ok()
+}
+
+fn present(text: &str) -> Option<&str> {
+    Some(text).filter(|text| !text.is_empty())
+}
+
+fn amount(text: &str) -> Option<Amount> {
+    match text.split_whitespace().count() {
+        2 => Amount::parse(text),
+        _ => None,
+    }
+}
+
+fn kind<'a>(cells: impl Iterator<Item = &'a str> + Clone) -> Kind {
+    let mut cells = cells.filter(|text| !text.is_empty()).peekable();
+    if cells.peek().is_none() {
+        return Kind::Text;
+    }
+    let all = |read: fn(&str) -> bool| cells.clone().all(read);
+    if all(is_date) {
+        Kind::Date
+    } else if all(|text| {
+        number(text)
+            .filter(|number| number.scale() == 0)
+            .and_then(|number| number.to_i64())
+            .is_some()
+    }) {
+        Kind::Int
+    } else if all(|text| number(text).is_some()) {
+        Kind::Float
+    } else if all(|text| amount(text).is_some()) {
+        Kind::Amount
+    } else {
+        Kind::Text
+    }
+}
+
+/// A column of the record batch with its buffers, a validity bitmap and
+/// then the values, or for text the offsets and the data.
+struct Column {
+    name: String,
+    type_id: u8,
+    type_table: Table,
+    null_count: usize,
+    buffers: Vec<Vec<u8>>,
+}
+
+impl Column {
+    fn new<T>(
+        name: String,
+        type_id: u8,
+        type_table: Table,
+        values: &[Option<T>],
+        write: impl Fn(&mut Vec<u8>, Option<&T>),
+    ) -> Column {
+        let mut validity = vec![0u8; values.len().div_ceil(8)];
+        let mut data = Vec::new();
+        for (i, value) in values.iter().enumerate() {
+            if value.is_some() {
+                validity[i / 8] |= 1 << (i % 8);
+            }
+            write(&mut data, value.as_ref());
+        }
+        let null_count = values.iter().filter(|value| value.is_none()).count();
+        if null_count == 0 {
+            validity.clear();
+        }
+        Column {
+            name,
+            type_id,
+            type_table,
+            null_count,
+            buffers: vec![validity, data],
+        }
+    }
+
+    fn text(name: String, values: &[Option<&str>]) -> Column {
+        let mut column = Column::new(name, TYPE_UTF8, Table::default(), values, |_, _| {});
+        let mut offsets = 0i32.to_le_bytes().to_vec();
+        let mut data = Vec::new();
+        for value in values {
+            data.extend_from_slice(value.unwrap_or_default().as_bytes());
+            offsets.extend_from_slice(&(data.len() as i32).to_le_bytes());
+        }
+        column.buffers = vec![column.buffers.remove(0), offsets, data];
+        column
+    }
+
+    /// A `decimal128` column of `numbers` at `scale`, or `None` if one of
+    /// them has more than its 38 digits there.
+    fn decimal(name: String, numbers: &[Option<Decimal>], scale: u32) -> Option<Column> {
+        let limit = 10i128.pow(DECIMAL_PRECISION as u32);
+        let mut values = Vec::with_capacity(numbers.len());
+        for number in numbers {
+            let value = match number {
+                Some(number) => {
+                    let factor = 10i128.checked_pow(scale - number.scale())?;
+                    let value = number.mantissa().checked_mul(factor)?;
+                    Some(value).filter(|value| value.abs() < limit)?
+                }
+                None => 0,
+            };
+            values.push(number.map(|_| value));
+        }
+        let decimal = Table::default()
+            .int(0, DECIMAL_PRECISION)
+            .int(1, scale as i32)
+            .int(2, 128);
+        Some(Column::new(
+            name,
+            TYPE_DECIMAL,
+            decimal,
+            &values,
+            |data, value| data.extend_from_slice(&value.copied().unwrap_or_default().to_le_bytes()),
+        ))
+    }
+
+    fn float(name: String, values: &[Option<f64>]) -> Column {
+        let precision = Table::default().short(0, DOUBLE);
+        Column::new(
+            name,
+            TYPE_FLOATING_POINT,
+            precision,
+            values,
+            |data, value| data.extend_from_slice(&value.copied().unwrap_or_default().to_le_bytes()),
+        )
+    }
+}
+
+/// The rows as an Arrow IPC stream of one record batch, which pandas,
+/// polars and DuckDB read with their column types.
+///
+/// Dates become `date32`, whole numbers `int64`, other numbers `float64`
+/// and anything else `utf8`, with empty cells as nulls. A column holding
+/// one amount per cell, such as `12.00 CNY`, becomes a `decimal128` column
+/// of its numbers at the widest scale among them and a `utf8` column of
+/// its currencies named after it with `_currency`, unless the rows already
+/// have a column of that name or a number has more than 38 digits.
+pub fn write(columns: &[&str], rows: &[Row]) -> Vec<u8> {
+    let mut batch = Vec::new();
+    for name in columns {
+        let cells = rows
+            .iter()
+            .map(|row| row.get(*name).map_or("", |text| text.trim()));
+        let name = name.to_string();
+        let mut kind = kind(cells.clone());
+        // Amounts stay text rather than repeat the name of another column.
+        if kind == Kind::Amount && columns.contains(&format!("{}_currency", name).as_str()) {
+            kind = Kind::Text;
+        }
+        match kind {
+            Kind::Date => {
+                let days: Vec<Option<i32>> = cells
+                    .map(|text| {
+                        present(text)
+                            .and_then(interval::parse_date)
+                            .map(|day| day as i32)
+                    })
+                    .collect();
+                let unit = Table::default().short(0, DAY);
+                batch.push(Column::new(name, TYPE_DATE, unit, &days, |data, day| {
+                    data.extend_from_slice(&day.copied().unwrap_or_default().to_le_bytes())
+                }));
+            }
+            Kind::Int => {
+                let numbers: Vec<Option<i64>> = cells
+                    .map(|text| {
+                        present(text)
+                            .and_then(number)
+                            .and_then(|number| number.to_i64())
+                    })
+                    .collect();
+                let int = Table::default().int(0, 64).bool(1, true);
+                batch.push(Column::new(
+                    name,
+                    TYPE_INT,
+                    int,
+                    &numbers,
+                    |data, number| {
+                        data.extend_from_slice(&number.copied().unwrap_or_default().to_le_bytes())
+                    },
+                ));
+            }
+            Kind::Float => {
+                let numbers: Vec<Option<f64>> = cells
+                    .map(|text| {
+                        present(text)
+                            .and_then(number)
+                            .and_then(|number| number.to_f64())
+                    })
+                    .collect();
+                batch.push(Column::float(name, &numbers));
+            }
+            Kind::Amount => {
+                let amounts: Vec<Option<Amount>> = cells.clone().map(amount).collect();
+                let mut scales = Scales::default();
+                let numbers: Vec<Option<Decimal>> = amounts
+                    .iter()
+                    .map(|amount| amount.as_ref().map(|amount| amount.number))
+                    .collect();
+                for number in numbers.iter().flatten() {
+                    scales.observe(None, *number);
+                }
+                let currencies: Vec<Option<&str>> = amounts
+                    .iter()
+                    .map(|amount| amount.as_ref().map(|amount| amount.currency.as_str()))
+                    .collect();
+                match Column::decimal(name.clone(), &numbers, scales.widest()) {
+                    Some(column) => {
+                        batch.push(column);
+                        batch.push(Column::text(format!("{}_currency", name), &currencies));
+                    }
+                    None => {
+                        let texts: Vec<Option<&str>> = cells.map(present).collect();
+                        batch.push(Column::text(name, &texts));
+                    }
+                }
+            }
+            Kind::Text => {
+                let texts: Vec<Option<&str>> = cells.map(present).collect();
+                batch.push(Column::text(name, &texts));
+            }
+        }
+    }
+
+    let fields = batch
+        .iter()
+        .map(|column| {
+            Table::default()
+                .string(0, &column.name)
+                .bool(1, true)
+                .byte(2, column.type_id)
+                .table(3, column.type_table.clone())
+                .tables(5, Vec::new())
+        })
+        .collect();
+    let schema = Table::default().tables(1, fields);
+
+    let mut nodes = Vec::new();
+    let mut buffers = Vec::new();
+    let mut body = Vec::new();
+    for column in &batch {
+        nodes.extend_from_slice(&(rows.len() as i64).to_le_bytes());
+        nodes.extend_from_slice(&(column.null_count as i64).to_le_bytes());
+        for buffer in &column.buffers {
+            buffers.extend_from_slice(&(body.len() as i64).to_le_bytes());
+            buffers.extend_from_slice(&(buffer.len() as i64).to_le_bytes());
+            body.extend_from_slice(buffer);
+            pad(&mut body, 8);
+        }
+    }
+    let record_batch = Table::default()
+        .long(0, rows.len() as i64)
+        .structs(1, nodes)
+        .structs(2, buffers);
+
+    let mut stream = Vec::new();
+    message(&mut stream, HEADER_SCHEMA, schema, &[]);
+    message(&mut stream, HEADER_RECORD_BATCH, record_batch, &body);
+    // The end of the stream.
+    stream.extend_from_slice(&u32::MAX.to_le_bytes());
+    stream.extend_from_slice(&0u32.to_le_bytes());
+    stream
+}
+
+/// An encapsulated message: a continuation marker, the size of the
+/// flatbuffer `Message`, the `Message` padded to 8 bytes, then the body.
+fn message(stream: &mut Vec<u8>, header_type: u8, header: Table, body: &[u8]) {
+    let message = Table::default()
+        .short(0, METADATA_VERSION)
+        .byte(1, header_type)
+        .table(2, header)
+        .long(3, body.len() as i64);
+    let mut metadata = finish(message);
+    pad(&mut metadata, 8);
+    stream.extend_from_slice(&u32::MAX.to_le_bytes());
+    stream.extend_from_slice(&(metadata.len() as i32).to_le_bytes());
+    stream.extend_from_slice(&metadata);
+    stream.extend_from_slice(body);
+}
+
+fn pad(bytes: &mut Vec<u8>, align: usize) {
+    bytes.resize(bytes.len().next_multiple_of(align), 0);
+}
+
+/// A flatbuffers table, by field id.
+#[derive(Debug, Clone, Default)]
+struct Table {
+    fields: Vec<(u16, Field)>,
+}
+
+#[derive(Debug, Clone)]
+enum Field {
+    /// A scalar, stored inline with its alignment being its size.
+    Scalar(Vec<u8>),
+    String(String),
+    Table(Table),
+    Tables(Vec<Table>),
+    /// A vector of structs of 16 bytes aligned to 8, as raw bytes.
+    Structs(Vec<u8>),
+}
+
+impl Table {
+    fn with(mut self, id: u16, field: Field) -> Table {
+        self.fields.push((id, field));
+        self
+    }
+
+    fn bool(self, id: u16, value: bool) -> Table {
+        self.byte(id, u8::from(value))
+    }
+
+    fn byte(self, id: u16, value: u8) -> Table {
+        self.with(id, Field::Scalar(vec![value]))
+    }
+
+    fn short(self, id: u16, value: i16) -> Table {
+        self.with(id, Field::Scalar(value.to_le_bytes().to_vec()))
+    }
+
+    fn int(self, id: u16, value: i32) -> Table {
+        self.with(id, Field::Scalar(value.to_le_bytes().to_vec()))
+    }
+
+    fn long(self, id: u16, value: i64) -> Table {
+        self.with(id, Field::Scalar(value.to_le_bytes().to_vec()))
+    }
+
+    fn string(self, id: u16, value: &str) -> Table {
+        self.with(id, Field::String(value.to_string()))
+    }
+
+    fn table(self, id: u16, value: Table) -> Table {
+        self.with(id, Field::Table(value))
+    }
+
+    fn tables(self, id: u16, value: Vec<Table>) -> Table {
+        self.with(id, Field::Tables(value))
+    }
+
+    fn structs(self, id: u16, value: Vec<u8>) -> Table {
+        self.with(id, Field::Structs(value))
+    }
+}
+
+/// A flatbuffer with `root` as its root table. The buffer is written front
+/// to back: each table after its vtable and before what it refers to, as
+/// offsets to other objects may only point forward.
+fn finish(root: Table) -> Vec<u8> {
+    let mut buffer = vec![0; 4];
+    let position = write_table(&mut buffer, &root);
+    patch(&mut buffer, 0, position);
+    buffer
+}
+
+/// Sets the offset at `at` to point to `target`.
+fn patch(buffer: &mut [u8], at: usize, target: usize) {
+    buffer[at..at + 4].copy_from_slice(&((target - at) as u32).to_le_bytes());
+}
+
+fn write_table(buffer: &mut Vec<u8>, table: &Table) -> usize {
+    // Inline, fields go largest first after the vtable offset, so that
+    // each is aligned to its size once the table is aligned to 8.
+    let size = |field: &Field| match field {
+        Field::Scalar(bytes) => bytes.len(),
+        _ => 4,
+    };
+    let mut order: Vec<&(u16, Field)> = table.fields.iter().collect();
+    order.sort_by_key(|(_, field)| std::cmp::Reverse(size(field)));
+    let mut offsets = Vec::new();
+    let mut inline: usize = 4;
+    for (id, field) in &order {
+        let size = size(field);
+        inline = inline.next_multiple_of(size);
+        offsets.push((*id, inline));
+        inline += size;
+    }
+
+    let slots = table
+        .fields
+        .iter()
+        .map(|(id, _)| *id + 1)
+        .max()
+        .unwrap_or(0) as usize;
+    let mut vtable = vec![0u16; 2 + slots];
+    vtable[0] = (4 + 2 * slots) as u16;
+    vtable[1] = inline as u16;
+    for (id, offset) in &offsets {
+        vtable[2 + *id as usize] = *offset as u16;
+    }
+    pad(buffer, 2);
+    let vtable_position = buffer.len();
+    for entry in vtable {
+        buffer.extend_from_slice(&entry.to_le_bytes());
+    }
+    pad(buffer, 8);
+    let position = buffer.len();
+    buffer.extend_from_slice(&((position - vtable_position) as i32).to_le_bytes());
+    buffer.resize(position + inline, 0);
+    for ((_, field), (_, offset)) in order.iter().zip(&offsets) {
+        if let Field::Scalar(bytes) = field {
+            buffer[position + offset..position + offset + bytes.len()].copy_from_slice(bytes);
+        }
+    }
+
+    for ((_, field), (_, offset)) in order.iter().zip(&offsets) {
+        let at = position + offset;
+        let target = match field {
+            Field::Scalar(_) => continue,
+            Field::String(text) => {
+                pad(buffer, 4);
+                let target = buffer.len();
+                buffer.extend_from_slice(&(text.len() as u32).to_le_bytes());
+                buffer.extend_from_slice(text.as_bytes());
+                buffer.push(0);
+                target
+            }
+            Field::Table(table) => write_table(buffer, table),
+            Field::Tables(tables) => {
+                pad(buffer, 4);
+                let target = buffer.len();
+                buffer.extend_from_slice(&(tables.len() as u32).to_le_bytes());
+                buffer.resize(target + 4 + 4 * tables.len(), 0);
+                for (i, table) in tables.iter().enumerate() {
+                    let position = write_table(buffer, table);
+                    patch(buffer, target + 4 + 4 * i, position);
+                }
+                target
+            }
+            Field::Structs(bytes) => {
+                // The length goes right before the first struct, which is
+                // aligned to 8.
+                pad(buffer, 8);
+                buffer.extend_from_slice(&[0; 4]);
+                let target = buffer.len();
+                buffer.extend_from_slice(&((bytes.len() / 16) as u32).to_le_bytes());
+                buffer.extend_from_slice(bytes);
+                target
+            }
+        };
+        patch(buffer, at, target);
+    }
+    position
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A table of a flatbuffer, read by the offsets its vtable holds.
+    #[derive(Clone, Copy)]
+    struct Read<'a> {
+        buffer: &'a [u8],
+        position: usize,
+    }
+
+    impl<'a> Read<'a> {
+        fn root(buffer: &'a [u8]) -> Read<'a> {
+            Read {
+                buffer,
+                position: u32_at(buffer, 0),
+            }
+        }
+
+        fn field(&self, id: usize) -> Option<usize> {
+            let vtable =
+                (self.position as i64 - i32_at(self.buffer, self.position) as i64) as usize;
+            let size = u16::from_le_bytes([self.buffer[vtable], self.buffer[vtable + 1]]) as usize;
+            let slot = vtable + 4 + 2 * id;
+            let offset = match slot + 2 <= vtable + size {
+                true => u16::from_le_bytes([self.buffer[slot], self.buffer[slot + 1]]) as usize,
+                false => 0,
+            };
+            Some(self.position + offset).filter(|_| offset != 0)
+        }
+
+        fn byte(&self, id: usize) -> u8 {
+            self.field(id).map_or(0, |at| self.buffer[at])
+        }
+
+        fn short(&self, id: usize) -> i16 {
+            self.field(id).map_or(0, |at| {
+                i16::from_le_bytes([self.buffer[at], self.buffer[at + 1]])
+            })
+        }
+
+        fn int(&self, id: usize) -> i32 {
+            self.field(id).map_or(0, |at| i32_at(self.buffer, at))
+        }
+
+        fn long(&self, id: usize) -> i64 {
+            self.field(id).map_or(0, |at| i64_at(self.buffer, at))
+        }
+
+        fn target(&self, id: usize) -> usize {
+            let at = self.field(id).unwrap();
+            at + u32_at(self.buffer, at)
+        }
+
+        fn table(&self, id: usize) -> Read<'a> {
+            Read {
+                buffer: self.buffer,
+                position: self.target(id),
+            }
+        }
+
+        fn string(&self, id: usize) -> &'a str {
+            let at = self.target(id);
+            let length = u32_at(self.buffer, at);
+            std::str::from_utf8(&self.buffer[at + 4..at + 4 + length]).unwrap()
+        }
+
+        fn tables(&self, id: usize) -> Vec<Read<'a>> {
+            let at = self.target(id);
+            (0..u32_at(self.buffer, at))
+                .map(|i| {
+                    let slot = at + 4 + 4 * i;
+                    Read {
+                        buffer: self.buffer,
+                        position: slot + u32_at(self.buffer, slot),
+                    }
+                })
+                .collect()
+        }
+
+        /// A vector of `(i64, i64)` structs, such as the nodes and buffers
+        /// of a record batch.
+        fn pairs(&self, id: usize) -> Vec<(i64, i64)> {
+            let at = self.target(id);
+            (0..u32_at(self.buffer, at))
+                .map(|i| {
+                    let pair = at + 4 + 16 * i;
+                    assert_eq!(pair % 8, 0);
+                    (i64_at(self.buffer, pair), i64_at(self.buffer, pair + 8))
+                })
+                .collect()
+        }
+    }
+
+    fn u32_at(buffer: &[u8], at: usize) -> usize {
+        i32_at(buffer, at) as u32 as usize
+    }
+
+    fn i32_at(buffer: &[u8], at: usize) -> i32 {
+        i32::from_le_bytes(buffer[at..at + 4].try_into().unwrap())
+    }
+
+    fn i64_at(buffer: &[u8], at: usize) -> i64 {
+        i64::from_le_bytes(buffer[at..at + 8].try_into().unwrap())
+    }
+
+    /// The messages of a stream: the header type, the flatbuffer of the
+    /// `Message` and the body.
+    fn messages(stream: &[u8]) -> Vec<(u8, &[u8], &[u8])> {
+        let mut messages = Vec::new();
+        let mut at = 0;
+        loop {
+            assert_eq!(u32_at(stream, at), u32::MAX as usize);
+            let size = u32_at(stream, at + 4);
+            at += 8;
+            if size == 0 {
+                assert_eq!(at, stream.len());
+                return messages;
+            }
+            assert_eq!(size % 8, 0);
+            let metadata = &stream[at..at + size];
+            let message = Read::root(metadata);
+            assert_eq!(message.short(0), METADATA_VERSION);
+            let body = message.long(3) as usize;
+            messages.push((
+                message.byte(1),
+                metadata,
+                &stream[at + size..at + size + body],
+            ));
+            at += size + body;
+        }
+    }
+
+    fn rows(cells: &[[&str; 5]]) -> Vec<Row> {
+        let columns = ["date", "count", "rate", "position", "payee"];
+        cells
+            .iter()
+            .map(|row| {
+                columns
+                    .iter()
+                    .zip(row)
+                    .map(|(column, cell)| (column.to_string(), cell.to_string()))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn writes_a_stream_that_reads_back() {
+        let rows = rows(&[
+            ["2024-01-02", "3", "0.5", "1,200.00 CNY", "Airline"],
+            ["2024-02-29", "", "-2", "-300 JPY", ""],
+            ["", "-4", "1.25", "", "Hotel"],
+        ]);
+        let stream = write(&["date", "count", "rate", "position", "payee"], &rows);
+        let messages = messages(&stream);
+        assert_eq!(messages.len(), 2);
+
+        let (header_type, metadata, body) = messages[0];
+        assert_eq!((header_type, body.len()), (HEADER_SCHEMA, 0));
+        let fields = Read::root(metadata).table(2).tables(1);
+        let types: Vec<(&str, u8)> = fields
+            .iter()
+            .map(|field| (field.string(0), field.byte(2)))
+            .collect();
+        assert_eq!(
+            types,
+            [
+                ("date", TYPE_DATE),
+                ("count", TYPE_INT),
+                ("rate", TYPE_FLOATING_POINT),
+                ("position", TYPE_DECIMAL),
+                ("position_currency", TYPE_UTF8),
+                ("payee", TYPE_UTF8),
+            ]
+        );
+        assert!(fields.iter().all(|field| field.byte(1) == 1));
+        assert_eq!(fields[0].table(3).short(0), DAY);
+        assert_eq!(
+            (fields[1].table(3).int(0), fields[1].table(3).byte(1)),
+            (64, 1)
+        );
+        assert_eq!(fields[2].table(3).short(0), DOUBLE);
+        let decimal = fields[3].table(3);
+        assert_eq!(
+            (decimal.int(0), decimal.int(1), decimal.int(2)),
+            (38, 2, 128)
+        );
+
+        let (header_type, metadata, body) = messages[1];
+        assert_eq!(header_type, HEADER_RECORD_BATCH);
+        let batch = Read::root(metadata).table(2);
+        assert_eq!(batch.long(0), 3);
+        let nulls: Vec<i64> = batch
+            .pairs(1)
+            .iter()
+            .map(|(length, nulls)| {
+                assert_eq!(*length, 3);
+                *nulls
+            })
+            .collect();
+        assert_eq!(nulls, [1, 1, 0, 1, 1, 1]);
+        let buffers = batch.pairs(2);
+        assert_eq!(buffers.len(), 4 * 2 + 2 * 3);
+        let buffer = |i: usize| {
+            let (offset, length) = buffers[i];
+            assert_eq!(offset % 8, 0);
+            &body[offset as usize..(offset + length) as usize]
+        };
+        // The validity and values of each column in turn.
+        assert_eq!(buffer(0), [0b011]);
+        let days: Vec<i32> = buffer(1)
+            .chunks(4)
+            .map(|day| i32::from_le_bytes(day.try_into().unwrap()))
+            .collect();
+        assert_eq!(days, [19724, 19782, 0]);
+        let counts: Vec<i64> = buffer(3)
+            .chunks(8)
+            .map(|count| i64::from_le_bytes(count.try_into().unwrap()))
+            .collect();
+        assert_eq!(counts, [3, 0, -4]);
+        assert!(buffer(4).is_empty());
+        let rates: Vec<f64> = buffer(5)
+            .chunks(8)
+            .map(|rate| f64::from_le_bytes(rate.try_into().unwrap()))
+            .collect();
+        assert_eq!(rates, [0.5, -2.0, 1.25]);
+        assert_eq!(buffer(6), [0b011]);
+        let numbers: Vec<i128> = buffer(7)
+            .chunks(16)
+            .map(|number| i128::from_le_bytes(number.try_into().unwrap()))
+            .collect();
+        assert_eq!(numbers, [120000, -30000, 0]);
+        assert_eq!(buffer(10), b"CNYJPY");
+        let offsets: Vec<i32> = buffer(9)
+            .chunks(4)
+            .map(|offset| i32::from_le_bytes(offset.try_into().unwrap()))
+            .collect();
+        assert_eq!(offsets, [0, 3, 6, 6]);
+        assert_eq!(buffer(11), [0b101]);
+        assert_eq!(buffer(13), b"AirlineHotel");
+    }
+
+    #[test]
+    fn keeps_amounts_as_text_past_the_decimal_precision() {
+        let rows = rows(&[
+            ["", "", "", "0.0000000000000000000000000001 CNY", ""],
+            ["", "", "", "79228162514264337593543950335 CNY", ""],
+        ]);
+        let stream = write(&["position"], &rows);
+        let fields = Read::root(messages(&stream)[0].1).table(2).tables(1);
+        assert_eq!(fields.len(), 1);
+        assert_eq!(
+            (fields[0].string(0), fields[0].byte(2)),
+            ("position", TYPE_UTF8)
+        );
+    }
+}
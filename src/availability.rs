@@ -0,0 +1,237 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::config::CircuitBreakerConfig;
+
+/// Upper bound for a back-off window, whatever fava's `Retry-After` says.
+const MAX_BACKOFF: Duration = Duration::from_secs(3600);
+
+/// Remembers that fava announced a maintenance window (a 502/503 response),
+/// so that requests during it fail fast instead of hammering fava, and the
+/// last failed upstream request for the status endpoint.
+///
+/// With a circuit breaker configured it also counts failed fetches in a row,
+/// and after too many lets requests fail fast for a cooldown. Then a single
+/// probe may try fava again, closing the circuit if it succeeds.
+#[derive(Debug, Default)]
+pub struct Availability {
+    until: Mutex<Option<Instant>>,
+    last_failure: Mutex<Option<(SystemTime, String)>>,
+    breaker: Mutex<Breaker>,
+}
+
+#[derive(Debug, Default)]
+struct Breaker {
+    failures: u32,
+    /// Set while the circuit is open; once past, it is half-open.
+    open_until: Option<Instant>,
+    /// When the probe of a half-open circuit started.
+    probe: Option<Instant>,
+}
+
+impl Availability {
+    /// Returns how long fava is still expected to be unavailable, if at all.
+    pub fn remaining(&self) -> Option<Duration> {
+        let mut until = self.until.lock().unwrap();
+        match *until {
+            Some(at) if at > Instant::now() => Some(at - Instant::now()),
+            _ => {
+                *until = None;
+                None
+            }
+        }
+    }
+
+    /// Starts a back-off window and returns its (possibly capped) length.
+    pub fn mark_unavailable(&self, retry_after: Duration) -> Duration {
+        let retry_after = retry_after.min(MAX_BACKOFF);
+        *self.until.lock().unwrap() = Some(Instant::now() + retry_after);
+        retry_after
+    }
+
+    /// Lets a fetch through, unless the circuit is open or another probe of
+    /// the half-open circuit is under way; returns how long to wait then.
+    pub fn admit(&self, config: &CircuitBreakerConfig) -> Result<(), Duration> {
+        let mut breaker = self.breaker.lock().unwrap();
+        let now = Instant::now();
+        match breaker.open_until {
+            None => Ok(()),
+            Some(until) if until > now => Err(until - now),
+            // A probe whose request was dropped must not block the circuit.
+            Some(_) => match breaker.probe {
+                Some(probe) if now - probe < Duration::from_secs(config.cooldown) => {
+                    Err(Duration::from_secs(1))
+                }
+                _ => {
+                    breaker.probe = Some(now);
+                    Ok(())
+                }
+            },
+        }
+    }
+
+    /// Counts the outcome of a fetch, opening the circuit after
+    /// `config.failures` failures in a row or a failed probe.
+    pub fn record_outcome(&self, config: &CircuitBreakerConfig, success: bool) {
+        let mut breaker = self.breaker.lock().unwrap();
+        if success {
+            if breaker.open_until.is_some() {
+                println!("fava answers again, circuit closed");
+            }
+            *breaker = Breaker::default();
+            return;
+        }
+        breaker.failures += 1;
+        if breaker.probe.is_some() || breaker.failures >= config.failures {
+            if breaker.open_until.is_none() {
+                println!(
+                    "fava failed {} times in a row, circuit open",
+                    breaker.failures
+                );
+            }
+            breaker.open_until = Some(Instant::now() + Duration::from_secs(config.cooldown));
+            breaker.probe = None;
+        }
+    }
+
+    /// `closed`, `open` or `half_open`.
+    pub fn circuit(&self) -> &'static str {
+        match self.breaker.lock().unwrap().open_until {
+            None => "closed",
+            Some(until) if until > Instant::now() => "open",
+            Some(_) => "half_open",
+        }
+    }
+
+    pub fn record_failure(&self, error: String) {
+        *self.last_failure.lock().unwrap() = Some((SystemTime::now(), error));
+    }
+
+    /// When the last upstream request failed, as an HTTP date, and why.
+    pub fn last_failure(&self) -> Option<(String, String)> {
+        self.last_failure
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|(at, error)| (httpdate::fmt_http_date(*at), error.clone()))
+    }
+}
+
+/// Parses a `Retry-After` header, given either as seconds or as an HTTP date.
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let at = httpdate::parse_http_date(value).ok()?;
+    Some(at.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        http::{header::RETRY_AFTER, HeaderMap, Request, StatusCode},
+        routing::get,
+        Router,
+    };
+
+    use super::*;
+    use crate::{backend::Backend, config::Config, testing, testing::Hits, AppState};
+
+    /// Our answer to a query while fava is in maintenance, announced with
+    /// `retry_after` if given, its `Retry-After` in seconds and its error
+    /// code, and how often fava was asked after a second query.
+    async fn maintenance(retry_after: Option<String>) -> (u64, String, usize) {
+        let hits = Hits::default();
+        let fava_hits = hits.clone();
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(move || async move {
+                fava_hits.hit();
+                let mut headers = HeaderMap::new();
+                if let Some(retry_after) = retry_after {
+                    headers.insert(RETRY_AFTER, retry_after.parse().unwrap());
+                }
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    headers,
+                    "<html>Backup</html>",
+                )
+            }),
+        );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none")
+            .retry_after(45);
+        let state = AppState::new(config);
+        let mut answer = None;
+        for _ in 0..2 {
+            let request = Request::get("/api/query_result?query_string=SELECT%201")
+                .body(axum::body::Body::empty())
+                .unwrap();
+            let (status, headers, body) = testing::send(&state, request).await;
+            assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            let seconds = headers[RETRY_AFTER].to_str().unwrap().parse().unwrap();
+            answer.get_or_insert((seconds, body["error_code"].as_str().unwrap().to_string()));
+        }
+        let (seconds, code) = answer.unwrap();
+        (seconds, code, hits.count())
+    }
+
+    #[tokio::test]
+    async fn passes_retry_after_in_seconds_through() {
+        assert_eq!(
+            maintenance(Some("7".into())).await,
+            (7, "upstream_unavailable".into(), 1)
+        );
+    }
+
+    #[tokio::test]
+    async fn passes_retry_after_as_a_date_through() {
+        let at = SystemTime::now() + Duration::from_secs(120);
+        let (seconds, code, hits) = maintenance(Some(httpdate::fmt_http_date(at))).await;
+        assert!((118..=120).contains(&seconds), "{}", seconds);
+        assert_eq!((code.as_str(), hits), ("upstream_unavailable", 1));
+    }
+
+    #[tokio::test]
+    async fn waits_the_configured_time_without_retry_after() {
+        assert_eq!(
+            maintenance(None).await,
+            (45, "upstream_unavailable".into(), 1)
+        );
+    }
+
+    #[test]
+    fn opens_the_circuit_and_lets_one_probe_through_after_the_cooldown() {
+        let config = CircuitBreakerConfig {
+            failures: 2,
+            cooldown: 60,
+        };
+        let availability = Availability::default();
+        availability.record_outcome(&config, false);
+        assert_eq!(availability.circuit(), "closed");
+        assert!(availability.admit(&config).is_ok());
+        availability.record_outcome(&config, false);
+        assert_eq!(availability.circuit(), "open");
+        assert!(availability.admit(&config).unwrap_err() > Duration::from_secs(59));
+
+        // The cooldown passes.
+        availability.breaker.lock().unwrap().open_until = Some(Instant::now());
+        assert_eq!(availability.circuit(), "half_open");
+        assert!(availability.admit(&config).is_ok());
+        assert_eq!(availability.admit(&config), Err(Duration::from_secs(1)));
+        // A failed probe opens the circuit again at once.
+        availability.record_outcome(&config, false);
+        assert_eq!(availability.circuit(), "open");
+
+        availability.breaker.lock().unwrap().open_until = Some(Instant::now());
+        assert!(availability.admit(&config).is_ok());
+        availability.record_outcome(&config, true);
+        assert_eq!(availability.circuit(), "closed");
+        assert!(availability.admit(&config).is_ok());
+    }
+}
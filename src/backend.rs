@@ -0,0 +1,308 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Mutex;
+
+use crate::{i18n::Message, ParsedRows, QueryResult, QueryResultData, Row};
+
+/// How to run queries against fava.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// The JSON API if fava has it, the HTML table otherwise.
+    #[default]
+    Auto,
+    /// Only `/api/query`, of fava 1.26 and later.
+    Json,
+    /// Only `/api/query_result`, scraping the HTML table it renders.
+    Html,
+}
+
+impl Backend {
+    pub fn parse(text: &str) -> Option<Backend> {
+        match text {
+            "auto" => Some(Backend::Auto),
+            "json" => Some(Backend::Json),
+            "html" => Some(Backend::Html),
+            _ => None,
+        }
+    }
+}
+
+/// Whether fava turned out to have the JSON API, once `auto` found out.
+#[derive(Debug, Default)]
+pub struct Detected {
+    json: Mutex<Option<bool>>,
+}
+
+impl Detected {
+    /// Whether the next query goes to `/api/query`.
+    pub fn use_json(&self, backend: Backend) -> bool {
+        match backend {
+            Backend::Auto => self.json.lock().unwrap().unwrap_or(true),
+            Backend::Json => true,
+            Backend::Html => false,
+        }
+    }
+
+    /// What `auto` found out so far.
+    pub fn known(&self) -> Option<bool> {
+        *self.json.lock().unwrap()
+    }
+
+    pub fn set(&self, json: bool) {
+        let previous = self.json.lock().unwrap().replace(json);
+        if previous != Some(json) && !json {
+            println!("fava has no /api/query, reading the HTML tables of /api/query_result");
+        }
+    }
+}
+
+/// The answer of fava's `/api/query`.
+#[derive(Debug, Deserialize)]
+pub struct JsonQueryResult {
+    success: bool,
+    error: Option<String>,
+    data: Option<JsonData>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "t", rename_all = "lowercase")]
+enum JsonData {
+    Table {
+        types: Vec<JsonColumn>,
+        rows: Vec<Vec<Value>>,
+    },
+    /// The text of statements without a result table, such as `EXPLAIN`.
+    String { contents: String },
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonColumn {
+    name: String,
+    /// fava's name of the column's type, such as `str` or `Inventory`.
+    #[serde(default)]
+    dtype: Option<String>,
+}
+
+/// A result table of the JSON API, its cells rendered like the HTML table
+/// renders them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonTable {
+    columns: Vec<String>,
+    #[serde(default)]
+    types: Vec<Option<String>>,
+    rows: Vec<Vec<String>>,
+}
+
+impl From<JsonQueryResult> for QueryResult {
+    fn from(result: JsonQueryResult) -> QueryResult {
+        let table = result.data.map(|data| match data {
+            JsonData::Table { types, rows } => JsonTable {
+                columns: types.iter().map(|column| column.name.clone()).collect(),
+                types: types.into_iter().map(|column| column.dtype).collect(),
+                rows: rows
+                    .iter()
+                    .map(|row| row.iter().map(render).collect())
+                    .collect(),
+            },
+            JsonData::String { contents } => JsonTable {
+                columns: vec!["result".into()],
+                types: vec![Some("str".into())],
+                rows: vec![vec![contents]],
+            },
+        });
+        QueryResult {
+            error: result.error,
+            success: result.success,
+            data: table.map(|table| QueryResultData {
+                table: String::new(),
+                json: Some(table),
+            }),
+            stale_for: None,
+        }
+    }
+}
+
+impl JsonTable {
+    /// The columns with their types, without reading any rows.
+    pub fn schema(&self) -> Vec<(String, Option<String>)> {
+        self.columns
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.clone(), self.types.get(i).cloned().flatten()))
+            .collect()
+    }
+    /// The rows keyed by column name, with the same warnings as the HTML
+    /// table gets.
+    pub fn parse(self) -> ParsedRows {
+        let mut parsed = ParsedRows::default();
+        let mut rows = Vec::new();
+        parsed.columns = self.each_row(&mut parsed.warnings, |_, row| {
+            rows.push(row);
+            true
+        });
+        parsed.rows = rows;
+        parsed
+    }
+
+    /// Hands the rows to `emit` one by one with the column names until it
+    /// returns `false`, and returns the names.
+    pub fn each_row(
+        self,
+        warnings: &mut Vec<Message>,
+        mut emit: impl FnMut(&[String], Row) -> bool,
+    ) -> Vec<String> {
+        let titles: Vec<String> = self
+            .columns
+            .into_iter()
+            .enumerate()
+            .map(|(i, title)| match title.trim().is_empty() {
+                true => {
+                    warnings.push(Message::new(
+                        "column_without_header",
+                        vec![(i + 1).to_string(), format!("column_{}", i + 1)],
+                    ));
+                    format!("column_{}", i + 1)
+                }
+                false => title,
+            })
+            .collect();
+        for (row, cells) in self.rows.into_iter().enumerate() {
+            if cells.len() > titles.len() {
+                warnings.push(Message::new(
+                    "extra_cells",
+                    vec![
+                        (row + 1).to_string(),
+                        cells.len().to_string(),
+                        titles.len().to_string(),
+                    ],
+                ));
+            }
+            let line: Row = titles.iter().cloned().zip(cells).collect();
+            if !emit(&titles, line) {
+                break;
+            }
+        }
+        titles
+    }
+}
+
+/// A cell as text: amounts as `number currency`, positions with their cost
+/// in braces, inventories and sets comma separated, and metadata like the
+/// HTML table prints the dict.
+fn render(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::Bool(true) => "TRUE".into(),
+        Value::Bool(false) => "FALSE".into(),
+        Value::Number(number) => number.to_string(),
+        Value::String(text) => text.clone(),
+        Value::Array(items) => items.iter().map(render).collect::<Vec<_>>().join(", "),
+        Value::Object(object) => {
+            if let (Some(number), Some(currency)) = (object.get("number"), object.get("currency")) {
+                return format!("{} {}", render(number), render(currency));
+            }
+            if let Some(units) = object.get("units") {
+                return match object.get("cost") {
+                    Some(cost) if !cost.is_null() => {
+                        format!("{} {{{}}}", render(units), render(cost))
+                    }
+                    _ => render(units),
+                };
+            }
+            // An inventory, currency to number.
+            if object.values().all(Value::is_number) {
+                return object
+                    .iter()
+                    .map(|(currency, number)| format!("{} {}", render(number), currency))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+            }
+            let entries: Vec<String> = object
+                .iter()
+                .map(|(key, value)| match value {
+                    Value::String(text) => format!("'{}': '{}'", key, text),
+                    value => format!("'{}': {}", key, render(value)),
+                })
+                .collect();
+            format!("{{{}}}", entries.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::get, Router};
+    use serde_json::json;
+
+    use super::*;
+    use crate::{config::Config, testing, testing::table, AppState};
+
+    #[test]
+    fn renders_cells_like_the_html_table() {
+        let cells = [
+            (json!(null), ""),
+            (json!(true), "TRUE"),
+            (json!(12), "12"),
+            (json!({"number": 10.5, "currency": "USD"}), "10.5 USD"),
+            (
+                json!({"units": {"number": 2, "currency": "AAPL"}, "cost": {"number": 150, "currency": "USD"}}),
+                "2 AAPL {150 USD}",
+            ),
+            (
+                json!({"units": {"number": 2, "currency": "AAPL"}, "cost": null}),
+                "2 AAPL",
+            ),
+            (json!({"CNY": -3, "USD": 4}), "-3 CNY, 4 USD"),
+            (json!(["food", "trip"]), "food, trip"),
+            (
+                json!({"filename": "main.bean", "lineno": 3}),
+                "{'filename': 'main.bean', 'lineno': 3}",
+            ),
+        ];
+        for (value, text) in cells {
+            assert_eq!(render(&value), text, "{}", value);
+        }
+    }
+
+    #[test]
+    fn names_the_columns_without_header() {
+        let result: JsonQueryResult = serde_json::from_value(json!({
+            "success": true,
+            "data": {"t": "table", "types": [{"name": "date", "dtype": "date"}, {"name": " "}], "rows": [["2024-01-02", 1, "extra"]]},
+        }))
+        .unwrap();
+        let table = QueryResult::from(result).data.unwrap().json.unwrap();
+        assert_eq!(
+            table.schema(),
+            [
+                ("date".to_string(), Some("date".to_string())),
+                (" ".to_string(), None)
+            ]
+        );
+        let parsed = table.parse();
+        assert_eq!(parsed.columns, ["date", "column_2"]);
+        assert_eq!(parsed.rows[0]["column_2"], "1");
+        let keys: Vec<&str> = parsed.warnings.iter().map(Message::key).collect();
+        assert_eq!(keys, ["column_without_header", "extra_cells"]);
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_the_html_table_of_old_favas() {
+        let rows = r#"{"success": true, "data": {"t": "table", "types": [{"name": "n", "dtype": "int"}], "rows": [[1]]}}"#;
+        let new = Router::new().route("/api/query", get(move || async move { rows }));
+        let old = Router::new().route(
+            "/api/query_result",
+            get(|| async { table(&["n"], &[&["1"]]) }),
+        );
+        for (fava, json) in [(new, true), (old, false)] {
+            let state = AppState::new(Config::new(testing::fava(fava).await).refresh_path("none"));
+            let (status, body) =
+                testing::get(&state, "/api/query_result?query_string=SELECT%20n").await;
+            assert_eq!(status, 200, "{}", body);
+            let body: Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(body["data"], json!([{ "n": "1" }]));
+            assert_eq!(state.backend.known(), Some(json));
+        }
+    }
+}
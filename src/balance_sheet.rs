@@ -0,0 +1,657 @@
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Response},
+    Json,
+};
+use reqwest::StatusCode;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::{
+    amount::{self, Scales},
+    empty_string_as_none,
+    i18n::{Lang, Message},
+    params::{QueryFields, StrictQuery},
+    AppState, ErrorResult, GeneratedQuery, Meta,
+};
+
+/// Root accounts of the balance sheet, in fava's order.
+const ROOTS: [&str; 3] = ["Assets", "Liabilities", "Equity"];
+
+/// The balances of the accounts below `Assets`, `Liabilities` and `Equity`,
+/// as a tree with a total per subtree.
+///
+/// `time` and `filter` are handed to fava like its balance sheet page does,
+/// so `time=2023` gives the balances at the end of 2023. Balances are at
+/// cost unless `conversion=units`, or converted into `currency`.
+pub async fn balance_sheet(
+    State(state): State<AppState>,
+    StrictQuery(params): StrictQuery<BalanceSheetParams>,
+    lang: Lang,
+) -> Result<BalanceSheetResult, ErrorResult> {
+    let value = balance(params.currency.as_deref(), params.conversion.as_deref())?;
+    let query = format!(
+        "SELECT account, {} AS balance WHERE account ~ '^({})(:|$)' GROUP BY account ORDER BY account",
+        value,
+        ROOTS.join("|")
+    );
+    let filters = fava_filters(params.time.as_deref(), params.filter.as_deref());
+    let parsed = state
+        .session()
+        .filtered(&filters)
+        .rows(&query)
+        .await
+        .map_err(|e| e.localize(lang))?;
+
+    let mut scales = Scales::default();
+    let mut balances: BTreeMap<String, Balances> = BTreeMap::new();
+    for row in &parsed.rows {
+        let account = match row.get("account") {
+            Some(account) if !account.is_empty() => account,
+            _ => continue,
+        };
+        let text = row.get("balance").map_or("", String::as_str);
+        add(
+            &mut balances.entry(account.clone()).or_default().own,
+            text,
+            &mut scales,
+        );
+    }
+    let roots = ROOTS
+        .iter()
+        .map(|root| node(root, &balances, &scales))
+        .collect();
+
+    let generated = GeneratedQuery {
+        query,
+        filters: described(&filters),
+        feeds: "data.roots".into(),
+    };
+    Ok(BalanceSheetResult {
+        success: true,
+        data: BalanceSheet { roots },
+        warnings: parsed
+            .warnings
+            .into_iter()
+            .map(|warning| warning.localize(lang))
+            .collect(),
+        meta: Meta::explain(params.explain, vec![generated]),
+    })
+}
+
+/// Every account with its balance as a debit or a credit per commodity, and
+/// the debit and credit totals, which agree for a balanced ledger.
+///
+/// Takes the params of `/api/balance_sheet`. Accounts whose balance is zero
+/// in a commodity get no row for it.
+pub async fn trial_balance(
+    State(state): State<AppState>,
+    StrictQuery(params): StrictQuery<BalanceSheetParams>,
+    lang: Lang,
+) -> Result<TrialBalanceResult, ErrorResult> {
+    let value = balance(params.currency.as_deref(), params.conversion.as_deref())?;
+    let query = format!(
+        "SELECT account, {} AS balance GROUP BY account ORDER BY account",
+        value
+    );
+    let filters = fava_filters(params.time.as_deref(), params.filter.as_deref());
+    let parsed = state
+        .session()
+        .filtered(&filters)
+        .rows(&query)
+        .await
+        .map_err(|e| e.localize(lang))?;
+
+    let mut scales = Scales::default();
+    let mut balances: BTreeMap<String, Inventory> = BTreeMap::new();
+    for row in &parsed.rows {
+        let account = match row.get("account") {
+            Some(account) if !account.is_empty() => account,
+            _ => continue,
+        };
+        let text = row.get("balance").map_or("", String::as_str);
+        add(
+            balances.entry(account.clone()).or_default(),
+            text,
+            &mut scales,
+        );
+    }
+    let format = |number: Decimal, currency: &str| {
+        let mut number = number;
+        number.rescale(number.scale().max(scales.common(Some(currency))));
+        amount::format_number(number)
+    };
+    let mut rows = Vec::new();
+    let mut sums: BTreeMap<String, (Decimal, Decimal)> = BTreeMap::new();
+    for (account, inventory) in balances {
+        for (currency, number) in inventory {
+            if number.is_zero() {
+                continue;
+            }
+            let sum = sums.entry(currency.clone()).or_default();
+            let (debit, credit) = match number.is_sign_positive() {
+                true => {
+                    sum.0 += number;
+                    (Some(format(number, &currency)), None)
+                }
+                false => {
+                    sum.1 -= number;
+                    (None, Some(format(-number, &currency)))
+                }
+            };
+            rows.push(TrialBalanceRow {
+                account: account.clone(),
+                currency,
+                debit,
+                credit,
+            });
+        }
+    }
+    let totals = sums
+        .into_iter()
+        .map(|(currency, (debit, credit))| {
+            let total = TrialBalanceTotal {
+                debit: format(debit, &currency),
+                credit: format(credit, &currency),
+            };
+            (currency, total)
+        })
+        .collect();
+
+    let generated = GeneratedQuery {
+        query,
+        filters: described(&filters),
+        feeds: "data.rows".into(),
+    };
+    Ok(TrialBalanceResult {
+        success: true,
+        data: TrialBalance { rows, totals },
+        warnings: parsed
+            .warnings
+            .into_iter()
+            .map(|warning| warning.localize(lang))
+            .collect(),
+        meta: Meta::explain(params.explain, vec![generated]),
+    })
+}
+
+/// The balance of one account and the accounts below it at the end of `at`,
+/// or of today, per commodity.
+pub async fn account_balance(
+    State(state): State<AppState>,
+    Path(account): Path<String>,
+    StrictQuery(params): StrictQuery<AccountBalanceParams>,
+    lang: Lang,
+) -> Result<AccountBalanceResult, ErrorResult> {
+    if !is_account(&account) {
+        return Err(ErrorResult::bad_request(format!(
+            "invalid account {}",
+            account
+        )));
+    }
+    let mut filter = format!("account ~ '^{}(:|$)'", account);
+    match params.at.as_deref() {
+        Some(at) if is_date(at) => filter.push_str(&format!(" AND date <= {}", at)),
+        Some(at) => {
+            return Err(ErrorResult::bad_request(format!(
+                "invalid date {}, expected YYYY-MM-DD",
+                at
+            )))
+        }
+        None => {}
+    }
+    let value = balance(params.currency.as_deref(), params.conversion.as_deref())?;
+    let query = format!("SELECT {} AS balance WHERE {}", value, filter);
+    let parsed = state
+        .session()
+        .rows(&query)
+        .await
+        .map_err(|e| e.localize(lang))?;
+    let mut scales = Scales::default();
+    let mut inventory = Inventory::new();
+    for row in &parsed.rows {
+        let text = row.get("balance").map_or("", String::as_str);
+        add(&mut inventory, text, &mut scales);
+    }
+
+    let generated = GeneratedQuery {
+        query,
+        filters: vec![filter],
+        feeds: "data.balance".into(),
+    };
+    Ok(AccountBalanceResult {
+        success: true,
+        data: AccountBalance {
+            account,
+            at: params.at,
+            balance: render(&inventory, &scales),
+        },
+        warnings: parsed
+            .warnings
+            .into_iter()
+            .map(|warning| warning.localize(lang))
+            .collect(),
+        meta: Meta::explain(params.explain, vec![generated]),
+    })
+}
+
+/// An account name such as `Assets:Bank-1`, safe to put into a regex.
+pub(crate) fn is_account(account: &str) -> bool {
+    account.split(':').all(|part| {
+        part.starts_with(|c: char| c.is_uppercase() || c.is_ascii_digit())
+            && part.chars().all(|c| c.is_alphanumeric() || c == '-')
+    })
+}
+
+fn is_date(text: &str) -> bool {
+    let parts: Vec<&str> = text.split('-').collect();
+    matches!(parts.as_slice(), [year, month, day]
+        if year.len() == 4 && month.len() == 2 && day.len() == 2
+            && parts.iter().all(|part| part.chars().all(|c| c.is_ascii_digit())))
+}
+
+/// The BQL expression of an account's balance: at cost, in units or
+/// converted into `currency`.
+pub(crate) fn balance(
+    currency: Option<&str>,
+    conversion: Option<&str>,
+) -> Result<String, ErrorResult> {
+    match (currency, conversion) {
+        (Some(currency), _) if amount::is_currency(currency) => {
+            Ok(format!("convert(sum(position), '{}')", currency))
+        }
+        (Some(currency), _) => Err(ErrorResult::bad_request(format!(
+            "invalid currency {}",
+            currency
+        ))),
+        (None, None | Some("at_cost")) => Ok("cost(sum(position))".to_string()),
+        (None, Some("units")) => Ok("units(sum(position))".to_string()),
+        (None, Some(conversion)) => Err(ErrorResult::bad_request(format!(
+            "invalid conversion {}, expected at_cost or units",
+            conversion
+        ))),
+    }
+}
+
+/// fava's own `time` and `filter` params, for the upstream session.
+pub(crate) fn fava_filters<'a>(
+    time: Option<&'a str>,
+    filter: Option<&'a str>,
+) -> Vec<(&'static str, &'a str)> {
+    let mut filters = Vec::new();
+    if let Some(time) = time {
+        filters.push(("time", time));
+    }
+    if let Some(filter) = filter {
+        filters.push(("filter", filter));
+    }
+    filters
+}
+
+pub(crate) fn described(filters: &[(&str, &str)]) -> Vec<String> {
+    filters
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect()
+}
+
+pub(crate) type Inventory = BTreeMap<String, Decimal>;
+
+/// What the query returned for one account.
+#[derive(Debug, Default)]
+pub(crate) struct Balances {
+    pub own: Inventory,
+    /// The balance per period of a report with an interval.
+    pub periods: BTreeMap<String, Inventory>,
+}
+
+/// Adds the amounts of an inventory cell to `inventory`.
+pub(crate) fn add(inventory: &mut Inventory, text: &str, scales: &mut Scales) {
+    for (number, currency) in amount::parse_inventory(text) {
+        scales.observe(Some(&currency), number);
+        *inventory.entry(currency).or_default() += number;
+    }
+}
+
+fn merge(into: &mut Inventory, inventory: &Inventory) {
+    for (currency, number) in inventory {
+        *into.entry(currency.clone()).or_default() += number;
+    }
+}
+
+/// `account` with its own balance and the accounts below it, each of which
+/// adds to the total.
+pub(crate) fn node(
+    account: &str,
+    balances: &BTreeMap<String, Balances>,
+    scales: &Scales,
+) -> AccountNode {
+    let prefix = format!("{}:", account);
+    let mut names: Vec<&str> = balances
+        .keys()
+        .filter_map(|name| name.strip_prefix(&prefix))
+        .map(|rest| rest.split(':').next().unwrap_or(rest))
+        .collect();
+    names.dedup();
+    let children: Vec<AccountNode> = names
+        .into_iter()
+        .map(|name| node(&format!("{}{}", prefix, name), balances, scales))
+        .collect();
+    let empty = Balances::default();
+    let own = balances.get(account).unwrap_or(&empty);
+    let mut total = own.own.clone();
+    let mut periods = own.periods.clone();
+    for child in &children {
+        merge(&mut total, &child.sums);
+        for (period, sums) in &child.period_sums {
+            merge(periods.entry(period.clone()).or_default(), sums);
+        }
+    }
+    AccountNode {
+        account: account.to_string(),
+        balance: render(&own.own, scales),
+        total: render(&total, scales),
+        periods: periods
+            .iter()
+            .map(|(period, sums)| (period.clone(), render(sums, scales)))
+            .collect(),
+        children,
+        sums: total,
+        period_sums: periods,
+    }
+}
+
+pub(crate) fn render(inventory: &Inventory, scales: &Scales) -> BTreeMap<String, String> {
+    inventory
+        .iter()
+        .filter(|(_, number)| !number.is_zero())
+        .map(|(currency, number)| {
+            let mut number = *number;
+            number.rescale(number.scale().max(scales.common(Some(currency))));
+            (currency.clone(), amount::format_number(number))
+        })
+        .collect()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BalanceSheetParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    time: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    filter: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    currency: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    conversion: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    explain: Option<bool>,
+}
+
+impl QueryFields for BalanceSheetParams {
+    const FIELDS: &[&str] = &["time", "filter", "currency", "conversion", "explain"];
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct AccountNode {
+    account: String,
+    /// Currency to the balance of the account's own postings.
+    balance: BTreeMap<String, String>,
+    /// Currency to the balance including all accounts below.
+    total: BTreeMap<String, String>,
+    /// Period to the total in that period, for reports with an interval.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    periods: BTreeMap<String, BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    children: Vec<AccountNode>,
+    #[serde(skip)]
+    sums: Inventory,
+    #[serde(skip)]
+    period_sums: BTreeMap<String, Inventory>,
+}
+
+#[derive(Debug, Serialize)]
+struct BalanceSheet {
+    roots: Vec<AccountNode>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BalanceSheetResult {
+    success: bool,
+    data: BalanceSheet,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<Meta>,
+}
+
+impl IntoResponse for BalanceSheetResult {
+    fn into_response(self) -> Response {
+        let body = Json(self);
+        (StatusCode::OK, body).into_response()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AccountBalanceParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    at: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    currency: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    conversion: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    explain: Option<bool>,
+}
+
+impl QueryFields for AccountBalanceParams {
+    const FIELDS: &[&str] = &["at", "currency", "conversion", "explain"];
+}
+
+#[derive(Debug, Serialize)]
+struct AccountBalance {
+    account: String,
+    /// Unset for the balance as of today, including future postings.
+    at: Option<String>,
+    /// Currency to the balance.
+    balance: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AccountBalanceResult {
+    success: bool,
+    data: AccountBalance,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<Meta>,
+}
+
+impl IntoResponse for AccountBalanceResult {
+    fn into_response(self) -> Response {
+        let body = Json(self);
+        (StatusCode::OK, body).into_response()
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct TrialBalanceRow {
+    account: String,
+    currency: String,
+    /// Set for a positive balance.
+    debit: Option<String>,
+    /// Set for a negative balance, as a positive number.
+    credit: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct TrialBalanceTotal {
+    debit: String,
+    credit: String,
+}
+
+#[derive(Debug, Serialize)]
+struct TrialBalance {
+    rows: Vec<TrialBalanceRow>,
+    /// Currency to the sums of the debit and the credit column.
+    totals: BTreeMap<String, TrialBalanceTotal>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TrialBalanceResult {
+    success: bool,
+    data: TrialBalance,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<Meta>,
+}
+
+impl IntoResponse for TrialBalanceResult {
+    fn into_response(self) -> Response {
+        let body = Json(self);
+        (StatusCode::OK, body).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::get, Router};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{backend::Backend, config::Config, testing, testing::table};
+
+    async fn state() -> AppState {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|| async {
+                table(
+                    &["account", "balance"],
+                    &[
+                        &["Assets:Bank", "1200.00 CNY"],
+                        &["Assets:Bank:Savings", "300.00 CNY, 5 USD"],
+                        &["Equity:Opening-Balances", "-1500.00 CNY"],
+                        &["Liabilities:Card", "-5 USD"],
+                    ],
+                )
+            }),
+        );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        AppState::new(config)
+    }
+
+    #[tokio::test]
+    async fn totals_each_subtree() {
+        let (status, body) = testing::get(
+            &state().await,
+            "/api/balance_sheet?time=2023&conversion=units&explain=true",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["data"]["roots"][0],
+            json!({
+                "account": "Assets",
+                "balance": {},
+                "total": {"CNY": "1500.00", "USD": "5"},
+                "children": [{
+                    "account": "Assets:Bank",
+                    "balance": {"CNY": "1200.00"},
+                    "total": {"CNY": "1500.00", "USD": "5"},
+                    "children": [{
+                        "account": "Assets:Bank:Savings",
+                        "balance": {"CNY": "300.00", "USD": "5"},
+                        "total": {"CNY": "300.00", "USD": "5"},
+                    }],
+                }],
+            })
+        );
+        assert_eq!(body["data"]["roots"][1]["total"], json!({"USD": "-5"}));
+        assert_eq!(
+            body["data"]["roots"][2]["total"],
+            json!({"CNY": "-1500.00"})
+        );
+        let generated = &body["meta"]["generated_queries"][0];
+        assert_eq!(
+            generated["query"],
+            "SELECT account, units(sum(position)) AS balance \
+             WHERE account ~ '^(Assets|Liabilities|Equity)(:|$)' \
+             GROUP BY account ORDER BY account"
+        );
+        assert_eq!(generated["filters"], json!(["time=2023"]));
+    }
+
+    #[tokio::test]
+    async fn refuses_invalid_conversions() {
+        let state = state().await;
+        let (status, _) = testing::get(&state, "/api/balance_sheet?conversion=market").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = testing::get(&state, "/api/balance_sheet?currency=usd").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn splits_balances_into_debits_and_credits() {
+        let (status, body) = testing::get(&state().await, "/api/trial_balance").await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["data"],
+            json!({
+                "rows": [
+                    {"account": "Assets:Bank", "currency": "CNY", "debit": "1200.00", "credit": null},
+                    {"account": "Assets:Bank:Savings", "currency": "CNY", "debit": "300.00", "credit": null},
+                    {"account": "Assets:Bank:Savings", "currency": "USD", "debit": "5", "credit": null},
+                    {"account": "Equity:Opening-Balances", "currency": "CNY", "debit": null, "credit": "1500.00"},
+                    {"account": "Liabilities:Card", "currency": "USD", "debit": null, "credit": "5"},
+                ],
+                "totals": {
+                    "CNY": {"debit": "1500.00", "credit": "1500.00"},
+                    "USD": {"debit": "5", "credit": "5"},
+                },
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn sums_one_account_at_a_date() {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|| async { table(&["balance"], &[&["1500.00 CNY, 5 USD"]]) }),
+        );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        let state = AppState::new(config);
+        let (status, body) = testing::get(
+            &state,
+            "/api/account/Assets:Bank/balance?at=2024-01-31&explain=true",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["data"],
+            json!({
+                "account": "Assets:Bank",
+                "at": "2024-01-31",
+                "balance": {"CNY": "1500.00", "USD": "5"},
+            })
+        );
+        assert_eq!(
+            body["meta"]["generated_queries"][0]["query"],
+            "SELECT cost(sum(position)) AS balance \
+             WHERE account ~ '^Assets:Bank(:|$)' AND date <= 2024-01-31"
+        );
+
+        let (status, _) =
+            testing::get(&state, "/api/account/Assets:Bank/balance?at=2024-1-31").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = testing::get(&state, "/api/account/Assets.Bank/balance").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,139 @@
+use axum::{
+    extract::{rejection::JsonRejection, State},
+    response::{IntoResponse, Response},
+    Json,
+};
+use reqwest::StatusCode;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{i18n::Lang, params, query_rows, AppState, ErrorResult, Params, SuccessResult};
+
+/// Most queries one batch may hold, so that one request can not flood fava.
+const MAX_BATCH: usize = 32;
+
+/// `POST /api/query_batch`: runs a JSON array of queries at once, each an
+/// object like the body of `POST /api/query_result` or just the query
+/// string. The results come in the same order, each with its own
+/// `success`, so one failing query does not fail the others.
+pub async fn query_batch(
+    State(state): State<AppState>,
+    lang: Lang,
+    body: Result<Json<Vec<Value>>, JsonRejection>,
+) -> Result<BatchResult, ErrorResult> {
+    let Json(items) = body.map_err(|rejection| ErrorResult::bad_request(rejection.to_string()))?;
+    if items.len() > MAX_BATCH {
+        return Err(ErrorResult::bad_request(format!(
+            "more than {} queries in one batch",
+            MAX_BATCH
+        )));
+    }
+    let tasks: Vec<_> = items
+        .into_iter()
+        .map(|item| {
+            let state = state.clone();
+            tokio::spawn(async move {
+                let params = match item {
+                    Value::String(query_string) => Ok(Params {
+                        query_string,
+                        ..Params::default()
+                    }),
+                    Value::Object(object) => params::from_object::<Params>(object, &state, lang),
+                    _ => Err(ErrorResult::bad_request(
+                        "a query must be an object or a string".into(),
+                    )),
+                };
+                match params {
+                    Ok(params) => query_rows(&state, &params).await,
+                    Err(e) => Err(e),
+                }
+            })
+        })
+        .collect();
+    let mut results = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        let result = match task.await {
+            Ok(Ok(result)) => BatchItem::Ok(result.localize(lang)),
+            Ok(Err(e)) => BatchItem::Err(e.localize(lang)),
+            Err(e) => BatchItem::Err(ErrorResult::new(e.to_string())),
+        };
+        results.push(result);
+    }
+    Ok(BatchResult {
+        success: true,
+        data: results,
+    })
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum BatchItem {
+    Ok(SuccessResult),
+    Err(ErrorResult),
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchResult {
+    success: bool,
+    data: Vec<BatchItem>,
+}
+
+impl IntoResponse for BatchResult {
+    fn into_response(self) -> Response {
+        let body = Json(self);
+        (StatusCode::OK, body).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, extract::Query, http::Request, routing::get, Router};
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{backend::Backend, config::Config, testing, testing::table};
+
+    async fn batch(state: &AppState, body: Value) -> (StatusCode, Value) {
+        let request = Request::post("/api/query_batch")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let (status, _, body) = testing::send(state, request).await;
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn answers_each_query_of_the_batch_in_order() {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|Query(params): Query<HashMap<String, String>>| async move {
+                match params["query_string"].as_str() {
+                    "SELECT broken" => {
+                        "{\"success\": false, \"error\": \"unknown column\"}".to_string()
+                    }
+                    query => table(&["query"], &[&[query]]),
+                }
+            }),
+        );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        let state = AppState::new(config);
+        let queries = json!(["SELECT a", {"query_string": "SELECT b"}, "SELECT broken", 3]);
+        let (status, body) = batch(&state, queries).await;
+        assert_eq!(status, StatusCode::OK);
+        let results = body["data"].as_array().unwrap();
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0]["data"], json!([{ "query": "SELECT a" }]));
+        assert_eq!(results[1]["data"], json!([{ "query": "SELECT b" }]));
+        assert_eq!(results[2]["success"], false);
+        assert_eq!(results[3]["error"], "a query must be an object or a string");
+
+        let (status, body) = batch(&state, json!(vec!["SELECT a"; MAX_BATCH + 1])).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "more than 32 queries in one batch");
+        let (status, _) = batch(&state, json!({"query_string": "SELECT a"})).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
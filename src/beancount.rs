@@ -0,0 +1,150 @@
+use crate::{amount::Amount, i18n::Message, journal::JournalEntry};
+
+/// Renders journal entries of `account` as beancount transactions. Each
+/// entry gets a posting for the account itself and an auto-balanced posting
+/// for its counterpart: the other posting's account when the entry has
+/// exactly one, `balancing_account` otherwise.
+pub fn render(
+    account: &str,
+    entries: &[JournalEntry],
+    balancing_account: &str,
+    warnings: &mut Vec<Message>,
+) -> String {
+    let mut output = String::new();
+    for (row, entry) in entries.iter().enumerate().rev() {
+        let amount = match Amount::parse(&entry.change) {
+            Some(amount) => amount,
+            None => {
+                warnings.push(Message::new(
+                    "unreadable_change_skipped",
+                    vec![(row + 1).to_string(), entry.change.trim().to_string()],
+                ));
+                continue;
+            }
+        };
+        let others: Vec<&String> = entry
+            .accounts
+            .iter()
+            .filter(|other| other.as_str() != account)
+            .collect();
+        let counterpart = match others.as_slice() {
+            [other] => other.as_str(),
+            _ => balancing_account,
+        };
+        let flag = match entry.flag.as_str() {
+            "!" => "!",
+            _ => "*",
+        };
+        output.push_str(&format!(
+            "{} {} \"{}\" \"{}\"\n  {}  {} {}\n  {}\n\n",
+            entry.date,
+            flag,
+            escape(&entry.payee),
+            escape(&entry.narration),
+            account,
+            amount.number,
+            amount.currency,
+            counterpart
+        ));
+    }
+    output
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{journal::RawCells, testing};
+
+    fn entry(date: &str, flag: &str, payee: &str, narration: &str, change: &str) -> JournalEntry {
+        JournalEntry {
+            date: date.to_string(),
+            flag: flag.to_string(),
+            payee: payee.to_string(),
+            narration: narration.to_string(),
+            change: change.to_string(),
+            balance: String::new(),
+            accounts: vec!["Assets:Bank".to_string(), "Expenses:Food".to_string()],
+            raw: RawCells {
+                date: String::new(),
+                change: String::new(),
+                balance: String::new(),
+                classes: Vec::new(),
+            },
+        }
+    }
+
+    /// The strings of a transaction line, unescaped, if it is one bean-check
+    /// reads: a date, a flag and two quoted strings.
+    fn strings(line: &str) -> Option<Vec<String>> {
+        let (date, rest) = line.split_once(' ')?;
+        crate::interval::parse_date(date)?;
+        let rest = rest
+            .strip_prefix("* ")
+            .or_else(|| rest.strip_prefix("! "))?;
+        let mut strings = Vec::new();
+        let mut chars = rest.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                ' ' if !strings.is_empty() => {}
+                '"' => {
+                    let mut text = String::new();
+                    loop {
+                        match chars.next()? {
+                            '"' => break,
+                            '\\' => text.push(chars.next()?),
+                            c => text.push(c),
+                        }
+                    }
+                    strings.push(text);
+                }
+                _ => return None,
+            }
+        }
+        Some(strings)
+    }
+
+    #[test]
+    fn escapes_quotes_and_keeps_unicode() {
+        let mut entries = [
+            entry(
+                "2024-01-03",
+                "*",
+                "Café \"Zum\" Hirsch",
+                "Kaffee ☕ und Kuchen",
+                "-4.50 EUR",
+            ),
+            entry(
+                "2024-01-02",
+                "!",
+                "東京 Store",
+                r#"Path C:\temp "x""#,
+                "-1,200.00 JPY",
+            ),
+            entry("2024-01-01", "P", "", "naïve résumé 🧾", "not an amount"),
+        ];
+        // With two other postings, the counterpart is not known.
+        entries[1].accounts.push("Expenses:Misc".to_string());
+        let mut warnings = Vec::new();
+        let text = render("Assets:Bank", &entries, "Equity:Unknown", &mut warnings);
+        assert!(testing::matches_snapshot(
+            "beancount/escaping.beancount",
+            text.as_bytes()
+        ));
+        assert_eq!(warnings.len(), 1);
+
+        let transactions: Vec<&str> = text.split_terminator("\n\n").collect();
+        assert_eq!(transactions.len(), 2);
+        for (transaction, entry) in transactions.iter().zip(entries[..2].iter().rev()) {
+            let mut lines = transaction.lines();
+            let header = strings(lines.next().unwrap()).unwrap();
+            assert_eq!(header, [entry.payee.as_str(), entry.narration.as_str()]);
+            for posting in lines {
+                assert!(posting.starts_with("  ") && !posting.starts_with("   "));
+            }
+        }
+    }
+}
@@ -0,0 +1,318 @@
+use serde::Deserialize;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::Hasher,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{AppState, FavaClient};
+
+/// How long a fetched change token is trusted before fava is asked again.
+const TOKEN_TTL: Duration = Duration::from_millis(1500);
+
+/// A summary of the whole ledger that changes with nearly every edit,
+/// whatever made fava reload it.
+const DIGEST_QUERY: &str = "SELECT count(position), sum(position), max(date)";
+
+/// Tracks the ledger version as a generation counter that is bumped every
+/// time the change token of the ledger changes.
+///
+/// The token is a digest of [`DIGEST_QUERY`], which reads the same no
+/// matter who asks. Fava's `changed` API is still asked first, as it makes
+/// fava reload changed beancount files, but it reports a reload only to the
+/// first caller after it, so web interfaces polling it would otherwise hide
+/// reloads from the cache.
+#[derive(Debug, Default)]
+pub struct LedgerVersion {
+    state: Mutex<VersionState>,
+}
+
+#[derive(Debug, Default)]
+struct VersionState {
+    generation: u64,
+    token: Option<u64>,
+    checked_at: Option<Instant>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChangedResult {
+    success: bool,
+    data: Option<bool>,
+}
+
+impl LedgerVersion {
+    /// Returns the current generation, or `None` if fava could not tell us
+    /// whether the ledger changed, in which case nothing should be cached.
+    pub async fn generation(&self, state: &AppState) -> Option<u64> {
+        {
+            let version = self.state.lock().unwrap();
+            if version
+                .checked_at
+                .is_some_and(|at| at.elapsed() < TOKEN_TTL)
+            {
+                return Some(version.generation);
+            }
+        }
+        let changed = fetch_changed(&state.client).await?;
+        let token = fetch_token(state).await?;
+        let mut version = self.state.lock().unwrap();
+        if changed || version.token.is_some_and(|last| last != token) {
+            version.generation += 1;
+        }
+        version.token = Some(token);
+        version.checked_at = Some(Instant::now());
+        Some(version.generation)
+    }
+}
+
+async fn fetch_changed(client: &FavaClient) -> Option<bool> {
+    let result = client
+        .fetch("/api/changed", &[])
+        .await
+        .ok()?
+        .json::<ChangedResult>()
+        .await
+        .ok()?;
+    if result.success {
+        result.data
+    } else {
+        None
+    }
+}
+
+/// The hash of fava's answer to [`DIGEST_QUERY`], from the query API that
+/// the other queries go to.
+async fn fetch_token(state: &AppState) -> Option<u64> {
+    let path = match state.backend.use_json(state.config.backend) {
+        true => "/api/query",
+        false => "/api/query_result",
+    };
+    let response = state
+        .client
+        .fetch(path, &[("query_string", DIGEST_QUERY)])
+        .await
+        .ok()?;
+    if !response.status().is_success() {
+        return None;
+    }
+    let mut hasher = DefaultHasher::new();
+    hasher.write(&response.bytes().await.ok()?);
+    Some(hasher.finish())
+}
+
+/// Upstream responses keyed by request, each valid only for the ledger
+/// generation it was fetched under.
+#[derive(Debug)]
+pub struct VersionedCache<T> {
+    entries: Mutex<HashMap<String, (u64, T)>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl<T> Default for VersionedCache<T> {
+    fn default() -> Self {
+        VersionedCache {
+            entries: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+}
+
+impl<T: Clone> VersionedCache<T> {
+    pub fn get(&self, key: &str, generation: u64) -> Option<T> {
+        let entries = self.entries.lock().unwrap();
+        let value = match entries.get(key) {
+            Some((entry_generation, value)) if *entry_generation == generation => {
+                Some(value.clone())
+            }
+            _ => None,
+        };
+        let counter = match value {
+            Some(_) => &self.hits,
+            None => &self.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        value
+    }
+
+    /// Whether `get` would hit, without counting as a lookup.
+    pub fn contains(&self, key: &str, generation: u64) -> bool {
+        let entries = self.entries.lock().unwrap();
+        matches!(entries.get(key), Some((entry_generation, _)) if *entry_generation == generation)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Lookups so far that hit and that missed.
+    pub fn stats(&self) -> (u64, u64) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Stores a value, dropping every entry left over from older generations.
+    pub fn insert(&self, key: String, generation: u64, value: T) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (entry_generation, _)| *entry_generation >= generation);
+        entries.insert(key, (generation, value));
+    }
+}
+
+/// Collapses whitespace so trivially different spellings share an entry,
+/// leaving string literals as they are.
+pub fn normalize_query(query_string: &str) -> String {
+    let mut normalized = String::with_capacity(query_string.len());
+    let mut quote = None;
+    let mut escaped = false;
+    let mut space = false;
+    for c in query_string.trim().chars() {
+        match quote {
+            Some(open) => {
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' {
+                    escaped = true;
+                } else if c == open {
+                    quote = None;
+                }
+            }
+            None if c.is_whitespace() => {
+                space = true;
+                continue;
+            }
+            None => {
+                if c == '\'' || c == '"' {
+                    quote = Some(c);
+                }
+            }
+        }
+        if std::mem::take(&mut space) {
+            normalized.push(' ');
+        }
+        normalized.push(c);
+    }
+    normalized
+}
+
+/// The last good response per key, kept to answer with while fava fails.
+#[derive(Debug)]
+pub struct Fallback<T> {
+    entries: Mutex<HashMap<String, (Instant, T)>>,
+}
+
+impl<T> Default for Fallback<T> {
+    fn default() -> Self {
+        Fallback {
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T: Clone> Fallback<T> {
+    /// Returns the value stored for `key` and its age, unless it is older
+    /// than `max_age`.
+    pub fn get(&self, key: &str, max_age: Duration) -> Option<(T, Duration)> {
+        let entries = self.entries.lock().unwrap();
+        let (stored_at, value) = entries.get(key)?;
+        let age = stored_at.elapsed();
+        (age <= max_age).then(|| (value.clone(), age))
+    }
+
+    /// Stores a value, dropping every entry that became too old to serve.
+    pub fn insert(&self, key: String, value: T, max_age: Duration) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (stored_at, _)| stored_at.elapsed() <= max_age);
+        entries.insert(key, (Instant::now(), value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::State, routing::get, Json, Router};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::testing;
+
+    /// A fava whose `changed` API never reports a reload, as if a web
+    /// interface polled it first, with the digest answer in the `String`.
+    async fn fava() -> (AppState, Arc<Mutex<String>>) {
+        let digest = Arc::new(Mutex::new("1".to_string()));
+        let fava = Router::new()
+            .route(
+                "/api/changed",
+                get(|| async { Json(json!({ "success": true, "data": false })) }),
+            )
+            .route(
+                "/api/query",
+                get(|State(digest): State<Arc<Mutex<String>>>| async move {
+                    digest.lock().unwrap().clone()
+                }),
+            )
+            .with_state(digest.clone());
+        (testing::state(&testing::fava(fava).await), digest)
+    }
+
+    fn expire(version: &LedgerVersion) {
+        version.state.lock().unwrap().checked_at = None;
+    }
+
+    #[tokio::test]
+    async fn serves_entries_of_the_same_token() {
+        let (state, _) = fava().await;
+        let cache = VersionedCache::default();
+        let generation = state.version.generation(&state).await.unwrap();
+        cache.insert("q".into(), generation, 1);
+        expire(&state.version);
+        let generation = state.version.generation(&state).await.unwrap();
+        assert_eq!(cache.get("q", generation), Some(1));
+        assert_eq!(cache.stats(), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn misses_after_the_token_changed() {
+        let (state, digest) = fava().await;
+        let cache = VersionedCache::default();
+        let before = state.version.generation(&state).await.unwrap();
+        cache.insert("q".into(), before, 1);
+        *digest.lock().unwrap() = "2".into();
+        // Still the trusted token.
+        assert_eq!(state.version.generation(&state).await, Some(before));
+        expire(&state.version);
+        let after = state.version.generation(&state).await.unwrap();
+        assert_ne!(after, before);
+        assert_eq!(cache.get("q", after), None);
+        assert_eq!(cache.stats(), (0, 1));
+    }
+
+    #[test]
+    fn drops_entries_of_older_generations() {
+        let cache = VersionedCache::default();
+        cache.insert("a".into(), 1, 1);
+        cache.insert("b".into(), 1, 2);
+        assert_eq!(cache.len(), 2);
+        cache.insert("c".into(), 2, 3);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.get("a", 1), None);
+        assert_eq!(cache.get("c", 2), Some(3));
+    }
+
+    #[test]
+    fn collapses_whitespace_outside_string_literals() {
+        assert_eq!(
+            normalize_query(
+                "  SELECT  account\n WHERE payee = 'A  B'\tAND narration ~ \"x \\\"  y\"  "
+            ),
+            "SELECT account WHERE payee = 'A  B' AND narration ~ \"x \\\"  y\""
+        );
+    }
+}
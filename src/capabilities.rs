@@ -0,0 +1,549 @@
+use axum::{
+    extract::State,
+    http::HeaderValue,
+    response::{IntoResponse, Response},
+    routing::MethodRouter,
+    Json, Router,
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::{config::Config, groups::EndpointGroup, AppState};
+
+/// Header listing the features enabled in this deployment, comma separated.
+const HEADER: &str = "x-fq-capabilities";
+
+/// Output formats of the account endpoint.
+const FORMATS: &[&str] = &["json", "beancount"];
+
+/// Something clients can detect, with the routes and query parameters that
+/// belong to it.
+pub struct Feature {
+    name: &'static str,
+    /// Endpoint group the routes are mounted in, if they can be switched off.
+    group: Option<EndpointGroup>,
+    routes: &'static [&'static str],
+    params: &'static [&'static str],
+    /// Whether the config turns the feature on.
+    enabled: fn(&Config) -> bool,
+}
+
+const fn feature(
+    name: &'static str,
+    group: Option<EndpointGroup>,
+    routes: &'static [&'static str],
+    params: &'static [&'static str],
+) -> Feature {
+    Feature {
+        name,
+        group,
+        routes,
+        params,
+        enabled: |_| true,
+    }
+}
+
+/// Every feature of the service. Routes are mounted through
+/// [`FeatureRoutes::feature_route`] and parameters read through
+/// `StrictQuery`, which both refuse anything missing here.
+const FEATURES: &[Feature] = &[
+    feature("version", None, &["/api/version"], &[]),
+    feature("status", None, &["/api/status"], &[]),
+    feature("health", None, &["/healthz", "/readyz"], &[]),
+    feature("upstream", None, &["/api/upstream"], &[]),
+    feature("options", None, &["/api/options"], &[]),
+    feature("localization", None, &[], &["lang"]),
+    feature(
+        "query",
+        Some(EndpointGroup::Query),
+        &["/api/query_result"],
+        &[
+            "query_string",
+            "account",
+            "filter",
+            "time",
+            "refresh_path",
+            "refresh",
+        ],
+    ),
+    feature(
+        "query_budget",
+        Some(EndpointGroup::Query),
+        &[],
+        &["budget_ms"],
+    ),
+    Feature {
+        enabled: |config| !config.transforms.is_empty(),
+        ..feature(
+            "transforms",
+            Some(EndpointGroup::Query),
+            &[],
+            &["transform"],
+        )
+    },
+    feature(
+        "search",
+        Some(EndpointGroup::Query),
+        &[],
+        &["search", "search_columns"],
+    ),
+    feature(
+        "aggregate",
+        Some(EndpointGroup::Query),
+        &[],
+        &["group_by", "aggregate"],
+    ),
+    feature(
+        "query_validate",
+        Some(EndpointGroup::Query),
+        &["/api/query_validate"],
+        &["query_string"],
+    ),
+    feature(
+        "templates",
+        Some(EndpointGroup::Query),
+        &["/api/template/:name", "/api/templates"],
+        &[],
+    ),
+    feature(
+        "saved_queries",
+        Some(EndpointGroup::Query),
+        &[
+            "/api/saved_queries",
+            "/api/saved_queries/:id",
+            "/api/saved_queries/:id/run",
+        ],
+        &["account", "filter", "time", "offset", "limit"],
+    ),
+    feature(
+        "saved_query_writes",
+        Some(EndpointGroup::Write),
+        &["/api/saved_queries", "/api/saved_queries/:id"],
+        &[],
+    ),
+    feature(
+        "row_filter",
+        Some(EndpointGroup::Query),
+        &[],
+        &["row_filter"],
+    ),
+    feature("sort", Some(EndpointGroup::Query), &[], &["sort"]),
+    feature("columns", Some(EndpointGroup::Query), &[], &["columns"]),
+    feature("post_query", Some(EndpointGroup::Query), &[], &[]),
+    feature("typed", None, &[], &["typed"]),
+    feature("csv", None, &[], &["format"]),
+    feature("output_locale", None, &[], &["output_locale"]),
+    feature("xlsx", Some(EndpointGroup::Query), &[], &["format"]),
+    feature("arrow", Some(EndpointGroup::Query), &[], &["format"]),
+    feature("ndjson", Some(EndpointGroup::Query), &[], &["format"]),
+    feature(
+        "html_fragment",
+        Some(EndpointGroup::Query),
+        &[],
+        &["format"],
+    ),
+    feature("pagination", None, &[], &["offset", "limit"]),
+    feature(
+        "query_batch",
+        Some(EndpointGroup::Query),
+        &["/api/query_batch"],
+        &[],
+    ),
+    feature(
+        "grpc",
+        None,
+        &[
+            "/fava_query.v1.FavaQuery/QueryResult",
+            "/fava_query.v1.FavaQuery/AccountSeries",
+            "/fava_query.v1.FavaQuery/BalanceSheet",
+        ],
+        &[],
+    ),
+    feature(
+        "graphql",
+        Some(EndpointGroup::Query),
+        &["/api/graphql"],
+        &["query", "variables", "operationName"],
+    ),
+    feature(
+        "from_link",
+        Some(EndpointGroup::Query),
+        &["/api/from_link"],
+        &["url"],
+    ),
+    feature(
+        "journal",
+        Some(EndpointGroup::Query),
+        &["/api/journal"],
+        &["account", "time", "filter"],
+    ),
+    feature(
+        "account_journal",
+        Some(EndpointGroup::Account),
+        &["/api/account/:account"],
+        &[
+            "negate",
+            "invert_columns",
+            "refresh_path",
+            "refresh",
+            "format",
+            "balancing_account",
+        ],
+    ),
+    feature("combined_accounts", Some(EndpointGroup::Account), &[], &[]),
+    feature(
+        "account_balance",
+        Some(EndpointGroup::Account),
+        &["/api/account/:account/balance"],
+        &["at", "currency", "conversion"],
+    ),
+    feature(
+        "account_transactions",
+        Some(EndpointGroup::Account),
+        &["/api/account/:account/transactions"],
+        &["time", "filter"],
+    ),
+    feature(
+        "accounts",
+        Some(EndpointGroup::Account),
+        &["/api/accounts"],
+        &[],
+    ),
+    feature(
+        "raw_cells",
+        Some(EndpointGroup::Account),
+        &[],
+        &["include_raw"],
+    ),
+    Feature {
+        enabled: |config| config.journal_by_year,
+        ..feature("pagination", Some(EndpointGroup::Account), &[], &[])
+    },
+    feature("balance", Some(EndpointGroup::Account), &["/balance"], &[]),
+    feature(
+        "tags",
+        Some(EndpointGroup::Aggregate),
+        &["/api/tag/:tag", "/api/tags", "/api/links"],
+        &["counts", "time", "explain"],
+    ),
+    feature(
+        "payees",
+        Some(EndpointGroup::Aggregate),
+        &["/api/payees"],
+        &["prefix"],
+    ),
+    Feature {
+        enabled: |config| !config.views.is_empty(),
+        ..feature(
+            "views",
+            Some(EndpointGroup::Aggregate),
+            &["/api/view/:name"],
+            &[],
+        )
+    },
+    feature(
+        "streaming_export",
+        Some(EndpointGroup::Aggregate),
+        &["/api/export.zip"],
+        &["root", "from", "to"],
+    ),
+    feature(
+        "income_expenses",
+        Some(EndpointGroup::Aggregate),
+        &["/api/income_expenses"],
+        &["interval", "from", "to", "currency", "breakdown", "negate"],
+    ),
+    feature(
+        "change_events",
+        Some(EndpointGroup::Streaming),
+        &["/api/events/stream"],
+        &[],
+    ),
+    feature(
+        "balance_sheet",
+        Some(EndpointGroup::Aggregate),
+        &["/api/balance_sheet"],
+        &["time", "filter", "currency", "conversion"],
+    ),
+    feature(
+        "income_statement",
+        Some(EndpointGroup::Aggregate),
+        &["/api/income_statement"],
+        &["time", "filter", "interval", "currency", "conversion"],
+    ),
+    feature(
+        "net_worth",
+        Some(EndpointGroup::Aggregate),
+        &["/api/net_worth"],
+        &["interval", "from", "to", "currency"],
+    ),
+    feature(
+        "holdings",
+        Some(EndpointGroup::Aggregate),
+        &["/api/holdings"],
+        &["time", "filter"],
+    ),
+    feature(
+        "uncleared",
+        Some(EndpointGroup::Aggregate),
+        &["/api/uncleared"],
+        &["account", "time", "filter", "padding", "todo"],
+    ),
+    feature(
+        "recurring",
+        Some(EndpointGroup::Aggregate),
+        &["/api/recurring"],
+        &["account", "time", "filter", "min_occurrences"],
+    ),
+    feature(
+        "interval_report",
+        Some(EndpointGroup::Aggregate),
+        &["/api/interval_report"],
+        &[
+            "account",
+            "interval",
+            "from",
+            "to",
+            "currency",
+            "conversion",
+            "time",
+            "filter",
+        ],
+    ),
+    feature(
+        "statistics",
+        Some(EndpointGroup::Aggregate),
+        &["/api/statistics"],
+        &[],
+    ),
+    feature(
+        "trial_balance",
+        Some(EndpointGroup::Aggregate),
+        &["/api/trial_balance"],
+        &["time", "filter", "currency", "conversion"],
+    ),
+    feature(
+        "commodities",
+        Some(EndpointGroup::Aggregate),
+        &["/api/commodities", "/api/prices"],
+        &["commodity", "base"],
+    ),
+    feature(
+        "events",
+        Some(EndpointGroup::Aggregate),
+        &["/api/events"],
+        &["type"],
+    ),
+    feature(
+        "budgets",
+        Some(EndpointGroup::Aggregate),
+        &["/api/budgets"],
+        &["account"],
+    ),
+    feature(
+        "documents",
+        Some(EndpointGroup::Aggregate),
+        &["/api/documents"],
+        &["account", "time"],
+    ),
+    feature(
+        "document_download",
+        Some(EndpointGroup::Passthrough),
+        &["/api/documents/download"],
+        &["filename"],
+    ),
+    Feature {
+        enabled: |config| !config.alerts.is_empty(),
+        ..feature("alerts", Some(EndpointGroup::Admin), &["/api/alerts"], &[])
+    },
+    feature(
+        "caching",
+        Some(EndpointGroup::Admin),
+        &["/api/cache/status"],
+        &[],
+    ),
+    feature("metrics", Some(EndpointGroup::Admin), &["/metrics"], &[]),
+    feature(
+        "ledger_errors",
+        Some(EndpointGroup::Admin),
+        &["/api/errors"],
+        &[],
+    ),
+    Feature {
+        enabled: |config| config.slugs,
+        ..feature(
+            "slugs",
+            Some(EndpointGroup::Passthrough),
+            &["/slugs/:slug/*route"],
+            &[],
+        )
+    },
+    Feature {
+        enabled: |config| config.stale_fallback.is_some(),
+        ..feature("stale_fallback", None, &[], &[])
+    },
+    Feature {
+        enabled: |config| config.circuit_breaker.is_some(),
+        ..feature("circuit_breaker", None, &[], &[])
+    },
+    Feature {
+        enabled: |config| config.poll_smoothing.is_some(),
+        ..feature("poll_smoothing", None, &[], &[])
+    },
+    Feature {
+        enabled: |config| config.strict_params,
+        ..feature("strict_params", None, &[], &[])
+    },
+    Feature {
+        enabled: |config| config.compression,
+        ..feature("compression", None, &[], &[])
+    },
+];
+
+impl Feature {
+    fn is_on(&self, config: &Config) -> bool {
+        self.group
+            .is_none_or(|group| config.endpoints.contains(&group))
+            && (self.enabled)(config)
+    }
+}
+
+/// `Router::route` that refuses routes no feature lists, so that clients
+/// always learn about new endpoints from `/api/version`.
+pub trait FeatureRoutes {
+    fn feature_route(self, path: &str, method_router: MethodRouter<AppState>) -> Self;
+}
+
+impl FeatureRoutes for Router<AppState> {
+    fn feature_route(self, path: &str, method_router: MethodRouter<AppState>) -> Self {
+        assert!(
+            FEATURES
+                .iter()
+                .any(|feature| feature.routes.contains(&path)),
+            "route {} belongs to no feature in capabilities::FEATURES",
+            path
+        );
+        self.route(path, method_router)
+    }
+}
+
+/// The first of `fields` that no feature lists.
+pub fn unregistered_param(fields: &[&'static str]) -> Option<&'static str> {
+    fields
+        .iter()
+        .find(|field| {
+            !FEATURES
+                .iter()
+                .any(|feature| feature.params.contains(field))
+        })
+        .copied()
+}
+
+pub async fn version(State(state): State<AppState>) -> Response {
+    let features: BTreeMap<&str, bool> = FEATURES
+        .iter()
+        .map(|feature| (feature.name, feature.is_on(&state.config)))
+        .collect();
+    let enabled: Vec<&str> = features
+        .iter()
+        .filter(|(_, enabled)| **enabled)
+        .map(|(name, _)| *name)
+        .collect();
+    let mut response = Json(VersionResult {
+        success: true,
+        data: Version {
+            version: env!("CARGO_PKG_VERSION"),
+            base_path: state.config.base_path.clone(),
+            capabilities: Capabilities {
+                formats: FORMATS,
+                features,
+            },
+        },
+    })
+    .into_response();
+    if let Ok(value) = HeaderValue::from_str(&enabled.join(",")) {
+        response.headers_mut().insert(HEADER, value);
+    }
+    response
+}
+
+#[derive(Debug, Serialize)]
+pub struct VersionResult {
+    success: bool,
+    data: Version,
+}
+
+#[derive(Debug, Serialize)]
+struct Version {
+    version: &'static str,
+    /// Path this ledger's routes are mounted below, empty at the root.
+    base_path: String,
+    capabilities: Capabilities,
+}
+
+#[derive(Debug, Serialize)]
+struct Capabilities {
+    formats: &'static [&'static str],
+    /// Every known feature, and whether this deployment has it on.
+    features: BTreeMap<&'static str, bool>,
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::CONTENT_TYPE, Method, Request},
+    };
+    use std::fs;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{params, routes, testing};
+
+    /// Every route of every feature, its segments filled in, under each
+    /// method, with a parameter no struct knows in its query and body.
+    #[tokio::test]
+    async fn every_params_struct_belongs_to_features() {
+        // Building the routes refuses any not in FEATURES.
+        let router = routes(testing::state("http://127.0.0.1:9"));
+        for route in FEATURES.iter().flat_map(|feature| feature.routes) {
+            let path: Vec<&str> = route
+                .split('/')
+                .map(|segment| match segment.chars().next() {
+                    Some(':') | Some('*') => "1",
+                    _ => segment,
+                })
+                .collect();
+            let uri = format!("{}?fq_unregistered=1", path.join("/"));
+            for method in [Method::GET, Method::POST, Method::PUT, Method::DELETE] {
+                let request = Request::builder()
+                    .method(method)
+                    .uri(&uri)
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(Body::from("{\"fq_unregistered\": 1}"))
+                    .unwrap();
+                // The body is left alone, as streams would never end.
+                router.clone().oneshot(request).await.unwrap();
+            }
+        }
+
+        let walked = params::WALKED.lock().unwrap().clone();
+        let mut missed = Vec::new();
+        for file in fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/src")).unwrap() {
+            let source = fs::read_to_string(file.unwrap().path()).unwrap();
+            for line in source.lines() {
+                let Some(name) = line.strip_prefix("impl QueryFields for ") else {
+                    continue;
+                };
+                let name = name.trim_end_matches(" {");
+                if !walked
+                    .iter()
+                    .any(|walked| walked.ends_with(&format!("::{}", name)))
+                {
+                    missed.push(name.to_string());
+                }
+            }
+        }
+        assert_eq!(missed, Vec::<String>::new(), "no route reads these");
+    }
+}
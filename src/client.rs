@@ -0,0 +1,506 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+
+use crate::{QueryResult, UpstreamError};
+
+/// Consecutive connection failures after which the connection pool is
+/// dropped and the host resolved again.
+const CONNECT_FAILURES: u32 = 3;
+
+/// `User-Agent` of requests to fava unless configured otherwise.
+const USER_AGENT: &str = concat!("fava-query/", env!("CARGO_PKG_VERSION"));
+
+/// Http access to a single fava ledger.
+///
+/// The service keeps one per router; embedding applications can use their
+/// own to run queries without going through the routes. Clones share their
+/// connections.
+#[derive(Debug, Clone)]
+pub struct FavaClient {
+    url: String,
+    overrides: Vec<(String, IpAddr)>,
+    options: Options,
+    auth: Option<Auth>,
+    headers: HeaderMap,
+    http: Arc<RwLock<reqwest::Client>>,
+    health: Arc<Mutex<Health>>,
+}
+
+/// Settings of the pooled http client.
+#[derive(Debug, Clone)]
+struct Options {
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    user_agent: String,
+    roots: Vec<reqwest::Certificate>,
+    accept_invalid_certs: bool,
+    /// Proxy for every request, instead of `HTTP_PROXY`/`HTTPS_PROXY`.
+    proxy: Option<String>,
+    /// Ignore the proxy variables of the environment.
+    no_proxy: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            connect_timeout: None,
+            timeout: None,
+            user_agent: USER_AGENT.into(),
+            roots: Vec::new(),
+            accept_invalid_certs: false,
+            proxy: None,
+            no_proxy: false,
+        }
+    }
+}
+
+/// Credentials sent with every request, for a fava behind an authenticating
+/// proxy.
+#[derive(Clone)]
+pub enum Auth {
+    Basic {
+        username: String,
+        password: Option<String>,
+    },
+    Bearer(String),
+}
+
+impl fmt::Debug for Auth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Auth::Basic { username, .. } => write!(f, "Basic({}, ***)", username),
+            Auth::Bearer(_) => write!(f, "Bearer(***)"),
+        }
+    }
+}
+
+/// What the client learned from earlier requests.
+#[derive(Debug, Default)]
+struct Health {
+    /// Whether any request reached fava yet, so that failures of a url that
+    /// never worked are not mistaken for a moved host.
+    connected: bool,
+    connect_failures: u32,
+    address: Option<SocketAddr>,
+}
+
+/// A failed call to fava.
+#[derive(Debug)]
+pub struct Error {
+    message: String,
+}
+
+impl FavaClient {
+    /// Creates a client for the ledger at `url`, e.g.
+    /// `http://fava:5000/beancount`.
+    pub fn new(url: impl Into<String>) -> FavaClient {
+        FavaClient {
+            url: url.into().trim_end_matches('/').to_string(),
+            overrides: Vec::new(),
+            options: Options::default(),
+            auth: None,
+            headers: HeaderMap::new(),
+            http: Arc::new(RwLock::new(build(&[], &Options::default()))),
+            health: Default::default(),
+        }
+    }
+
+    /// Connects to `ip` whenever the ledger url names `host`, instead of
+    /// asking DNS.
+    pub fn resolve(mut self, host: impl Into<String>, ip: IpAddr) -> FavaClient {
+        self.overrides.push((host.into(), ip));
+        self.rebuild()
+    }
+
+    /// Gives up connecting to fava after `timeout`.
+    pub fn connect_timeout(mut self, timeout: Duration) -> FavaClient {
+        self.options.connect_timeout = Some(timeout);
+        self.rebuild()
+    }
+
+    /// Gives up on a request, including reading its response, after
+    /// `timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> FavaClient {
+        self.options.timeout = Some(timeout);
+        self.rebuild()
+    }
+
+    /// Sends `user_agent` instead of `fava-query/<version>`.
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> FavaClient {
+        self.options.user_agent = user_agent.into();
+        self.rebuild()
+    }
+
+    /// Trusts `certificate` in addition to the system's roots, e.g. the CA
+    /// of a self-signed fava certificate.
+    pub fn add_root_certificate(mut self, certificate: reqwest::Certificate) -> FavaClient {
+        self.options.roots.push(certificate);
+        self.rebuild()
+    }
+
+    /// Accepts any certificate fava presents. Only for testing or trusted
+    /// networks, as anybody in between can read and change the traffic.
+    pub fn danger_accept_invalid_certs(mut self, accept_invalid_certs: bool) -> FavaClient {
+        self.options.accept_invalid_certs = accept_invalid_certs;
+        self.rebuild()
+    }
+
+    /// Reaches fava through the http proxy at `url`, except for hosts
+    /// `NO_PROXY` lists. Without one, `HTTP_PROXY`, `HTTPS_PROXY` and
+    /// `NO_PROXY` apply.
+    pub fn proxy(mut self, url: impl Into<String>) -> FavaClient {
+        self.options.proxy = Some(url.into());
+        self.rebuild()
+    }
+
+    /// Connects to fava directly, whatever proxy the environment names.
+    pub fn no_proxy(mut self) -> FavaClient {
+        self.options.no_proxy = true;
+        self.rebuild()
+    }
+
+    fn rebuild(mut self) -> FavaClient {
+        self.http = Arc::new(RwLock::new(build(&self.overrides, &self.options)));
+        self
+    }
+
+    /// Authenticates every request with `auth`.
+    pub fn auth(self, auth: Auth) -> FavaClient {
+        FavaClient {
+            auth: Some(auth),
+            ..self
+        }
+    }
+
+    /// Sends the header `name` with every request, e.g. the key a reverse
+    /// proxy asks for.
+    pub fn header(mut self, name: HeaderName, mut value: HeaderValue) -> FavaClient {
+        value.set_sensitive(true);
+        self.headers.append(name, value);
+        self
+    }
+
+    /// Base url of the ledger.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Runs a BQL query, without any caching, and returns its rows keyed by
+    /// column name.
+    pub async fn query(&self, query_string: &str) -> Result<Vec<BTreeMap<String, String>>, Error> {
+        let result = self
+            .fetch("/api/query_result", &[("query_string", query_string)])
+            .await
+            .map_err(UpstreamError::from)?
+            .json::<QueryResult>()
+            .await
+            .map_err(UpstreamError::from)?;
+        if !result.success {
+            return Err(Error {
+                message: result.error.unwrap_or("Something went wrong".into()),
+            });
+        }
+        Ok(result
+            .data
+            .map(|data| data.parse().rows)
+            .unwrap_or_default())
+    }
+
+    /// GETs a page or API below the ledger url. After repeated connection
+    /// failures to a host that used to work, pooled connections are dropped
+    /// so the next request resolves the host name again, as a dynamic DNS
+    /// name may point somewhere else by now.
+    pub(crate) async fn fetch(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> reqwest::Result<reqwest::Response> {
+        let http = self.http.read().unwrap().clone();
+        let mut request = http
+            .get(format!("{}/{}", self.url, path.trim_start_matches('/')))
+            .headers(self.headers.clone())
+            .query(query);
+        request = match &self.auth {
+            Some(Auth::Basic { username, password }) => {
+                request.basic_auth(username, password.as_ref())
+            }
+            Some(Auth::Bearer(token)) => request.bearer_auth(token),
+            None => request,
+        };
+        let result = request.send().await;
+        match &result {
+            Ok(_) => {
+                let first = {
+                    let mut health = self.health.lock().unwrap();
+                    health.connect_failures = 0;
+                    !std::mem::replace(&mut health.connected, true)
+                };
+                if first {
+                    self.health.lock().unwrap().address = self.lookup().await;
+                }
+            }
+            Err(e) if e.is_connect() => {
+                let reconnect = {
+                    let mut health = self.health.lock().unwrap();
+                    health.connect_failures += 1;
+                    health.connected && health.connect_failures >= CONNECT_FAILURES
+                };
+                if reconnect {
+                    self.reconnect().await;
+                }
+            }
+            Err(_) => {}
+        }
+        result
+    }
+
+    async fn reconnect(&self) {
+        *self.http.write().unwrap() = build(&self.overrides, &self.options);
+        let address = self.lookup().await;
+        let mut health = self.health.lock().unwrap();
+        health.connect_failures = 0;
+        if address.is_some() && address != health.address {
+            println!(
+                "fava host now resolves to {}, was {}",
+                address
+                    .map(|address| address.to_string())
+                    .unwrap_or_default(),
+                health
+                    .address
+                    .map(|address| address.to_string())
+                    .unwrap_or("unknown".into())
+            );
+            health.address = address;
+        } else {
+            println!("fava unreachable, dropped pooled connections");
+        }
+    }
+
+    /// The address the ledger's host currently resolves to.
+    async fn lookup(&self) -> Option<SocketAddr> {
+        let url = reqwest::Url::parse(&self.url).ok()?;
+        let host = url.host_str()?;
+        let port = url.port_or_known_default()?;
+        if let Some((_, ip)) = self.overrides.iter().find(|(name, _)| name == host) {
+            return Some(SocketAddr::new(*ip, port));
+        }
+        let address = tokio::net::lookup_host((host, port)).await.ok()?.next();
+        address
+    }
+}
+
+fn build(overrides: &[(String, IpAddr)], options: &Options) -> reqwest::Client {
+    let mut builder = reqwest::Client::builder().user_agent(&options.user_agent);
+    if let Some(timeout) = options.connect_timeout {
+        builder = builder.connect_timeout(timeout);
+    }
+    if let Some(timeout) = options.timeout {
+        builder = builder.timeout(timeout);
+    }
+    for certificate in &options.roots {
+        builder = builder.add_root_certificate(certificate.clone());
+    }
+    if options.accept_invalid_certs {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    if options.no_proxy {
+        builder = builder.no_proxy();
+    } else if let Some(proxy) = &options.proxy {
+        let proxy = proxy.clone();
+        let bypass = no_proxy_hosts();
+        builder = builder.proxy(reqwest::Proxy::custom(move |url| {
+            let host = url.host_str()?;
+            (!bypass.iter().any(|entry| bypasses(entry, host))).then(|| proxy.clone())
+        }));
+    }
+    for (host, ip) in overrides {
+        // The port always comes from the url.
+        builder = builder.resolve(host, SocketAddr::new(*ip, 0));
+    }
+    builder.build().expect("can not create http client")
+}
+
+/// The entries of `NO_PROXY`, or `no_proxy`.
+fn no_proxy_hosts() -> Vec<String> {
+    std::env::var("NO_PROXY")
+        .or_else(|_| std::env::var("no_proxy"))
+        .unwrap_or_default()
+        .split(',')
+        .map(|entry| entry.trim().trim_start_matches('.').to_lowercase())
+        .filter(|entry| !entry.is_empty())
+        .collect()
+}
+
+/// Whether a `NO_PROXY` entry covers `host`: `*`, the host itself, or a
+/// domain it belongs to.
+fn bypasses(entry: &str, host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    entry == "*"
+        || host.eq_ignore_ascii_case(entry)
+        || host
+            .to_lowercase()
+            .strip_suffix(entry)
+            .is_some_and(|rest| rest.ends_with('.'))
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.message.fmt(f)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<UpstreamError> for Error {
+    fn from(e: UpstreamError) -> Error {
+        Error {
+            message: e.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::get, Router};
+    use std::net::{Ipv4Addr, TcpListener};
+    use tokio::{sync::oneshot, task::JoinHandle};
+
+    use super::*;
+    use crate::testing;
+
+    /// A fava answering every query on `listener`, until the sender is
+    /// used, after which the handle finishes once its connections closed.
+    fn serve(listener: TcpListener) -> (oneshot::Sender<()>, JoinHandle<()>) {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|| async { testing::table(&["n"], &[&["1"]]) }),
+        );
+        let (stop, stopped) = oneshot::channel::<()>();
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(fava.into_make_service())
+            .with_graceful_shutdown(async {
+                stopped.await.ok();
+            });
+        (stop, tokio::spawn(async { server.await.unwrap() }))
+    }
+
+    fn failures(client: &FavaClient) -> u32 {
+        client.health.lock().unwrap().connect_failures
+    }
+
+    #[tokio::test]
+    async fn reconnects_to_a_fava_that_came_back_without_a_restart() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // The static override stands in for DNS.
+        let client = FavaClient::new(format!("http://fava.test:{}", port))
+            .resolve("fava.test", IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let (stop, server) = serve(listener);
+        assert_eq!(client.query("SELECT n").await.unwrap().len(), 1);
+        assert_eq!(
+            client.health.lock().unwrap().address,
+            Some(SocketAddr::from((Ipv4Addr::LOCALHOST, port)))
+        );
+
+        stop.send(()).unwrap();
+        server.await.unwrap();
+        for failed in 1..CONNECT_FAILURES {
+            assert!(client.query("SELECT n").await.is_err());
+            assert_eq!(failures(&client), failed);
+        }
+        // The last failure in a row drops the pool and starts over.
+        assert!(client.query("SELECT n").await.is_err());
+        assert_eq!(failures(&client), 0);
+
+        let (_stop, _server) = serve(TcpListener::bind(("127.0.0.1", port)).unwrap());
+        assert_eq!(client.query("SELECT n").await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn keeps_the_pool_of_a_fava_that_never_answered() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let client = FavaClient::new(format!("http://127.0.0.1:{}", port));
+        for _ in 0..CONNECT_FAILURES + 1 {
+            assert!(client.query("SELECT n").await.is_err());
+        }
+        assert_eq!(failures(&client), CONNECT_FAILURES + 1);
+        assert_eq!(client.health.lock().unwrap().address, None);
+    }
+
+    /// A fava answering every query with the request headers `names`.
+    async fn echoing(names: &'static [&'static str]) -> String {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(move |headers: HeaderMap| async move {
+                let cells: Vec<&str> = names
+                    .iter()
+                    .map(|name| {
+                        headers
+                            .get(*name)
+                            .map_or("", |value| value.to_str().unwrap())
+                    })
+                    .collect();
+                testing::table(names, &[&cells])
+            }),
+        );
+        testing::fava(fava).await
+    }
+
+    #[tokio::test]
+    async fn sends_the_credentials_and_headers_with_every_request() {
+        let url = echoing(&["authorization", "x-api-key"]).await;
+        let client = FavaClient::new(&url)
+            .auth(Auth::Basic {
+                username: "fava".into(),
+                password: Some("secret".into()),
+            })
+            .header(
+                HeaderName::from_static("x-api-key"),
+                HeaderValue::from_static("key"),
+            );
+        let rows = client.query("SELECT 1").await.unwrap();
+        assert_eq!(rows[0]["authorization"], "Basic ZmF2YTpzZWNyZXQ=");
+        assert_eq!(rows[0]["x-api-key"], "key");
+
+        let client = FavaClient::new(&url).auth(Auth::Bearer("token".into()));
+        let rows = client.query("SELECT 1").await.unwrap();
+        assert_eq!(rows[0]["authorization"], "Bearer token");
+        assert_eq!(format!("{:?}", client.auth.unwrap()), "Bearer(***)");
+    }
+
+    #[tokio::test]
+    async fn names_itself_and_gives_up_on_slow_answers() {
+        let url = echoing(&["user-agent"]).await;
+        let rows = FavaClient::new(&url).query("SELECT 1").await.unwrap();
+        assert_eq!(rows[0]["user-agent"], USER_AGENT);
+        let client = FavaClient::new(&url).user_agent("ledger-bot/2");
+        assert_eq!(
+            client.query("SELECT 1").await.unwrap()[0]["user-agent"],
+            "ledger-bot/2"
+        );
+
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|| async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                testing::table(&["n"], &[&["1"]])
+            }),
+        );
+        let client = FavaClient::new(testing::fava(fava).await).timeout(Duration::from_millis(50));
+        let started = std::time::Instant::now();
+        assert!(client.query("SELECT n").await.is_err());
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}
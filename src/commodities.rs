@@ -0,0 +1,351 @@
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::{
+    amount::{self, Amount},
+    empty_string_as_none,
+    i18n::{Lang, Message},
+    params::{QueryFields, StrictQuery},
+    AppState, ErrorResult, GeneratedQuery, Meta,
+};
+
+const COMMODITIES_QUERY: &str = "SELECT name, date, meta FROM #commodities ORDER BY name";
+
+/// Metadata beancount adds to every directive.
+const SOURCE_META: [&str; 2] = ["filename", "lineno"];
+
+/// The `commodity` directives of the ledger with their metadata, such as
+/// `name` or `precision`. Needs the query tables of beanquery.
+pub async fn commodities(
+    State(state): State<AppState>,
+    StrictQuery(params): StrictQuery<CommoditiesParams>,
+    lang: Lang,
+) -> Result<CommoditiesResult, ErrorResult> {
+    let parsed = state
+        .session()
+        .rows(COMMODITIES_QUERY)
+        .await
+        .map_err(|e| e.localize(lang))?;
+    let commodities = parsed
+        .rows
+        .iter()
+        .filter_map(|row| {
+            let name = row.get("name").filter(|name| !name.is_empty())?;
+            Some(Commodity {
+                name: name.clone(),
+                date: row.get("date").cloned().filter(|date| !date.is_empty()),
+                meta: parse_meta(row.get("meta").map_or("", String::as_str)),
+            })
+        })
+        .collect();
+
+    let generated = GeneratedQuery {
+        query: COMMODITIES_QUERY.into(),
+        filters: Vec::new(),
+        feeds: "data.commodities".into(),
+    };
+    Ok(CommoditiesResult {
+        success: true,
+        data: Commodities { commodities },
+        warnings: parsed
+            .warnings
+            .into_iter()
+            .map(|warning| warning.localize(lang))
+            .collect(),
+        meta: Meta::explain(params.explain, vec![generated]),
+    })
+}
+
+/// The `price` directives of `commodity`, oldest first, optionally only
+/// those quoted in `base`.
+pub async fn prices(
+    State(state): State<AppState>,
+    StrictQuery(params): StrictQuery<PricesParams>,
+    lang: Lang,
+) -> Result<PricesResult, ErrorResult> {
+    for currency in std::iter::once(&params.commodity).chain(&params.base) {
+        if !amount::is_currency(currency) {
+            return Err(ErrorResult::bad_request(format!(
+                "invalid currency {}",
+                currency
+            )));
+        }
+    }
+    let filter = format!("currency = '{}'", params.commodity);
+    let query = format!(
+        "SELECT date, currency, amount FROM #prices WHERE {} ORDER BY date",
+        filter
+    );
+    let parsed = state
+        .session()
+        .rows(&query)
+        .await
+        .map_err(|e| e.localize(lang))?;
+    let prices = parsed
+        .rows
+        .iter()
+        .filter_map(|row| {
+            let price = Amount::parse(row.get("amount")?)?;
+            if params
+                .base
+                .as_ref()
+                .is_some_and(|base| *base != price.currency)
+            {
+                return None;
+            }
+            Some(Price {
+                date: row.get("date")?.clone(),
+                price: amount::format_number(price.number),
+                currency: price.currency,
+            })
+        })
+        .collect();
+
+    let mut filters = vec![filter];
+    if let Some(base) = &params.base {
+        filters.push(format!("amount currency = '{}'", base));
+    }
+    let generated = GeneratedQuery {
+        query,
+        filters,
+        feeds: "data.prices".into(),
+    };
+    Ok(PricesResult {
+        success: true,
+        data: Prices {
+            commodity: params.commodity,
+            base: params.base,
+            prices,
+        },
+        warnings: parsed
+            .warnings
+            .into_iter()
+            .map(|warning| warning.localize(lang))
+            .collect(),
+        meta: Meta::explain(params.explain, vec![generated]),
+    })
+}
+
+/// Reads the metadata dict the way fava prints it, `{'name': 'US Dollar',
+/// 'precision': 2}`, leaving out the source location.
+fn parse_meta(text: &str) -> BTreeMap<String, String> {
+    let mut meta = BTreeMap::new();
+    let mut chars = text.trim().trim_start_matches('{').chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace() || *c == ',').is_some() {}
+        let key = match chars.peek() {
+            Some('\'' | '"') => quoted(&mut chars),
+            _ => break,
+        };
+        while chars.next_if(|c| c.is_whitespace() || *c == ':').is_some() {}
+        let value = match chars.peek() {
+            Some('\'' | '"') => quoted(&mut chars),
+            _ => {
+                let mut value = String::new();
+                while let Some(c) = chars.next_if(|c| *c != ',' && *c != '}') {
+                    value.push(c);
+                }
+                value.trim().to_string()
+            }
+        };
+        if !SOURCE_META.contains(&key.as_str()) {
+            meta.insert(key, value);
+        }
+    }
+    meta
+}
+
+/// A python string literal, without its quotes and escapes.
+fn quoted(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let quote = chars.next();
+    let mut text = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => text.extend(chars.next()),
+            c if Some(c) == quote => break,
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CommoditiesParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    explain: Option<bool>,
+}
+
+impl QueryFields for CommoditiesParams {
+    const FIELDS: &[&str] = &["explain"];
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PricesParams {
+    commodity: String,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    base: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    explain: Option<bool>,
+}
+
+impl QueryFields for PricesParams {
+    const FIELDS: &[&str] = &["commodity", "base", "explain"];
+}
+
+#[derive(Debug, Serialize)]
+struct Commodity {
+    name: String,
+    /// The date of the `commodity` directive.
+    date: Option<String>,
+    meta: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Commodities {
+    commodities: Vec<Commodity>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CommoditiesResult {
+    success: bool,
+    data: Commodities,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<Meta>,
+}
+
+impl IntoResponse for CommoditiesResult {
+    fn into_response(self) -> Response {
+        let body = Json(self);
+        (StatusCode::OK, body).into_response()
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Price {
+    date: String,
+    price: String,
+    /// The currency of the price, `base` if given.
+    currency: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Prices {
+    commodity: String,
+    base: Option<String>,
+    prices: Vec<Price>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PricesResult {
+    success: bool,
+    data: Prices,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<Meta>,
+}
+
+impl IntoResponse for PricesResult {
+    fn into_response(self) -> Response {
+        let body = Json(self);
+        (StatusCode::OK, body).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::Query, routing::get, Router};
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{backend::Backend, config::Config, testing, testing::table};
+
+    async fn state() -> AppState {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|Query(params): Query<HashMap<String, String>>| async move {
+                match params["query_string"].as_str() {
+                    COMMODITIES_QUERY => table(
+                        &["name", "date", "meta"],
+                        &[
+                            &[
+                                "USD",
+                                "2020-01-01",
+                                "{'filename': 'main.bean', 'lineno': 3, \
+                                 'name': 'US \\'Dollar\\'', 'precision': 2}",
+                            ],
+                            &["", "2020-01-01", "{}"],
+                            &["AAPL", "", "{}"],
+                        ],
+                    ),
+                    _ => table(
+                        &["date", "currency", "amount"],
+                        &[
+                            &["2024-01-02", "AAPL", "150.50 USD"],
+                            &["2024-01-03", "AAPL", "1100 CNY"],
+                            &["2024-01-04", "AAPL", "151 USD"],
+                        ],
+                    ),
+                }
+            }),
+        );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        AppState::new(config)
+    }
+
+    #[tokio::test]
+    async fn reads_the_commodity_metadata() {
+        let (status, body) = testing::get(&state().await, "/api/commodities").await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["data"]["commodities"],
+            json!([
+                {
+                    "name": "USD",
+                    "date": "2020-01-01",
+                    "meta": {"name": "US 'Dollar'", "precision": "2"},
+                },
+                {"name": "AAPL", "date": null, "meta": {}},
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn keeps_the_prices_in_the_base() {
+        let state = state().await;
+        let (status, body) =
+            testing::get(&state, "/api/prices?commodity=AAPL&base=USD&explain=true").await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["data"],
+            json!({
+                "commodity": "AAPL",
+                "base": "USD",
+                "prices": [
+                    {"date": "2024-01-02", "price": "150.50", "currency": "USD"},
+                    {"date": "2024-01-04", "price": "151", "currency": "USD"},
+                ],
+            })
+        );
+        assert_eq!(
+            body["meta"]["generated_queries"][0]["query"],
+            "SELECT date, currency, amount FROM #prices WHERE currency = 'AAPL' ORDER BY date"
+        );
+
+        let (status, _) = testing::get(&state, "/api/prices?commodity=aapl").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,1100 @@
+use axum::{
+    body::{boxed, Full},
+    extract::State,
+    http::{
+        header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, VARY},
+        HeaderMap, HeaderValue, Request, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use std::{cmp::Reverse, collections::BinaryHeap, ops::Range};
+
+use crate::AppState;
+
+/// Responses shorter than this are sent as they are, as the headers and code
+/// tables of either coding would take most of what it saves.
+const MIN_SIZE: usize = 1024;
+
+/// Content types of the responses that are built in full before they are
+/// sent. Streamed ones, such as `application/x-ndjson`, server-sent events,
+/// gRPC and the zip export, are left alone, so that their data still
+/// arrives as it is produced, as are the ones marked [`Streamed`].
+const COMPRESSIBLE: &[&str] = &[
+    "application/json",
+    "text/csv",
+    "text/html",
+    "text/plain",
+    "application/vnd.apache.arrow.stream",
+    "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+];
+
+/// Back references reach this far back, the most DEFLATE allows.
+const WINDOW: usize = 1 << 15;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+/// Earlier positions of the same hash compared before settling for the
+/// longest match so far, trading a little size for speed.
+const MAX_CHAIN: usize = 64;
+
+const LENGTH_BASE: [usize; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u32; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [usize; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+/// Brotli meta-blocks hold at most this much, what the six nibbles of their
+/// length can express.
+const META_BLOCK: usize = 1 << 24;
+
+/// The first insert length of each brotli insert length code, and the extra
+/// bits of the code; the same for copy lengths.
+const INSERT_BASE: [usize; 24] = [
+    0, 1, 2, 3, 4, 5, 6, 8, 10, 14, 18, 26, 34, 50, 66, 98, 130, 194, 322, 578, 1090, 2114, 6210,
+    22594,
+];
+const INSERT_EXTRA: [u32; 24] = [
+    0, 0, 0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 7, 8, 9, 10, 12, 14, 24,
+];
+const COPY_BASE: [usize; 24] = [
+    2, 3, 4, 5, 6, 7, 8, 9, 10, 12, 14, 18, 22, 30, 38, 54, 70, 102, 134, 198, 326, 582, 1094, 2118,
+];
+const COPY_EXTRA: [u32; 24] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 7, 8, 9, 10, 24,
+];
+
+/// The first insert and copy length codes of each cell of 64 brotli command
+/// symbols. The first two cells reuse the last distance, which the encoder
+/// never does, so it only writes the others.
+const CELLS: [(usize, usize); 11] = [
+    (0, 0),
+    (0, 8),
+    (0, 0),
+    (0, 8),
+    (8, 0),
+    (8, 8),
+    (0, 16),
+    (16, 0),
+    (8, 16),
+    (16, 8),
+    (16, 16),
+];
+
+/// The order brotli sends the code lengths of the code length code in.
+const CODE_LENGTH_ORDER: [usize; 18] =
+    [1, 2, 3, 4, 0, 5, 17, 6, 16, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+
+/// The bits and their count that send each code length of the code length
+/// code, 0 to 5.
+const CODE_LENGTH_BITS: [(u32, u32); 6] = [(0, 2), (7, 4), (3, 3), (2, 2), (1, 2), (15, 4)];
+
+const DISTANCE_EXTRA: [u32; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Marks a response that is streamed through whatever its content type,
+/// such as a document from fava, which may be larger than anything worth
+/// holding in memory.
+#[derive(Debug, Clone, Copy)]
+pub struct Streamed;
+
+/// The codings responses are compressed with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Coding {
+    Brotli,
+    Gzip,
+}
+
+impl Coding {
+    fn name(self) -> &'static str {
+        match self {
+            Coding::Brotli => "br",
+            Coding::Gzip => "gzip",
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Coding::Brotli => brotli(data, META_BLOCK),
+            Coding::Gzip => gzip(data),
+        }
+    }
+}
+
+/// Compresses responses for clients that accept brotli or gzip, unless
+/// the config turns compression off. Responses that could be compressed
+/// vary by `Accept-Encoding` either way.
+pub async fn compress<B>(
+    State(state): State<AppState>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let coding = accepted(request.headers());
+    let mut response = next.run(request).await;
+    if !state.config.compression || !compressible(&response) {
+        return response;
+    }
+    response
+        .headers_mut()
+        .append(VARY, HeaderValue::from_static("accept-encoding"));
+    let Some(coding) = coding else {
+        return response;
+    };
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    if bytes.len() < MIN_SIZE {
+        return Response::from_parts(parts, boxed(Full::from(bytes)));
+    }
+    let (bytes, compressed) = match tokio::task::spawn_blocking(move || {
+        let compressed = coding.compress(&bytes);
+        (bytes, compressed)
+    })
+    .await
+    {
+        Ok(result) => result,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    // Data that does not shrink, such as already compressed cells, is sent
+    // as it is.
+    if compressed.len() >= bytes.len() {
+        return Response::from_parts(parts, boxed(Full::from(bytes)));
+    }
+    parts
+        .headers
+        .insert(CONTENT_ENCODING, HeaderValue::from_static(coding.name()));
+    parts.headers.remove(CONTENT_LENGTH);
+    Response::from_parts(parts, boxed(Full::from(compressed)))
+}
+
+/// The coding `Accept-Encoding` prefers, brotli on a tie as it compresses
+/// better, or none if it turns both down with `q=0` or lists neither
+/// nor `*`.
+fn accepted(headers: &HeaderMap) -> Option<Coding> {
+    let header = headers
+        .get(ACCEPT_ENCODING)
+        .and_then(|accept| accept.to_str().ok())
+        .unwrap_or_default();
+    let quality = |wanted: &str| {
+        header.split(',').find_map(|item| {
+            let mut parts = item.split(';');
+            let coding = parts.next()?.trim();
+            if !coding.eq_ignore_ascii_case(wanted) {
+                return None;
+            }
+            Some(
+                parts
+                    .find_map(|part| part.trim().strip_prefix("q="))
+                    .and_then(|q| q.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0),
+            )
+        })
+    };
+    let any = quality("*");
+    let brotli = quality("br").or(any).unwrap_or(0.0);
+    let gzip = quality("gzip")
+        .or_else(|| quality("x-gzip"))
+        .or(any)
+        .unwrap_or(0.0);
+    match (brotli, gzip) {
+        (brotli, gzip) if brotli > 0.0 && brotli >= gzip => Some(Coding::Brotli),
+        (_, gzip) if gzip > 0.0 => Some(Coding::Gzip),
+        _ => None,
+    }
+}
+
+fn compressible(response: &Response) -> bool {
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .and_then(|content_type| content_type.split(';').next())
+        .map(str::trim);
+    !response.headers().contains_key(CONTENT_ENCODING)
+        && response.extensions().get::<Streamed>().is_none()
+        && content_type.is_some_and(|content_type| COMPRESSIBLE.contains(&content_type))
+}
+
+/// `data` as a gzip member of one DEFLATE block with the fixed Huffman
+/// codes, which need no code tables and still shrink JSON and CSV several
+/// times over through their back references.
+fn gzip(data: &[u8]) -> Vec<u8> {
+    // No file name or modification time, and an unknown operating system.
+    let mut bits = Bits {
+        out: vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255],
+        buffer: 0,
+        count: 0,
+    };
+    // The final block, with the fixed codes.
+    bits.write(1, 1);
+    bits.write(1, 2);
+
+    let mut matcher = Matcher::new();
+    let mut i = 0;
+    while i < data.len() {
+        match matcher.longest(data, i) {
+            Some((length, distance)) => {
+                bits.length(length);
+                bits.distance(distance);
+                for position in i..i + length {
+                    matcher.insert(data, position);
+                }
+                i += length;
+            }
+            None => {
+                bits.literal(usize::from(data[i]));
+                matcher.insert(data, i);
+                i += 1;
+            }
+        }
+    }
+    bits.literal(256);
+
+    let mut out = bits.finish();
+    out.extend_from_slice(&crc32fast::hash(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+/// `data` as a brotli stream of meta-blocks of at most `block` bytes. Each
+/// has a single prefix code for its literals, commands and distances,
+/// built from how often it uses their symbols, and the back references of
+/// [`Matcher`], which span the meta-blocks.
+fn brotli(data: &[u8], block: usize) -> Vec<u8> {
+    let mut bits = Bits {
+        out: Vec::new(),
+        buffer: 0,
+        count: 0,
+    };
+    // A window of 64 KiB less 16 bytes, which holds all of WINDOW.
+    bits.write(0, 1);
+    if data.is_empty() {
+        // The last meta-block, which is empty.
+        bits.write(0b11, 2);
+        return bits.finish();
+    }
+
+    let mut matcher = Matcher::new();
+    for start in (0..data.len()).step_by(block) {
+        let end = (start + block).min(data.len());
+        let commands = matcher.commands(&data[..end], start);
+        meta_block(&mut bits, data, start..end, &commands);
+    }
+    bits.finish()
+}
+
+/// Inserts the literals of a range, then copies `copy` bytes from
+/// `distance` back, unless `copy` is 0 at the end of a meta-block.
+struct Command {
+    literals: Range<usize>,
+    copy: usize,
+    distance: usize,
+}
+
+impl Command {
+    fn codes(&self) -> (usize, usize) {
+        let code = |base: &[usize], length: usize| base.iter().rposition(|base| *base <= length);
+        (
+            code(&INSERT_BASE, self.literals.len()).unwrap_or(0),
+            code(&COPY_BASE, self.copy).unwrap_or(0),
+        )
+    }
+
+    /// The command symbol, which tells the insert and copy length codes.
+    fn symbol(&self) -> usize {
+        let (insert, copy) = self.codes();
+        let cell = CELLS
+            .iter()
+            .skip(2)
+            .position(|cell| *cell == (insert & !7, copy & !7))
+            .unwrap_or(0);
+        (cell + 2) * 64 + ((insert & 7) << 3) + (copy & 7)
+    }
+
+    /// The distance symbol, with its extra bits and their count, as the
+    /// meta-blocks have no postfix bits and no direct distance codes.
+    fn distance(&self) -> (usize, u32, u32) {
+        let offset = self.distance + 3;
+        let extra = offset.ilog2() - 1;
+        let high = (offset >> extra) & 1;
+        let symbol = 16 + 2 * (extra as usize - 1) + high;
+        (symbol, (offset - ((2 + high) << extra)) as u32, extra)
+    }
+}
+
+/// Appends the meta-block of `data[range]`, the last one if it ends `data`.
+fn meta_block(bits: &mut Bits, data: &[u8], range: Range<usize>, commands: &[Command]) {
+    let last = range.end == data.len();
+    let length = range.len() - 1;
+    let nibbles = (usize::BITS - length.leading_zeros()).div_ceil(4).max(4);
+    bits.write(u32::from(last), 1);
+    if last {
+        // Not empty.
+        bits.write(0, 1);
+    }
+    bits.write(nibbles - 4, 2);
+    bits.write(length as u32, nibbles * 4);
+    if !last {
+        // Compressed.
+        bits.write(0, 1);
+    }
+    // One block type of literals, commands and distances each, no postfix
+    // bits and direct distance codes, the first context mode for literals
+    // and a single prefix code of literals and distances.
+    bits.write(0, 3);
+    bits.write(0, 6);
+    bits.write(0, 2);
+    bits.write(0, 2);
+
+    let mut literal_counts = vec![0; 256];
+    let mut command_counts = vec![0; 704];
+    let mut distance_counts = vec![0; 64];
+    for command in commands {
+        for byte in &data[command.literals.clone()] {
+            literal_counts[usize::from(*byte)] += 1;
+        }
+        command_counts[command.symbol()] += 1;
+        if command.copy > 0 {
+            distance_counts[command.distance().0] += 1;
+        }
+    }
+    let literal_codes = prefix_code(bits, &literal_counts, 8);
+    let command_codes = prefix_code(bits, &command_counts, 10);
+    let distance_codes = prefix_code(bits, &distance_counts, 6);
+
+    for command in commands {
+        let (insert, copy) = command.codes();
+        let (code, length) = command_codes[command.symbol()];
+        bits.code(code, length);
+        bits.write(
+            (command.literals.len() - INSERT_BASE[insert]) as u32,
+            INSERT_EXTRA[insert],
+        );
+        // A command that ends the meta-block with its literals copies
+        // nothing, whatever its copy length.
+        bits.write(
+            command.copy.saturating_sub(COPY_BASE[copy]) as u32,
+            COPY_EXTRA[copy],
+        );
+        for byte in &data[command.literals.clone()] {
+            let (code, length) = literal_codes[usize::from(*byte)];
+            bits.code(code, length);
+        }
+        if command.copy > 0 {
+            let (symbol, extra, count) = command.distance();
+            let (code, length) = distance_codes[symbol];
+            bits.code(code, length);
+            bits.write(extra, count);
+        }
+    }
+}
+
+/// Appends the prefix code of the symbols used `counts` times, of an
+/// alphabet whose symbols take `alphabet_bits`, and returns the code and
+/// length of each symbol.
+fn prefix_code(bits: &mut Bits, counts: &[u32], alphabet_bits: u32) -> Vec<(u32, u32)> {
+    let used: Vec<usize> = (0..counts.len()).filter(|i| counts[*i] > 0).collect();
+    if used.len() < 2 {
+        // A simple code of one symbol, which then takes no bits.
+        bits.write(1, 2);
+        bits.write(0, 2);
+        bits.write(used.first().copied().unwrap_or(0) as u32, alphabet_bits);
+        return vec![(0, 0); counts.len()];
+    }
+
+    // The code lengths up to the last used symbol, after which the code is
+    // complete, themselves sent with a code of their own.
+    let lengths = code_lengths(counts, 15);
+    let sent = &lengths[..=used[used.len() - 1]];
+    let mut length_counts = [0; 18];
+    for length in sent {
+        length_counts[*length as usize] += 1;
+    }
+    let mut length_lengths = code_lengths(&length_counts, 5);
+    // A single code length takes no bits, and the lengths of the code
+    // length code are then sent in full as they never complete it.
+    let single = length_counts.iter().filter(|count| **count > 0).count() == 1;
+    if single {
+        for (length, count) in length_lengths.iter_mut().zip(length_counts) {
+            *length = u32::from(count > 0);
+        }
+    }
+    let order = match single {
+        true => &CODE_LENGTH_ORDER[..],
+        false => {
+            let end = CODE_LENGTH_ORDER
+                .iter()
+                .rposition(|symbol| length_lengths[*symbol] > 0)
+                .unwrap_or(0);
+            &CODE_LENGTH_ORDER[..=end]
+        }
+    };
+    // None of the code lengths of the code length code skipped.
+    bits.write(0, 2);
+    for symbol in order {
+        let (value, count) = CODE_LENGTH_BITS[length_lengths[*symbol] as usize];
+        bits.write(value, count);
+    }
+    let length_codes = match single {
+        true => vec![(0, 0); 18],
+        false => canonical(&length_lengths),
+    };
+    for length in sent {
+        let (code, count) = length_codes[*length as usize];
+        bits.code(code, count);
+    }
+    canonical(&lengths)
+}
+
+/// The lengths of a Huffman code for symbols used `counts` times, none
+/// longer than `max`. Rare symbols count as more common ones until the
+/// code fits, which keeps it complete.
+fn code_lengths(counts: &[u32], max: u32) -> Vec<u32> {
+    let mut floor = 1;
+    loop {
+        let mut parents = Vec::new();
+        let mut leaves = vec![None; counts.len()];
+        let mut heap = BinaryHeap::new();
+        for (symbol, count) in counts.iter().enumerate().filter(|(_, count)| **count > 0) {
+            leaves[symbol] = Some(parents.len());
+            heap.push(Reverse((u64::from((*count).max(floor)), parents.len())));
+            parents.push(None);
+        }
+        while let (Some(Reverse((a, i))), Some(Reverse((b, j)))) = (heap.pop(), heap.pop()) {
+            parents[i] = Some(parents.len());
+            parents[j] = Some(parents.len());
+            heap.push(Reverse((a + b, parents.len())));
+            parents.push(None);
+        }
+        let depth = |mut node: usize| {
+            let mut depth = 0;
+            while let Some(parent) = parents[node] {
+                node = parent;
+                depth += 1;
+            }
+            depth
+        };
+        let lengths: Vec<u32> = leaves.iter().map(|leaf| leaf.map_or(0, depth)).collect();
+        if lengths.iter().all(|length| *length <= max) {
+            return lengths;
+        }
+        floor *= 2;
+    }
+}
+
+/// The canonical code of each symbol with its length, shorter codes first
+/// and codes of the same length in the order of their symbols.
+fn canonical(lengths: &[u32]) -> Vec<(u32, u32)> {
+    let mut next = [0; 16];
+    let mut code = 0;
+    for length in 1..16 {
+        let shorter = lengths.iter().filter(|l| **l == length - 1 && length > 1);
+        code = (code + shorter.count() as u32) << 1;
+        next[length as usize] = code;
+    }
+    lengths
+        .iter()
+        .map(|length| match length {
+            0 => (0, 0),
+            length => {
+                let code = next[*length as usize];
+                next[*length as usize] += 1;
+                (code, *length)
+            }
+        })
+        .collect()
+}
+
+/// The positions seen so far by the hash of the three bytes they start,
+/// newest first.
+struct Matcher {
+    head: Vec<usize>,
+    /// The position seen before each one with the same hash, by position
+    /// within the window.
+    prev: Vec<usize>,
+}
+
+impl Matcher {
+    fn new() -> Matcher {
+        Matcher {
+            head: vec![usize::MAX; 1 << HASH_BITS],
+            prev: vec![usize::MAX; WINDOW],
+        }
+    }
+
+    fn hash(data: &[u8], i: usize) -> usize {
+        let bytes = u32::from(data[i]) << 16 | u32::from(data[i + 1]) << 8 | u32::from(data[i + 2]);
+        (bytes.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, data: &[u8], i: usize) {
+        if i + MIN_MATCH > data.len() {
+            return;
+        }
+        let hash = Matcher::hash(data, i);
+        self.prev[i % WINDOW] = self.head[hash];
+        self.head[hash] = i;
+    }
+
+    /// The length and distance of the longest earlier match of the bytes
+    /// at `i`, if one is long enough to pay off.
+    fn longest(&self, data: &[u8], i: usize) -> Option<(usize, usize)> {
+        if i + MIN_MATCH > data.len() {
+            return None;
+        }
+        let max = (data.len() - i).min(MAX_MATCH);
+        let mut best = (0, 0);
+        let mut candidate = self.head[Matcher::hash(data, i)];
+        for _ in 0..MAX_CHAIN {
+            if candidate == usize::MAX || i - candidate > WINDOW {
+                break;
+            }
+            let length = data[candidate..]
+                .iter()
+                .zip(&data[i..i + max])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if length > best.0 {
+                best = (length, i - candidate);
+                if length == max {
+                    break;
+                }
+            }
+            // A slot reused by a newer position ends the chain.
+            match self.prev[candidate % WINDOW] {
+                next if next < candidate => candidate = next,
+                _ => break,
+            }
+        }
+        Some(best).filter(|(length, _)| *length >= MIN_MATCH)
+    }
+
+    /// The brotli commands of `data` from `start` on, the positions before
+    /// it having been inserted already.
+    fn commands(&mut self, data: &[u8], start: usize) -> Vec<Command> {
+        let mut commands = Vec::new();
+        let mut literals = start;
+        let mut i = start;
+        while i < data.len() {
+            match self.longest(data, i) {
+                Some((copy, distance)) => {
+                    commands.push(Command {
+                        literals: literals..i,
+                        copy,
+                        distance,
+                    });
+                    for position in i..i + copy {
+                        self.insert(data, position);
+                    }
+                    i += copy;
+                    literals = i;
+                }
+                None => {
+                    self.insert(data, i);
+                    i += 1;
+                }
+            }
+        }
+        if literals < data.len() {
+            commands.push(Command {
+                literals: literals..data.len(),
+                copy: 0,
+                distance: 0,
+            });
+        }
+        commands
+    }
+}
+
+/// Bits packed from the least significant one of each byte up, as DEFLATE
+/// wants them.
+struct Bits {
+    out: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl Bits {
+    fn write(&mut self, value: u32, count: u32) {
+        self.buffer |= u64::from(value) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// A Huffman code, which goes from its most significant bit down. The
+    /// code of an alphabet of one symbol has no bits.
+    fn code(&mut self, code: u32, length: u32) {
+        if length == 0 {
+            return;
+        }
+        self.write(code.reverse_bits() >> (32 - length), length);
+    }
+
+    /// A literal byte, the end of the block as 256 or the symbol of a
+    /// match length, in the fixed literal/length code.
+    fn literal(&mut self, symbol: usize) {
+        let symbol = symbol as u32;
+        match symbol {
+            0..=143 => self.code(0x30 + symbol, 8),
+            144..=255 => self.code(0x190 + symbol - 144, 9),
+            256..=279 => self.code(symbol - 256, 7),
+            _ => self.code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn length(&mut self, length: usize) {
+        let index = LENGTH_BASE
+            .iter()
+            .rposition(|base| *base <= length)
+            .unwrap_or(0);
+        self.literal(257 + index);
+        self.write((length - LENGTH_BASE[index]) as u32, LENGTH_EXTRA[index]);
+    }
+
+    fn distance(&mut self, distance: usize) {
+        let index = DISTANCE_BASE
+            .iter()
+            .rposition(|base| *base <= distance)
+            .unwrap_or(0);
+        self.code(index as u32, 5);
+        self.write(
+            (distance - DISTANCE_BASE[index]) as u32,
+            DISTANCE_EXTRA[index],
+        );
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.buffer as u8);
+        }
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::CONTENT_TYPE, Request, StatusCode},
+        routing::get,
+        Router,
+    };
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{
+        backend::Backend,
+        config::Config,
+        testing::{self, table},
+    };
+
+    /// Reads bits from the least significant one of each byte up.
+    struct Reader<'a> {
+        data: &'a [u8],
+        position: usize,
+    }
+
+    impl Reader<'_> {
+        fn bits(&mut self, count: u32) -> usize {
+            let mut value = 0;
+            for i in 0..count {
+                let bit = (self.data[self.position / 8] >> (self.position % 8)) & 1;
+                value |= usize::from(bit) << i;
+                self.position += 1;
+            }
+            value
+        }
+
+        /// A symbol of a code by its code and length, or the one symbol of
+        /// a code without bits.
+        fn symbol(&mut self, code: &HashMap<(u32, u32), usize>) -> usize {
+            if let Some(symbol) = code.get(&(0, 0)) {
+                return *symbol;
+            }
+            let (mut bits, mut length) = (0, 0);
+            loop {
+                bits = bits << 1 | self.bits(1) as u32;
+                length += 1;
+                assert!(length <= 15, "no such code");
+                if let Some(symbol) = code.get(&(bits, length)) {
+                    return *symbol;
+                }
+            }
+        }
+
+        /// A prefix code of brotli, as the encoder writes them.
+        fn prefix_code(
+            &mut self,
+            alphabet: usize,
+            alphabet_bits: u32,
+        ) -> HashMap<(u32, u32), usize> {
+            let by_code = |lengths: &[u32]| {
+                canonical(lengths)
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(_, (_, length))| *length > 0)
+                    .map(|(symbol, code)| (code, symbol))
+                    .collect::<HashMap<_, _>>()
+            };
+            match self.bits(2) {
+                1 => {
+                    assert_eq!(self.bits(2), 0, "a simple code of one symbol");
+                    let symbol = self.bits(alphabet_bits);
+                    assert!(symbol < alphabet);
+                    return HashMap::from([((0, 0), symbol)]);
+                }
+                skip => assert_eq!(skip, 0, "no code lengths skipped"),
+            }
+
+            let mut length_lengths = [0; 18];
+            let mut space = 32;
+            for symbol in CODE_LENGTH_ORDER {
+                let length = match self.bits(2) {
+                    3 => match self.bits(1) {
+                        0 => 2,
+                        _ => [1, 5][self.bits(1)],
+                    },
+                    bits => [0, 4, 3][bits],
+                };
+                length_lengths[symbol] = length;
+                if length > 0 {
+                    space -= 32 >> length;
+                    if space <= 0 {
+                        break;
+                    }
+                }
+            }
+            let used: Vec<usize> = (0..18).filter(|i| length_lengths[*i] > 0).collect();
+            let length_code = match used[..] {
+                [symbol] => HashMap::from([((0, 0), symbol)]),
+                _ => {
+                    assert_eq!(space, 0, "an incomplete code length code");
+                    by_code(&length_lengths)
+                }
+            };
+
+            let mut lengths = vec![0; alphabet];
+            let mut space = 32768;
+            let mut symbol = 0;
+            while space > 0 {
+                let length = self.symbol(&length_code) as u32;
+                assert!(length < 16, "no repeated code lengths");
+                lengths[symbol] = length;
+                if length > 0 {
+                    space -= 32768 >> length;
+                }
+                symbol += 1;
+            }
+            assert_eq!(space, 0, "an incomplete code");
+            by_code(&lengths)
+        }
+    }
+
+    /// Decodes the brotli streams the encoder writes, asserting that they
+    /// keep to what RFC 7932 allows.
+    fn unbrotli(data: &[u8]) -> Vec<u8> {
+        let mut reader = Reader { data, position: 0 };
+        assert_eq!(reader.bits(1), 0, "a window of 64 KiB");
+        let mut out = Vec::new();
+        loop {
+            let last = reader.bits(1) == 1;
+            if last && reader.bits(1) == 1 {
+                break;
+            }
+            let nibbles = reader.bits(2) as u32 + 4;
+            assert!(nibbles <= 6, "no metadata");
+            let length = reader.bits(nibbles * 4) + 1;
+            if nibbles > 4 {
+                assert_ne!((length - 1) >> (nibbles * 4 - 4), 0, "the fewest nibbles");
+            }
+            if !last {
+                assert_eq!(reader.bits(1), 0, "compressed");
+            }
+            assert_eq!(reader.bits(3), 0, "one block type each");
+            assert_eq!(reader.bits(6), 0, "no postfix bits or direct codes");
+            reader.bits(2);
+            assert_eq!(reader.bits(2), 0, "one code of literals and distances");
+            let literals = reader.prefix_code(256, 8);
+            let commands = reader.prefix_code(704, 10);
+            let distances = reader.prefix_code(64, 6);
+
+            let end = out.len() + length;
+            while out.len() < end {
+                let symbol = reader.symbol(&commands);
+                let (insert, copy) = CELLS[symbol >> 6];
+                let (insert, copy) = (insert + ((symbol >> 3) & 7), copy + (symbol & 7));
+                let insert = INSERT_BASE[insert] + reader.bits(INSERT_EXTRA[insert]);
+                let copy = COPY_BASE[copy] + reader.bits(COPY_EXTRA[copy]);
+                for _ in 0..insert {
+                    out.push(reader.symbol(&literals) as u8);
+                }
+                if out.len() == end {
+                    break;
+                }
+                assert!(symbol >= 128, "no reused distance");
+                let code = reader.symbol(&distances);
+                assert!(code >= 16, "no distance of the ring");
+                let extra = 1 + ((code - 16) >> 1) as u32;
+                let offset = ((2 + ((code - 16) & 1)) << extra) - 4;
+                let distance = offset + reader.bits(extra) + 1;
+                assert!(distance <= out.len() && distance <= (1 << 16) - 16);
+                for _ in 0..copy {
+                    out.push(out[out.len() - distance]);
+                }
+            }
+            assert_eq!(out.len(), end, "commands within their meta-block");
+            if last {
+                break;
+            }
+        }
+        assert_eq!(
+            reader.position.div_ceil(8),
+            data.len(),
+            "nothing after the end"
+        );
+        out
+    }
+
+    /// Decodes the gzip members the encoder writes, of one final DEFLATE
+    /// block with the fixed codes, checking their CRC32 and ISIZE.
+    fn ungzip(data: &[u8]) -> Vec<u8> {
+        assert_eq!(
+            data[..10],
+            [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255],
+            "the header"
+        );
+        let mut reader = Reader { data, position: 80 };
+        assert_eq!(reader.bits(1), 1, "the final block");
+        assert_eq!(reader.bits(2), 1, "the fixed codes");
+        let code = |lengths: &[u32]| {
+            canonical(lengths)
+                .into_iter()
+                .enumerate()
+                .map(|(symbol, code)| (code, symbol))
+                .collect::<HashMap<_, _>>()
+        };
+        let lengths: Vec<u32> = (0..288)
+            .map(|symbol| match symbol {
+                0..=143 => 8,
+                144..=255 => 9,
+                256..=279 => 7,
+                _ => 8,
+            })
+            .collect();
+        let literals = code(&lengths);
+        let distances = code(&[5; 30]);
+
+        let mut out = Vec::new();
+        loop {
+            let symbol = reader.symbol(&literals);
+            match symbol {
+                0..=255 => out.push(symbol as u8),
+                256 => break,
+                _ => {
+                    let index = symbol - 257;
+                    assert!(index < 29, "no length symbol {}", symbol);
+                    let length = LENGTH_BASE[index] + reader.bits(LENGTH_EXTRA[index]);
+                    let index = reader.symbol(&distances);
+                    let distance = DISTANCE_BASE[index] + reader.bits(DISTANCE_EXTRA[index]);
+                    assert!(distance <= out.len() && distance <= WINDOW);
+                    for _ in 0..length {
+                        out.push(out[out.len() - distance]);
+                    }
+                }
+            }
+        }
+        let trailer = &data[reader.position.div_ceil(8)..];
+        assert_eq!(trailer.len(), 8, "the CRC32 and ISIZE, then nothing");
+        assert_eq!(
+            trailer[..4],
+            crc32fast::hash(&out).to_le_bytes(),
+            "the CRC32"
+        );
+        assert_eq!(trailer[4..], (out.len() as u32).to_le_bytes(), "the ISIZE");
+        out
+    }
+
+    /// Data of every kind: runs of one byte, text from a few words with
+    /// matches near and far, and noise that has none.
+    fn data(rng: &mut StdRng) -> Vec<u8> {
+        let words = [
+            "Assets:Bank",
+            "2024-01-02",
+            "-1234.56",
+            "CNY",
+            ",",
+            "\n",
+            "Café",
+        ];
+        let length = rng.gen_range(0..20_000);
+        match rng.gen_range(0..4) {
+            0 => vec![rng.gen(); length],
+            1 => (0..length).map(|_| rng.gen()).collect(),
+            2 => (0..length).map(|_| rng.gen_range(b'a'..b'c')).collect(),
+            _ => {
+                let mut data = Vec::new();
+                while data.len() < length {
+                    data.extend(words[rng.gen_range(0..words.len())].as_bytes());
+                }
+                data
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_brotli() {
+        let mut rng = StdRng::seed_from_u64(318);
+        for _ in 0..200 {
+            let data = data(&mut rng);
+            let block = rng.gen_range(1..30_000);
+            assert_eq!(unbrotli(&brotli(&data, block)), data);
+        }
+        assert_eq!(unbrotli(&brotli(b"", META_BLOCK)), b"");
+        let all: Vec<u8> = (0..=255).cycle().take(256 * 4).collect();
+        assert_eq!(unbrotli(&brotli(&all, META_BLOCK)), all);
+    }
+
+    #[test]
+    fn round_trips_gzip() {
+        let mut rng = StdRng::seed_from_u64(318);
+        for _ in 0..200 {
+            let data = data(&mut rng);
+            assert_eq!(ungzip(&gzip(&data)), data);
+        }
+        assert_eq!(ungzip(&gzip(b"")), b"");
+        let all: Vec<u8> = (0..=255).cycle().take(256 * 4).collect();
+        assert_eq!(ungzip(&gzip(&all)), all);
+        // Matches of every length up to the longest, near and a window away.
+        let mut far: Vec<u8> = (0..WINDOW).map(|_| rng.gen()).collect();
+        far.extend_from_within(..MAX_MATCH + 1);
+        assert_eq!(ungzip(&gzip(&far)), far);
+    }
+
+    #[test]
+    fn shrinks_json_with_brotli() {
+        let row = r#"{"date":"2024-01-02","account":"Expenses:Food","position":"-12.00 CNY"},"#;
+        let data = row.repeat(1000);
+        let compressed = brotli(data.as_bytes(), META_BLOCK);
+        assert!(compressed.len() * 20 < data.len(), "{}", compressed.len());
+        assert_eq!(unbrotli(&compressed), data.as_bytes());
+    }
+
+    #[test]
+    fn limits_code_lengths() {
+        // Counts like the Fibonacci numbers make the deepest Huffman trees.
+        let mut counts = vec![1u32, 1];
+        while counts.len() < 30 {
+            counts.push(counts[counts.len() - 1] + counts[counts.len() - 2]);
+        }
+        let lengths = code_lengths(&counts, 15);
+        assert_eq!(lengths.iter().max(), Some(&15));
+        let space: u32 = lengths.iter().map(|length| 32768 >> length).sum();
+        assert_eq!(space, 32768);
+    }
+
+    #[test]
+    fn prefers_brotli_unless_gzip_is_preferred() {
+        let accepted = |accept: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(ACCEPT_ENCODING, HeaderValue::from_str(accept).unwrap());
+            super::accepted(&headers)
+        };
+        assert_eq!(accepted("gzip, deflate, br"), Some(Coding::Brotli));
+        assert_eq!(accepted("br;q=0.5, gzip;q=0.8"), Some(Coding::Gzip));
+        assert_eq!(accepted("gzip, br;q=0"), Some(Coding::Gzip));
+        assert_eq!(accepted("x-gzip"), Some(Coding::Gzip));
+        assert_eq!(accepted("*"), Some(Coding::Brotli));
+        assert_eq!(accepted("gzip;q=0, *"), Some(Coding::Brotli));
+        assert_eq!(accepted("br;q=0, gzip;q=0, *"), None);
+        assert_eq!(accepted("identity"), None);
+        assert_eq!(super::accepted(&HeaderMap::new()), None);
+    }
+
+    /// The status, `Content-Encoding` and body of `uri` requested with
+    /// `accept` as its `Accept-Encoding`.
+    async fn fetch(
+        state: &crate::AppState,
+        uri: &str,
+        accept: &str,
+    ) -> (StatusCode, Option<String>, Vec<u8>) {
+        let request = Request::get(uri)
+            .header(ACCEPT_ENCODING, accept)
+            .body(Body::empty())
+            .unwrap();
+        let (status, headers, body) = testing::send(state, request).await;
+        let coding = headers
+            .get(CONTENT_ENCODING)
+            .map(|coding| coding.to_str().unwrap().to_string());
+        (status, coding, body)
+    }
+
+    fn state(url: &str) -> crate::AppState {
+        crate::AppState::new(Config::new(url).backend(Backend::Html).refresh_path("none"))
+    }
+
+    #[tokio::test]
+    async fn compresses_results_with_the_accepted_coding() {
+        let rows: Vec<[String; 2]> = (0..200)
+            .map(|i| [format!("Expenses:Food:{}", i % 7), format!("{}.00 CNY", i)])
+            .collect();
+        let rows: Vec<Vec<&str>> = rows
+            .iter()
+            .map(|row| row.iter().map(String::as_str).collect())
+            .collect();
+        let rows: Vec<&[&str]> = rows.iter().map(Vec::as_slice).collect();
+        let body = table(&["account", "position"], &rows);
+        let fava = Router::new().route("/api/query_result", get(move || async move { body }));
+        let state = state(&testing::fava(fava).await);
+        let uri = "/api/query_result?query_string=SELECT%20account&format=csv";
+
+        let (status, coding, plain) = fetch(&state, uri, "identity").await;
+        assert_eq!((status, coding), (StatusCode::OK, None));
+        assert!(plain.len() > MIN_SIZE);
+        let (_, coding, body) = fetch(&state, uri, "gzip, br").await;
+        assert_eq!(coding.as_deref(), Some("br"));
+        assert_eq!(unbrotli(&body), plain);
+        let (_, coding, body) = fetch(&state, uri, "gzip, br;q=0.1").await;
+        assert_eq!(coding.as_deref(), Some("gzip"));
+        assert_eq!(body[..3], [0x1f, 0x8b, 8]);
+    }
+
+    #[tokio::test]
+    async fn streams_documents_as_fava_sends_them() {
+        let document = "2024-01-02 receipt\n".repeat(1000);
+        let fava = Router::new()
+            .route(
+                "/api/query_result",
+                get(|| async {
+                    table(
+                        &["date", "account", "filename"],
+                        &[&["2024-01-02", "Expenses:Food", "/docs/receipt.txt"]],
+                    )
+                }),
+            )
+            .route(
+                "/document/",
+                get(move || async move { ([(CONTENT_TYPE, "text/plain")], document) }),
+            );
+        let state = state(&testing::fava(fava).await);
+        let uri = "/api/documents/download?filename=/docs/receipt.txt";
+        let (status, coding, body) = fetch(&state, uri, "br, gzip").await;
+        assert_eq!((status, coding), (StatusCode::OK, None));
+        assert_eq!(body, "2024-01-02 receipt\n".repeat(1000).as_bytes());
+    }
+}
@@ -0,0 +1,1046 @@
+use reqwest::header::{HeaderName, HeaderValue};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env, fs,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    backend::Backend, balance_sheet::is_account, client::Auth, groups::EndpointGroup, locale,
+    templates,
+};
+
+/// Default fava page requested before each upstream call so that fava
+/// reloads changed beancount files.
+const DEFAULT_REFRESH_PATH: &str = "/income_statement/";
+
+/// Default counterpart account for exported entries whose other posting is
+/// not known.
+const DEFAULT_BALANCING_ACCOUNT: &str = "Equity:Unknown";
+
+/// Default `Retry-After` (seconds) when fava is unavailable without saying
+/// for how long.
+const DEFAULT_RETRY_AFTER: u64 = 30;
+
+/// Settings of the service, usually read from the environment at startup
+/// plus the optional TOML file named by the `config` variable.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Base url of the fava ledger, e.g. `http://fava:5000/beancount`.
+    pub(crate) url: String,
+    /// Fava's JSON query API or its HTML tables.
+    pub(crate) backend: Backend,
+    /// Page requested to refresh fava's data, or `none` to skip it.
+    pub(crate) refresh_path: String,
+    /// Counterpart account used by `format=beancount` exports.
+    pub(crate) balancing_account: String,
+    /// Seconds to back off when fava answers 502/503 without `Retry-After`.
+    pub(crate) retry_after: u64,
+    /// Composite dashboard payloads served from memory by `/api/view/:name`.
+    pub(crate) views: BTreeMap<String, ViewConfig>,
+    /// Rules evaluated in the background and reported by `/api/alerts`.
+    pub(crate) alerts: BTreeMap<String, AlertConfig>,
+    /// Named row transforms, used by views and the `transform` parameter.
+    pub(crate) transforms: BTreeMap<String, TransformConfig>,
+    /// Named BQL queries with `{placeholders}`, run by `/api/template/:name`.
+    pub(crate) templates: BTreeMap<String, String>,
+    /// JSON file of the queries saved through `/api/saved_queries`, which
+    /// only live in memory without one.
+    pub(crate) saved_queries: Option<PathBuf>,
+    /// Serve `/api/query_result` polls from proactively refreshed results.
+    pub(crate) poll_smoothing: Option<PollSmoothingConfig>,
+    /// Retry fava fetches failing with 502/503 or a connection error.
+    pub(crate) retry: Option<RetryConfig>,
+    /// Fail fast after repeated fava failures.
+    pub(crate) circuit_breaker: Option<CircuitBreakerConfig>,
+    /// Endpoint groups that are mounted; all of them unless configured.
+    pub(crate) endpoints: BTreeSet<EndpointGroup>,
+    /// Static addresses for upstream host names, bypassing DNS.
+    pub(crate) resolve: Vec<(String, IpAddr)>,
+    /// How old a query result may be to still answer with it when fava
+    /// fails; results are not kept for that unless set.
+    pub(crate) stale_fallback: Option<Duration>,
+    /// Reject requests with unknown query parameters.
+    pub(crate) strict_params: bool,
+    /// Fetch account journals in one page per year.
+    pub(crate) journal_by_year: bool,
+    /// Compress responses with brotli or gzip for clients that accept them.
+    pub(crate) compression: bool,
+    /// Profile CSV and XLSX outputs render numbers and dates with unless
+    /// `output_locale` picks one; machine formats without one.
+    pub(crate) output_locale: Option<String>,
+    /// Formatting profiles by name, next to the built-in `en`, `de` and `zh`.
+    pub(crate) locales: BTreeMap<String, LocaleConfig>,
+    /// Commodity that reports convert into, e.g. `CNY`.
+    pub(crate) operating_currency: Option<String>,
+    /// Further fava instances, served below `/ledgers/<name>`.
+    pub(crate) ledgers: BTreeMap<String, LedgerConfig>,
+    /// Path all routes are mounted below, e.g. `/fava-query`, or empty.
+    pub(crate) base_path: String,
+    /// Serve the other ledgers of the fava instance below `/slugs/<slug>`.
+    pub(crate) slugs: bool,
+    /// Credentials, headers and timeouts of every request to fava, of every
+    /// ledger.
+    pub(crate) upstream: Upstream,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Upstream {
+    pub(crate) auth: Option<Auth>,
+    pub(crate) headers: Vec<(HeaderName, HeaderValue)>,
+    pub(crate) connect_timeout: Option<Duration>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) user_agent: Option<String>,
+    pub(crate) roots: Vec<reqwest::Certificate>,
+    pub(crate) accept_invalid_certs: bool,
+    /// Proxy url, or `none` to ignore `HTTP_PROXY`/`HTTPS_PROXY`.
+    pub(crate) proxy: Option<String>,
+}
+
+/// The parts of the configuration that only the config file can express.
+#[derive(Debug, Default, Deserialize)]
+struct FileConfig {
+    backend: Option<Backend>,
+    #[serde(default)]
+    views: BTreeMap<String, ViewConfig>,
+    #[serde(default)]
+    alerts: BTreeMap<String, AlertConfig>,
+    #[serde(default)]
+    transforms: BTreeMap<String, TransformConfig>,
+    #[serde(default)]
+    templates: BTreeMap<String, String>,
+    saved_queries: Option<PathBuf>,
+    poll_smoothing: Option<PollSmoothingConfig>,
+    retry: Option<RetryConfig>,
+    circuit_breaker: Option<CircuitBreakerConfig>,
+    endpoints: Option<EndpointsConfig>,
+    #[serde(default)]
+    serve_stale_on_error: bool,
+    /// Seconds.
+    #[serde(default = "default_max_staleness")]
+    max_staleness: u64,
+    strict_params: Option<bool>,
+    #[serde(default)]
+    journal_by_year: bool,
+    compression: Option<bool>,
+    output_locale: Option<String>,
+    #[serde(default)]
+    locales: BTreeMap<String, LocaleConfig>,
+    #[serde(default)]
+    slugs: bool,
+    #[serde(default)]
+    ledgers: BTreeMap<String, LedgerConfig>,
+    upstream: Option<UpstreamConfig>,
+}
+
+/// The variables the http client takes a proxy from.
+const PROXY_VARIABLES: [&str; 4] = ["HTTP_PROXY", "http_proxy", "HTTPS_PROXY", "https_proxy"];
+
+/// `[upstream]`, for a fava behind basic auth, a bearer token or a proxy
+/// that wants its own headers, and for slow or far away fava instances.
+#[derive(Debug, Default, Deserialize)]
+struct UpstreamConfig {
+    username: Option<String>,
+    password: Option<String>,
+    token: Option<String>,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    /// Seconds.
+    connect_timeout: Option<u64>,
+    /// Seconds.
+    timeout: Option<u64>,
+    user_agent: Option<String>,
+    /// PEM file of further CAs to trust, e.g. for a self-signed fava.
+    ca_bundle: Option<String>,
+    /// Accept any certificate; has to be turned on explicitly.
+    insecure_skip_verify: Option<bool>,
+    /// `http://proxy:3128`, or `none` to ignore the proxy variables. SOCKS
+    /// proxies are not supported.
+    proxy: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LedgerConfig {
+    /// Base url of the ledger, like the `url` variable.
+    pub url: String,
+    /// Refresh page of this ledger, if it differs from the global one.
+    pub refresh_path: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EndpointsConfig {
+    enabled: BTreeSet<EndpointGroup>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PollSmoothingConfig {
+    /// Seconds between refreshes of each polled query.
+    pub interval: u64,
+    /// Seconds without a poll after which a query leaves the schedule.
+    #[serde(default = "default_idle")]
+    pub idle: u64,
+    /// Random spread of each refresh, as a fraction of the interval.
+    #[serde(default = "default_jitter")]
+    pub jitter: f64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RetryConfig {
+    /// Tries of each fetch, including the first one.
+    pub attempts: u32,
+    /// Seconds to wait before the first retry, doubling for each further one.
+    #[serde(default = "default_backoff")]
+    pub backoff: f64,
+    /// Random spread of each wait, as a fraction of it.
+    #[serde(default = "default_jitter")]
+    pub jitter: f64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CircuitBreakerConfig {
+    /// Failed fetches in a row that open the circuit.
+    pub failures: u32,
+    /// Seconds the circuit stays open before a probe may try fava again.
+    #[serde(default = "default_cooldown")]
+    pub cooldown: u64,
+}
+
+fn default_max_staleness() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_idle() -> u64 {
+    300
+}
+
+fn default_jitter() -> f64 {
+    0.1
+}
+
+fn default_backoff() -> f64 {
+    0.2
+}
+
+fn default_cooldown() -> u64 {
+    30
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ViewConfig {
+    /// Recompute at least this often (seconds), even if the ledger is unchanged.
+    pub ttl: Option<u64>,
+    pub components: BTreeMap<String, ViewComponentConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ViewComponentConfig {
+    #[serde(flatten)]
+    pub source: ComponentConfig,
+    /// Name of a transform applied to the component's rows.
+    pub transform: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ComponentConfig {
+    Query {
+        query: String,
+    },
+    Account {
+        account: String,
+        #[serde(default)]
+        negate: bool,
+    },
+}
+
+impl Config {
+    /// Default settings for the ledger at `url`, without views, alerts,
+    /// transforms or poll smoothing, and with every endpoint group mounted.
+    pub fn new(url: impl Into<String>) -> Config {
+        Config {
+            url: url.into(),
+            backend: Backend::Auto,
+            refresh_path: DEFAULT_REFRESH_PATH.into(),
+            balancing_account: DEFAULT_BALANCING_ACCOUNT.into(),
+            retry_after: DEFAULT_RETRY_AFTER,
+            views: BTreeMap::new(),
+            alerts: BTreeMap::new(),
+            transforms: BTreeMap::new(),
+            templates: BTreeMap::new(),
+            saved_queries: None,
+            poll_smoothing: None,
+            retry: None,
+            circuit_breaker: None,
+            endpoints: EndpointGroup::ALL.into_iter().collect(),
+            resolve: Vec::new(),
+            stale_fallback: None,
+            strict_params: true,
+            journal_by_year: false,
+            compression: true,
+            output_locale: None,
+            locales: BTreeMap::new(),
+            operating_currency: None,
+            ledgers: BTreeMap::new(),
+            base_path: String::new(),
+            slugs: false,
+            upstream: Upstream::default(),
+        }
+    }
+
+    /// Reads the `url`, `refresh_path`, `balancing_account`, `base_path`,
+    /// `retry_after`, `fava_backend`, `fava_resolve`, `fava_username`/
+    /// `fava_password`/`fava_token`/`fava_headers`, `fava_connect_timeout`/
+    /// `fava_timeout`/`fava_user_agent`, `fava_ca_bundle`/
+    /// `fava_insecure_skip_verify` and `fava_proxy`, `fava_slugs`,
+    /// `saved_queries`, `compression`, `output_locale` and
+    /// `operating_currency` variables and the file named by `config`,
+    /// panicking if `url` is missing or a setting is unusable.
+    pub fn from_env() -> Config {
+        Config::try_from_env().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like [`Config::from_env`], but reports unusable settings instead of
+    /// panicking, e.g. when reloading a running server.
+    pub fn try_from_env() -> Result<Config, String> {
+        // Without `url` only the ledgers of the config file are served.
+        let mut config = Config::new(env::var("url").unwrap_or_default());
+        if let Ok(val) = env::var("refresh_path") {
+            config = config.refresh_path(val);
+        }
+        if let Ok(val) = env::var("balancing_account") {
+            config = config.balancing_account(val);
+        }
+        if let Ok(val) = env::var("base_path") {
+            config = config.base_path(val);
+        }
+        if let Ok(val) = env::var("fava_backend") {
+            let backend = Backend::parse(&val).ok_or_else(|| {
+                format!("invalid fava_backend {}, expected auto, json or html", val)
+            })?;
+            config = config.backend(backend);
+        }
+        if let Ok(val) = env::var("fava_slugs") {
+            config = config.slugs(val == "true");
+        }
+        if let Ok(val) = env::var("compression") {
+            config = config.compression(val != "false");
+        }
+        if let Ok(val) = env::var("output_locale") {
+            config = config.output_locale(val);
+        }
+        if let Ok(val) = env::var("operating_currency") {
+            config = config.operating_currency(val);
+        }
+        if let Ok(val) = env::var("saved_queries") {
+            config = config.saved_queries(val);
+        }
+        if let Some(val) = env::var("retry_after")
+            .ok()
+            .and_then(|val| val.parse().ok())
+        {
+            config = config.retry_after(val);
+        }
+        // `fava_resolve=host:ip[,host:ip...]`
+        if let Ok(val) = env::var("fava_resolve") {
+            for item in val.split(',').filter(|item| !item.trim().is_empty()) {
+                let (host, ip) = item
+                    .trim()
+                    .split_once(':')
+                    .and_then(|(host, ip)| Some((host, ip.parse().ok()?)))
+                    .ok_or_else(|| format!("invalid fava_resolve entry {}", item))?;
+                config = config.resolve(host, ip);
+            }
+        }
+        config = config.upstream_config(UpstreamConfig {
+            username: env::var("fava_username").ok(),
+            password: env::var("fava_password").ok(),
+            token: env::var("fava_token").ok(),
+            headers: BTreeMap::new(),
+            connect_timeout: seconds("fava_connect_timeout")?,
+            timeout: seconds("fava_timeout")?,
+            user_agent: env::var("fava_user_agent").ok(),
+            ca_bundle: env::var("fava_ca_bundle").ok(),
+            insecure_skip_verify: env::var("fava_insecure_skip_verify")
+                .ok()
+                .map(|val| val == "true"),
+            proxy: env::var("fava_proxy").ok(),
+        })?;
+        // `fava_headers=name:value[,name:value...]`
+        if let Ok(val) = env::var("fava_headers") {
+            for item in val.split(',').filter(|item| !item.trim().is_empty()) {
+                let (name, value) = item
+                    .split_once(':')
+                    .ok_or_else(|| format!("invalid fava_headers entry {}", item))?;
+                config = config.upstream_header_str(name.trim(), value.trim())?;
+            }
+        }
+        let config = match env::var("config") {
+            Ok(path) => config.with_file(&path)?,
+            Err(_) => config,
+        };
+        if config.url.is_empty() && config.ledgers.is_empty() {
+            return Err("url not set".into());
+        }
+        if let Some(name) = &config.output_locale {
+            if locale::profile(&config.locales, name).is_none() {
+                return Err(format!("unknown output_locale {}", name));
+            }
+        }
+        // The http client would silently go around a proxy variable it can
+        // not use.
+        if config.upstream.proxy.is_none() {
+            check_proxy_variables(|name| env::var(name).ok())?;
+        }
+        Ok(config)
+    }
+
+    /// Adds the views, alerts, transforms, query templates, poll smoothing,
+    /// retries, circuit breaker, endpoint groups, ledgers and upstream
+    /// credentials of a TOML config file.
+    pub fn with_file(self, path: impl AsRef<Path>) -> Result<Config, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("can not read config {}: {}", path.display(), e))?;
+        let file: FileConfig = toml::from_str(&text)
+            .map_err(|e| format!("invalid config {}: {}", path.display(), e))?;
+        for (name, template) in &file.templates {
+            if let Err(e) = templates::placeholders(template) {
+                return Err(format!(
+                    "invalid template {} in {}: {}",
+                    name,
+                    path.display(),
+                    e
+                ));
+            }
+        }
+        // The account of a source is put into BQL and fava urls as it is.
+        let sources = file
+            .alerts
+            .iter()
+            .map(|(name, alert)| (format!("alert {}", name), &alert.source))
+            .chain(file.views.iter().flat_map(|(view, config)| {
+                config.components.iter().map(move |(name, component)| {
+                    (format!("view {}.{}", view, name), &component.source)
+                })
+            }));
+        for (name, source) in sources {
+            if let ComponentConfig::Account { account, .. } = source {
+                if !is_account(account) {
+                    return Err(format!(
+                        "invalid account {} of {} in {}",
+                        account,
+                        name,
+                        path.display()
+                    ));
+                }
+            }
+        }
+        let config = match file.upstream {
+            Some(upstream) => self
+                .upstream_config(upstream)
+                .map_err(|e| format!("{} in {}", e, path.display()))?,
+            None => self,
+        };
+        Ok(Config {
+            views: file.views,
+            alerts: file.alerts,
+            transforms: file.transforms,
+            templates: file.templates,
+            saved_queries: file.saved_queries.or(config.saved_queries),
+            backend: file.backend.unwrap_or(config.backend),
+            poll_smoothing: file.poll_smoothing,
+            retry: file.retry.or(config.retry),
+            circuit_breaker: file.circuit_breaker.or(config.circuit_breaker),
+            endpoints: match file.endpoints {
+                Some(endpoints) => endpoints.enabled,
+                None => config.endpoints,
+            },
+            stale_fallback: match file.serve_stale_on_error {
+                true => Some(Duration::from_secs(file.max_staleness)),
+                false => config.stale_fallback,
+            },
+            strict_params: file.strict_params.unwrap_or(config.strict_params),
+            journal_by_year: file.journal_by_year || config.journal_by_year,
+            compression: file.compression.unwrap_or(config.compression),
+            output_locale: file.output_locale.or(config.output_locale),
+            locales: config.locales.into_iter().chain(file.locales).collect(),
+            slugs: file.slugs || config.slugs,
+            ledgers: match file.ledgers.keys().find(|name| !is_ledger_name(name)) {
+                Some(name) => {
+                    return Err(format!(
+                        "invalid ledger name {} in {}, use letters, digits, - and _",
+                        name,
+                        path.display()
+                    ))
+                }
+                None => config.ledgers.into_iter().chain(file.ledgers).collect(),
+            },
+            ..config
+        })
+    }
+
+    /// Serves the fava instance at `url` below `/ledgers/<name>` as well,
+    /// with the same settings apart from the url.
+    pub fn ledger(mut self, name: impl Into<String>, url: impl Into<String>) -> Config {
+        self.ledgers.insert(
+            name.into(),
+            LedgerConfig {
+                url: url.into(),
+                refresh_path: None,
+            },
+        );
+        self
+    }
+
+    /// Mounts all routes below `base_path`, for a service that shares its
+    /// host with others, e.g. `/fava-query`.
+    pub fn base_path(self, base_path: impl Into<String>) -> Config {
+        let base_path = base_path.into().trim_matches('/').to_string();
+        Config {
+            base_path: match base_path.is_empty() {
+                true => base_path,
+                false => format!("/{}", base_path),
+            },
+            ..self
+        }
+    }
+
+    /// The settings of the ledger `name` of the `ledgers`.
+    pub(crate) fn for_ledger(&self, name: &str, ledger: &LedgerConfig) -> Config {
+        Config {
+            url: ledger.url.clone(),
+            base_path: format!("{}/ledgers/{}", self.base_path, name),
+            refresh_path: ledger
+                .refresh_path
+                .clone()
+                .unwrap_or(self.refresh_path.clone()),
+            ledgers: BTreeMap::new(),
+            ..self.clone()
+        }
+    }
+
+    /// The settings of the ledger at `slug` of the same fava instance, the
+    /// url with its last path segment replaced.
+    pub(crate) fn for_slug(&self, slug: &str) -> Option<Config> {
+        let mut url = reqwest::Url::parse(&self.url).ok()?;
+        url.path_segments_mut()
+            .ok()?
+            .pop_if_empty()
+            .pop()
+            .push(slug);
+        Some(Config {
+            url: url.to_string(),
+            base_path: format!("{}/slugs/{}", self.base_path, slug),
+            slugs: false,
+            ledgers: BTreeMap::new(),
+            ..self.clone()
+        })
+    }
+
+    /// Serves the other ledgers of the fava instance, which fava tells apart
+    /// by the slug ending their url, below `/slugs/<slug>`.
+    pub fn slugs(self, slugs: bool) -> Config {
+        Config { slugs, ..self }
+    }
+
+    /// Runs queries through fava's JSON API, scraping its HTML tables, or,
+    /// by default, whichever fava has.
+    pub fn backend(self, backend: Backend) -> Config {
+        Config { backend, ..self }
+    }
+
+    /// Page requested to refresh fava's data, or `none` to skip it.
+    pub fn refresh_path(self, refresh_path: impl Into<String>) -> Config {
+        Config {
+            refresh_path: refresh_path.into(),
+            ..self
+        }
+    }
+
+    /// Counterpart account used by `format=beancount` exports.
+    pub fn balancing_account(self, balancing_account: impl Into<String>) -> Config {
+        Config {
+            balancing_account: balancing_account.into(),
+            ..self
+        }
+    }
+
+    /// Commodity `/api/income_expenses` converts into unless a request asks
+    /// for another one.
+    pub fn operating_currency(self, operating_currency: impl Into<String>) -> Config {
+        Config {
+            operating_currency: Some(operating_currency.into()),
+            ..self
+        }
+    }
+
+    /// Keeps the queries saved through `/api/saved_queries` in the JSON
+    /// file at `path`.
+    pub fn saved_queries(self, path: impl Into<PathBuf>) -> Config {
+        Config {
+            saved_queries: Some(path.into()),
+            ..self
+        }
+    }
+
+    /// Logs into fava with http basic auth.
+    pub fn basic_auth(self, username: impl Into<String>, password: Option<String>) -> Config {
+        self.upstream_auth(Auth::Basic {
+            username: username.into(),
+            password,
+        })
+    }
+
+    /// Sends `Authorization: Bearer <token>` to fava.
+    pub fn bearer_auth(self, token: impl Into<String>) -> Config {
+        self.upstream_auth(Auth::Bearer(token.into()))
+    }
+
+    fn upstream_auth(self, auth: Auth) -> Config {
+        Config {
+            upstream: Upstream {
+                auth: Some(auth),
+                ..self.upstream
+            },
+            ..self
+        }
+    }
+
+    /// Sends the header `name` with every request to fava.
+    pub fn upstream_header(mut self, name: HeaderName, value: HeaderValue) -> Config {
+        self.upstream.headers.push((name, value));
+        self
+    }
+
+    fn upstream_header_str(self, name: &str, value: &str) -> Result<Config, String> {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| format!("invalid upstream header name {}", name))?;
+        let value = HeaderValue::from_str(value)
+            .map_err(|_| format!("invalid value of upstream header {}", name))?;
+        Ok(self.upstream_header(name, value))
+    }
+
+    fn upstream_config(self, upstream: UpstreamConfig) -> Result<Config, String> {
+        let mut config = match (upstream.username, upstream.token) {
+            (Some(_), Some(_)) => {
+                return Err("upstream username and token can not be used together".into())
+            }
+            (Some(username), None) => self.basic_auth(username, upstream.password),
+            (None, Some(token)) => self.bearer_auth(token),
+            (None, None) if upstream.password.is_some() => {
+                return Err("upstream password given without a username".into())
+            }
+            (None, None) => self,
+        };
+        for (name, value) in &upstream.headers {
+            config = config.upstream_header_str(name, value)?;
+        }
+        if let Some(proxy) = &upstream.proxy {
+            config = config.upstream_proxy(proxy)?;
+        }
+        if let Some(path) = &upstream.ca_bundle {
+            for certificate in read_ca_bundle(path)? {
+                config = config.upstream_root_certificate(certificate);
+            }
+        }
+        Ok(Config {
+            upstream: Upstream {
+                connect_timeout: upstream
+                    .connect_timeout
+                    .map(Duration::from_secs)
+                    .or(config.upstream.connect_timeout),
+                timeout: upstream
+                    .timeout
+                    .map(Duration::from_secs)
+                    .or(config.upstream.timeout),
+                user_agent: upstream.user_agent.or(config.upstream.user_agent),
+                accept_invalid_certs: upstream
+                    .insecure_skip_verify
+                    .unwrap_or(config.upstream.accept_invalid_certs),
+                ..config.upstream
+            },
+            ..config
+        })
+    }
+
+    /// Gives up connecting to fava after `timeout`.
+    pub fn upstream_connect_timeout(mut self, timeout: Duration) -> Config {
+        self.upstream.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Gives up on a request to fava, including reading its response, after
+    /// `timeout`.
+    pub fn upstream_timeout(mut self, timeout: Duration) -> Config {
+        self.upstream.timeout = Some(timeout);
+        self
+    }
+
+    /// Trusts `certificate` for fava's TLS, e.g. the CA of a self-signed
+    /// certificate on the LAN.
+    pub fn upstream_root_certificate(mut self, certificate: reqwest::Certificate) -> Config {
+        self.upstream.roots.push(certificate);
+        self
+    }
+
+    /// Skips verifying fava's certificate altogether, which lets anybody in
+    /// between read and change the traffic.
+    pub fn upstream_insecure_skip_verify(mut self, skip: bool) -> Config {
+        self.upstream.accept_invalid_certs = skip;
+        self
+    }
+
+    /// Reaches fava through the http proxy at `url`, or directly for `none`,
+    /// instead of through the proxy `HTTP_PROXY`/`HTTPS_PROXY` name.
+    /// `NO_PROXY` applies either way.
+    ///
+    /// Only http proxies are supported, as the http client is built without
+    /// SOCKS. Behind an SSH tunnel, forward a local port to fava with
+    /// `ssh -L` and use that as the url, or put an http proxy in front of
+    /// the SOCKS one.
+    pub fn upstream_proxy(mut self, url: &str) -> Result<Config, String> {
+        if url != "none" {
+            check_proxy(url)?;
+        }
+        self.upstream.proxy = Some(url.to_string());
+        Ok(self)
+    }
+
+    /// `User-Agent` of requests to fava, `fava-query/<version>` by default.
+    pub fn upstream_user_agent(mut self, user_agent: impl Into<String>) -> Config {
+        self.upstream.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Retries fetches that fail with 502/503, as fava does while reloading
+    /// the ledger, or with a connection error.
+    pub fn retry(self, retry: RetryConfig) -> Config {
+        Config {
+            retry: Some(retry),
+            ..self
+        }
+    }
+
+    /// Fails fast for a while after repeated fava failures instead of
+    /// trying it with every request.
+    pub fn circuit_breaker(self, circuit_breaker: CircuitBreakerConfig) -> Config {
+        Config {
+            circuit_breaker: Some(circuit_breaker),
+            ..self
+        }
+    }
+
+    /// Seconds to back off when fava answers 502/503 without `Retry-After`.
+    pub fn retry_after(self, retry_after: u64) -> Config {
+        Config {
+            retry_after,
+            ..self
+        }
+    }
+
+    /// Connects to `ip` for the upstream host name `host`, for split-horizon
+    /// setups where DNS gives the wrong answer.
+    pub fn resolve(mut self, host: impl Into<String>, ip: IpAddr) -> Config {
+        self.resolve.push((host.into(), ip));
+        self
+    }
+
+    /// Whether requests with unknown query parameters are rejected, which
+    /// they are by default.
+    pub fn strict_params(self, strict_params: bool) -> Config {
+        Config {
+            strict_params,
+            ..self
+        }
+    }
+
+    /// Fetches account journals in one page per year, for journals too long
+    /// for fava to render in one go.
+    pub fn journal_by_year(self, journal_by_year: bool) -> Config {
+        Config {
+            journal_by_year,
+            ..self
+        }
+    }
+
+    /// Whether responses are compressed with brotli or gzip for clients that
+    /// accept them, which they are by default.
+    pub fn compression(self, compression: bool) -> Config {
+        Config {
+            compression,
+            ..self
+        }
+    }
+
+    /// Renders CSV and XLSX outputs with the formatting profile `name` unless
+    /// a request picks another with `output_locale`.
+    pub fn output_locale(self, name: impl Into<String>) -> Config {
+        Config {
+            output_locale: Some(name.into()),
+            ..self
+        }
+    }
+
+    /// Adds a formatting profile for `output_locale`, replacing a built-in
+    /// one of the same name.
+    pub fn locale(mut self, name: impl Into<String>, locale: LocaleConfig) -> Config {
+        self.locales.insert(name.into(), locale);
+        self
+    }
+
+    /// Answers with the last good result of a query, if it is at most
+    /// `max_staleness` old, when fava can not be reached.
+    pub fn serve_stale_on_error(self, max_staleness: Duration) -> Config {
+        Config {
+            stale_fallback: Some(max_staleness),
+            ..self
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AlertConfig {
+    /// The saved query or account whose value is watched.
+    #[serde(flatten)]
+    pub source: ComponentConfig,
+    /// Column holding the value of a query source; may be left out when the
+    /// query selects a single column.
+    pub column: Option<String>,
+    pub comparison: Comparison,
+    pub threshold: Decimal,
+    /// How far the value has to move back past the threshold to resolve.
+    #[serde(default)]
+    pub hysteresis: Decimal,
+    /// Url notified with a JSON POST when the alert fires or resolves.
+    pub webhook: Option<String>,
+    /// Evaluate at least this often (seconds), even if the ledger is unchanged.
+    pub interval: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Comparison {
+    Gt,
+    Lt,
+    /// Fires when the value moved by more than the threshold between two
+    /// evaluations.
+    AbsChange,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TransformConfig {
+    /// Old column name to new column name.
+    #[serde(default)]
+    pub rename: BTreeMap<String, String>,
+    /// Computed column name to the expression producing it.
+    #[serde(default)]
+    pub columns: BTreeMap<String, String>,
+    /// Boolean expression a row has to satisfy to be kept.
+    pub filter: Option<String>,
+}
+
+/// `[locales.<name>]`: how display outputs render numbers and dates.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct LocaleConfig {
+    /// Between each three integer digits of amounts, e.g. `.`.
+    pub thousands: String,
+    /// Before the fraction digits, e.g. `,`.
+    pub decimal: String,
+    /// Pattern of dates, with `YYYY`, `MM`/`M` and `DD`/`D` for the year,
+    /// month and day, e.g. `DD.MM.YYYY`.
+    pub date: String,
+}
+
+/// Every certificate of a PEM file.
+fn read_ca_bundle(path: &str) -> Result<Vec<reqwest::Certificate>, String> {
+    const END: &str = "-----END CERTIFICATE-----";
+    let text =
+        fs::read_to_string(path).map_err(|e| format!("can not read ca_bundle {}: {}", path, e))?;
+    let certificates: Vec<reqwest::Certificate> = text
+        .split_inclusive(END)
+        .filter(|block| block.contains(END))
+        .map(|block| reqwest::Certificate::from_pem(block.trim().as_bytes()))
+        .collect::<Result<_, _>>()
+        .map_err(|e| format!("invalid certificate in ca_bundle {}: {}", path, e))?;
+    if certificates.is_empty() {
+        return Err(format!("no certificate in ca_bundle {}", path));
+    }
+    Ok(certificates)
+}
+
+/// The environment variable `name` as whole seconds, if set.
+fn seconds(name: &str) -> Result<Option<u64>, String> {
+    match env::var(name) {
+        Ok(val) => val
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("invalid {} {}, expected seconds", name, val)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Refuses a proxy url that is not an http one.
+fn check_proxy(url: &str) -> Result<(), String> {
+    let scheme = reqwest::Url::parse(url)
+        .map_err(|e| format!("invalid proxy {}: {}", url, e))?
+        .scheme()
+        .to_string();
+    match scheme.as_str() {
+        "http" | "https" => Ok(()),
+        "socks5" | "socks5h" => Err(format!(
+            "proxy {}: socks proxies are not supported, use an http proxy or an ssh -L tunnel",
+            url
+        )),
+        _ => Err(format!("invalid proxy {}, expected an http url", url)),
+    }
+}
+
+/// Refuses a SOCKS proxy in the proxy variables of the environment, which
+/// `var` reads.
+fn check_proxy_variables(var: impl Fn(&str) -> Option<String>) -> Result<(), String> {
+    for name in PROXY_VARIABLES {
+        let socks = var(name).filter(|url| {
+            let url = url.trim().to_ascii_lowercase();
+            url.starts_with("socks5://") || url.starts_with("socks5h://")
+        });
+        if let Some(url) = socks {
+            return Err(format!(
+                "{}={}: socks proxies are not supported, use an http proxy or an ssh -L tunnel",
+                name, url
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn is_ledger_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn takes_http_proxies_only() {
+        let config = Config::new("http://fava:5000");
+        assert!(config.clone().upstream_proxy("http://proxy:3128").is_ok());
+        assert!(config.clone().upstream_proxy("none").is_ok());
+        let error = config
+            .clone()
+            .upstream_proxy("socks5h://127.0.0.1:1080")
+            .unwrap_err();
+        assert!(
+            error.contains("socks proxies are not supported"),
+            "{}",
+            error
+        );
+        assert!(config.upstream_proxy("ftp://proxy").is_err());
+    }
+
+    #[test]
+    fn refuses_a_socks_proxy_of_the_environment() {
+        let variables = |socks: &'static str| {
+            move |name: &str| match name {
+                "HTTP_PROXY" => Some("http://proxy:3128".to_string()),
+                "https_proxy" => Some(socks.to_string()),
+                _ => None,
+            }
+        };
+        let error = check_proxy_variables(variables("SOCKS5://tunnel:1080")).unwrap_err();
+        assert!(
+            error.starts_with("https_proxy=SOCKS5://tunnel:1080: "),
+            "{}",
+            error
+        );
+        assert!(check_proxy_variables(variables("http://other:3128")).is_ok());
+    }
+
+    #[test]
+    fn refuses_a_broken_template_on_load() {
+        let config = |template: &str| {
+            let path = testing::temp_file(
+                "config.toml",
+                Some(&format!("[templates]\ntop = {:?}\n", template)),
+            );
+            let config = Config::new("http://fava:5000").with_file(&path);
+            fs::remove_file(path).unwrap();
+            config
+        };
+        assert!(config("SELECT payee LIMIT {limit}").is_ok());
+        for template in ["SELECT {limit", "SELECT 'x", "SELECT 1 }"] {
+            let error = config(template).unwrap_err();
+            assert!(error.starts_with("invalid template top in "), "{}", error);
+        }
+    }
+
+    #[test]
+    fn refuses_a_source_account_that_is_not_one() {
+        let config = |account: &str| {
+            let alert = format!(
+                "[alerts.cash]\naccount = {:?}\ncomparison = \"lt\"\nthreshold = 0\n",
+                account
+            );
+            let view = format!("[views.home.components.cash]\naccount = {:?}\n", account);
+            [alert, view].map(|text| {
+                let path = testing::temp_file("config.toml", Some(&text));
+                let config = Config::new("http://fava:5000").with_file(&path);
+                fs::remove_file(path).unwrap();
+                config.map(|_| ())
+            })
+        };
+        assert!(config("Assets:Bank-1").iter().all(Result::is_ok));
+        let [alert, view] = config("Assets:Bank' OR account ~ '.");
+        assert!(alert
+            .unwrap_err()
+            .starts_with("invalid account Assets:Bank' OR account ~ '. of alert cash in "));
+        assert!(view.unwrap_err().contains(" of view home.cash in "));
+    }
+
+    /// A self-signed certificate for `fava.test`.
+    const CERTIFICATE: &str = "-----BEGIN CERTIFICATE-----\n\
+                               MIIBfjCCASWgAwIBAgIUFM98YK4ofOsdCu887KHGf14bAskwCgYIKoZIzj0EAwIw\n\
+                               FDESMBAGA1UEAwwJZmF2YS50ZXN0MCAXDTI2MTAxNDE2MDUwOFoYDzIxMjYwOTIw\n\
+                               MTYwNTA4WjAUMRIwEAYDVQQDDAlmYXZhLnRlc3QwWTATBgcqhkjOPQIBBggqhkjO\n\
+                               PQMBBwNCAATV75XkHDYLTBbuWkQ+yh0TjmNJD/EumXxmbkhaS2tFQcDbzgwzRiDj\n\
+                               eeLyZQLzcoLZrrCQ24yapCYWrLivP9wso1MwUTAdBgNVHQ4EFgQUDvqIPA6Czluo\n\
+                               yDo5MFSXkEFQzfUwHwYDVR0jBBgwFoAUDvqIPA6CzluoyDo5MFSXkEFQzfUwDwYD\n\
+                               VR0TAQH/BAUwAwEB/zAKBggqhkjOPQQDAgNHADBEAiBIlsIqzWsezlEOUdAUkcpX\n\
+                               zUQSToXD4TP4fDaUe91YNgIgUzyT0FaDYybvoJj5FHmu2/LQ7mntvKAI4yTrfTbE\n\
+                               UAg=\n\
+                               -----END CERTIFICATE-----\n";
+
+    #[test]
+    fn reads_every_certificate_of_the_ca_bundle() {
+        let config = |bundle: Option<&str>| {
+            let path = testing::temp_file("ca.pem", bundle);
+            let text = format!("[upstream]\nca_bundle = {:?}\n", path.to_str().unwrap());
+            let file = testing::temp_file("config.toml", Some(&text));
+            let config = Config::new("https://fava.test").with_file(&file);
+            fs::remove_file(file).unwrap();
+            let _ = fs::remove_file(path);
+            config
+        };
+        let trusted = config(Some(&format!("{}{}", CERTIFICATE, CERTIFICATE))).unwrap();
+        assert_eq!(trusted.upstream.roots.len(), 2);
+        assert!(!trusted.upstream.accept_invalid_certs);
+        assert!(config(None)
+            .unwrap_err()
+            .starts_with("can not read ca_bundle "));
+        assert!(config(Some("no pem here"))
+            .unwrap_err()
+            .starts_with("no certificate in ca_bundle "));
+        let broken = CERTIFICATE.replace("MIIB", "MIIA");
+        assert!(config(Some(&broken))
+            .unwrap_err()
+            .starts_with("invalid certificate in ca_bundle "));
+    }
+}
@@ -0,0 +1,56 @@
+use std::borrow::Cow;
+
+use crate::{config::LocaleConfig, Row};
+
+/// Renders rows as CSV under a header line of `columns`, quoting fields as
+/// RFC 4180 asks. Cells missing from a row are left empty, and with a
+/// `locale` numbers and dates are rendered for display.
+pub fn write(columns: &[&str], rows: &[Row], locale: Option<&LocaleConfig>) -> String {
+    let mut output = line(columns.iter().map(|column| Cow::Borrowed(*column)));
+    for row in rows {
+        output.push_str(&line(columns.iter().map(|column| {
+            let text = row.get(*column).map(String::as_str).unwrap_or_default();
+            match locale {
+                Some(locale) => Cow::Owned(locale.cell(text)),
+                None => Cow::Borrowed(text),
+            }
+        })));
+    }
+    output
+}
+
+fn line<'a>(fields: impl Iterator<Item = Cow<'a, str>>) -> String {
+    let fields: Vec<String> = fields.map(|text| field(&text)).collect();
+    fields.join(",") + "\r\n"
+}
+fn field(text: &str) -> String {
+    if text.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_fields_and_leaves_missing_cells_empty() {
+        let rows = [
+            Row::from([
+                ("payee".to_string(), "Shop, \"Co\"".to_string()),
+                ("narration".to_string(), "two\nlines".to_string()),
+                ("position".to_string(), "-1234.50 CNY".to_string()),
+            ]),
+            Row::from([("position".to_string(), "3 USD".to_string())]),
+        ];
+        assert_eq!(
+            write(&["position", "payee", "narration"], &rows, None),
+            "position,payee,narration\r\n\
+             -1234.50 CNY,\"Shop, \"\"Co\"\"\",\"two\nlines\"\r\n\
+             3 USD,,\r\n"
+        );
+        assert_eq!(write(&["position"], &[], None), "position\r\n");
+    }
+}
@@ -0,0 +1,179 @@
+use axum::{extract::State, Json};
+use serde::Serialize;
+use std::{fmt, sync::Mutex, time::Duration, time::SystemTime};
+
+use crate::{backend::Backend, journal, AppState};
+
+/// How often the version is looked up again, as fava may be upgraded
+/// under a running service.
+const INTERVAL: Duration = Duration::from_secs(3600);
+
+/// First fava version with the JSON query API, which replaced
+/// `/api/query_result`.
+const JSON_API: FavaVersion = FavaVersion([1, 26, 0]);
+
+/// A fava release, such as 1.27.3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FavaVersion(pub [u32; 3]);
+
+impl fmt::Display for FavaVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [major, minor, patch] = self.0;
+        write!(f, "{}.{}.{}", major, minor, patch)
+    }
+}
+
+/// The version of the upstream fava, as far as the last lookup could tell.
+#[derive(Debug, Default)]
+pub struct Detection {
+    found: Mutex<Option<(FavaVersion, SystemTime)>>,
+}
+
+impl Detection {
+    pub fn version(&self) -> Option<FavaVersion> {
+        self.found.lock().unwrap().map(|(version, _)| version)
+    }
+
+    /// The journal layout of the detected version, or of the latest one.
+    pub fn layout(&self) -> &'static journal::Layout {
+        journal::layout(self.version())
+    }
+}
+
+/// Looks the version up now and every hour after.
+pub fn spawn(state: &AppState) {
+    let task_state = state.clone();
+    state.spawn_background(async move {
+        loop {
+            detect(&task_state).await;
+            tokio::time::sleep(INTERVAL).await;
+        }
+    });
+}
+
+/// Reads the version off fava's help page and picks the matching query API
+/// unless the config names one.
+async fn detect(state: &AppState) {
+    let help = match state.client.fetch("/help/", &[]).await {
+        Ok(response) if response.status().is_success() => response.text().await.ok(),
+        _ => None,
+    };
+    let version = match help.as_deref().and_then(parse_version) {
+        Some(version) => version,
+        None => return,
+    };
+    let previous = state
+        .detection
+        .found
+        .lock()
+        .unwrap()
+        .replace((version, SystemTime::now()));
+    if previous.map(|(previous, _)| previous) != Some(version) {
+        println!("fava {} detected", version);
+    }
+    if state.config.backend == Backend::Auto {
+        state.backend.set(version >= JSON_API);
+    }
+}
+
+/// The version fava prints on its help page, such as `Fava v1.27.3`.
+pub fn parse_version(help: &str) -> Option<FavaVersion> {
+    let help = help.to_lowercase();
+    help.match_indices("fava").find_map(|(i, _)| {
+        let rest = help[i + 4..].trim_start();
+        let rest = rest.strip_prefix("version").unwrap_or(rest).trim_start();
+        let rest = rest.strip_prefix('v').unwrap_or(rest);
+        let version: String = rest
+            .chars()
+            .take_while(|c| c.is_ascii_digit() || *c == '.')
+            .collect();
+        let mut parts = version.split('.').filter(|part| !part.is_empty());
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next()?.parse().ok()?;
+        let patch = parts.next().and_then(|part| part.parse().ok()).unwrap_or(0);
+        Some(FavaVersion([major, minor, patch]))
+    })
+}
+
+/// What the service knows about its upstream fava.
+pub async fn upstream(State(state): State<AppState>) -> Json<UpstreamResult> {
+    let found = *state.detection.found.lock().unwrap();
+    let backend = match state.config.backend {
+        Backend::Json => Some(true),
+        Backend::Html => Some(false),
+        Backend::Auto => state.backend.known(),
+    };
+    Json(UpstreamResult {
+        success: true,
+        data: Upstream {
+            fava_version: found.map(|(version, _)| version.to_string()),
+            detected_at: found.map(|(_, at)| httpdate::fmt_http_date(at)),
+            query_api: backend.map(|json| match json {
+                true => "json",
+                false => "html",
+            }),
+            journal_layout: state.detection.layout().name,
+        },
+    })
+}
+
+#[derive(Debug, Serialize)]
+pub struct UpstreamResult {
+    success: bool,
+    data: Upstream,
+}
+
+#[derive(Debug, Serialize)]
+struct Upstream {
+    /// Unknown until fava's help page could be read.
+    fava_version: Option<String>,
+    detected_at: Option<String>,
+    /// `json` or `html`, unknown until the first query in `auto` mode.
+    query_api: Option<&'static str>,
+    journal_layout: &'static str,
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::get, Router};
+
+    use super::*;
+    use crate::{config::Config, testing};
+
+    #[test]
+    fn reads_the_version_off_the_help_page() {
+        for (help, version) in [
+            ("<footer>Fava v1.27.3</footer>", Some([1, 27, 3])),
+            ("fava version 1.26", Some([1, 26, 0])),
+            (
+                "<h1>Fava</h1><p>Running fava 1.9.1 on beancount 2.3</p>",
+                Some([1, 9, 1]),
+            ),
+            ("<h1>Fava</h1>", None),
+        ] {
+            assert_eq!(parse_version(help), version.map(FavaVersion), "{}", help);
+        }
+        assert!(FavaVersion([1, 9, 0]) < FavaVersion([1, 26, 0]));
+    }
+
+    #[tokio::test]
+    async fn picks_the_query_api_of_the_detected_version() {
+        for (help, query_api) in [("Fava v1.25.1", "html"), ("Fava v1.27.0", "json")] {
+            let fava = Router::new().route("/help/", get(move || async move { help }));
+            let state = AppState::new(Config::new(testing::fava(fava).await).refresh_path("none"));
+            let (_, body) = testing::get(&state, "/api/upstream").await;
+            let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(body["data"]["fava_version"], serde_json::Value::Null);
+            assert_eq!(body["data"]["query_api"], serde_json::Value::Null);
+
+            detect(&state).await;
+            let (status, body) = testing::get(&state, "/api/upstream").await;
+            assert_eq!(status, 200);
+            let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(body["data"]["fava_version"], &help[6..]);
+            assert_eq!(body["data"]["query_api"], query_api);
+            assert_eq!(body["data"]["journal_layout"], "flex-table");
+            assert!(body["data"]["detected_at"].is_string());
+        }
+    }
+}
@@ -0,0 +1,578 @@
+use axum::{
+    body::{boxed, Body},
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
+use reqwest::{
+    header::{CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE},
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::{
+    amount::{self, Amount},
+    balance_sheet::{described, fava_filters},
+    compression::Streamed,
+    empty_string_as_none,
+    i18n::{Lang, Message},
+    params::{QueryFields, StrictQuery},
+    AppState, ErrorResult, GeneratedQuery, Meta,
+};
+
+/// The `event` directives of the ledger, oldest first, such as
+/// `2023-04-01 event "location" "Berlin"`, and the latest value per type.
+/// Needs the query tables of beanquery.
+pub async fn events(
+    State(state): State<AppState>,
+    StrictQuery(params): StrictQuery<EventsParams>,
+    lang: Lang,
+) -> Result<EventsResult, ErrorResult> {
+    let filter = match params.r#type.as_deref() {
+        Some(kind) if kind.contains(['\'', '\\']) => {
+            return Err(ErrorResult::bad_request(format!(
+                "invalid event type {}",
+                kind
+            )))
+        }
+        Some(kind) => Some(format!("type = '{}'", kind)),
+        None => None,
+    };
+    let query = match &filter {
+        Some(filter) => format!(
+            "SELECT date, type, description FROM #events WHERE {} ORDER BY date",
+            filter
+        ),
+        None => "SELECT date, type, description FROM #events ORDER BY date".to_string(),
+    };
+    let parsed = state
+        .session()
+        .rows(&query)
+        .await
+        .map_err(|e| e.localize(lang))?;
+    let events: Vec<Event> = parsed
+        .rows
+        .iter()
+        .filter_map(|row| {
+            Some(Event {
+                date: row.get("date").filter(|date| !date.is_empty())?.clone(),
+                r#type: row.get("type")?.clone(),
+                description: row.get("description").cloned().unwrap_or_default(),
+            })
+        })
+        .collect();
+    let latest = events
+        .iter()
+        .map(|event| (event.r#type.clone(), event.description.clone()))
+        .collect();
+
+    let generated = GeneratedQuery {
+        query,
+        filters: filter.into_iter().collect(),
+        feeds: "data.events, data.latest".into(),
+    };
+    Ok(EventsResult {
+        success: true,
+        data: Events { events, latest },
+        warnings: parsed
+            .warnings
+            .into_iter()
+            .map(|warning| warning.localize(lang))
+            .collect(),
+        meta: Meta::explain(params.explain, vec![generated]),
+    })
+}
+
+const BUDGETS_QUERY: &str =
+    "SELECT date, type, values FROM #custom WHERE type = 'budget' ORDER BY date";
+
+/// Periods fava's `custom "budget"` directives may name.
+const BUDGET_PERIODS: [&str; 5] = ["daily", "weekly", "monthly", "quarterly", "yearly"];
+
+/// fava's `custom "budget"` directives, such as
+/// `2024-01-01 custom "budget" Expenses:Food "monthly" 400.00 CNY`, oldest
+/// first. A budget applies from its date until the next one of the account.
+/// Needs the query tables of beanquery.
+pub async fn budgets(
+    State(state): State<AppState>,
+    StrictQuery(params): StrictQuery<BudgetsParams>,
+    lang: Lang,
+) -> Result<BudgetsResult, ErrorResult> {
+    let parsed = state
+        .session()
+        .rows(BUDGETS_QUERY)
+        .await
+        .map_err(|e| e.localize(lang))?;
+    let budgets = parsed
+        .rows
+        .iter()
+        .filter_map(|row| {
+            let values = split_values(row.get("values")?);
+            let (account, period, amount) = match values.as_slice() {
+                [account, period, amount, ..] => (account, period, Amount::parse(amount)?),
+                _ => return None,
+            };
+            if !BUDGET_PERIODS.contains(&period.as_str()) {
+                return None;
+            }
+            if params.account.as_ref().is_some_and(|prefix| {
+                account != prefix && !account.starts_with(&format!("{}:", prefix))
+            }) {
+                return None;
+            }
+            Some(Budget {
+                date: row.get("date")?.clone(),
+                account: account.clone(),
+                period: period.clone(),
+                amount: amount::format_number(amount.number),
+                currency: amount.currency,
+            })
+        })
+        .collect();
+
+    let generated = GeneratedQuery {
+        query: BUDGETS_QUERY.into(),
+        filters: vec!["type = 'budget'".into()],
+        feeds: "data.budgets".into(),
+    };
+    Ok(BudgetsResult {
+        success: true,
+        data: Budgets { budgets },
+        warnings: parsed
+            .warnings
+            .into_iter()
+            .map(|warning| warning.localize(lang))
+            .collect(),
+        meta: Meta::explain(params.explain, vec![generated]),
+    })
+}
+
+/// The values of a custom directive, printed as a list such as
+/// `['Expenses:Food', 'monthly', 400.00 CNY]` or comma separated.
+fn split_values(text: &str) -> Vec<String> {
+    text.trim()
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .split(", ")
+        .map(|value| value.trim().trim_matches(['\'', '"']).to_string())
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+const DOCUMENTS_QUERY: &str = "SELECT date, account, filename FROM #documents ORDER BY date";
+
+/// The `document` directives of the ledger, oldest first, including those
+/// fava found in its documents folders. `account` and `time` are fava's
+/// filters.
+pub async fn documents(
+    State(state): State<AppState>,
+    StrictQuery(params): StrictQuery<DocumentsParams>,
+    lang: Lang,
+) -> Result<DocumentsResult, ErrorResult> {
+    let mut filters = fava_filters(params.time.as_deref(), None);
+    if let Some(account) = &params.account {
+        filters.push(("account", account.as_str()));
+    }
+    let parsed = state
+        .session()
+        .filtered(&filters)
+        .rows(DOCUMENTS_QUERY)
+        .await
+        .map_err(|e| e.localize(lang))?;
+    let documents = parsed
+        .rows
+        .iter()
+        .filter_map(|row| {
+            Some(Document {
+                date: row.get("date")?.clone(),
+                account: row.get("account")?.clone(),
+                filename: row.get("filename").filter(|name| !name.is_empty())?.clone(),
+            })
+        })
+        .collect();
+
+    let generated = GeneratedQuery {
+        query: DOCUMENTS_QUERY.into(),
+        filters: described(&filters),
+        feeds: "data.documents".into(),
+    };
+    Ok(DocumentsResult {
+        success: true,
+        data: Documents { documents },
+        warnings: parsed
+            .warnings
+            .into_iter()
+            .map(|warning| warning.localize(lang))
+            .collect(),
+        meta: Meta::explain(params.explain, vec![generated]),
+    })
+}
+
+/// Streams a document from fava. Only files of the ledger's `document`
+/// directives are served, so the service does not hand out other files
+/// fava may be able to read.
+pub async fn download(
+    State(state): State<AppState>,
+    StrictQuery(params): StrictQuery<DownloadParams>,
+    lang: Lang,
+) -> Result<Response, ErrorResult> {
+    let parsed = state
+        .session()
+        .rows(DOCUMENTS_QUERY)
+        .await
+        .map_err(|e| e.localize(lang))?;
+    let known = parsed
+        .rows
+        .iter()
+        .any(|row| row.get("filename") == Some(&params.filename));
+    let not_found = || ErrorResult {
+        error_code: Some("document_not_found".into()),
+        status: StatusCode::NOT_FOUND,
+        ..ErrorResult::new(format!("no document {}", params.filename))
+    };
+    if !known {
+        return Err(not_found());
+    }
+    let mut response = state
+        .session()
+        .download("/document/", &[("filename", &params.filename)])
+        .await
+        .map_err(|e| ErrorResult::from(e).localize(lang))?;
+    match response.status() {
+        status if status.is_success() => {}
+        StatusCode::NOT_FOUND => return Err(not_found()),
+        status => {
+            return Err(ErrorResult {
+                status: StatusCode::BAD_GATEWAY,
+                ..ErrorResult::new(format!("fava answered the document with {}", status))
+            })
+        }
+    }
+
+    // Streamed as fava sends it, never held in memory to be compressed.
+    let mut builder = Response::builder().extension(Streamed).header(
+        CONTENT_DISPOSITION,
+        format!("inline; filename=\"{}\"", file_name(&params.filename)),
+    );
+    for name in [CONTENT_TYPE, CONTENT_LENGTH] {
+        if let Some(value) = response.headers().get(&name) {
+            builder = builder.header(name, value.clone());
+        }
+    }
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        while let Ok(Some(chunk)) = response.chunk().await {
+            if sender.send_data(chunk).await.is_err() {
+                return;
+            }
+        }
+    });
+    builder
+        .body(boxed(body))
+        .map_err(|e| ErrorResult::new(e.to_string()))
+}
+
+/// The last path component, without characters that would end the quoted
+/// header value.
+fn file_name(path: &str) -> String {
+    path.rsplit(['/', '\\'])
+        .next()
+        .unwrap_or(path)
+        .chars()
+        .filter(|c| !c.is_control() && *c != '"')
+        .collect()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BudgetsParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    account: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    explain: Option<bool>,
+}
+
+impl QueryFields for BudgetsParams {
+    const FIELDS: &[&str] = &["account", "explain"];
+}
+
+#[derive(Debug, Serialize)]
+struct Budget {
+    /// From when the budget applies.
+    date: String,
+    account: String,
+    period: String,
+    amount: String,
+    currency: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Budgets {
+    budgets: Vec<Budget>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BudgetsResult {
+    success: bool,
+    data: Budgets,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<Meta>,
+}
+
+impl IntoResponse for BudgetsResult {
+    fn into_response(self) -> Response {
+        let body = Json(self);
+        (StatusCode::OK, body).into_response()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DocumentsParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    account: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    time: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    explain: Option<bool>,
+}
+
+impl QueryFields for DocumentsParams {
+    const FIELDS: &[&str] = &["account", "time", "explain"];
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DownloadParams {
+    filename: String,
+}
+
+impl QueryFields for DownloadParams {
+    const FIELDS: &[&str] = &["filename"];
+}
+
+#[derive(Debug, Serialize)]
+struct Document {
+    date: String,
+    account: String,
+    /// The path fava knows the document by, for
+    /// `/api/documents/download?filename=`.
+    filename: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Documents {
+    documents: Vec<Document>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DocumentsResult {
+    success: bool,
+    data: Documents,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<Meta>,
+}
+
+impl IntoResponse for DocumentsResult {
+    fn into_response(self) -> Response {
+        let body = Json(self);
+        (StatusCode::OK, body).into_response()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EventsParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    r#type: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    explain: Option<bool>,
+}
+
+impl QueryFields for EventsParams {
+    const FIELDS: &[&str] = &["type", "explain"];
+}
+
+#[derive(Debug, Serialize)]
+struct Event {
+    date: String,
+    r#type: String,
+    description: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Events {
+    events: Vec<Event>,
+    /// Event type to the description of its last event.
+    latest: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EventsResult {
+    success: bool,
+    data: Events,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<Meta>,
+}
+
+impl IntoResponse for EventsResult {
+    fn into_response(self) -> Response {
+        let body = Json(self);
+        (StatusCode::OK, body).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::Query, routing::get, Router};
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{backend::Backend, config::Config, testing, testing::table};
+
+    async fn state() -> AppState {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|Query(params): Query<HashMap<String, String>>| async move {
+                let query = params["query_string"].as_str();
+                match query {
+                    _ if query.contains("#events") => table(
+                        &["date", "type", "description"],
+                        &[
+                            &["2023-04-01", "location", "Berlin"],
+                            &["2023-06-01", "employer", "ACME"],
+                            &["", "location", "Nowhere"],
+                            &["2024-02-01", "location", "Paris"],
+                        ],
+                    ),
+                    DOCUMENTS_QUERY => table(
+                        &["date", "account", "filename"],
+                        &[
+                            &["2024-01-02", "Assets:Bank", "/docs/statement.pdf"],
+                            &["2024-01-03", "Assets:Bank", ""],
+                        ],
+                    ),
+                    BUDGETS_QUERY => table(
+                        &["date", "type", "values"],
+                        &[
+                            &[
+                                "2024-01-01",
+                                "budget",
+                                "['Expenses:Food', 'monthly', 400.00 CNY]",
+                            ],
+                            &["2024-01-01", "budget", "['Expenses:Rent', 'hourly', 1 CNY]"],
+                            &["2024-01-01", "budget", "Expenses:Travel, yearly, 5000 CNY"],
+                            &["2024-03-01", "budget", "['Expenses:Food:Snacks', 'weekly']"],
+                            &[
+                                "2024-04-01",
+                                "budget",
+                                "['Expenses:Foods', 'weekly', 10 CNY]",
+                            ],
+                        ],
+                    ),
+                    _ => table(&[], &[]),
+                }
+            }),
+        );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        AppState::new(config)
+    }
+
+    #[tokio::test]
+    async fn keeps_the_latest_value_of_each_event_type() {
+        let state = state().await;
+        let (status, body) = testing::get(&state, "/api/events").await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["data"]["events"].as_array().unwrap().len(), 3);
+        assert_eq!(
+            body["data"]["latest"],
+            json!({"employer": "ACME", "location": "Paris"})
+        );
+
+        let (_, body) = testing::get(&state, "/api/events?type=location&explain=true").await;
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["meta"]["generated_queries"][0]["query"],
+            "SELECT date, type, description FROM #events WHERE type = 'location' ORDER BY date"
+        );
+        let (status, _) = testing::get(&state, "/api/events?type=it's").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn lists_documents_and_serves_only_those() {
+        let state = state().await;
+        let (status, body) =
+            testing::get(&state, "/api/documents?account=Assets:Bank&explain=true").await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["data"]["documents"],
+            json!([{
+                "date": "2024-01-02",
+                "account": "Assets:Bank",
+                "filename": "/docs/statement.pdf",
+            }])
+        );
+        assert_eq!(
+            body["meta"]["generated_queries"][0]["filters"],
+            json!(["account=Assets:Bank"])
+        );
+
+        let (status, body) =
+            testing::get(&state, "/api/documents/download?filename=/etc/passwd").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(body.contains("document_not_found"), "{}", body);
+    }
+
+    #[test]
+    fn names_the_download_by_its_last_component() {
+        assert_eq!(file_name("/docs/2024/statement.pdf"), "statement.pdf");
+        assert_eq!(file_name("C:\\docs\\a\"b.pdf"), "ab.pdf");
+    }
+
+    #[tokio::test]
+    async fn reads_the_budgets_of_an_account() {
+        let state = state().await;
+        let (status, body) = testing::get(&state, "/api/budgets").await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["data"]["budgets"],
+            json!([
+                {
+                    "date": "2024-01-01",
+                    "account": "Expenses:Food",
+                    "period": "monthly",
+                    "amount": "400.00",
+                    "currency": "CNY",
+                },
+                {
+                    "date": "2024-01-01",
+                    "account": "Expenses:Travel",
+                    "period": "yearly",
+                    "amount": "5000",
+                    "currency": "CNY",
+                },
+                {
+                    "date": "2024-04-01",
+                    "account": "Expenses:Foods",
+                    "period": "weekly",
+                    "amount": "10",
+                    "currency": "CNY",
+                },
+            ])
+        );
+
+        let (_, body) = testing::get(&state, "/api/budgets?account=Expenses:Food").await;
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["data"]["budgets"].as_array().unwrap().len(), 1);
+    }
+}
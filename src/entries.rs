@@ -0,0 +1,420 @@
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Response},
+    Json,
+};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::{
+    amount::{self, Amount},
+    balance_sheet::{described, fava_filters, is_account},
+    empty_string_as_none,
+    i18n::{Lang, Message},
+    params::{QueryFields, StrictQuery},
+    tags::split_tags,
+    AppState, ErrorResult, GeneratedQuery, Meta, Row,
+};
+
+const JOURNAL_COLUMNS: &str = "id, date, flag, payee, narration, tags, links, account, position";
+
+/// The transactions of the ledger with all their postings, oldest first.
+///
+/// `account`, `time` and `filter` are fava's filters, so `account` keeps
+/// the transactions with a posting to the account or one below it, and
+/// still lists their other postings.
+pub async fn journal(
+    State(state): State<AppState>,
+    StrictQuery(params): StrictQuery<JournalParams>,
+    lang: Lang,
+) -> Result<JournalResult, ErrorResult> {
+    load(&state, params.account.as_deref(), None, &params, lang).await
+}
+
+/// `GET /api/account/:account/transactions`: the journal of one account
+/// with every field of its transactions, where `/api/account/:account`
+/// only keeps the date, the change and the balance.
+pub async fn account_transactions(
+    State(state): State<AppState>,
+    Path(account): Path<String>,
+    StrictQuery(params): StrictQuery<AccountTransactionsParams>,
+    lang: Lang,
+) -> Result<JournalResult, ErrorResult> {
+    if !is_account(&account) {
+        return Err(ErrorResult::bad_request(format!(
+            "invalid account {}",
+            account
+        )));
+    }
+    let params = JournalParams {
+        account: None,
+        time: params.time,
+        filter: params.filter,
+        explain: params.explain,
+    };
+    load(&state, Some(&account), None, &params, lang).await
+}
+
+/// `GET /api/uncleared`: the transactions flagged `!` that still need a
+/// review, with the padding entries fava adds for `pad` directives when
+/// `padding` is set and the transactions tagged `#todo` when `todo` is.
+pub async fn uncleared(
+    State(state): State<AppState>,
+    StrictQuery(params): StrictQuery<UnclearedParams>,
+    lang: Lang,
+) -> Result<JournalResult, ErrorResult> {
+    let mut conditions = vec!["flag = '!'"];
+    if params.padding == Some(true) {
+        conditions.push("flag = 'P'");
+    }
+    if params.todo == Some(true) {
+        conditions.push("'todo' IN tags");
+    }
+    let condition = conditions.join(" OR ");
+    let journal = JournalParams {
+        account: None,
+        time: params.time,
+        filter: params.filter,
+        explain: params.explain,
+    };
+    load(
+        &state,
+        params.account.as_deref(),
+        Some(&condition),
+        &journal,
+        lang,
+    )
+    .await
+}
+
+/// The transactions matching the fava filters of `params` and `account`,
+/// and the BQL `condition` if there is one.
+async fn load(
+    state: &AppState,
+    account: Option<&str>,
+    condition: Option<&str>,
+    params: &JournalParams,
+    lang: Lang,
+) -> Result<JournalResult, ErrorResult> {
+    let mut filters = fava_filters(params.time.as_deref(), params.filter.as_deref());
+    if let Some(account) = account {
+        filters.push(("account", account));
+    }
+    let query = match condition {
+        Some(condition) => format!(
+            "SELECT {} WHERE {} ORDER BY date, id",
+            JOURNAL_COLUMNS, condition
+        ),
+        None => format!("SELECT {} ORDER BY date, id", JOURNAL_COLUMNS),
+    };
+    let parsed = state
+        .session()
+        .filtered(&filters)
+        .rows(&query)
+        .await
+        .map_err(|e| e.localize(lang))?;
+
+    let generated = GeneratedQuery {
+        query,
+        filters: described(&filters),
+        feeds: "data.transactions".into(),
+    };
+    Ok(JournalResult {
+        success: true,
+        data: Journal {
+            transactions: transactions(parsed.rows),
+        },
+        warnings: parsed
+            .warnings
+            .into_iter()
+            .map(|warning| warning.localize(lang))
+            .collect(),
+        meta: Meta::explain(params.explain, vec![generated]),
+    })
+}
+
+/// Groups the per-posting rows into transactions, in the order of their
+/// first posting.
+fn transactions(rows: Vec<Row>) -> Vec<Transaction> {
+    let mut transactions: Vec<Transaction> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for mut row in rows {
+        let mut take = |key: &str| row.remove(key).unwrap_or_default();
+        let id = take("id");
+        let account = take("account");
+        let position = take("position");
+        let i = *index.entry(id.clone()).or_insert_with(|| {
+            transactions.push(Transaction {
+                id,
+                date: take("date"),
+                flag: take("flag"),
+                payee: take("payee"),
+                narration: take("narration"),
+                tags: split_tags(&take("tags")).map(str::to_string).collect(),
+                links: split_tags(&take("links")).map(str::to_string).collect(),
+                postings: Vec::new(),
+            });
+            transactions.len() - 1
+        });
+        transactions[i].postings.push(posting(account, &position));
+    }
+    transactions
+}
+
+/// A posting of a rendered position such as `10 AAPL {150.50 USD}`.
+fn posting(account: String, position: &str) -> Posting {
+    let cost = position
+        .split_once('{')
+        .and_then(|(_, rest)| rest.split_once('}'))
+        .map(|(cost, _)| cost.trim().to_string())
+        .filter(|cost| !cost.is_empty());
+    let (amount, currency) = Amount::parse(position)
+        .map(|amount| (amount::format_number(amount.number), amount.currency))
+        .unwrap_or_default();
+    Posting {
+        account,
+        amount,
+        currency,
+        cost,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JournalParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    account: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    time: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    filter: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    explain: Option<bool>,
+}
+
+impl QueryFields for JournalParams {
+    const FIELDS: &[&str] = &["account", "time", "filter", "explain"];
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AccountTransactionsParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    time: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    filter: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    explain: Option<bool>,
+}
+
+impl QueryFields for AccountTransactionsParams {
+    const FIELDS: &[&str] = &["time", "filter", "explain"];
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnclearedParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    account: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    time: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    filter: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    padding: Option<bool>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    todo: Option<bool>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    explain: Option<bool>,
+}
+
+impl QueryFields for UnclearedParams {
+    const FIELDS: &[&str] = &["account", "time", "filter", "padding", "todo", "explain"];
+}
+
+#[derive(Debug, Serialize)]
+struct Posting {
+    account: String,
+    amount: String,
+    currency: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cost: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Transaction {
+    id: String,
+    date: String,
+    flag: String,
+    payee: String,
+    narration: String,
+    tags: Vec<String>,
+    links: Vec<String>,
+    postings: Vec<Posting>,
+}
+
+#[derive(Debug, Serialize)]
+struct Journal {
+    transactions: Vec<Transaction>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JournalResult {
+    success: bool,
+    data: Journal,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<Meta>,
+}
+
+impl IntoResponse for JournalResult {
+    fn into_response(self) -> Response {
+        let body = Json(self);
+        (StatusCode::OK, body).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::get, Router};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{backend::Backend, config::Config, testing, testing::table};
+
+    async fn state() -> AppState {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|| async {
+                table(
+                    &[
+                        "id",
+                        "date",
+                        "flag",
+                        "payee",
+                        "narration",
+                        "tags",
+                        "links",
+                        "account",
+                        "position",
+                    ],
+                    &[
+                        &[
+                            "1",
+                            "2024-01-02",
+                            "*",
+                            "Broker",
+                            "Buy",
+                            "invest",
+                            "^trade-1",
+                            "Assets:Broker",
+                            "10 AAPL {150.50 USD}",
+                        ],
+                        &["1", "", "", "", "", "", "", "Assets:Bank", "-1505.00 USD"],
+                        &[
+                            "2",
+                            "2024-01-03",
+                            "!",
+                            "",
+                            "Check",
+                            "",
+                            "",
+                            "Assets:Bank",
+                            "5 USD",
+                        ],
+                    ],
+                )
+            }),
+        );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        AppState::new(config)
+    }
+
+    #[tokio::test]
+    async fn groups_postings_into_transactions() {
+        let (status, body) = testing::get(
+            &state().await,
+            "/api/journal?account=Assets:Broker&time=2024&explain=true",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["data"]["transactions"][0],
+            json!({
+                "id": "1",
+                "date": "2024-01-02",
+                "flag": "*",
+                "payee": "Broker",
+                "narration": "Buy",
+                "tags": ["invest"],
+                "links": ["trade-1"],
+                "postings": [
+                    {
+                        "account": "Assets:Broker",
+                        "amount": "10",
+                        "currency": "AAPL",
+                        "cost": "150.50 USD",
+                    },
+                    {"account": "Assets:Bank", "amount": "-1505.00", "currency": "USD"},
+                ],
+            })
+        );
+        assert_eq!(body["data"]["transactions"][1]["tags"], json!([]));
+        let generated = &body["meta"]["generated_queries"][0];
+        assert_eq!(
+            generated["query"],
+            format!("SELECT {} ORDER BY date, id", JOURNAL_COLUMNS)
+        );
+        assert_eq!(
+            generated["filters"],
+            json!(["time=2024", "account=Assets:Broker"])
+        );
+    }
+
+    #[tokio::test]
+    async fn filters_the_journal_of_one_account() {
+        let state = state().await;
+        let (status, body) = testing::get(
+            &state,
+            "/api/account/Assets:Bank/transactions?filter=%23invest&explain=true",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["data"]["transactions"].as_array().unwrap().len(), 2);
+        assert_eq!(
+            body["meta"]["generated_queries"][0]["filters"],
+            json!(["filter=#invest", "account=Assets:Bank"])
+        );
+
+        let (status, _) = testing::get(&state, "/api/account/Assets:bank/transactions").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn asks_for_the_transactions_to_review() {
+        let state = state().await;
+        let (status, body) =
+            testing::get(&state, "/api/uncleared?todo=true&padding=true&explain=true").await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["meta"]["generated_queries"][0]["query"],
+            format!(
+                "SELECT {} WHERE flag = '!' OR flag = 'P' OR 'todo' IN tags ORDER BY date, id",
+                JOURNAL_COLUMNS
+            )
+        );
+        let (_, body) = testing::get(&state, "/api/uncleared?explain=true").await;
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["meta"]["generated_queries"][0]["query"],
+            format!(
+                "SELECT {} WHERE flag = '!' ORDER BY date, id",
+                JOURNAL_COLUMNS
+            )
+        );
+    }
+}
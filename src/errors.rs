@@ -0,0 +1,177 @@
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
+use nipper::Document;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{i18n::Lang, AppState, ErrorResult};
+
+/// The errors fava found loading the ledger, such as failed balance
+/// assertions, with the file and line they come from.
+///
+/// Newer fava versions list them at `/api/errors`; older ones only count
+/// them there, so their errors page is read instead.
+pub async fn errors(
+    State(state): State<AppState>,
+    lang: Lang,
+) -> Result<ErrorsResult, ErrorResult> {
+    let session = state.session();
+    let listed = session
+        .download("/api/errors", &[])
+        .await
+        .map_err(|e| ErrorResult::from(e).localize(lang))?;
+    let listed = match listed.status().is_success() {
+        true => listed.json::<ApiErrors>().await.ok(),
+        false => None,
+    };
+    let errors = match listed.map(|listed| listed.data) {
+        Some(Value::Array(errors)) => errors
+            .into_iter()
+            .filter_map(|error| serde_json::from_value::<ApiError>(error).ok())
+            .map(|error| LedgerError {
+                r#type: error.r#type,
+                message: error.message,
+                filename: error
+                    .source
+                    .as_ref()
+                    .and_then(|source| source.filename.clone()),
+                lineno: error.source.and_then(|source| source.lineno),
+            })
+            .collect(),
+        _ => {
+            let page = session
+                .page("/errors/", &[])
+                .await
+                .map_err(|e| ErrorResult::from(e).localize(lang))?;
+            parse_errors(&page)
+        }
+    };
+    Ok(ErrorsResult {
+        success: true,
+        data: Errors {
+            count: errors.len(),
+            errors,
+        },
+    })
+}
+
+/// The rows of the table on fava's errors page: file, line and message.
+fn parse_errors(html: &str) -> Vec<LedgerError> {
+    let document = Document::from(html);
+    document
+        .select("table tbody tr")
+        .iter()
+        .filter_map(|row| {
+            let cells: Vec<String> = row
+                .select("td")
+                .iter()
+                .map(|cell| cell.text().trim().to_string())
+                .collect();
+            match cells.as_slice() {
+                [filename, lineno, message, ..] => Some(LedgerError {
+                    r#type: None,
+                    message: message.clone(),
+                    filename: Some(filename.clone()).filter(|name| !name.is_empty()),
+                    lineno: lineno.parse().ok(),
+                }),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiErrors {
+    data: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiError {
+    r#type: Option<String>,
+    message: String,
+    source: Option<ApiSource>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiSource {
+    filename: Option<String>,
+    lineno: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct LedgerError {
+    /// The beancount error class, such as `BalanceError`, if fava says.
+    r#type: Option<String>,
+    message: String,
+    filename: Option<String>,
+    lineno: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct Errors {
+    count: usize,
+    errors: Vec<LedgerError>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorsResult {
+    success: bool,
+    data: Errors,
+}
+
+impl IntoResponse for ErrorsResult {
+    fn into_response(self) -> Response {
+        let body = Json(self);
+        (StatusCode::OK, body).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::get, Router};
+    use serde_json::json;
+
+    use super::*;
+    use crate::{config::Config, testing, testing::FixtureSet};
+
+    #[test]
+    fn reads_each_captured_errors_page() {
+        for set in FixtureSet::all() {
+            let errors = parse_errors(&set.file("errors"));
+            assert!(!errors.is_empty(), "fava {}", set.fava_version);
+            for error in errors {
+                assert!(!error.message.is_empty(), "fava {}", set.fava_version);
+                assert!(error.filename.is_some() && error.lineno.is_some());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn lists_the_errors_of_the_json_api_or_of_the_page() {
+        let listed = r#"{"success": true, "data": [{"type": "BalanceError", "message": "Balance failed for 'Assets:Bank'", "source": {"filename": "main.bean", "lineno": 12}}]}"#;
+        let page = "<table><tbody><tr><td>main.bean</td><td>7</td><td>Invalid token</td></tr></tbody></table>";
+        let new = Router::new().route("/api/errors", get(move || async move { listed }));
+        // Older favas only count the errors.
+        let old = Router::new()
+            .route(
+                "/api/errors",
+                get(|| async { "{\"success\": true, \"data\": 1}" }),
+            )
+            .route("/errors/", get(move || async move { page }));
+        let expected = [
+            json!({"type": "BalanceError", "message": "Balance failed for 'Assets:Bank'", "filename": "main.bean", "lineno": 12}),
+            json!({"type": null, "message": "Invalid token", "filename": "main.bean", "lineno": 7}),
+        ];
+        for (fava, error) in [new, old].into_iter().zip(expected) {
+            let config = Config::new(testing::fava(fava).await).refresh_path("none");
+            let (status, body) = testing::get(&AppState::new(config), "/api/errors").await;
+            assert_eq!(status, StatusCode::OK);
+            let body: Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(body["data"], json!({"count": 1, "errors": [error]}));
+        }
+    }
+}
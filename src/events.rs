@@ -0,0 +1,180 @@
+use axum::{
+    body::{boxed, Body, Bytes},
+    extract::State,
+    http::header::{HeaderName, CACHE_CONTROL, CONTENT_TYPE},
+    response::{IntoResponse, Response},
+};
+use std::{sync::Mutex, time::Duration};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::AppState;
+
+/// How often fava's `changed` API is asked while anybody listens.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Quiet time after which a comment keeps proxies from closing the stream.
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// Tells subscribers the ledger generation whenever fava reloaded the
+/// beancount files.
+#[derive(Debug)]
+pub struct Events {
+    sender: broadcast::Sender<u64>,
+    polling: Mutex<bool>,
+}
+
+impl Default for Events {
+    fn default() -> Events {
+        Events {
+            sender: broadcast::channel(16).0,
+            polling: Mutex::new(false),
+        }
+    }
+}
+
+/// `GET /api/events/stream`: server-sent events, a `ready` event with the
+/// current generation and then a `changed` event for every reload.
+pub async fn stream(State(state): State<AppState>) -> Response {
+    let mut receiver = state.events.sender.subscribe();
+    let generation = state.version.generation(&state).await;
+    start_polling(&state, generation);
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        let mut frame = Some(event("ready", generation));
+        loop {
+            if let Some(frame) = frame.take() {
+                if sender.send_data(Bytes::from(frame)).await.is_err() {
+                    return;
+                }
+            }
+            frame = tokio::select! {
+                changed = receiver.recv() => match changed {
+                    Ok(generation) => Some(event("changed", Some(generation))),
+                    // Only the latest generation matters.
+                    Err(RecvError::Lagged(_)) => None,
+                    Err(RecvError::Closed) => return,
+                },
+                _ = tokio::time::sleep(KEEP_ALIVE) => Some(": keep-alive\n\n".into()),
+            };
+        }
+    });
+    (
+        [
+            (CONTENT_TYPE, "text/event-stream"),
+            (CACHE_CONTROL, "no-cache"),
+            // Keeps nginx from buffering the events.
+            (HeaderName::from_static("x-accel-buffering"), "no"),
+        ],
+        boxed(body),
+    )
+        .into_response()
+}
+
+fn event(name: &str, generation: Option<u64>) -> String {
+    match generation {
+        Some(generation) => format!(
+            "event: {}\nid: {}\ndata: {{\"generation\":{}}}\n\n",
+            name, generation, generation
+        ),
+        None => format!("event: {}\ndata: {{\"generation\":null}}\n\n", name),
+    }
+}
+
+/// Starts the poller with the first subscriber. It stays idle while nobody
+/// listens.
+fn start_polling(state: &AppState, generation: Option<u64>) {
+    {
+        let mut polling = state.events.polling.lock().unwrap();
+        if *polling {
+            return;
+        }
+        *polling = true;
+    }
+    let task_state = state.clone();
+    state.spawn_background(async move {
+        let events = &task_state.events;
+        let mut last = generation;
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            if events.sender.receiver_count() == 0 {
+                continue;
+            }
+            let generation = match task_state.version.generation(&task_state).await {
+                Some(generation) => generation,
+                None => continue,
+            };
+            if last.is_some_and(|last| last != generation) {
+                let _ = events.sender.send(generation);
+            }
+            last = Some(generation);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{http::Request, routing::get, Json, Router};
+    use hyper::body::{Buf, HttpBody};
+    use serde_json::json;
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{backend::Backend, config::Config, routes, testing};
+
+    /// The next frame of an event stream.
+    async fn next<B>(body: &mut B) -> String
+    where
+        B: HttpBody + Unpin,
+        B::Error: std::fmt::Debug,
+    {
+        let frame = tokio::time::timeout(Duration::from_secs(10), body.data())
+            .await
+            .expect("no event in time")
+            .unwrap()
+            .unwrap();
+        String::from_utf8(frame.chunk().to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn sends_ready_then_changed_once_fava_reloads() {
+        let reloaded = Arc::new(AtomicBool::new(false));
+        let changed = reloaded.clone();
+        let fava = Router::new()
+            .route(
+                "/api/changed",
+                // Fava tells of a reload once.
+                get(move || async move {
+                    Json(json!({"success": true, "data": changed.swap(false, Ordering::SeqCst)}))
+                }),
+            )
+            .route(
+                "/api/query_result",
+                get(|| async { testing::table(&["count"], &[&["4"]]) }),
+            );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        let state = AppState::new(config);
+        let request = Request::get("/api/events/stream")
+            .body(Body::empty())
+            .unwrap();
+        let response = routes(state.clone()).oneshot(request).await.unwrap();
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/event-stream");
+        let mut body = response.into_body();
+
+        assert_eq!(
+            next(&mut body).await,
+            "event: ready\nid: 0\ndata: {\"generation\":0}\n\n"
+        );
+        reloaded.store(true, Ordering::SeqCst);
+        assert_eq!(
+            next(&mut body).await,
+            "event: changed\nid: 1\ndata: {\"generation\":1}\n\n"
+        );
+        state.stop();
+    }
+}
@@ -0,0 +1,306 @@
+use axum::{
+    body::{boxed, Body, Bytes},
+    extract::State,
+    response::{IntoResponse, Response},
+};
+use reqwest::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use serde::Deserialize;
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::{
+    csv, empty_string_as_none,
+    journal::{self, JournalEntry},
+    params::{QueryFields, StrictQuery},
+    zip::ZipWriter,
+    AppState, ErrorResult, Row,
+};
+
+/// Journals fetched at the same time while building an archive.
+const CONCURRENCY: usize = 4;
+
+/// The open ends of a `time` filter. Fava takes the end of a range as the
+/// day after it, so the last one is a day short of the last it can parse.
+const EARLIEST: &str = "0001-01-01";
+const LATEST: &str = "9999-12-30";
+
+const COLUMNS: [&str; 6] = ["date", "flag", "payee", "narration", "change", "balance"];
+
+/// Streams a zip archive with one CSV journal per account below `root`,
+/// which fava limits to the days from `from` through `to`. Accounts whose
+/// journal can not be fetched are listed in `ERRORS.txt` instead of failing
+/// the whole archive.
+pub async fn export(
+    State(state): State<AppState>,
+    StrictQuery(params): StrictQuery<ExportParams>,
+) -> Result<Response, ErrorResult> {
+    for date in [&params.from, &params.to].into_iter().flatten() {
+        if !is_date(date) {
+            return Err(ErrorResult::bad_request(format!(
+                "invalid date {}, expected YYYY-MM-DD",
+                date
+            )));
+        }
+    }
+    let root = params.root.as_deref();
+    if root.is_some_and(|root| {
+        !root
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == ':' || c == '-')
+    }) {
+        return Err(ErrorResult::bad_request(format!(
+            "invalid account {}",
+            root.unwrap_or_default()
+        )));
+    }
+    let accounts: Vec<String> = state
+        .session()
+        .rows(&accounts_query(root))
+        .await?
+        .rows
+        .into_iter()
+        .filter_map(|mut row| row.remove("account"))
+        .filter(|account| !account.is_empty())
+        .collect();
+
+    let time = fava_time(params.from.as_deref(), params.to.as_deref());
+
+    // Journals are fetched ahead by a bounded queue of tasks, and written in
+    // account order as they complete.
+    let (tasks, mut pending) =
+        mpsc::channel::<(String, JoinHandle<Result<String, String>>)>(CONCURRENCY);
+    let fetch_state = state.clone();
+    tokio::spawn(async move {
+        for account in accounts {
+            let state = fetch_state.clone();
+            let time = time.clone();
+            let name = account.clone();
+            let task = tokio::spawn(async move {
+                let entries = match &time {
+                    Some(time) => {
+                        let html = state
+                            .session()
+                            .account_page(&name, Some(time))
+                            .await
+                            .map_err(|e| e.to_string())?;
+                        journal::parse_journal(&html, state.detection.layout())
+                    }
+                    None => {
+                        state
+                            .session()
+                            .account_journal(&name)
+                            .await
+                            .map_err(|e| e.error)?
+                            .entries
+                    }
+                };
+                Ok(render(entries))
+            });
+            if tasks.send((account, task)).await.is_err() {
+                return;
+            }
+        }
+    });
+
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        let mut zip = ZipWriter::default();
+        let mut errors = String::new();
+        while let Some((account, task)) = pending.recv().await {
+            let csv = match task.await.map_err(|e| e.to_string()).and_then(|csv| csv) {
+                Ok(csv) => csv,
+                Err(e) => {
+                    errors.push_str(&format!("{}: {}\n", account, e));
+                    continue;
+                }
+            };
+            let entry = zip.entry(&entry_name(&account), csv.as_bytes());
+            if sender.send_data(Bytes::from(entry)).await.is_err() {
+                return;
+            }
+        }
+        if !errors.is_empty() {
+            let entry = zip.entry("ERRORS.txt", errors.as_bytes());
+            if sender.send_data(Bytes::from(entry)).await.is_err() {
+                return;
+            }
+        }
+        let _ = sender.send_data(Bytes::from(zip.finish())).await;
+    });
+
+    Ok((
+        [
+            (CONTENT_TYPE, "application/zip"),
+            (CONTENT_DISPOSITION, "attachment; filename=\"export.zip\""),
+        ],
+        boxed(body),
+    )
+        .into_response())
+}
+
+fn accounts_query(root: Option<&str>) -> String {
+    match root {
+        Some(root) => format!(
+            "SELECT DISTINCT account WHERE account ~ '^{}(:|$)' ORDER BY account",
+            root
+        ),
+        None => "SELECT DISTINCT account ORDER BY account".into(),
+    }
+}
+
+/// Fava's `time` filter for the days from `from` through `to`, an open end
+/// reaching as far as fava's dates go.
+fn fava_time(from: Option<&str>, to: Option<&str>) -> Option<String> {
+    if from.is_none() && to.is_none() {
+        return None;
+    }
+    Some(format!(
+        "{} - {}",
+        from.unwrap_or(EARLIEST),
+        to.unwrap_or(LATEST)
+    ))
+}
+
+/// The journal as CSV.
+fn render(entries: Vec<JournalEntry>) -> String {
+    let rows: Vec<Row> = entries
+        .into_iter()
+        .map(|entry| {
+            let mut row = Row::new();
+            row.insert("date".into(), entry.date);
+            row.insert("flag".into(), entry.flag);
+            row.insert("payee".into(), entry.payee);
+            row.insert("narration".into(), entry.narration);
+            row.insert("change".into(), entry.change.trim().to_string());
+            row.insert("balance".into(), entry.balance.trim().to_string());
+            row
+        })
+        .collect();
+    csv::write(&COLUMNS, &rows, None)
+}
+
+/// `Expenses:Food` becomes `Expenses/Food.csv`, with anything but
+/// letters, digits, `-` and `_` in a component replaced.
+fn entry_name(account: &str) -> String {
+    let components: Vec<String> = account
+        .split(':')
+        .map(|component| {
+            component
+                .chars()
+                .map(|c| {
+                    if c.is_alphanumeric() || c == '-' || c == '_' {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect()
+        })
+        .collect();
+    format!("{}.csv", components.join("/"))
+}
+
+fn is_date(text: &str) -> bool {
+    let bytes = text.as_bytes();
+    bytes.len() == 10
+        && bytes.iter().enumerate().all(|(i, b)| match i {
+            4 | 7 => *b == b'-',
+            _ => b.is_ascii_digit(),
+        })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    root: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    from: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    to: Option<String>,
+}
+
+impl QueryFields for ExportParams {
+    const FIELDS: &[&str] = &["root", "from", "to"];
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        extract::{Path, Query, State as Extract},
+        http::{Request, StatusCode},
+        routing::get,
+        Router,
+    };
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+    use crate::{backend::Backend, config::Config, testing, zip};
+
+    const JOURNAL: &str = include_str!("../tests/fixtures/1.27/account.html");
+
+    /// The archive exported for `query`, and the `time` filters fava got
+    /// for the journals.
+    async fn exported(query: &str) -> (Vec<(String, Vec<u8>)>, Vec<String>) {
+        let times = Arc::new(Mutex::new(Vec::new()));
+        let fava = Router::new()
+            .route(
+                "/api/query_result",
+                get(|| async {
+                    testing::table(&["account"], &[&["Assets:Bank"], &["Assets:Gone"]])
+                }),
+            )
+            .route(
+                "/account/*account",
+                get(
+                    |Path(account): Path<String>,
+                     Query(query): Query<HashMap<String, String>>,
+                     Extract(times): Extract<Arc<Mutex<Vec<String>>>>| async move {
+                        times.lock().unwrap().extend(query.get("time").cloned());
+                        match account.as_str() {
+                            "Assets:Gone" => (StatusCode::NOT_FOUND, String::new()),
+                            _ => (StatusCode::OK, JOURNAL.to_string()),
+                        }
+                    },
+                ),
+            )
+            .with_state(times.clone());
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        let state = AppState::new(config);
+        let request = Request::get(format!("/api/export.zip?root=Assets{}", query))
+            .body(Body::empty())
+            .unwrap();
+        let (status, _, body) = testing::send(&state, request).await;
+        assert_eq!(status, StatusCode::OK);
+        let archive = zip::read(&body, 0);
+        let times = times.lock().unwrap().clone();
+        (archive, times)
+    }
+
+    #[tokio::test]
+    async fn unpacks_into_a_journal_per_account_and_the_errors() {
+        let (archive, times) = exported("&from=2024-01-01&to=2024-12-31").await;
+        let names: Vec<&str> = archive.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["Assets/Bank.csv", "ERRORS.txt"]);
+        let csv = String::from_utf8(archive[0].1.clone()).unwrap();
+        assert!(csv.starts_with("date,flag,payee,narration,change,balance\r\n"));
+        assert!(csv.contains(",-300.00 CNY,700.00 CNY\r\n"), "{}", csv);
+        let errors = String::from_utf8(archive[1].1.clone()).unwrap();
+        assert!(errors.starts_with("Assets:Gone: "), "{}", errors);
+        assert_eq!(times, ["2024-01-01 - 2024-12-31"; 2]);
+    }
+
+    #[tokio::test]
+    async fn leaves_the_dates_to_fava() {
+        let (_, times) = exported("&from=2024-01-01").await;
+        assert_eq!(times, ["2024-01-01 - 9999-12-30"; 2]);
+        let (_, times) = exported("&to=2024-12-31").await;
+        assert_eq!(times, ["0001-01-01 - 2024-12-31"; 2]);
+        let (archive, times) = exported("").await;
+        assert!(times.is_empty());
+        assert_eq!(archive.len(), 2);
+    }
+}
@@ -0,0 +1,178 @@
+use nipper::Document;
+use reqwest::header::SERVER;
+use serde_json::Value;
+use std::sync::Mutex;
+
+use crate::{AppState, UpstreamError};
+
+/// Consecutive undecodable responses after which the upstream is probed.
+const DECODE_FAILURES: u32 = 3;
+
+/// Whether the configured url looks like fava, as far as the last probe
+/// could tell.
+#[derive(Debug, Default)]
+pub struct Fingerprint {
+    state: Mutex<FingerprintState>,
+}
+
+#[derive(Debug, Default)]
+struct FingerprintState {
+    decode_failures: u32,
+    mismatch: Option<String>,
+}
+
+impl Fingerprint {
+    /// The error to report while the upstream does not look like fava.
+    pub fn mismatch(&self) -> Option<String> {
+        self.state.lock().unwrap().mismatch.clone()
+    }
+
+    /// Counts a decoded response, which proves the upstream speaks fava's API.
+    fn decoded(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.decode_failures = 0;
+        state.mismatch = None;
+    }
+
+    /// Counts an undecodable response and tells whether it is time to probe.
+    fn failed(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.decode_failures += 1;
+        state.decode_failures.is_multiple_of(DECODE_FAILURES)
+    }
+
+    fn set(&self, mismatch: Option<String>) {
+        self.state.lock().unwrap().mismatch = mismatch;
+    }
+}
+
+/// Probes the upstream once in the background, so that a wrong url shows
+/// up in the logs at startup rather than with the first request.
+pub fn spawn(state: &AppState) {
+    let task_state = state.clone();
+    state.spawn_background(async move { probe(&task_state).await });
+}
+
+/// Tracks the outcome of decoding a fava API response. Repeated decode
+/// failures trigger a probe, and while the upstream does not look like fava
+/// the failure is replaced by an error saying so.
+pub async fn check<T>(
+    state: &AppState,
+    result: Result<T, UpstreamError>,
+) -> Result<T, UpstreamError> {
+    match result {
+        Ok(value) => {
+            state.fingerprint.decoded();
+            Ok(value)
+        }
+        Err(e) if e.is_decode() => {
+            if state.fingerprint.failed() {
+                probe(state).await;
+            }
+            match state.fingerprint.mismatch() {
+                Some(mismatch) => Err(UpstreamError::NotFava(mismatch)),
+                None => Err(e),
+            }
+        }
+        Err(e) => Err(e),
+    }
+}
+
+async fn probe(state: &AppState) {
+    let mismatch = match identify(state).await {
+        Some(Ok(())) => None,
+        Some(Err(got)) => Some(format!(
+            "the configured url does not appear to be a Fava server (got: {})",
+            got
+        )),
+        // Unreachable, which the regular errors already report.
+        None => return,
+    };
+    if let Some(mismatch) = &mismatch {
+        println!("{}", mismatch);
+    }
+    state.fingerprint.set(mismatch);
+}
+
+/// Looks for fava's markers: the JSON envelope of its `ledger_data` API,
+/// which unlike `changed` leaves the reload notice to whoever polls for it,
+/// then, as proxies may rewrite error pages or strip headers, any mention
+/// of fava in the ledger page. Returns a description of the upstream otherwise, or
+/// `None` if it can not be reached at all.
+async fn identify(state: &AppState) -> Option<Result<(), String>> {
+    let mut reachable = false;
+    if let Ok(response) = state.client.fetch("/api/ledger_data", &[]).await {
+        reachable = true;
+        if let Ok(Value::Object(envelope)) = response.json::<Value>().await {
+            if envelope.get("success").is_some_and(Value::is_boolean) {
+                return Some(Ok(()));
+            }
+        }
+    }
+
+    let response = match state.client.fetch("/", &[]).await {
+        Ok(response) => response,
+        Err(_) if reachable => return Some(Err("no ledger page".into())),
+        Err(_) => return None,
+    };
+    let status = response.status();
+    let server = response
+        .headers()
+        .get(SERVER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let body = response.text().await.unwrap_or_default();
+    if body.to_lowercase().contains("fava") {
+        return Some(Ok(()));
+    }
+    let title = Document::from(body.as_str())
+        .select("title")
+        .text()
+        .trim()
+        .to_string();
+    Some(Err(if !title.is_empty() {
+        title
+    } else if let Some(server) = server {
+        server
+    } else {
+        format!("HTTP {}", status)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::get, Json, Router};
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{self, Hits};
+
+    #[tokio::test]
+    async fn identifies_fava_without_taking_the_reload_notice() {
+        let changed = Hits::default();
+        let hits = changed.clone();
+        let fava = Router::new()
+            .route(
+                "/api/ledger_data",
+                get(|| async { Json(json!({ "success": true, "data": {} })) }),
+            )
+            .route(
+                "/api/changed",
+                get(move || async move {
+                    hits.hit();
+                    Json(json!({ "success": true, "data": true }))
+                }),
+            );
+        let state = testing::state(&testing::fava(fava).await);
+        assert_eq!(identify(&state).await, Some(Ok(())));
+        assert_eq!(changed.count(), 0);
+    }
+
+    #[tokio::test]
+    async fn describes_other_servers_by_their_title() {
+        let other =
+            Router::new().route("/", get(|| async { "<html><title>Grafana</title></html>" }));
+        let state = testing::state(&testing::fava(other).await);
+        assert_eq!(identify(&state).await, Some(Err("Grafana".to_string())));
+    }
+}
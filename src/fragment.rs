@@ -0,0 +1,198 @@
+use crate::{
+    amount,
+    config::LocaleConfig,
+    i18n::{Lang, Message},
+    Row,
+};
+
+/// Renders rows as an HTML fragment to embed in a page: a table of
+/// `columns` under a caption counting the rows, then the warnings of the
+/// result. The labels are in `lang`, the column names as they are, and
+/// with a `locale` numbers and dates are rendered for display. It carries
+/// classes to style it by but no styles of its own.
+pub fn write(
+    columns: &[&str],
+    rows: &[Row],
+    warnings: &[Message],
+    locale: Option<&LocaleConfig>,
+    lang: Lang,
+) -> String {
+    let label = |key, args| Message::new(key, args).localize(lang).to_string();
+    let caption = match rows.len() {
+        1 => label("fragment_one_row", vec![]),
+        count => label("fragment_rows", vec![count.to_string()]),
+    };
+    let mut output = format!(
+        "<div class=\"fava-query\" lang=\"{}\">\n<table>\n<caption>{}</caption>\n<thead><tr>",
+        lang.tag(),
+        escape(&caption)
+    );
+    for column in columns {
+        output.push_str(&format!("<th scope=\"col\">{}</th>", escape(column)));
+    }
+    output.push_str("</tr></thead>\n<tbody>\n");
+    if rows.is_empty() {
+        output.push_str(&format!(
+            "<tr><td class=\"empty\" colspan=\"{}\">{}</td></tr>\n",
+            columns.len().max(1),
+            escape(&label("fragment_no_rows", vec![]))
+        ));
+    }
+    for row in rows {
+        output.push_str("<tr>");
+        for column in columns {
+            let text = row.get(*column).map_or("", String::as_str);
+            // Numbers and amounts line up on the right once styled so.
+            let class = match amount::parse_positions(text) {
+                Some(_) => " class=\"number\"",
+                None => "",
+            };
+            let text = match locale {
+                Some(locale) => locale.cell(text),
+                None => text.to_string(),
+            };
+            output.push_str(&format!("<td{}>{}</td>", class, escape(&text)));
+        }
+        output.push_str("</tr>\n");
+    }
+    output.push_str("</tbody>\n</table>\n");
+    if !warnings.is_empty() {
+        output.push_str(&format!(
+            "<details class=\"warnings\"><summary>{}</summary><ul>",
+            escape(&label("fragment_warnings", vec![]))
+        ));
+        for warning in warnings {
+            output.push_str(&format!("<li>{}</li>", escape(&warning.to_string())));
+        }
+        output.push_str("</ul></details>\n");
+    }
+    output.push_str("</div>\n");
+    output
+}
+
+/// Escapes text for HTML element content and quoted attribute values.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::get, Router};
+
+    use super::*;
+    use crate::{
+        backend::Backend,
+        config::Config,
+        testing::{self, table},
+        AppState,
+    };
+
+    fn rows() -> Vec<Row> {
+        let row = [("date", "2024-01-02"), ("payee", "<Shop & Co>")];
+        vec![row
+            .iter()
+            .map(|(column, cell)| (column.to_string(), cell.to_string()))
+            .collect()]
+    }
+
+    #[test]
+    fn escapes_names_and_cells() {
+        let fragment = write(&["date", "payee"], &rows(), &[], None, Lang::default());
+        assert_eq!(
+            fragment,
+            "<div class=\"fava-query\" lang=\"en\">\n<table>\n<caption>1 row</caption>\n\
+             <thead><tr><th scope=\"col\">date</th><th scope=\"col\">payee</th></tr></thead>\n\
+             <tbody>\n<tr><td>2024-01-02</td><td>&lt;Shop &amp; Co&gt;</td></tr>\n\
+             </tbody>\n</table>\n</div>\n"
+        );
+    }
+
+    #[test]
+    fn says_when_there_are_no_rows() {
+        let fragment = write(&["date"], &[], &[], None, Lang::default());
+        assert!(fragment.contains("<caption>0 rows</caption>"));
+        assert!(fragment.contains("<td class=\"empty\" colspan=\"1\">No rows</td>"));
+    }
+
+    /// The text of `fragment` outside its tags, less the headers and cells
+    /// that are the result's own.
+    fn labels(fragment: &str) -> Vec<&str> {
+        let mut labels = Vec::new();
+        let mut rest = fragment;
+        while let Some(start) = rest.find('<') {
+            let tag = &rest[start + 1..rest[start..].find('>').unwrap() + start];
+            rest = &rest[start + tag.len() + 2..];
+            let text = &rest[..rest.find('<').unwrap_or(rest.len())];
+            let own = [
+                "caption",
+                "summary",
+                "li",
+                "td class=\"empty\" colspan=\"2\"",
+            ];
+            if own.contains(&tag) && !text.trim().is_empty() {
+                labels.push(text);
+            }
+        }
+        labels
+    }
+
+    #[tokio::test]
+    async fn renders_in_the_asked_language() {
+        // The extra cell of the second row is dropped with a warning.
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|| async {
+                table(
+                    &["date", "position"],
+                    &[
+                        &["2024-01-02", "-1234.5 CNY"],
+                        &["2024-01-03", "3 USD", "extra"],
+                    ],
+                )
+            }),
+        );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        let state = AppState::new(config);
+        let uri = "/api/query_result?query_string=SELECT%20date&format=html_fragment";
+
+        let (status, fragment) = testing::get(&state, &format!("{}&lang=zh", uri)).await;
+        assert_eq!(status, 200);
+        assert_eq!(
+            labels(&fragment),
+            [
+                "共 2 行",
+                "警告",
+                "第 2 行有 3 个单元格，但表格只有 2 列，多余的单元格已丢弃"
+            ]
+        );
+        assert!(fragment.starts_with("<div class=\"fava-query\" lang=\"zh\">"));
+        assert!(fragment.contains("<td>2024年1月2日</td><td class=\"number\">-1,234.5 CNY</td>"));
+
+        let (_, fragment) = testing::get(&state, uri).await;
+        assert_eq!(
+            labels(&fragment),
+            [
+                "2 rows",
+                "Warnings",
+                "row 2 has 3 cells but the table has 2 columns, extra cells were dropped"
+            ]
+        );
+        assert!(fragment.contains("<td>01/02/2024</td>"));
+        let (_, fragment) =
+            testing::get(&state, &format!("{}&lang=zh&output_locale=de", uri)).await;
+        assert!(fragment.contains("<td>02.01.2024</td><td class=\"number\">-1.234,5 CNY</td>"));
+        assert_eq!(labels(&fragment)[0], "共 2 行");
+    }
+}
@@ -0,0 +1,37 @@
+//! The parsers of fava's HTML with the invariants they keep for any input,
+//! asserted by the property tests and by the targets in `fuzz/`.
+
+use crate::{get_account_data, get_table_data, journal, AccountParams};
+
+/// Parses `html` as a query result table, and checks that every row only
+/// has keys among the columns, none of them empty.
+pub fn table_data(html: &str) {
+    let parsed = get_table_data(html.to_string());
+    assert!(parsed.columns.iter().all(|column| !column.is_empty()));
+    for row in &parsed.rows {
+        assert!(row.len() <= parsed.columns.len());
+        assert!(row.keys().all(|key| parsed.columns.contains(key)));
+    }
+}
+
+/// Parses `html` as an account journal, and checks that each row has a
+/// date of its own and changes and balances that read back as positions.
+pub fn account_data(html: &str) {
+    let entries = journal::parse_journal(html, journal::layout(None));
+    let params = AccountParams {
+        include_raw: Some(true),
+        ..AccountParams::default()
+    };
+    let parsed = get_account_data(&entries, &params);
+    assert_eq!(parsed.raw.len(), parsed.rows.len());
+    let mut dates = Vec::new();
+    for row in &parsed.rows {
+        assert!(row.keys().eq(["balance", "changed", "date"]));
+        let date = &row["date"];
+        assert!(!date.is_empty() && !dates.contains(&date));
+        dates.push(date);
+        for key in ["changed", "balance"] {
+            assert!(crate::amount::parse_positions(&row[key]).is_some());
+        }
+    }
+}
@@ -0,0 +1,1677 @@
+use axum::{
+    extract::{rejection::JsonRejection, Path, State},
+    response::{IntoResponse, Response},
+    Json,
+};
+use reqwest::StatusCode;
+use rust_decimal::Decimal;
+use serde::{ser::SerializeMap, Deserialize, Serialize, Serializer};
+use serde_json::{json, Map, Value};
+use std::{cmp::Ordering, collections::HashMap, future::Future, pin::Pin};
+
+use crate::{
+    accounts,
+    amount::Amount,
+    balance_sheet, empty_string_as_none, entries,
+    groups::EndpointGroup,
+    i18n::Lang,
+    params::{self, QueryFields, StrictQuery},
+    pattern::Pattern,
+    query_rows, AppState, ErrorResult, Params,
+};
+
+/// Deepest nesting of selections, fragments, lists and objects a document
+/// may have.
+const MAX_DEPTH: usize = 32;
+/// Most fields one request may fetch from fava, so that a selection below
+/// every account can not flood it.
+const MAX_FETCHES: usize = 64;
+
+/// Arguments every list field takes, applied in this order.
+const LIST_ARGUMENTS: &[&str] = &["where", "offset", "limit"];
+/// Operators of a `where` condition, which must all hold.
+const OPERATORS: &[&str] = &[
+    "eq", "ne", "in", "contains", "matches", "gt", "gte", "lt", "lte", "is_null",
+];
+
+/// What a value stands for, which decides the fields fetched from fava
+/// rather than read from the value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Query,
+    Accounts,
+    Account,
+    Object,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Query => "Query",
+            Kind::Accounts => "Accounts",
+            Kind::Account => "Account",
+            Kind::Object => "Object",
+        }
+    }
+
+    /// The kind of the values a field of this kind holds.
+    fn field(self, name: &str) -> Kind {
+        match (self, name) {
+            (Kind::Query, "accounts") => Kind::Accounts,
+            (Kind::Query, "account") | (Kind::Accounts, "roots") | (Kind::Account, "children") => {
+                Kind::Account
+            }
+            _ => Kind::Object,
+        }
+    }
+}
+
+/// The fields fetched from fava, with the endpoint group of the routes
+/// they stand for. Their arguments are the parameters of those routes.
+const FETCHED: &[(Kind, &str, EndpointGroup)] = &[
+    (Kind::Query, "query", EndpointGroup::Query),
+    (Kind::Query, "journal", EndpointGroup::Query),
+    (Kind::Query, "accounts", EndpointGroup::Account),
+    (Kind::Query, "account", EndpointGroup::Account),
+    (Kind::Query, "account_balance", EndpointGroup::Account),
+    (Kind::Query, "balance_sheet", EndpointGroup::Aggregate),
+    (Kind::Query, "trial_balance", EndpointGroup::Aggregate),
+    (Kind::Account, "balance", EndpointGroup::Account),
+    (Kind::Account, "transactions", EndpointGroup::Account),
+];
+
+/// `GET /api/graphql?query=...`, with `variables` as a JSON object.
+pub async fn graphql_get(
+    State(state): State<AppState>,
+    StrictQuery(params): StrictQuery<GraphqlParams>,
+    lang: Lang,
+) -> Result<GraphqlResponse, ErrorResult> {
+    let variables = match params.variables.as_deref() {
+        Some(variables) => Some(
+            serde_json::from_str(variables)
+                .map_err(|e| ErrorResult::bad_request(format!("invalid variables: {}", e)))?,
+        ),
+        None => None,
+    };
+    let request = GraphqlRequest {
+        query: params.query,
+        variables,
+        operation_name: params.operation_name,
+    };
+    Ok(execute(&state, lang, request).await)
+}
+
+/// `POST /api/graphql`: the queries, accounts, balances and journals of
+/// the REST endpoints as one GraphQL schema, for frontends that fetch
+/// several of them at once or only need a few of their fields.
+///
+/// ```graphql
+/// type Query {
+///   query(query_string: String!, ...): QueryResult  # /api/query_result
+///   journal(account: String, time: String, filter: String): Journal
+///   accounts: Accounts                              # /api/accounts
+///   account(name: String!): Account
+///   account_balance(account: String!, at: String, ...): AccountBalance
+///   balance_sheet(time: String, ...): BalanceSheet
+///   trial_balance(time: String, ...): TrialBalance
+/// }
+/// type QueryResult { columns: [String] rows: [Row] meta: Meta }
+/// type Account {
+///   name parent open close currencies children: [Account]
+///   balance(at: String, currency: String, conversion: String): JSON
+///   transactions(time: String, filter: String): [Transaction]
+/// }
+/// ```
+///
+/// Below the root fields the objects are the `data` of the endpoints, with
+/// the same field names. A field without a selection gives its whole
+/// value, and fields a value lacks are `null`.
+///
+/// Every list field takes `offset`, `limit` and a `where` condition such
+/// as `{payee: {contains: "Shop"}, postings: {account: {matches:
+/// "^Expenses"}}}`: a value compares with `eq`, `ne`, `in`, `contains`,
+/// `matches`, `gt`, `gte`, `lt`, `lte` or `is_null`, a condition on a list
+/// holds for any of its items, and `or` and `not` combine conditions.
+pub async fn graphql_post(
+    State(state): State<AppState>,
+    lang: Lang,
+    body: Result<Json<GraphqlRequest>, JsonRejection>,
+) -> Result<GraphqlResponse, ErrorResult> {
+    let Json(request) =
+        body.map_err(|rejection| ErrorResult::bad_request(rejection.to_string()))?;
+    Ok(execute(&state, lang, request).await)
+}
+
+async fn execute(state: &AppState, lang: Lang, request: GraphqlRequest) -> GraphqlResponse {
+    let document = match Parser::document(&request.query) {
+        Ok(document) => document,
+        Err(e) => return GraphqlResponse::failed(format!("syntax error: {}", e)),
+    };
+    let operation = match (&request.operation_name, &document.operations[..]) {
+        (Some(name), operations) => operations
+            .iter()
+            .find(|operation| operation.name.as_ref() == Some(name)),
+        (None, [operation]) => Some(operation),
+        (None, _) => {
+            return GraphqlResponse::failed(
+                "operationName is needed for a document of several operations".into(),
+            )
+        }
+    };
+    let operation = match operation {
+        Some(operation) => operation,
+        None => return GraphqlResponse::failed("no such operation".into()),
+    };
+
+    let mut provided = request.variables.unwrap_or_default();
+    let mut variables = Map::new();
+    for (name, default) in &operation.variables {
+        let value = match (provided.remove(name), default) {
+            (Some(value), _) => value,
+            (None, Some(default)) => default.clone(),
+            (None, None) => Value::Null,
+        };
+        variables.insert(name.clone(), value);
+    }
+    let mut executor = Executor {
+        state,
+        lang,
+        fragments: &document.fragments,
+        variables,
+        errors: Vec::new(),
+        warnings: Vec::new(),
+        fetches: 0,
+        path: Vec::new(),
+    };
+    let selections = operation.selections.iter().collect();
+    let data = executor
+        .object(&Value::Null, Kind::Query, selections, 0)
+        .await;
+    GraphqlResponse {
+        status: StatusCode::OK,
+        data: Some(data),
+        errors: executor.errors,
+        extensions: match executor.warnings.is_empty() {
+            true => None,
+            false => Some(json!({ "warnings": executor.warnings })),
+        },
+    }
+}
+
+struct Executor<'a> {
+    state: &'a AppState,
+    lang: Lang,
+    fragments: &'a HashMap<String, Vec<Selection>>,
+    variables: Map<String, Value>,
+    errors: Vec<GraphqlError>,
+    /// Warnings of the endpoints the fields were fetched from.
+    warnings: Vec<Value>,
+    fetches: usize,
+    /// Response keys and list indices down to the field being resolved.
+    path: Vec<Value>,
+}
+
+type Resolving<'b> = Pin<Box<dyn Future<Output = Output> + Send + 'b>>;
+
+impl<'a> Executor<'a> {
+    fn error(&mut self, e: ErrorResult) -> Output {
+        let e = e.localize(self.lang);
+        self.errors.push(GraphqlError {
+            extensions: json!({ "code": e.code() }),
+            message: e.error,
+            path: self.path.clone(),
+        });
+        Output::Value(Value::Null)
+    }
+
+    async fn object(
+        &mut self,
+        parent: &Value,
+        kind: Kind,
+        selections: Vec<&'a Selection>,
+        depth: usize,
+    ) -> Output {
+        let mut fields: Vec<(&'a str, Vec<&'a Field>)> = Vec::new();
+        if let Err(e) = self.collect(selections, &mut fields, depth) {
+            return self.error(e);
+        }
+        let mut output = Vec::with_capacity(fields.len());
+        for (key, fields) in fields {
+            self.path.push(key.into());
+            let value = self.field(parent, kind, fields, depth).await;
+            self.path.pop();
+            output.push((key.to_string(), value));
+        }
+        Output::Object(output)
+    }
+
+    /// The fields of `selections` that `@skip` and `@include` keep, by
+    /// response key, with fragments spread into them.
+    fn collect(
+        &self,
+        selections: Vec<&'a Selection>,
+        fields: &mut Vec<(&'a str, Vec<&'a Field>)>,
+        depth: usize,
+    ) -> Result<(), ErrorResult> {
+        if depth > MAX_DEPTH {
+            return Err(ErrorResult::bad_request("selections nest too deep".into()));
+        }
+        for selection in selections {
+            let (directives, spread) = match selection {
+                Selection::Field(field) => {
+                    if self.included(&field.directives)? {
+                        let key = field.alias.as_deref().unwrap_or(&field.name);
+                        match fields.iter_mut().find(|(known, _)| *known == key) {
+                            Some((_, same)) => same.push(field),
+                            None => fields.push((key, vec![field])),
+                        }
+                    }
+                    continue;
+                }
+                Selection::Fragment { name, directives } => match self.fragments.get(name) {
+                    Some(selections) => (directives, selections),
+                    None => {
+                        return Err(ErrorResult::bad_request(format!(
+                            "unknown fragment {}",
+                            name
+                        )))
+                    }
+                },
+                Selection::Inline {
+                    directives,
+                    selections,
+                } => (directives, selections),
+            };
+            if self.included(directives)? {
+                self.collect(spread.iter().collect(), fields, depth + 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn included(&self, directives: &[Directive]) -> Result<bool, ErrorResult> {
+        for directive in directives {
+            let condition = match directive.arguments.iter().find(|(name, _)| name == "if") {
+                Some((_, condition)) => self.value(condition)?,
+                None => Value::Null,
+            };
+            let condition = match condition {
+                Value::Bool(condition) => condition,
+                _ => {
+                    return Err(ErrorResult::bad_request(format!(
+                        "@{} needs a boolean if",
+                        directive.name
+                    )))
+                }
+            };
+            match directive.name.as_str() {
+                "skip" if condition => return Ok(false),
+                "include" if !condition => return Ok(false),
+                "skip" | "include" => {}
+                name => {
+                    return Err(ErrorResult::bad_request(format!(
+                        "unknown directive @{}",
+                        name
+                    )))
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Resolves the fields of one response key, which select the union of
+    /// their selections with the arguments of the first.
+    async fn field(
+        &mut self,
+        parent: &Value,
+        kind: Kind,
+        fields: Vec<&'a Field>,
+        depth: usize,
+    ) -> Output {
+        let field = fields[0];
+        let name = field.name.as_str();
+        if name == "__typename" {
+            return Output::Value(kind.name().into());
+        }
+        let mut arguments = match self.arguments(&field.arguments) {
+            Ok(arguments) => arguments,
+            Err(e) => return self.error(e),
+        };
+        // The root fields return objects, and take `offset` and `limit` as
+        // the parameters of their endpoints.
+        let mut list = Map::new();
+        if kind != Kind::Query {
+            for argument in LIST_ARGUMENTS {
+                if let Some(value) = arguments.remove(*argument) {
+                    list.insert(argument.to_string(), value);
+                }
+            }
+        }
+        let value = match FETCHED
+            .iter()
+            .find(|(of, known, _)| *of == kind && *known == name)
+        {
+            Some((_, _, group)) if !self.state.config.endpoints.contains(group) => {
+                return self.error(ErrorResult::bad_request(format!("{} is not enabled", name)))
+            }
+            Some(_) => match self.fetch(kind, parent, name, arguments).await {
+                Ok(value) => value,
+                Err(e) => return self.error(e),
+            },
+            None if kind == Kind::Query => {
+                return self.error(ErrorResult::bad_request(format!(
+                    "unknown field {} on Query",
+                    name
+                )))
+            }
+            None if !arguments.is_empty() => {
+                return self.error(ErrorResult::bad_request(format!(
+                    "{} takes no arguments but {}",
+                    name,
+                    LIST_ARGUMENTS.join(", ")
+                )))
+            }
+            None => parent.get(name).cloned().unwrap_or_default(),
+        };
+        let value = match filtered(value, &list) {
+            Ok(value) => value,
+            Err(e) => return self.error(ErrorResult::bad_request(e)),
+        };
+        let selections = fields.iter().flat_map(|field| &field.selections).collect();
+        self.complete(value, kind.field(name), selections, depth + 1)
+            .await
+    }
+
+    fn complete<'b>(
+        &'b mut self,
+        value: Value,
+        kind: Kind,
+        selections: Vec<&'a Selection>,
+        depth: usize,
+    ) -> Resolving<'b> {
+        Box::pin(async move {
+            if selections.is_empty() {
+                return Output::Value(value);
+            }
+            match value {
+                Value::Null => Output::Value(Value::Null),
+                Value::Array(items) => {
+                    let mut list = Vec::with_capacity(items.len());
+                    for (i, item) in items.into_iter().enumerate() {
+                        self.path.push(i.into());
+                        list.push(self.complete(item, kind, selections.clone(), depth).await);
+                        self.path.pop();
+                    }
+                    Output::List(list)
+                }
+                Value::Object(_) => self.object(&value, kind, selections, depth).await,
+                _ => self.error(ErrorResult::bad_request(
+                    "a value without fields can not have a selection".into(),
+                )),
+            }
+        })
+    }
+
+    fn arguments(
+        &self,
+        arguments: &[(String, Literal)],
+    ) -> Result<Map<String, Value>, ErrorResult> {
+        let mut values = Map::new();
+        for (name, literal) in arguments {
+            match self.value(literal)? {
+                Value::Null => {}
+                value => {
+                    values.insert(name.clone(), value);
+                }
+            }
+        }
+        Ok(values)
+    }
+
+    fn value(&self, literal: &Literal) -> Result<Value, ErrorResult> {
+        Ok(match literal {
+            Literal::Variable(name) => match self.variables.get(name) {
+                Some(value) => value.clone(),
+                None => {
+                    return Err(ErrorResult::bad_request(format!(
+                        "variable ${} is not defined",
+                        name
+                    )))
+                }
+            },
+            Literal::Value(value) => value.clone(),
+            Literal::List(items) => Value::Array(
+                items
+                    .iter()
+                    .map(|item| self.value(item))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Literal::Object(fields) => Value::Object(
+                fields
+                    .iter()
+                    .map(|(name, value)| Ok((name.clone(), self.value(value)?)))
+                    .collect::<Result<_, ErrorResult>>()?,
+            ),
+        })
+    }
+
+    /// A field of [`FETCHED`], as the `data` its endpoint answers with.
+    async fn fetch(
+        &mut self,
+        kind: Kind,
+        parent: &Value,
+        name: &str,
+        mut arguments: Map<String, Value>,
+    ) -> Result<Value, ErrorResult> {
+        self.fetches += 1;
+        if self.fetches > MAX_FETCHES {
+            return Err(ErrorResult::bad_request(format!(
+                "more than {} fields fetched in one request",
+                MAX_FETCHES
+            )));
+        }
+        let state = self.state.clone();
+        let lang = self.lang;
+        let result = match (kind, name) {
+            (Kind::Query, "query") => {
+                let params: Params = params::from_object(arguments, &state, lang)?;
+                let result = query_rows(&state, &params).await?.localize(lang);
+                let columns = result.columns.clone();
+                let mut result = to_value(result)?;
+                json!({
+                    "data": {
+                        "columns": columns,
+                        "rows": result["data"].take(),
+                        "meta": result["meta"].take(),
+                    },
+                    "warnings": result["warnings"].take(),
+                })
+            }
+            (Kind::Query, "journal") => to_value(
+                entries::journal(
+                    State(state.clone()),
+                    StrictQuery(params::from_object(arguments, &state, lang)?),
+                    lang,
+                )
+                .await?,
+            )?,
+            (Kind::Query, "accounts") => to_value(
+                accounts::accounts(
+                    State(state.clone()),
+                    StrictQuery(params::from_object(arguments, &state, lang)?),
+                    lang,
+                )
+                .await?,
+            )?,
+            (Kind::Query, "account") => {
+                let account = required(&mut arguments, "name")?;
+                let mut result = to_value(
+                    accounts::accounts(
+                        State(state.clone()),
+                        StrictQuery(params::from_object(arguments, &state, lang)?),
+                        lang,
+                    )
+                    .await?,
+                )?;
+                let node = find(&result["data"]["roots"], &account).unwrap_or_default();
+                result["data"] = node;
+                result
+            }
+            (Kind::Query, "account_balance") => {
+                let account = required(&mut arguments, "account")?;
+                to_value(
+                    balance_sheet::account_balance(
+                        State(state.clone()),
+                        Path(account),
+                        StrictQuery(params::from_object(arguments, &state, lang)?),
+                        lang,
+                    )
+                    .await?,
+                )?
+            }
+            (Kind::Query, "balance_sheet") => to_value(
+                balance_sheet::balance_sheet(
+                    State(state.clone()),
+                    StrictQuery(params::from_object(arguments, &state, lang)?),
+                    lang,
+                )
+                .await?,
+            )?,
+            (Kind::Query, "trial_balance") => to_value(
+                balance_sheet::trial_balance(
+                    State(state.clone()),
+                    StrictQuery(params::from_object(arguments, &state, lang)?),
+                    lang,
+                )
+                .await?,
+            )?,
+            (Kind::Account, "balance") => {
+                let mut result = to_value(
+                    balance_sheet::account_balance(
+                        State(state.clone()),
+                        Path(account_name(parent)?),
+                        StrictQuery(params::from_object(arguments, &state, lang)?),
+                        lang,
+                    )
+                    .await?,
+                )?;
+                result["data"] = result["data"]["balance"].take();
+                result
+            }
+            (Kind::Account, "transactions") => {
+                let mut result = to_value(
+                    entries::account_transactions(
+                        State(state.clone()),
+                        Path(account_name(parent)?),
+                        StrictQuery(params::from_object(arguments, &state, lang)?),
+                        lang,
+                    )
+                    .await?,
+                )?;
+                result["data"] = result["data"]["transactions"].take();
+                result
+            }
+            _ => Value::Null,
+        };
+        let mut result = result;
+        if let Value::Array(warnings) = result["warnings"].take() {
+            self.warnings.extend(warnings);
+        }
+        Ok(result["data"].take())
+    }
+}
+
+fn to_value(result: impl Serialize) -> Result<Value, ErrorResult> {
+    serde_json::to_value(result).map_err(|e| ErrorResult::new(e.to_string()))
+}
+
+fn required(arguments: &mut Map<String, Value>, name: &str) -> Result<String, ErrorResult> {
+    match arguments.remove(name) {
+        Some(Value::String(value)) => Ok(value),
+        _ => Err(ErrorResult::bad_request(format!(
+            "{} needs to be a string",
+            name
+        ))),
+    }
+}
+
+fn account_name(account: &Value) -> Result<String, ErrorResult> {
+    match account.get("name") {
+        Some(Value::String(name)) => Ok(name.clone()),
+        _ => Err(ErrorResult::bad_request("an account without a name".into())),
+    }
+}
+
+/// The node of `account` in a tree of `/api/accounts`.
+fn find(nodes: &Value, account: &str) -> Option<Value> {
+    nodes
+        .as_array()?
+        .iter()
+        .find_map(|node| match node.get("name") {
+            Some(name) if name == account => Some(node.clone()),
+            _ => find(node.get("children")?, account),
+        })
+}
+
+/// Applies the `where`, `offset` and `limit` of a list field.
+fn filtered(value: Value, arguments: &Map<String, Value>) -> Result<Value, String> {
+    if arguments.is_empty() {
+        return Ok(value);
+    }
+    let items = match value {
+        Value::Array(items) => items,
+        Value::Null => return Ok(Value::Null),
+        _ => return Err(format!("{} only apply to lists", LIST_ARGUMENTS.join(", "))),
+    };
+    let count = |name: &str| match arguments.get(name) {
+        None => Ok(None),
+        Some(count) => count
+            .as_u64()
+            .map(|count| Some(count as usize))
+            .ok_or_else(|| format!("{} needs to be a number", name)),
+    };
+    let (offset, limit) = (count("offset")?, count("limit")?);
+    let items = match arguments.get("where") {
+        Some(condition) => {
+            let condition = conditions(condition)?;
+            let mut kept = Vec::new();
+            for item in items {
+                if matches(&item, condition)? {
+                    kept.push(item);
+                }
+            }
+            kept
+        }
+        None => items,
+    };
+    Ok(Value::Array(
+        items
+            .into_iter()
+            .skip(offset.unwrap_or(0))
+            .take(limit.unwrap_or(usize::MAX))
+            .collect(),
+    ))
+}
+
+fn conditions(condition: &Value) -> Result<&Map<String, Value>, String> {
+    condition
+        .as_object()
+        .ok_or_else(|| "a where condition needs to be an object".into())
+}
+
+/// Whether `item` meets every condition of a `where`.
+fn matches(item: &Value, conditions: &Map<String, Value>) -> Result<bool, String> {
+    for (key, condition) in conditions {
+        let holds = match key.as_str() {
+            "or" => {
+                let alternatives = condition
+                    .as_array()
+                    .ok_or_else(|| "or needs a list of conditions".to_string())?;
+                let mut any = false;
+                for alternative in alternatives {
+                    if matches(item, self::conditions(alternative)?)? {
+                        any = true;
+                        break;
+                    }
+                }
+                any
+            }
+            "not" => !matches(item, self::conditions(condition)?)?,
+            _ => holds(item.get(key).unwrap_or(&Value::Null), condition)?,
+        };
+        if !holds {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn holds(value: &Value, condition: &Value) -> Result<bool, String> {
+    match condition {
+        Value::Object(operators)
+            if !operators.is_empty()
+                && operators
+                    .keys()
+                    .all(|operator| OPERATORS.contains(&operator.as_str())) =>
+        {
+            for (operator, operand) in operators {
+                if !compare(value, operator, operand)? {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        }
+        // Conditions on the fields of nested objects.
+        Value::Object(conditions) => match value {
+            Value::Array(items) => {
+                for item in items {
+                    if matches(item, conditions)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+            Value::Object(_) => matches(value, conditions),
+            _ => Ok(false),
+        },
+        operand => compare(value, "eq", operand),
+    }
+}
+
+fn compare(value: &Value, operator: &str, operand: &Value) -> Result<bool, String> {
+    match (operator, value) {
+        ("is_null", _) => {
+            let empty = matches!(value, Value::Null) || value.as_str() == Some("");
+            return match operand {
+                Value::Bool(null) => Ok(empty == *null),
+                _ => Err("is_null needs a boolean".into()),
+            };
+        }
+        ("ne", _) => return compare(value, "eq", operand).map(|equal| !equal),
+        (_, Value::Array(items)) => {
+            for item in items {
+                if compare(item, operator, operand)? {
+                    return Ok(true);
+                }
+            }
+            return Ok(false);
+        }
+        _ => {}
+    }
+    let text = match text(value) {
+        Some(text) => text,
+        None => return Ok(false),
+    };
+    let operand_text = || text_of(operator, operand);
+    Ok(match operator {
+        "eq" => ordering(&text, &operand_text()?) == Ordering::Equal,
+        "in" => match operand {
+            Value::Array(operands) => operands
+                .iter()
+                .filter_map(self::text)
+                .any(|operand| ordering(&text, &operand) == Ordering::Equal),
+            _ => return Err("in needs a list".into()),
+        },
+        "contains" => text.contains(&operand_text()?),
+        "matches" => Pattern::new(&operand_text()?)?.is_match(&text),
+        "gt" => ordering(&text, &operand_text()?) == Ordering::Greater,
+        "gte" => ordering(&text, &operand_text()?) != Ordering::Less,
+        "lt" => ordering(&text, &operand_text()?) == Ordering::Less,
+        _ => ordering(&text, &operand_text()?) != Ordering::Greater,
+    })
+}
+
+fn text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        Value::Bool(flag) => Some(flag.to_string()),
+        _ => None,
+    }
+}
+
+fn text_of(operator: &str, operand: &Value) -> Result<String, String> {
+    text(operand).ok_or_else(|| format!("{} needs a string, a number or a boolean", operator))
+}
+
+/// Orders numbers and amounts by their number, and anything else, dates
+/// included, as text.
+fn ordering(left: &str, right: &str) -> Ordering {
+    let number =
+        |text: &str| -> Option<Decimal> { Amount::parse_number(text).map(|(number, _)| number) };
+    match (number(left), number(right)) {
+        (Some(left), Some(right)) => left.cmp(&right),
+        _ => left.cmp(right),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GraphqlRequest {
+    query: String,
+    #[serde(default)]
+    variables: Option<Map<String, Value>>,
+    #[serde(default, rename = "operationName")]
+    operation_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GraphqlParams {
+    query: String,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    variables: Option<String>,
+    #[serde(
+        default,
+        rename = "operationName",
+        deserialize_with = "empty_string_as_none"
+    )]
+    operation_name: Option<String>,
+}
+
+impl QueryFields for GraphqlParams {
+    const FIELDS: &[&str] = &["query", "variables", "operationName"];
+}
+
+/// A response in the order of the selections, which a JSON map would not
+/// keep.
+#[derive(Debug)]
+enum Output {
+    Value(Value),
+    List(Vec<Output>),
+    Object(Vec<(String, Output)>),
+}
+
+impl Serialize for Output {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Output::Value(value) => value.serialize(serializer),
+            Output::List(items) => serializer.collect_seq(items),
+            Output::Object(fields) => {
+                let mut map = serializer.serialize_map(Some(fields.len()))?;
+                for (key, value) in fields {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct GraphqlError {
+    message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    path: Vec<Value>,
+    extensions: Value,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GraphqlResponse {
+    #[serde(skip)]
+    status: StatusCode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Output>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<GraphqlError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extensions: Option<Value>,
+}
+
+impl GraphqlResponse {
+    /// A request that could not be run at all.
+    fn failed(message: String) -> GraphqlResponse {
+        GraphqlResponse {
+            status: StatusCode::BAD_REQUEST,
+            data: None,
+            errors: vec![GraphqlError {
+                message,
+                path: Vec::new(),
+                extensions: json!({ "code": "bad_request" }),
+            }],
+            extensions: None,
+        }
+    }
+}
+
+impl IntoResponse for GraphqlResponse {
+    fn into_response(self) -> Response {
+        let status = self.status;
+        (status, Json(self)).into_response()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Document {
+    operations: Vec<Operation>,
+    fragments: HashMap<String, Vec<Selection>>,
+}
+
+#[derive(Debug)]
+struct Operation {
+    name: Option<String>,
+    /// Variable names with their defaults.
+    variables: Vec<(String, Option<Value>)>,
+    selections: Vec<Selection>,
+}
+
+#[derive(Debug)]
+enum Selection {
+    Field(Field),
+    Fragment {
+        name: String,
+        directives: Vec<Directive>,
+    },
+    Inline {
+        directives: Vec<Directive>,
+        selections: Vec<Selection>,
+    },
+}
+
+#[derive(Debug)]
+struct Field {
+    alias: Option<String>,
+    name: String,
+    arguments: Vec<(String, Literal)>,
+    directives: Vec<Directive>,
+    selections: Vec<Selection>,
+}
+
+#[derive(Debug)]
+struct Directive {
+    name: String,
+    arguments: Vec<(String, Literal)>,
+}
+
+#[derive(Debug, Clone)]
+enum Literal {
+    Variable(String),
+    Value(Value),
+    List(Vec<Literal>),
+    Object(Vec<(String, Literal)>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Punct(char),
+    Spread,
+    Name(String),
+    Number(String),
+    String(String),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Punct(c) => write!(f, "{}", c),
+            Token::Spread => write!(f, "..."),
+            Token::Name(name) | Token::Number(name) => write!(f, "{}", name),
+            Token::String(text) => write!(f, "{:?}", text),
+        }
+    }
+}
+
+/// The tokens of a GraphQL document, without its whitespace, commas and
+/// comments.
+fn tokens(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() || c == ',' || c == '\u{feff}' => {}
+            '#' => while chars.next_if(|c| *c != '\n' && *c != '\r').is_some() {},
+            '!' | '$' | '&' | '(' | ')' | ':' | '=' | '@' | '[' | ']' | '{' | '|' | '}' => {
+                tokens.push(Token::Punct(c))
+            }
+            '.' => match (chars.next(), chars.next()) {
+                (Some('.'), Some('.')) => tokens.push(Token::Spread),
+                _ => return Err("expected ...".into()),
+            },
+            '"' => tokens.push(Token::String(string(&mut chars)?)),
+            c if c == '-' || c.is_ascii_digit() => {
+                let mut number = c.to_string();
+                while let Some(c) =
+                    chars.next_if(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '+' | '-'))
+                {
+                    number.push(c);
+                }
+                if serde_json::from_str::<serde_json::Number>(&number).is_err() {
+                    return Err(format!("invalid number {}", number));
+                }
+                tokens.push(Token::Number(number));
+            }
+            c if c == '_' || c.is_ascii_alphabetic() => {
+                let mut name = c.to_string();
+                while let Some(c) = chars.next_if(|c| *c == '_' || c.is_ascii_alphanumeric()) {
+                    name.push(c);
+                }
+                tokens.push(Token::Name(name));
+            }
+            c => return Err(format!("unexpected {:?}", c)),
+        }
+    }
+    Ok(tokens)
+}
+
+/// A string after its opening quote, or a block string when two more
+/// quotes follow.
+fn string(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> Result<String, String> {
+    let mut ahead = chars.clone();
+    if ahead.next() == Some('"') && ahead.next() == Some('"') {
+        chars.next();
+        chars.next();
+        return block_string(chars);
+    }
+    let mut text = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(text),
+            Some('\\') => match chars.next() {
+                Some(c @ ('"' | '\\' | '/')) => text.push(c),
+                Some('b') => text.push('\u{8}'),
+                Some('f') => text.push('\u{c}'),
+                Some('n') => text.push('\n'),
+                Some('r') => text.push('\r'),
+                Some('t') => text.push('\t'),
+                Some('u') => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    let c = u32::from_str_radix(&code, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| format!("invalid escape \\u{}", code))?;
+                    text.push(c);
+                }
+                c => return Err(format!("invalid escape \\{}", c.unwrap_or(' '))),
+            },
+            Some('\n' | '\r') | None => return Err("unterminated string".into()),
+            Some(c) => text.push(c),
+        }
+    }
+}
+
+/// A `"""` string after its opening quotes, without the indentation its
+/// lines have in common or its blank first and last lines.
+fn block_string(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> Result<String, String> {
+    let mut raw = String::new();
+    loop {
+        if raw.ends_with("\\\"\"\"") {
+            raw.truncate(raw.len() - 4);
+            raw.push_str("\"\"\"");
+        } else if raw.ends_with("\"\"\"") {
+            raw.truncate(raw.len() - 3);
+            break;
+        }
+        match chars.next() {
+            Some(c) => raw.push(c),
+            None => return Err("unterminated block string".into()),
+        }
+    }
+    let lines: Vec<&str> = raw.lines().collect();
+    let indent = lines
+        .iter()
+        .skip(1)
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    let mut lines: Vec<&str> = lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| match i {
+            0 => line,
+            _ => line.get(indent..).unwrap_or(""),
+        })
+        .collect();
+    while lines.first().is_some_and(|line| line.trim().is_empty()) {
+        lines.remove(0);
+    }
+    while lines.last().is_some_and(|line| line.trim().is_empty()) {
+        lines.pop();
+    }
+    Ok(lines.join("\n"))
+}
+
+struct Parser {
+    tokens: std::iter::Peekable<std::vec::IntoIter<Token>>,
+    depth: usize,
+}
+
+impl Parser {
+    fn document(source: &str) -> Result<Document, String> {
+        let mut parser = Parser {
+            tokens: tokens(source)?.into_iter().peekable(),
+            depth: 0,
+        };
+        let mut document = Document::default();
+        while let Some(token) = parser.tokens.peek() {
+            match token {
+                Token::Punct('{') => document.operations.push(Operation {
+                    name: None,
+                    variables: Vec::new(),
+                    selections: parser.selections()?,
+                }),
+                Token::Name(keyword) if keyword == "query" => {
+                    parser.tokens.next();
+                    document.operations.push(parser.operation()?);
+                }
+                Token::Name(keyword) if keyword == "mutation" || keyword == "subscription" => {
+                    return Err(format!("only queries are supported, not {}", keyword))
+                }
+                Token::Name(keyword) if keyword == "fragment" => {
+                    parser.tokens.next();
+                    let name = parser.name()?;
+                    parser.keyword("on")?;
+                    parser.name()?;
+                    parser.directives()?;
+                    let selections = parser.selections()?;
+                    if document
+                        .fragments
+                        .insert(name.clone(), selections)
+                        .is_some()
+                    {
+                        return Err(format!("fragment {} is defined twice", name));
+                    }
+                }
+                token => return Err(format!("unexpected {}", token)),
+            }
+        }
+        if document.operations.is_empty() {
+            return Err("no operation".into());
+        }
+        Ok(document)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        self.tokens
+            .next()
+            .ok_or_else(|| "unexpected end".to_string())
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.next()? {
+            Token::Punct(c) if c == expected => Ok(()),
+            token => Err(format!("expected {}, found {}", expected, token)),
+        }
+    }
+
+    fn punct(&mut self, expected: char) -> bool {
+        self.tokens.next_if_eq(&Token::Punct(expected)).is_some()
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        match self.next()? {
+            Token::Name(name) => Ok(name),
+            token => Err(format!("expected a name, found {}", token)),
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Result<(), String> {
+        match self.name()? {
+            name if name == keyword => Ok(()),
+            name => Err(format!("expected {}, found {}", keyword, name)),
+        }
+    }
+
+    fn nested(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        match self.depth > MAX_DEPTH {
+            true => Err("document nests too deep".into()),
+            false => Ok(()),
+        }
+    }
+
+    fn operation(&mut self) -> Result<Operation, String> {
+        let name = match self.tokens.peek() {
+            Some(Token::Name(_)) => Some(self.name()?),
+            _ => None,
+        };
+        let mut variables = Vec::new();
+        if self.punct('(') {
+            while !self.punct(')') {
+                self.expect('$')?;
+                let name = self.name()?;
+                self.expect(':')?;
+                self.variable_type()?;
+                let default = match self.punct('=') {
+                    true => Some(literal_value(self.value()?)?),
+                    false => None,
+                };
+                self.directives()?;
+                variables.push((name, default));
+            }
+        }
+        self.directives()?;
+        Ok(Operation {
+            name,
+            variables,
+            selections: self.selections()?,
+        })
+    }
+
+    /// Skips a type such as `[String!]!`, as values are not checked
+    /// against the types of their variables.
+    fn variable_type(&mut self) -> Result<(), String> {
+        match self.punct('[') {
+            true => {
+                self.nested()?;
+                self.variable_type()?;
+                self.expect(']')?;
+                self.depth -= 1;
+            }
+            false => {
+                self.name()?;
+            }
+        }
+        self.punct('!');
+        Ok(())
+    }
+
+    fn selections(&mut self) -> Result<Vec<Selection>, String> {
+        self.expect('{')?;
+        self.nested()?;
+        let mut selections = Vec::new();
+        while !self.punct('}') {
+            selections.push(self.selection()?);
+        }
+        self.depth -= 1;
+        match selections.is_empty() {
+            true => Err("empty selection".into()),
+            false => Ok(selections),
+        }
+    }
+
+    fn selection(&mut self) -> Result<Selection, String> {
+        if self.tokens.next_if_eq(&Token::Spread).is_some() {
+            return match self.tokens.peek() {
+                Some(Token::Name(name)) if name != "on" => Ok(Selection::Fragment {
+                    name: self.name()?,
+                    directives: self.directives()?,
+                }),
+                _ => {
+                    if let Some(Token::Name(_)) = self.tokens.peek() {
+                        self.keyword("on")?;
+                        self.name()?;
+                    }
+                    Ok(Selection::Inline {
+                        directives: self.directives()?,
+                        selections: self.selections()?,
+                    })
+                }
+            };
+        }
+        let mut alias = None;
+        let mut name = self.name()?;
+        if self.punct(':') {
+            alias = Some(name);
+            name = self.name()?;
+        }
+        let arguments = self.arguments()?;
+        let directives = self.directives()?;
+        let selections = match self.tokens.peek() {
+            Some(Token::Punct('{')) => self.selections()?,
+            _ => Vec::new(),
+        };
+        Ok(Selection::Field(Field {
+            alias,
+            name,
+            arguments,
+            directives,
+            selections,
+        }))
+    }
+
+    fn arguments(&mut self) -> Result<Vec<(String, Literal)>, String> {
+        let mut arguments = Vec::new();
+        if self.punct('(') {
+            while !self.punct(')') {
+                let name = self.name()?;
+                self.expect(':')?;
+                arguments.push((name, self.value()?));
+            }
+        }
+        Ok(arguments)
+    }
+
+    fn directives(&mut self) -> Result<Vec<Directive>, String> {
+        let mut directives = Vec::new();
+        while self.punct('@') {
+            directives.push(Directive {
+                name: self.name()?,
+                arguments: self.arguments()?,
+            });
+        }
+        Ok(directives)
+    }
+
+    fn value(&mut self) -> Result<Literal, String> {
+        Ok(match self.next()? {
+            Token::Punct('$') => Literal::Variable(self.name()?),
+            Token::Number(number) => Literal::Value(
+                serde_json::from_str(&number).map_err(|_| format!("invalid number {}", number))?,
+            ),
+            Token::String(text) => Literal::Value(Value::String(text)),
+            Token::Name(name) => Literal::Value(match name.as_str() {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                "null" => Value::Null,
+                // Enum values, which no argument tells from strings.
+                _ => Value::String(name),
+            }),
+            Token::Punct('[') => {
+                self.nested()?;
+                let mut items = Vec::new();
+                while !self.punct(']') {
+                    items.push(self.value()?);
+                }
+                self.depth -= 1;
+                Literal::List(items)
+            }
+            Token::Punct('{') => {
+                self.nested()?;
+                let mut fields = Vec::new();
+                while !self.punct('}') {
+                    let name = self.name()?;
+                    self.expect(':')?;
+                    fields.push((name, self.value()?));
+                }
+                self.depth -= 1;
+                Literal::Object(fields)
+            }
+            token => return Err(format!("expected a value, found {}", token)),
+        })
+    }
+}
+
+/// The value of a variable's default, which may not use variables.
+fn literal_value(literal: Literal) -> Result<Value, String> {
+    Ok(match literal {
+        Literal::Variable(name) => {
+            return Err(format!("the default of a variable can not use ${}", name))
+        }
+        Literal::Value(value) => value,
+        Literal::List(items) => Value::Array(
+            items
+                .into_iter()
+                .map(literal_value)
+                .collect::<Result<_, _>>()?,
+        ),
+        Literal::Object(fields) => Value::Object(
+            fields
+                .into_iter()
+                .map(|(name, value)| Ok((name, literal_value(value)?)))
+                .collect::<Result<_, String>>()?,
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::CONTENT_TYPE, Request},
+    };
+
+    use super::*;
+    use crate::{backend::Backend, config::Config, testing, testing::FixtureSet};
+
+    fn rejected(source: &str) -> String {
+        Parser::document(source).unwrap_err()
+    }
+
+    #[test]
+    fn parses_operations_fragments_and_variables() {
+        let document = Parser::document(
+            r##"
+            # Two operations and a fragment.
+            query Trips($limit: Int = 2, $tags: [String!]! = ["trip"]) @cached {
+              journal(filter: "#trip") { ...Names transactions(limit: $limit) { id } }
+            }
+            query { t: __typename, ... on Query @skip(if: false) { __typename } }
+            fragment Names on Journal { transactions { payee } }
+            "##,
+        )
+        .unwrap();
+        assert_eq!(document.operations.len(), 2);
+        let trips = &document.operations[0];
+        assert_eq!(trips.name.as_deref(), Some("Trips"));
+        assert_eq!(
+            trips.variables,
+            [
+                ("limit".to_string(), Some(json!(2))),
+                ("tags".to_string(), Some(json!(["trip"]))),
+            ]
+        );
+        let Selection::Field(journal) = &trips.selections[0] else {
+            panic!("{:?}", trips.selections)
+        };
+        assert_eq!(journal.name, "journal");
+        assert!(matches!(
+            &journal.arguments[..],
+            [(name, Literal::Value(Value::String(filter)))] if name == "filter" && filter == "#trip"
+        ));
+        assert!(matches!(
+            &journal.selections[0],
+            Selection::Fragment { name, .. } if name == "Names"
+        ));
+        let Selection::Field(field) = &document.operations[1].selections[0] else {
+            panic!()
+        };
+        assert_eq!(
+            (field.alias.as_deref(), field.name.as_str()),
+            (Some("t"), "__typename")
+        );
+        assert!(matches!(
+            &document.operations[1].selections[1],
+            Selection::Inline { directives, .. } if directives[0].name == "skip"
+        ));
+        assert!(document.fragments.contains_key("Names"));
+    }
+
+    #[test]
+    fn reads_strings_and_block_strings() {
+        let document = Parser::document(
+            "{ a(x: \"tab\\tquote\\\" \\u00e9\", y: \"\"\"\n    first\n      second\n    \"\"\") }",
+        )
+        .unwrap();
+        let Selection::Field(field) = &document.operations[0].selections[0] else {
+            panic!()
+        };
+        let values: Vec<&Value> = field
+            .arguments
+            .iter()
+            .map(|(_, literal)| match literal {
+                Literal::Value(value) => value,
+                literal => panic!("{:?}", literal),
+            })
+            .collect();
+        assert_eq!(
+            values,
+            [&json!("tab\tquote\" é"), &json!("first\n  second")]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_documents() {
+        assert_eq!(rejected(""), "no operation");
+        assert_eq!(rejected("{ journal "), "unexpected end");
+        assert_eq!(rejected("{ }"), "empty selection");
+        assert_eq!(rejected("{ a(x 1) }"), "expected :, found 1");
+        assert_eq!(rejected("{ a(x: \"open) }"), "unterminated string");
+        assert_eq!(rejected("{ a(x: \"\\q\") }"), "invalid escape \\q");
+        assert_eq!(rejected("{ a(x: 1.2.3) }"), "invalid number 1.2.3");
+        assert_eq!(rejected("{ a .. }"), "expected ...");
+        assert_eq!(rejected("{ a } }"), "unexpected }");
+        assert_eq!(
+            rejected("mutation { a }"),
+            "only queries are supported, not mutation"
+        );
+        assert_eq!(
+            rejected("query ($a: Int = $b) { a }"),
+            "the default of a variable can not use $b"
+        );
+        assert_eq!(
+            rejected("{ a } fragment F on Q { a } fragment F on Q { b }"),
+            "fragment F is defined twice"
+        );
+    }
+
+    #[test]
+    fn rejects_documents_that_nest_too_deep() {
+        let depth = |depth: usize| format!("{}a{}", "{ a ".repeat(depth), " }".repeat(depth));
+        assert!(Parser::document(&depth(MAX_DEPTH)).is_ok());
+        assert_eq!(
+            Parser::document(&depth(MAX_DEPTH + 1)).unwrap_err(),
+            "document nests too deep"
+        );
+        let list = format!(
+            "{{ a(x: {}1{}) }}",
+            "[".repeat(MAX_DEPTH),
+            "]".repeat(MAX_DEPTH)
+        );
+        assert_eq!(rejected(&list), "document nests too deep");
+        let object = format!(
+            "{{ a(x: {}) }}",
+            "{y: ".repeat(MAX_DEPTH) + "1" + &"}".repeat(MAX_DEPTH)
+        );
+        assert_eq!(rejected(&object), "document nests too deep");
+    }
+
+    fn kept(conditions: Value) -> Vec<Value> {
+        let items = json!([
+            {"id": "a", "payee": "Shop", "tags": ["trip"], "amount": "12.00 CNY",
+             "postings": [{"account": "Expenses:Food"}, {"account": "Assets:Bank"}]},
+            {"id": "b", "payee": "", "tags": [], "amount": "-3 CNY",
+             "postings": [{"account": "Liabilities:CC"}]},
+            {"id": "c", "payee": "Shop & Co", "amount": "100 CNY",
+             "postings": [{"account": "Expenses:Home"}]},
+        ]);
+        let mut arguments = Map::new();
+        arguments.insert("where".into(), conditions);
+        match filtered(items, &arguments).unwrap() {
+            Value::Array(items) => items.into_iter().map(|item| item["id"].clone()).collect(),
+            value => panic!("{}", value),
+        }
+    }
+
+    #[test]
+    fn keeps_the_items_a_where_holds_for() {
+        assert_eq!(kept(json!({"payee": "Shop"})), [json!("a")]);
+        assert_eq!(
+            kept(json!({"payee": {"ne": "Shop"}})),
+            [json!("b"), json!("c")]
+        );
+        assert_eq!(
+            kept(json!({"id": {"in": ["a", "c", "z"]}})),
+            [json!("a"), json!("c")]
+        );
+        assert_eq!(kept(json!({"payee": {"contains": "Co"}})), [json!("c")]);
+        assert_eq!(kept(json!({"payee": {"is_null": true}})), [json!("b")]);
+        assert_eq!(kept(json!({"tags": {"is_null": true}})), [json!("c")]);
+        assert_eq!(kept(json!({"tags": "trip"})), [json!("a")]);
+        // Amounts compare by their number, not as text.
+        assert_eq!(
+            kept(json!({"amount": {"gt": 9, "lte": "12"}})),
+            [json!("a")]
+        );
+        assert_eq!(kept(json!({"amount": {"lt": 0}})), [json!("b")]);
+        assert_eq!(
+            kept(json!({"postings": {"account": {"matches": "^Expenses:"}}})),
+            [json!("a"), json!("c")]
+        );
+        assert_eq!(
+            kept(json!({"or": [{"id": "b"}, {"payee": "Shop"}]})),
+            [json!("a"), json!("b")]
+        );
+        assert_eq!(
+            kept(json!({"not": {"postings": {"account": {"matches": "^Expenses"}}}})),
+            [json!("b")]
+        );
+        let mut arguments = Map::new();
+        arguments.insert("where".into(), json!({"id": {"in": "a"}}));
+        assert_eq!(
+            filtered(json!([{"id": "a"}]), &arguments).unwrap_err(),
+            "in needs a list"
+        );
+        arguments.insert("where".into(), json!({"id": {"is_null": 1}}));
+        assert_eq!(
+            filtered(json!([{"id": "a"}]), &arguments).unwrap_err(),
+            "is_null needs a boolean"
+        );
+    }
+
+    async fn fixture_state() -> AppState {
+        let set = &FixtureSet::all()[0];
+        AppState::new(Config::new(set.fava().await).backend(Backend::Html))
+    }
+
+    async fn post(state: &AppState, request: Value) -> (StatusCode, Value) {
+        let request = Request::post("/api/graphql")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(request.to_string()))
+            .unwrap();
+        let (status, _, body) = testing::send(state, request).await;
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn selects_fields_and_pages_lists() {
+        let state = fixture_state().await;
+        let (status, body) = post(
+            &state,
+            json!({"query": "{ journal { transactions(offset: 1, limit: 1) { payee postings { account } } } }"}),
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert_eq!(
+            body,
+            json!({"data": {"journal": {"transactions": [{
+                "payee": "Hotel",
+                "postings": [{"account": "Expenses:Travel"}, {"account": "Liabilities:CC"}],
+            }]}}})
+        );
+
+        let query = "{ journal { transactions(where: {postings: {account: {matches: \"^Liabilities\"}}}) { id date } } }";
+        let (_, body) = post(&state, json!({ "query": query })).await;
+        assert_eq!(
+            body["data"]["journal"]["transactions"],
+            json!([{"id": "b2", "date": "2024-01-03"}])
+        );
+    }
+
+    #[tokio::test]
+    async fn runs_the_named_operation_with_its_variables() {
+        let state = fixture_state().await;
+        let query = "query First($n: Int = 1) { journal { transactions(limit: $n) { id } } } \
+                     query Kind { __typename }";
+        let ids = |body: &Value| body["data"]["journal"]["transactions"].clone();
+        let (_, body) = post(&state, json!({"query": query, "operationName": "First"})).await;
+        assert_eq!(ids(&body), json!([{"id": "a1"}]));
+        let (_, body) = post(
+            &state,
+            json!({"query": query, "operationName": "First", "variables": {"n": 5}}),
+        )
+        .await;
+        assert_eq!(ids(&body), json!([{"id": "a1"}, {"id": "b2"}]));
+        let (_, body) = post(&state, json!({"query": query, "operationName": "Kind"})).await;
+        assert_eq!(body, json!({"data": {"__typename": "Query"}}));
+
+        let (status, body) = post(&state, json!({ "query": query })).await;
+        assert_eq!(status, 400);
+        assert_eq!(
+            body["errors"][0]["message"],
+            "operationName is needed for a document of several operations"
+        );
+        let (status, body) = post(&state, json!({"query": query, "operationName": "Other"})).await;
+        assert_eq!(
+            (status.as_u16(), body["errors"][0]["message"].clone()),
+            (400, json!("no such operation"))
+        );
+        let (status, body) = post(&state, json!({"query": "{ journal "})).await;
+        assert_eq!(status, 400);
+        assert_eq!(body["errors"][0]["message"], "syntax error: unexpected end");
+    }
+
+    #[tokio::test]
+    async fn answers_the_fields_that_resolve_next_to_the_errors() {
+        let state = fixture_state().await;
+        let (status, body) = post(
+            &state,
+            json!({"query": "{ journal { transactions(limit: 1) { id } } ledger }"}),
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert_eq!(
+            body,
+            json!({
+                "data": {"journal": {"transactions": [{"id": "a1"}]}, "ledger": null},
+                "errors": [{
+                    "message": "unknown field ledger on Query",
+                    "path": ["ledger"],
+                    "extensions": {"code": "bad_request"},
+                }],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn fetches_only_enabled_groups() {
+        let set = &FixtureSet::all()[0];
+        let mut config = Config::new(set.fava().await).backend(Backend::Html);
+        config.endpoints.remove(&EndpointGroup::Account);
+        let state = AppState::new(config);
+        let (status, body) = post(
+            &state,
+            json!({"query": "{ accounts { roots { name } } journal { transactions(limit: 1) { id } } }"}),
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert_eq!(body["data"]["accounts"], Value::Null);
+        assert_eq!(
+            body["data"]["journal"]["transactions"],
+            json!([{"id": "a1"}])
+        );
+        assert_eq!(body["errors"][0]["message"], "accounts is not enabled");
+        assert_eq!(body["errors"][0]["path"], json!(["accounts"]));
+    }
+
+    #[tokio::test]
+    async fn limits_fetches_and_fragment_depth() {
+        let state = fixture_state().await;
+        let fields: String = (0..=MAX_FETCHES)
+            .map(|i| format!("j{}: journal {{ transactions(limit: 0) {{ id }} }} ", i))
+            .collect();
+        let (_, body) = post(&state, json!({ "query": format!("{{ {} }}", fields) })).await;
+        let errors = body["errors"].as_array().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0]["message"],
+            format!("more than {} fields fetched in one request", MAX_FETCHES)
+        );
+        assert_eq!(errors[0]["path"], json!([format!("j{}", MAX_FETCHES)]));
+        assert_eq!(body["data"]["j0"], json!({"transactions": []}));
+
+        // Fragments that spread each other stop at the depth limit.
+        let query = "{ ...A } fragment A on Query { ...B } fragment B on Query { ...A }";
+        let (status, body) = post(&state, json!({ "query": query })).await;
+        assert_eq!(status, 200);
+        assert_eq!(body["errors"][0]["message"], "selections nest too deep");
+    }
+}
@@ -0,0 +1,142 @@
+use axum::{
+    extract::State,
+    http::Request,
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Router,
+};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::AppState;
+
+/// Groups of endpoints that a deployment can switch off as a whole.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EndpointGroup {
+    Query,
+    Account,
+    Aggregate,
+    Admin,
+    Write,
+    Passthrough,
+    Streaming,
+}
+
+impl EndpointGroup {
+    pub const ALL: [EndpointGroup; 7] = [
+        EndpointGroup::Query,
+        EndpointGroup::Account,
+        EndpointGroup::Aggregate,
+        EndpointGroup::Admin,
+        EndpointGroup::Write,
+        EndpointGroup::Passthrough,
+        EndpointGroup::Streaming,
+    ];
+}
+
+/// Mounts a group's routes behind a check that answers 404, exactly like an
+/// unknown route, while the group is disabled in the config.
+pub fn group(state: &AppState, group: EndpointGroup, routes: Router<AppState>) -> Router<AppState> {
+    routes.route_layer(middleware::from_fn_with_state(
+        (state.clone(), group),
+        enabled_only,
+    ))
+}
+
+async fn enabled_only<B>(
+    State((state, group)): State<(AppState, EndpointGroup)>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if state.config.endpoints.contains(&group) {
+        next.run(request).await
+    } else {
+        StatusCode::NOT_FOUND.into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Method};
+
+    use super::*;
+    use crate::{config::Config, testing};
+
+    /// A state without `disabled`, for a fava that can not be reached.
+    fn without(disabled: EndpointGroup) -> AppState {
+        let mut config = Config::new("http://127.0.0.1:9");
+        config.endpoints.remove(&disabled);
+        AppState::new(config)
+    }
+
+    async fn status(state: &AppState, method: Method, uri: &str) -> (StatusCode, bool) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from("{}"))
+            .unwrap();
+        let (status, _, body) = testing::send(state, request).await;
+        (status, body.is_empty())
+    }
+
+    #[tokio::test]
+    async fn write_disables_only_the_saved_query_mutations() {
+        let state = without(EndpointGroup::Write);
+        for (method, uri) in [
+            (Method::POST, "/api/saved_queries"),
+            (Method::PUT, "/api/saved_queries/1"),
+            (Method::DELETE, "/api/saved_queries/1"),
+        ] {
+            assert_eq!(
+                status(&state, method, uri).await,
+                (StatusCode::NOT_FOUND, true)
+            );
+        }
+        let enabled = AppState::new(Config::new("http://127.0.0.1:9"));
+        let (code, _) = status(&enabled, Method::POST, "/api/saved_queries").await;
+        assert_ne!(code, StatusCode::NOT_FOUND);
+        for uri in ["/api/saved_queries", "/api/saved_queries/1"] {
+            assert_ne!(
+                status(&state, Method::GET, uri).await,
+                (StatusCode::NOT_FOUND, true)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn passthrough_disables_document_downloads() {
+        let state = without(EndpointGroup::Passthrough);
+        let download = "/api/documents/download?filename=a.pdf";
+        assert_eq!(
+            status(&state, Method::GET, download).await,
+            (StatusCode::NOT_FOUND, true)
+        );
+        assert_ne!(
+            status(&state, Method::GET, "/api/documents").await,
+            (StatusCode::NOT_FOUND, true)
+        );
+        let enabled = AppState::new(Config::new("http://127.0.0.1:9"));
+        assert_ne!(
+            status(&enabled, Method::GET, download).await,
+            (StatusCode::NOT_FOUND, true)
+        );
+    }
+
+    #[tokio::test]
+    async fn passthrough_disables_slugs() {
+        let mut config = Config::new("http://127.0.0.1:9").slugs(true);
+        config.endpoints.remove(&EndpointGroup::Passthrough);
+        let state = AppState::new(config);
+        assert_eq!(
+            status(&state, Method::GET, "/slugs/company/api/version").await,
+            (StatusCode::NOT_FOUND, true)
+        );
+        let enabled = AppState::new(Config::new("http://127.0.0.1:9").slugs(true));
+        assert_eq!(
+            status(&enabled, Method::GET, "/slugs/company/api/version").await,
+            (StatusCode::OK, false)
+        );
+    }
+}
@@ -0,0 +1,607 @@
+use axum::{
+    body::{boxed, Body, Bytes},
+    extract::State,
+    http::{HeaderMap, HeaderValue},
+    response::{IntoResponse, Response},
+};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use reqwest::{header::CONTENT_TYPE, StatusCode};
+use serde_json::{Map, Value};
+
+use crate::{
+    amount, arrow, balance_sheet,
+    i18n::Lang,
+    params::{self, StrictQuery},
+    protobuf::{self, Field, Writer},
+    query_rows, AccountParams, AppState, ErrorResult, Params,
+};
+
+/// Bytes of `grpc-message` that are percent-encoded, besides those outside
+/// ASCII.
+const MESSAGE: &AsciiSet = &CONTROLS.add(b'%');
+
+/// The gRPC status codes the service answers with.
+#[derive(Debug, Clone, Copy)]
+enum Code {
+    Ok = 0,
+    Unknown = 2,
+    InvalidArgument = 3,
+    DeadlineExceeded = 4,
+    NotFound = 5,
+    Unimplemented = 12,
+    Unavailable = 14,
+}
+
+#[derive(Debug)]
+struct Status {
+    code: Code,
+    message: String,
+}
+
+impl Status {
+    fn new(code: Code, message: impl Into<String>) -> Status {
+        Status {
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("grpc-status", HeaderValue::from(self.code as u32));
+        if !self.message.is_empty() {
+            let message = utf8_percent_encode(&self.message, MESSAGE).to_string();
+            if let Ok(message) = HeaderValue::from_str(&message) {
+                headers.insert("grpc-message", message);
+            }
+        }
+        headers
+    }
+}
+
+impl From<ErrorResult> for Status {
+    fn from(e: ErrorResult) -> Status {
+        let code = match e.status {
+            StatusCode::BAD_REQUEST => Code::InvalidArgument,
+            StatusCode::NOT_FOUND => Code::NotFound,
+            StatusCode::GATEWAY_TIMEOUT => Code::DeadlineExceeded,
+            StatusCode::SERVICE_UNAVAILABLE | StatusCode::BAD_GATEWAY => Code::Unavailable,
+            _ => Code::Unknown,
+        };
+        Status::new(code, e.error)
+    }
+}
+
+/// A response of only trailers, for a call that failed before it sent
+/// any message.
+impl IntoResponse for Status {
+    fn into_response(self) -> Response {
+        let mut headers = self.headers();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+        (headers, ()).into_response()
+    }
+}
+
+/// `QueryResult` of `proto/fava_query.proto`: a `Header` with the columns
+/// and warnings of a query, then a `Row` per row with its cells in the
+/// order of the columns.
+pub async fn query_result(
+    State(state): State<AppState>,
+    lang: Lang,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    reply(query(&state, lang, &headers, &body).await)
+}
+
+async fn query(
+    state: &AppState,
+    lang: Lang,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<Vec<Writer>, Status> {
+    let mut params = Params::default();
+    for (number, field) in decode(headers, body)? {
+        match (number, field) {
+            (1, Field::Bytes(text)) => params.query_string = string(text)?,
+            (2, Field::Bytes(text)) => params.account = present(text)?,
+            (3, Field::Bytes(text)) => params.filter = present(text)?,
+            (4, Field::Bytes(text)) => params.time = present(text)?,
+            (5, Field::Varint(offset)) => params.offset = Some(offset as usize),
+            (6, Field::Varint(limit)) => params.limit = Some(limit as usize),
+            (7, Field::Varint(refresh)) => params.refresh = Some(refresh != 0),
+            _ => {}
+        }
+    }
+    let result = query_rows(state, &params)
+        .await
+        .map_err(|e| e.localize(lang))?
+        .localize(lang);
+
+    let columns = result.ordered_columns();
+    let mut header = Writer::default();
+    for column in &columns {
+        header.string(1, column);
+    }
+    for warning in &result.warnings {
+        header.string(2, &warning.to_string());
+    }
+    let mut messages = Vec::with_capacity(result.data.len() + 1);
+    let mut message = Writer::default();
+    message.message(1, header);
+    messages.push(message);
+    for row in &result.data {
+        let mut cells = Writer::default();
+        for column in &columns {
+            cells.message(1, cell(row.get(*column).map_or("", String::as_str)));
+        }
+        let mut message = Writer::default();
+        message.message(2, cells);
+        messages.push(message);
+    }
+    Ok(messages)
+}
+
+/// `AccountSeries`: a `SeriesPoint` per day of an account's journal, like
+/// `/api/account/:account` gives.
+pub async fn account_series(
+    State(state): State<AppState>,
+    lang: Lang,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    reply(series(&state, lang, &headers, &body).await)
+}
+
+async fn series(
+    state: &AppState,
+    lang: Lang,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<Vec<Writer>, Status> {
+    let mut account = String::new();
+    let mut params = AccountParams::default();
+    for (number, field) in decode(headers, body)? {
+        match (number, field) {
+            (1, Field::Bytes(text)) => account = string(text)?,
+            (2, Field::Varint(negate)) => params.negate = Some(negate != 0),
+            (3, Field::Varint(refresh)) => params.refresh = Some(refresh != 0),
+            _ => {}
+        }
+    }
+    if !balance_sheet::is_account(&account) {
+        return Err(Status::new(
+            Code::InvalidArgument,
+            format!("invalid account {}", account),
+        ));
+    }
+    let journal = state
+        .session()
+        .refresh(params.refresh)
+        .account_journal(&account)
+        .await
+        .map_err(|e| e.localize(lang))?;
+    let parsed = crate::get_account_data(&journal.entries, &params);
+    Ok(parsed
+        .rows
+        .iter()
+        .map(|row| {
+            let mut point = Writer::default();
+            if let Some(date) = row.get("date") {
+                point.string(1, date);
+            }
+            for (field, column) in [(2, "changed"), (3, "balance")] {
+                let positions = row
+                    .get(column)
+                    .and_then(|text| amount::parse_positions(text));
+                for (number, currency) in positions.into_iter().flatten() {
+                    point.message(
+                        field,
+                        amount_message(&number.to_string(), currency.as_deref().unwrap_or("")),
+                    );
+                }
+            }
+            point
+        })
+        .collect())
+}
+
+/// `BalanceSheet`: the tree of `/api/balance_sheet`, each node with the
+/// balance of its own postings and the total below it.
+pub async fn balance_sheet(
+    State(state): State<AppState>,
+    lang: Lang,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    reply(sheet(&state, lang, &headers, &body).await)
+}
+
+async fn sheet(
+    state: &AppState,
+    lang: Lang,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<Vec<Writer>, Status> {
+    let mut object = Map::new();
+    for (number, field) in decode(headers, body)? {
+        let name = match number {
+            1 => "time",
+            2 => "filter",
+            3 => "currency",
+            4 => "conversion",
+            _ => continue,
+        };
+        if let Field::Bytes(text) = field {
+            if let Some(text) = present(text)? {
+                object.insert(name.into(), Value::String(text));
+            }
+        }
+    }
+    let params = params::from_object(object, state, lang)?;
+    let result = balance_sheet::balance_sheet(State(state.clone()), StrictQuery(params), lang)
+        .await
+        .map_err(|e| e.localize(lang))?;
+    let result =
+        serde_json::to_value(result).map_err(|e| Status::new(Code::Unknown, e.to_string()))?;
+
+    let mut reply = Writer::default();
+    for root in result["data"]["roots"].as_array().into_iter().flatten() {
+        reply.message(1, node(root));
+    }
+    for warning in result["warnings"].as_array().into_iter().flatten() {
+        reply.string(2, warning.as_str().unwrap_or_default());
+    }
+    Ok(vec![reply])
+}
+
+fn node(node: &Value) -> Writer {
+    let mut message = Writer::default();
+    message.string(1, node["account"].as_str().unwrap_or_default());
+    for (field, column) in [(2, "balance"), (3, "total")] {
+        for (currency, number) in node[column].as_object().into_iter().flatten() {
+            message.message(
+                field,
+                amount_message(number.as_str().unwrap_or_default(), currency),
+            );
+        }
+    }
+    for child in node["children"].as_array().into_iter().flatten() {
+        message.message(4, self::node(child));
+    }
+    message
+}
+
+/// A cell as a `Value`: a date, a number, one amount or an inventory of
+/// several, or else its text. Empty cells set none of them.
+fn cell(text: &str) -> Writer {
+    let text = text.trim();
+    let mut value = Writer::default();
+    if text.is_empty() {
+        return value;
+    }
+    if arrow::is_date(text) {
+        value.string(5, text);
+        return value;
+    }
+    if let Some(number) = arrow::number(text) {
+        value.string(2, &number.to_string());
+        return value;
+    }
+    // Only cells that are nothing but amounts, so that a position with a
+    // cost or a price keeps its text.
+    let positions = amount::parse_inventory(text);
+    if positions.is_empty() || text.split_whitespace().count() != positions.len() * 2 {
+        value.string(1, text);
+        return value;
+    }
+    match &positions[..] {
+        [(number, currency)] => {
+            value.message(3, amount_message(&number.to_string(), currency));
+        }
+        positions => {
+            let mut inventory = Writer::default();
+            for (number, currency) in positions {
+                inventory.message(1, amount_message(&number.to_string(), currency));
+            }
+            value.message(4, inventory);
+        }
+    }
+    value
+}
+
+/// An `Amount`, with its number as decimal text so that it keeps every
+/// digit.
+fn amount_message(number: &str, currency: &str) -> Writer {
+    let mut amount = Writer::default();
+    amount.string(1, number);
+    if !currency.is_empty() {
+        amount.string(2, currency);
+    }
+    amount
+}
+
+/// The one message of a call, read from its length-prefixed frame.
+fn decode<'a>(headers: &HeaderMap, body: &'a [u8]) -> Result<Vec<(u32, Field<'a>)>, Status> {
+    let grpc = headers
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("application/grpc"));
+    if !grpc {
+        return Err(Status::new(
+            Code::InvalidArgument,
+            "content-type must be application/grpc",
+        ));
+    }
+    let (prefix, message) = match body.len() {
+        len if len >= 5 => body.split_at(5),
+        _ => {
+            return Err(Status::new(
+                Code::InvalidArgument,
+                "missing request message",
+            ))
+        }
+    };
+    if prefix[0] != 0 {
+        return Err(Status::new(
+            Code::Unimplemented,
+            "compressed messages are not supported",
+        ));
+    }
+    let len = u32::from_be_bytes([prefix[1], prefix[2], prefix[3], prefix[4]]) as usize;
+    if message.len() != len {
+        return Err(Status::new(
+            Code::InvalidArgument,
+            "expected exactly one request message",
+        ));
+    }
+    protobuf::fields(message).map_err(|e| Status::new(Code::InvalidArgument, e))
+}
+
+fn string(bytes: &[u8]) -> Result<String, Status> {
+    String::from_utf8(bytes.to_vec())
+        .map_err(|_| Status::new(Code::InvalidArgument, "string field is not UTF-8"))
+}
+
+/// A string field, unset when empty as proto3 does not tell them apart.
+fn present(bytes: &[u8]) -> Result<Option<String>, Status> {
+    string(bytes).map(|text| Some(text).filter(|text| !text.is_empty()))
+}
+
+/// Sends the messages in length-prefixed frames, then the trailers with
+/// the status.
+fn reply(result: Result<Vec<Writer>, Status>) -> Response {
+    let messages = match result {
+        Ok(messages) => messages,
+        Err(status) => return status.into_response(),
+    };
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        for message in messages {
+            let message = message.into_bytes();
+            let mut frame = Vec::with_capacity(message.len() + 5);
+            frame.push(0);
+            frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+            frame.extend_from_slice(&message);
+            if sender.send_data(Bytes::from(frame)).await.is_err() {
+                return;
+            }
+        }
+        let _ = sender
+            .send_trailers(Status::new(Code::Ok, "").headers())
+            .await;
+    });
+    ([(CONTENT_TYPE, "application/grpc")], boxed(body)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use hyper::body::HttpBody;
+    use percent_encoding::percent_decode_str;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        backend::Backend,
+        config::Config,
+        groups::EndpointGroup,
+        routes,
+        testing::{self, table},
+    };
+
+    fn call(method: &str) -> Request<Body> {
+        Request::post(format!("/fava_query.v1.FavaQuery/{}", method))
+            .header("content-type", "application/grpc")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    /// A call of `method` with `message` in a frame, compressed if flagged
+    /// so.
+    fn framed(method: &str, message: Writer, compressed: bool) -> Request<Body> {
+        let message = message.into_bytes();
+        let mut frame = vec![u8::from(compressed)];
+        frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&message);
+        Request::post(format!("/fava_query.v1.FavaQuery/{}", method))
+            .header("content-type", "application/grpc")
+            .body(Body::from(frame))
+            .unwrap()
+    }
+
+    /// The messages of the frames of a reply, and its status with the
+    /// decoded message, from the trailers or else the headers.
+    async fn answer(state: &AppState, request: Request<Body>) -> (Vec<Vec<u8>>, String, String) {
+        let response = routes(state.clone()).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/grpc");
+        let headers = response.headers().clone();
+        let mut body = response.into_body();
+        let mut bytes = Vec::new();
+        while let Some(data) = body.data().await {
+            bytes.extend_from_slice(&data.unwrap());
+        }
+        let trailers = body.trailers().await.unwrap().unwrap_or(headers);
+        let mut messages = Vec::new();
+        let mut rest = &bytes[..];
+        while !rest.is_empty() {
+            assert_eq!(rest[0], 0, "replies are not compressed");
+            let len = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]) as usize;
+            messages.push(rest[5..5 + len].to_vec());
+            rest = &rest[5 + len..];
+        }
+        let text = |name| {
+            let value = trailers
+                .get(name)
+                .map_or("", |value| value.to_str().unwrap());
+            percent_decode_str(value).decode_utf8().unwrap().to_string()
+        };
+        (messages, text("grpc-status"), text("grpc-message"))
+    }
+
+    /// The only field `number` of a message, which has to be bytes.
+    fn only(message: &[u8], number: u32) -> &[u8] {
+        let fields = protobuf::fields(message).unwrap();
+        match fields[..] {
+            [(found, Field::Bytes(bytes))] if found == number => bytes,
+            _ => panic!("expected only field {}, got {:?}", number, fields),
+        }
+    }
+
+    fn text(bytes: &[u8]) -> &str {
+        std::str::from_utf8(bytes).unwrap()
+    }
+
+    /// The number and currency of an `Amount`.
+    fn amount(message: &[u8]) -> (String, String) {
+        let fields = protobuf::fields(message).unwrap();
+        let field = |number| {
+            fields.iter().find_map(|(found, field)| match field {
+                Field::Bytes(bytes) if *found == number => Some(text(bytes).to_string()),
+                _ => None,
+            })
+        };
+        (field(1).unwrap(), field(2).unwrap_or_default())
+    }
+
+    /// The amounts of an `Inventory`.
+    fn inventory(message: &[u8]) -> Vec<(String, String)> {
+        protobuf::fields(message)
+            .unwrap()
+            .into_iter()
+            .map(|(number, field)| match (number, field) {
+                (1, Field::Bytes(bytes)) => amount(bytes),
+                _ => panic!("an inventory holds only amounts"),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn answers_queries_with_typed_cells() {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|| async {
+                table(
+                    &["date", "number", "amount", "inventory", "payee", "note"],
+                    &[&[
+                        "2024-01-02",
+                        "1,200.5",
+                        "-300 JPY",
+                        "12.00 CNY, 3 USD",
+                        "Airline",
+                        "",
+                    ]],
+                )
+            }),
+        );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        let state = AppState::new(config);
+        let mut request = Writer::default();
+        request.string(1, "SELECT date");
+        let (messages, status, message) =
+            answer(&state, framed("QueryResult", request, false)).await;
+        assert_eq!((status.as_str(), message.as_str()), ("0", ""));
+        assert_eq!(messages.len(), 2);
+
+        let header = only(&messages[0], 1);
+        let columns: Vec<&str> = protobuf::fields(header)
+            .unwrap()
+            .into_iter()
+            .map(|(number, field)| match (number, field) {
+                (1, Field::Bytes(column)) => text(column),
+                _ => panic!("a header without warnings holds only columns"),
+            })
+            .collect();
+        assert_eq!(
+            columns,
+            ["date", "number", "amount", "inventory", "payee", "note"]
+        );
+
+        let cells: Vec<&[u8]> = protobuf::fields(only(&messages[1], 2))
+            .unwrap()
+            .into_iter()
+            .map(|(number, field)| match (number, field) {
+                (1, Field::Bytes(cell)) => cell,
+                _ => panic!("a row holds only cells"),
+            })
+            .collect();
+        assert_eq!(text(only(cells[0], 5)), "2024-01-02");
+        assert_eq!(text(only(cells[1], 2)), "1200.5");
+        assert_eq!(amount(only(cells[2], 3)), ("-300".into(), "JPY".into()));
+        assert_eq!(
+            inventory(only(cells[3], 4)),
+            [("12.00".into(), "CNY".into()), ("3".into(), "USD".into())]
+        );
+        assert_eq!(text(only(cells[4], 1)), "Airline");
+        assert!(cells[5].is_empty());
+    }
+
+    #[tokio::test]
+    async fn answers_failures_in_the_status() {
+        let state = AppState::new(Config::new("http://127.0.0.1:9").refresh_path("none"));
+        let mut request = Writer::default();
+        request.string(1, "SELECT date");
+        let (_, status, message) = answer(&state, framed("QueryResult", request, true)).await;
+        assert_eq!(
+            (status.as_str(), message.as_str()),
+            ("12", "compressed messages are not supported")
+        );
+
+        let mut request = Writer::default();
+        request.string(1, "Assets:银行 卡");
+        let (messages, status, message) =
+            answer(&state, framed("AccountSeries", request, false)).await;
+        assert!(messages.is_empty());
+        assert_eq!(
+            (status.as_str(), message.as_str()),
+            ("3", "invalid account Assets:银行 卡")
+        );
+
+        let mut request = Writer::default();
+        request.string(1, "SELECT date");
+        let (_, status, _) = answer(&state, framed("QueryResult", request, false)).await;
+        assert_eq!(status, "14");
+        let (_, status, message) = answer(&state, call("QueryResult")).await;
+        assert_eq!(
+            (status.as_str(), message.as_str()),
+            ("3", "missing request message")
+        );
+    }
+
+    #[tokio::test]
+    async fn methods_follow_their_endpoint_groups() {
+        let mut config = Config::new("http://127.0.0.1:9");
+        config.endpoints.remove(&EndpointGroup::Query);
+        let state = AppState::new(config);
+        let (status, _, _) = testing::send(&state, call("QueryResult")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        for method in ["AccountSeries", "BalanceSheet"] {
+            let (status, headers, _) = testing::send(&state, call(method)).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(headers["grpc-status"], "3");
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
+use reqwest::StatusCode;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    amount::{self, Amount},
+    balance_sheet::{described, fava_filters},
+    empty_string_as_none,
+    i18n::{Lang, Message},
+    params::{QueryFields, StrictQuery},
+    AppState, ErrorResult, GeneratedQuery, Meta,
+};
+
+const HOLDINGS_QUERY: &str = "SELECT account, currency, cost_currency, \
+    units(sum(position)) AS units, cost(sum(position)) AS cost, \
+    value(sum(position)) AS market_value WHERE account ~ '^Assets(:|$)' \
+    GROUP BY account, currency, cost_currency ORDER BY account, currency";
+
+/// The commodities held in the `Assets` accounts, per account, commodity
+/// and cost currency, like fava's holdings report.
+///
+/// Numbers and currencies come as fields of their own, numbers as decimal
+/// strings like everywhere else. Holdings without a price are worth their
+/// cost, and `unrealized` is only set when the market value is in the cost
+/// currency.
+pub async fn holdings(
+    State(state): State<AppState>,
+    StrictQuery(params): StrictQuery<HoldingsParams>,
+    lang: Lang,
+) -> Result<HoldingsResult, ErrorResult> {
+    let filters = fava_filters(params.time.as_deref(), params.filter.as_deref());
+    let parsed = state
+        .session()
+        .filtered(&filters)
+        .rows(HOLDINGS_QUERY)
+        .await
+        .map_err(|e| e.localize(lang))?;
+    let holdings = parsed
+        .rows
+        .iter()
+        .filter_map(|row| {
+            let units = Amount::parse(row.get("units")?)?;
+            if units.number.is_zero() {
+                return None;
+            }
+            let cost = row.get("cost").and_then(|cost| Amount::parse(cost));
+            let market = row
+                .get("market_value")
+                .and_then(|value| Amount::parse(value));
+            let cost = cost.filter(|cost| cost.currency != units.currency);
+            let unrealized = match (&cost, &market) {
+                (Some(cost), Some(market)) if cost.currency == market.currency => {
+                    Some(market.number - cost.number)
+                }
+                _ => None,
+            };
+            Some(Holding {
+                account: row.get("account")?.clone(),
+                average_cost: cost
+                    .as_ref()
+                    .map(|cost| format(cost.number / units.number, cost.number.scale())),
+                cost: cost.as_ref().map(|cost| amount::format_number(cost.number)),
+                cost_currency: cost.map(|cost| cost.currency),
+                market_value: market
+                    .as_ref()
+                    .map(|market| amount::format_number(market.number)),
+                market_currency: market.map(|market| market.currency),
+                unrealized: unrealized.map(amount::format_number),
+                units: amount::format_number(units.number),
+                commodity: units.currency,
+            })
+        })
+        .collect();
+
+    let generated = GeneratedQuery {
+        query: HOLDINGS_QUERY.into(),
+        filters: described(&filters),
+        feeds: "data.holdings".into(),
+    };
+    Ok(HoldingsResult {
+        success: true,
+        data: Holdings { holdings },
+        warnings: parsed
+            .warnings
+            .into_iter()
+            .map(|warning| warning.localize(lang))
+            .collect(),
+        meta: Meta::explain(params.explain, vec![generated]),
+    })
+}
+
+/// A quotient rounded to a few more places than the cost.
+fn format(number: Decimal, scale: u32) -> String {
+    amount::format_number(number.round_dp(scale + 2).normalize())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HoldingsParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    time: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    filter: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    explain: Option<bool>,
+}
+
+impl QueryFields for HoldingsParams {
+    const FIELDS: &[&str] = &["time", "filter", "explain"];
+}
+
+#[derive(Debug, Serialize)]
+struct Holding {
+    account: String,
+    commodity: String,
+    units: String,
+    /// Unset for holdings without cost, such as cash.
+    cost: Option<String>,
+    cost_currency: Option<String>,
+    /// The cost of one unit.
+    average_cost: Option<String>,
+    market_value: Option<String>,
+    market_currency: Option<String>,
+    /// Market value minus cost.
+    unrealized: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Holdings {
+    holdings: Vec<Holding>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HoldingsResult {
+    success: bool,
+    data: Holdings,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<Meta>,
+}
+
+impl IntoResponse for HoldingsResult {
+    fn into_response(self) -> Response {
+        let body = Json(self);
+        (StatusCode::OK, body).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::get, Router};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{backend::Backend, config::Config, testing, testing::table};
+
+    #[tokio::test]
+    async fn values_holdings_at_cost_and_market() {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|| async {
+                table(
+                    &[
+                        "account",
+                        "currency",
+                        "cost_currency",
+                        "units",
+                        "cost",
+                        "market_value",
+                    ],
+                    &[
+                        &[
+                            "Assets:Bank",
+                            "CNY",
+                            "",
+                            "1,200.00 CNY",
+                            "1,200.00 CNY",
+                            "1,200.00 CNY",
+                        ],
+                        &[
+                            "Assets:Broker",
+                            "AAPL",
+                            "USD",
+                            "10 AAPL",
+                            "1,500.00 USD",
+                            "1,800.00 USD",
+                        ],
+                        &["Assets:Broker", "VT", "EUR", "3 VT", "100 EUR", "120 USD"],
+                        &["Assets:Old", "VT", "EUR", "0 VT", "0 EUR", "0 EUR"],
+                    ],
+                )
+            }),
+        );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        let state = AppState::new(config);
+        let (status, body) = testing::get(&state, "/api/holdings?explain=true").await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["data"]["holdings"],
+            json!([
+                {
+                    "account": "Assets:Bank",
+                    "commodity": "CNY",
+                    "units": "1200.00",
+                    "cost": null,
+                    "cost_currency": null,
+                    "average_cost": null,
+                    "market_value": "1200.00",
+                    "market_currency": "CNY",
+                    "unrealized": null,
+                },
+                {
+                    "account": "Assets:Broker",
+                    "commodity": "AAPL",
+                    "units": "10",
+                    "cost": "1500.00",
+                    "cost_currency": "USD",
+                    "average_cost": "150",
+                    "market_value": "1800.00",
+                    "market_currency": "USD",
+                    "unrealized": "300.00",
+                },
+                {
+                    "account": "Assets:Broker",
+                    "commodity": "VT",
+                    "units": "3",
+                    "cost": "100",
+                    "cost_currency": "EUR",
+                    "average_cost": "33.33",
+                    "market_value": "120",
+                    "market_currency": "USD",
+                    "unrealized": null,
+                },
+            ])
+        );
+        assert_eq!(
+            body["meta"]["generated_queries"][0]["query"],
+            HOLDINGS_QUERY
+        );
+
+        let (status, _) = testing::get(&state, "/api/holdings?currency=USD").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
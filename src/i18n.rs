@@ -0,0 +1,237 @@
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header::ACCEPT_LANGUAGE, request::Parts},
+};
+use serde::{Serialize, Serializer};
+use std::{convert::Infallible, fmt};
+
+/// Message catalogs, keyed by language. Templates refer to their arguments
+/// as `{0}`, `{1}` and so on; keys missing from a catalog fall back to
+/// English.
+const CATALOGS: &[(&str, &[(&str, &str)])] = &[("en", EN), ("zh", ZH)];
+
+const EN: &[(&str, &str)] = &[
+    (
+        "column_without_header",
+        "column {0} has no header, named it {1}",
+    ),
+    (
+        "extra_cells",
+        "row {0} has {1} cells but the table has {2} columns, extra cells were dropped",
+    ),
+    (
+        "transaction_without_date",
+        "transaction {0} has no date, skipped it",
+    ),
+    (
+        "unreadable_change",
+        "transaction {0} has an unreadable change '{1}', used 0",
+    ),
+    (
+        "unreadable_balance",
+        "transaction {0} has an unreadable balance '{1}', used 0",
+    ),
+    (
+        "unreadable_change_skipped",
+        "transaction {0} has an unreadable change '{1}', skipped it",
+    ),
+    ("something_went_wrong", "Something went wrong"),
+    ("unsupported_format", "unsupported format: {0}"),
+    ("unknown_transform", "unknown transform: {0}"),
+    ("unknown_locale", "unknown output locale: {0}"),
+    ("invalid_tag", "invalid tag: {0}"),
+    ("budget_exceeded", "query did not finish within {0} ms"),
+    (
+        "stale_fallback",
+        "fava could not be reached, showing a result from {0} seconds ago",
+    ),
+    (
+        "unknown_parameter",
+        "unknown parameter {0}, did you mean {1}?",
+    ),
+    (
+        "unexpected_parameter",
+        "unknown parameter {0}, expected one of {1}",
+    ),
+    (
+        "unconverted_amount",
+        "{0} of {1} in {2} could not be converted to {3}, left it out",
+    ),
+    (
+        "unpriced_commodity",
+        "{0} has no price in {1} before {2}, left it out",
+    ),
+    ("fragment_rows", "{0} rows"),
+    ("fragment_one_row", "1 row"),
+    ("fragment_no_rows", "No rows"),
+    ("fragment_warnings", "Warnings"),
+];
+
+const ZH: &[(&str, &str)] = &[
+    ("column_without_header", "第 {0} 列没有表头，已命名为 {1}"),
+    (
+        "extra_cells",
+        "第 {0} 行有 {1} 个单元格，但表格只有 {2} 列，多余的单元格已丢弃",
+    ),
+    ("transaction_without_date", "第 {0} 笔交易没有日期，已跳过"),
+    (
+        "unreadable_change",
+        "第 {0} 笔交易的变动“{1}”无法识别，已按 0 处理",
+    ),
+    (
+        "unreadable_balance",
+        "第 {0} 笔交易的余额“{1}”无法识别，已按 0 处理",
+    ),
+    (
+        "unreadable_change_skipped",
+        "第 {0} 笔交易的变动“{1}”无法识别，已跳过",
+    ),
+    ("something_went_wrong", "出错了"),
+    ("unsupported_format", "不支持的格式：{0}"),
+    ("unknown_transform", "未知的转换：{0}"),
+    ("unknown_locale", "未知的输出区域设置：{0}"),
+    ("invalid_tag", "无效的标签：{0}"),
+    ("budget_exceeded", "查询未能在 {0} 毫秒内完成"),
+    ("stale_fallback", "无法连接 fava，显示的是 {0} 秒前的结果"),
+    ("unknown_parameter", "未知参数 {0}，是否想用 {1}？"),
+    ("unexpected_parameter", "未知参数 {0}，可用的参数有 {1}"),
+    (
+        "unconverted_amount",
+        "{2} {1} 的 {0} 无法换算为 {3}，已略去",
+    ),
+    (
+        "unpriced_commodity",
+        "{0} 在 {2} 之前没有以 {1} 计的价格，已略去",
+    ),
+    ("fragment_rows", "共 {0} 行"),
+    ("fragment_one_row", "共 1 行"),
+    ("fragment_no_rows", "没有结果"),
+    ("fragment_warnings", "警告"),
+];
+
+/// Language of the human-readable strings in a response, picked by the
+/// `lang` parameter or else the `Accept-Language` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lang(&'static str);
+
+impl Default for Lang {
+    fn default() -> Self {
+        Lang("en")
+    }
+}
+
+impl Lang {
+    /// The tag of the language, such as `zh`.
+    pub fn tag(self) -> &'static str {
+        self.0
+    }
+
+    /// The catalog matching a language tag such as `zh-CN`, if there is one.
+    fn find(tag: &str) -> Option<Lang> {
+        let primary = tag.trim().split(['-', '_']).next()?.to_lowercase();
+        CATALOGS
+            .iter()
+            .find(|(lang, _)| *lang == primary)
+            .map(|(lang, _)| Lang(lang))
+    }
+
+    fn from_accept_language(header: &str) -> Option<Lang> {
+        let mut tags: Vec<(f32, &str)> = header
+            .split(',')
+            .filter_map(|item| {
+                let mut parts = item.split(';');
+                let tag = parts.next()?.trim();
+                let quality = parts
+                    .find_map(|part| part.trim().strip_prefix("q="))
+                    .and_then(|q| q.parse().ok())
+                    .unwrap_or(1.0);
+                Some((quality, tag))
+            })
+            .collect();
+        tags.sort_by(|a, b| b.0.total_cmp(&a.0));
+        tags.into_iter().find_map(|(_, tag)| Lang::find(tag))
+    }
+
+    fn template(self, key: &str) -> Option<&'static str> {
+        let catalog = |lang: &str| {
+            CATALOGS
+                .iter()
+                .find(|(name, _)| *name == lang)
+                .and_then(|(_, catalog)| catalog.iter().find(|(name, _)| *name == key))
+                .map(|(_, template)| *template)
+        };
+        catalog(self.0).or_else(|| catalog("en"))
+    }
+
+    /// Renders the message stored under `key` with `args`.
+    fn text(self, key: &str, args: &[String]) -> String {
+        let mut text = self.template(key).unwrap_or(key).to_string();
+        for (i, arg) in args.iter().enumerate() {
+            text = text.replace(&format!("{{{}}}", i), arg);
+        }
+        text
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Lang {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let param = parts.uri.query().and_then(|query| {
+            query
+                .split('&')
+                .filter_map(|pair| pair.strip_prefix("lang="))
+                .find_map(Lang::find)
+        });
+        let header = || {
+            parts
+                .headers
+                .get(ACCEPT_LANGUAGE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(Lang::from_accept_language)
+        };
+        Ok(param.or_else(header).unwrap_or_default())
+    }
+}
+
+/// A human-readable note kept as a catalog key plus arguments, so that it
+/// can be rendered in the language of the response it ends up in. Serializes
+/// as the rendered text.
+#[derive(Debug, Clone)]
+pub struct Message {
+    key: &'static str,
+    args: Vec<String>,
+    lang: Lang,
+}
+
+impl Message {
+    pub fn new(key: &'static str, args: Vec<String>) -> Message {
+        Message {
+            key,
+            args,
+            lang: Lang::default(),
+        }
+    }
+
+    pub fn localize(self, lang: Lang) -> Message {
+        Message { lang, ..self }
+    }
+
+    pub fn key(&self) -> &'static str {
+        self.key
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.lang.text(self.key, &self.args).fmt(f)
+    }
+}
+
+impl Serialize for Message {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
@@ -0,0 +1,833 @@
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
+use reqwest::StatusCode;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::{
+    amount::{self, Amount, Scales},
+    balance_sheet::{self, AccountNode, Balances},
+    empty_string_as_none,
+    i18n::{Lang, Message},
+    interval::{bound, periods, Interval, Period, MAX_PERIODS},
+    params::{QueryFields, StrictQuery},
+    AppState, ErrorResult, GeneratedQuery, Meta, Row,
+};
+
+/// Income and expenses per month or year, converted into one commodity.
+/// Periods without any activity are included with zeros.
+///
+/// Like fava's income statement, income and expenses are both positive and
+/// `net` is income minus expenses; `negate=true` keeps beancount's signs
+/// instead.
+pub async fn income_expenses(
+    State(state): State<AppState>,
+    StrictQuery(params): StrictQuery<IncomeParams>,
+    lang: Lang,
+) -> Result<IncomeResult, ErrorResult> {
+    let interval = params
+        .interval
+        .as_deref()
+        .map(Interval::parse)
+        .transpose()?
+        .unwrap_or(Interval::Month);
+    let from = bound(params.from.as_deref(), interval, false)?;
+    let to = bound(params.to.as_deref(), interval, true)?;
+    let currency = match params
+        .currency
+        .as_ref()
+        .or(state.config.operating_currency.as_ref())
+    {
+        Some(currency) if amount::is_currency(currency) => currency.clone(),
+        Some(currency) => {
+            return Err(ErrorResult::bad_request(format!(
+                "invalid currency {}",
+                currency
+            )))
+        }
+        None => {
+            return Err(ErrorResult::bad_request(
+                "no currency given and operating_currency is not configured".into(),
+            ))
+        }
+    };
+
+    let query = income_query(interval, from, to, &currency);
+    let parsed = state
+        .session()
+        .rows(&query)
+        .await
+        .map_err(|e| e.localize(lang))?;
+    let mut warnings: Vec<Message> = parsed.warnings;
+    let mut scales = Scales::default();
+    let mut sums: BTreeMap<Period, BTreeMap<String, Decimal>> = BTreeMap::new();
+    for row in &parsed.rows {
+        let period = match Period::from_row(row, interval) {
+            Some(period) => period,
+            None => continue,
+        };
+        let root = row.get("root").cloned().unwrap_or_default();
+        for (number, commodity) in
+            amount::parse_inventory(row.get("amount").map_or("", String::as_str))
+        {
+            if commodity != currency {
+                warnings.push(Message::new(
+                    "unconverted_amount",
+                    vec![
+                        format!("{} {}", amount::format_number(number), commodity),
+                        root.clone(),
+                        period.to_string(),
+                        currency.clone(),
+                    ],
+                ));
+                continue;
+            }
+            scales.observe(Some(&currency), number);
+            *sums
+                .entry(period)
+                .or_default()
+                .entry(root.clone())
+                .or_default() += number;
+        }
+    }
+
+    let from = from.or_else(|| sums.keys().next().copied());
+    let to = to.or_else(|| sums.keys().next_back().copied());
+    let periods = match (from, to) {
+        (Some(from), Some(to)) => periods(from, to),
+        _ => Vec::new(),
+    };
+    if periods.len() > MAX_PERIODS {
+        return Err(ErrorResult::bad_request(format!(
+            "more than {} periods requested",
+            MAX_PERIODS
+        )));
+    }
+    let zero = Decimal::new(0, scales.common(Some(&currency)));
+    let signed = |number: Decimal| match params.negate {
+        Some(true) => amount::format_number(-number),
+        _ => amount::format_number(number),
+    };
+    let series = periods
+        .into_iter()
+        .map(|period| {
+            let roots = sums.remove(&period).unwrap_or_default();
+            let (mut income, mut expenses) = (zero, zero);
+            let mut accounts = BTreeMap::new();
+            for (root, sum) in roots {
+                let value = match root.starts_with("Income") {
+                    true => {
+                        income -= sum;
+                        -sum
+                    }
+                    false => {
+                        expenses += sum;
+                        sum
+                    }
+                };
+                accounts.insert(root, signed(value));
+            }
+            PeriodTotals {
+                period: period.to_string(),
+                income: signed(income),
+                expenses: signed(expenses),
+                net: signed(income - expenses),
+                accounts: (Some(true) == params.breakdown).then_some(accounts),
+            }
+        })
+        .collect();
+
+    let generated = GeneratedQuery {
+        query,
+        filters: Vec::new(),
+        feeds: "data.series".into(),
+    };
+    Ok(IncomeResult {
+        success: true,
+        data: IncomeData { currency, series },
+        warnings: warnings
+            .into_iter()
+            .map(|warning| warning.localize(lang))
+            .collect(),
+        meta: Meta::explain(params.explain, vec![generated]),
+    })
+}
+
+/// Net worth, the market value of assets and liabilities, at the end of
+/// every month or year from the first one with a posting.
+///
+/// Holdings are valued at the last price before the end of the period, of
+/// the commodity in `currency` or the inverse of `currency` in the
+/// commodity.
+pub async fn net_worth(
+    State(state): State<AppState>,
+    StrictQuery(params): StrictQuery<NetWorthParams>,
+    lang: Lang,
+) -> Result<NetWorthResult, ErrorResult> {
+    let interval = params
+        .interval
+        .as_deref()
+        .map(Interval::parse)
+        .transpose()?
+        .unwrap_or(Interval::Month);
+    let from = bound(params.from.as_deref(), interval, false)?;
+    let to = bound(params.to.as_deref(), interval, true)?;
+    let currency = match params
+        .currency
+        .as_ref()
+        .or(state.config.operating_currency.as_ref())
+    {
+        Some(currency) if amount::is_currency(currency) => currency.clone(),
+        Some(currency) => {
+            return Err(ErrorResult::bad_request(format!(
+                "invalid currency {}",
+                currency
+            )))
+        }
+        None => {
+            return Err(ErrorResult::bad_request(
+                "no currency given and operating_currency is not configured".into(),
+            ))
+        }
+    };
+
+    let query = net_worth_query(interval, to);
+    let session = state.session();
+    let parsed = session.rows(&query).await.map_err(|e| e.localize(lang))?;
+    let prices = session
+        .rows(PRICES_QUERY)
+        .await
+        .map_err(|e| e.localize(lang))?;
+    let mut warnings: Vec<Message> = parsed.warnings;
+    warnings.extend(prices.warnings);
+    let prices = PriceTable::new(&prices.rows);
+
+    let mut scales = Scales::default();
+    let mut changes: BTreeMap<Period, BTreeMap<String, Decimal>> = BTreeMap::new();
+    for row in &parsed.rows {
+        let period = match Period::from_row(row, interval) {
+            Some(period) => period,
+            None => continue,
+        };
+        for (number, commodity) in
+            amount::parse_inventory(row.get("balance").map_or("", String::as_str))
+        {
+            if commodity == currency {
+                scales.observe(Some(&currency), number);
+            }
+            *changes
+                .entry(period)
+                .or_default()
+                .entry(commodity)
+                .or_default() += number;
+        }
+    }
+
+    let first = changes.keys().next().copied();
+    let last = changes.keys().next_back().copied();
+    let periods = match (first, to.or(last)) {
+        (Some(first), Some(to)) => periods(first, to),
+        _ => Vec::new(),
+    };
+    if periods.len() > MAX_PERIODS {
+        return Err(ErrorResult::bad_request(format!(
+            "more than {} periods requested",
+            MAX_PERIODS
+        )));
+    }
+    let scale = scales.common(Some(&currency));
+    let mut holdings: BTreeMap<String, Decimal> = BTreeMap::new();
+    let mut unpriced = BTreeSet::new();
+    let mut series = Vec::new();
+    for period in periods {
+        for (commodity, number) in changes.remove(&period).unwrap_or_default() {
+            *holdings.entry(commodity).or_default() += number;
+        }
+        if from.is_some_and(|from| period < from) {
+            continue;
+        }
+        let end = period.next().start();
+        let mut value = Decimal::new(0, scale);
+        for (commodity, number) in &holdings {
+            if number.is_zero() {
+                continue;
+            }
+            match prices.rate(commodity, &currency, &end) {
+                Some(rate) => value += number * rate,
+                None => {
+                    if unpriced.insert(commodity.clone()) {
+                        warnings.push(Message::new(
+                            "unpriced_commodity",
+                            vec![commodity.clone(), currency.clone(), end.clone()],
+                        ));
+                    }
+                }
+            }
+        }
+        series.push(NetWorthPoint {
+            period: period.to_string(),
+            value: amount::format_number(value.round_dp(scale)),
+        });
+    }
+
+    let generated = [
+        GeneratedQuery {
+            query,
+            filters: Vec::new(),
+            feeds: "data.series".into(),
+        },
+        GeneratedQuery {
+            query: PRICES_QUERY.into(),
+            filters: Vec::new(),
+            feeds: "data.series".into(),
+        },
+    ];
+    Ok(NetWorthResult {
+        success: true,
+        data: NetWorth { currency, series },
+        warnings: warnings
+            .into_iter()
+            .map(|warning| warning.localize(lang))
+            .collect(),
+        meta: Meta::explain(params.explain, generated.into()),
+    })
+}
+
+const PRICES_QUERY: &str = "SELECT date, currency, amount FROM #prices ORDER BY date";
+
+/// The `price` directives, per commodity and quote currency in date order.
+struct PriceTable {
+    prices: HashMap<(String, String), Vec<(String, Decimal)>>,
+}
+
+impl PriceTable {
+    fn new(rows: &[Row]) -> PriceTable {
+        let mut prices: HashMap<(String, String), Vec<(String, Decimal)>> = HashMap::new();
+        for row in rows {
+            let (date, commodity, price) = match (
+                row.get("date"),
+                row.get("currency"),
+                row.get("amount").and_then(|price| Amount::parse(price)),
+            ) {
+                (Some(date), Some(commodity), Some(price)) => (date, commodity, price),
+                _ => continue,
+            };
+            prices
+                .entry((commodity.clone(), price.currency))
+                .or_default()
+                .push((date.clone(), price.number));
+        }
+        PriceTable { prices }
+    }
+
+    /// The last price of one `commodity` in `currency` before `end`.
+    fn rate(&self, commodity: &str, currency: &str, end: &str) -> Option<Decimal> {
+        if commodity == currency {
+            return Some(Decimal::ONE);
+        }
+        let last = |from: &str, to: &str| {
+            self.prices
+                .get(&(from.to_string(), to.to_string()))?
+                .iter()
+                .rev()
+                .find(|(date, _)| date.as_str() < end)
+                .map(|(_, price)| *price)
+        };
+        last(commodity, currency).or_else(|| {
+            last(currency, commodity)
+                .filter(|price| !price.is_zero())
+                .map(|price| Decimal::ONE / price)
+        })
+    }
+}
+
+fn net_worth_query(interval: Interval, to: Option<Period>) -> String {
+    let group = interval.columns();
+    let mut filter = "account ~ '^(Assets|Liabilities)(:|$)'".to_string();
+    if let Some(to) = to {
+        filter.push_str(&format!(" AND date < {}", to.next().start()));
+    }
+    format!(
+        "SELECT {group}, units(sum(position)) AS balance WHERE {filter} \
+         GROUP BY {group} ORDER BY {group}",
+    )
+}
+
+/// The accounts below `Income` and `Expenses` as trees, like fava's income
+/// statement, with their totals per month or year given an `interval`.
+///
+/// Unlike `/api/income_expenses` the amounts keep beancount's signs and
+/// are not converted unless `currency` is given. `time` and `filter` are
+/// handed to fava.
+pub async fn income_statement(
+    State(state): State<AppState>,
+    StrictQuery(params): StrictQuery<StatementParams>,
+    lang: Lang,
+) -> Result<StatementResult, ErrorResult> {
+    let interval = params
+        .interval
+        .as_deref()
+        .map(Interval::parse)
+        .transpose()?;
+    let value = balance_sheet::balance(params.currency.as_deref(), params.conversion.as_deref())?;
+    let group = match interval {
+        Some(interval) => format!("account, {}", interval.columns()),
+        None => "account".to_string(),
+    };
+    let query = format!(
+        "SELECT {group}, {value} AS balance WHERE account ~ '^({roots})(:|$)' \
+         GROUP BY {group} ORDER BY {group}",
+        roots = STATEMENT_ROOTS.join("|"),
+    );
+    let filters = balance_sheet::fava_filters(params.time.as_deref(), params.filter.as_deref());
+    let parsed = state
+        .session()
+        .filtered(&filters)
+        .rows(&query)
+        .await
+        .map_err(|e| e.localize(lang))?;
+
+    let mut scales = Scales::default();
+    let mut balances: BTreeMap<String, Balances> = BTreeMap::new();
+    let mut periods = BTreeSet::new();
+    for row in &parsed.rows {
+        let account = match row.get("account") {
+            Some(account) if !account.is_empty() => account,
+            _ => continue,
+        };
+        let text = row.get("balance").map_or("", String::as_str);
+        let balances = balances.entry(account.clone()).or_default();
+        balance_sheet::add(&mut balances.own, text, &mut scales);
+        if let Some(period) = interval.and_then(|interval| Period::from_row(row, interval)) {
+            let period = period.to_string();
+            let inventory = balances.periods.entry(period.clone()).or_default();
+            balance_sheet::add(inventory, text, &mut scales);
+            periods.insert(period);
+        }
+    }
+    let roots = STATEMENT_ROOTS
+        .iter()
+        .map(|root| balance_sheet::node(root, &balances, &scales))
+        .collect();
+
+    let generated = GeneratedQuery {
+        query,
+        filters: balance_sheet::described(&filters),
+        feeds: "data.roots".into(),
+    };
+    Ok(StatementResult {
+        success: true,
+        data: Statement {
+            periods: periods.into_iter().collect(),
+            roots,
+        },
+        warnings: parsed
+            .warnings
+            .into_iter()
+            .map(|warning| warning.localize(lang))
+            .collect(),
+        meta: Meta::explain(params.explain, vec![generated]),
+    })
+}
+
+/// Root accounts of the income statement, in fava's order.
+const STATEMENT_ROOTS: [&str; 2] = ["Income", "Expenses"];
+
+fn income_query(
+    interval: Interval,
+    from: Option<Period>,
+    to: Option<Period>,
+    currency: &str,
+) -> String {
+    let group = interval.columns();
+    let mut filter = "account ~ '^(Income|Expenses)(:|$)'".to_string();
+    if let Some(from) = from {
+        filter.push_str(&format!(" AND date >= {}", from.start()));
+    }
+    if let Some(to) = to {
+        filter.push_str(&format!(" AND date < {}", to.next().start()));
+    }
+    format!(
+        "SELECT {group}, root(account, 2) AS root, convert(sum(position), '{currency}') AS amount \
+         WHERE {filter} GROUP BY {group}, root ORDER BY {group}, root",
+    )
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IncomeParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    interval: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    from: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    to: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    currency: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    breakdown: Option<bool>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    negate: Option<bool>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    explain: Option<bool>,
+}
+
+impl QueryFields for IncomeParams {
+    const FIELDS: &[&str] = &[
+        "interval",
+        "from",
+        "to",
+        "currency",
+        "breakdown",
+        "negate",
+        "explain",
+    ];
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NetWorthParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    interval: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    from: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    to: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    currency: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    explain: Option<bool>,
+}
+
+impl QueryFields for NetWorthParams {
+    const FIELDS: &[&str] = &["interval", "from", "to", "currency", "explain"];
+}
+
+#[derive(Debug, Serialize)]
+struct NetWorthPoint {
+    period: String,
+    value: String,
+}
+
+#[derive(Debug, Serialize)]
+struct NetWorth {
+    currency: String,
+    series: Vec<NetWorthPoint>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NetWorthResult {
+    success: bool,
+    data: NetWorth,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<Meta>,
+}
+
+impl IntoResponse for NetWorthResult {
+    fn into_response(self) -> Response {
+        let body = Json(self);
+        (StatusCode::OK, body).into_response()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatementParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    time: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    filter: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    interval: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    currency: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    conversion: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    explain: Option<bool>,
+}
+
+impl QueryFields for StatementParams {
+    const FIELDS: &[&str] = &[
+        "time",
+        "filter",
+        "interval",
+        "currency",
+        "conversion",
+        "explain",
+    ];
+}
+
+#[derive(Debug, Serialize)]
+struct Statement {
+    /// The periods with activity, in order, with an interval.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    periods: Vec<String>,
+    roots: Vec<AccountNode>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatementResult {
+    success: bool,
+    data: Statement,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<Meta>,
+}
+
+impl IntoResponse for StatementResult {
+    fn into_response(self) -> Response {
+        let body = Json(self);
+        (StatusCode::OK, body).into_response()
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct PeriodTotals {
+    period: String,
+    income: String,
+    expenses: String,
+    net: String,
+    /// Totals per `Income:*` and `Expenses:*` account, with `breakdown=true`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    accounts: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Debug, Serialize)]
+struct IncomeData {
+    currency: String,
+    series: Vec<PeriodTotals>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IncomeResult {
+    success: bool,
+    data: IncomeData,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<Meta>,
+}
+
+impl IntoResponse for IncomeResult {
+    fn into_response(self) -> Response {
+        let body = Json(self);
+        (StatusCode::OK, body).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::Query, routing::get, Router};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{backend::Backend, config::Config, testing, testing::table};
+
+    #[tokio::test]
+    async fn fills_the_periods_without_activity_with_zeros() {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|| async {
+                table(
+                    &["year", "month", "root", "amount"],
+                    &[
+                        &["2024", "1", "Expenses:Food", "120.00 CNY"],
+                        &["2024", "1", "Income:Salary", "-1000.00 CNY"],
+                        &["2024", "3", "Expenses:Rent", "500.00 CNY, 20 USD"],
+                    ],
+                )
+            }),
+        );
+        let mut config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        config.operating_currency = Some("CNY".into());
+        let state = AppState::new(config);
+        let (status, body) = testing::get(&state, "/api/income_expenses?breakdown=true").await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["data"],
+            json!({
+                "currency": "CNY",
+                "series": [
+                    {
+                        "period": "2024-01",
+                        "income": "1000.00",
+                        "expenses": "120.00",
+                        "net": "880.00",
+                        "accounts": {"Expenses:Food": "120.00", "Income:Salary": "1000.00"},
+                    },
+                    {
+                        "period": "2024-02",
+                        "income": "0.00",
+                        "expenses": "0.00",
+                        "net": "0.00",
+                        "accounts": {},
+                    },
+                    {
+                        "period": "2024-03",
+                        "income": "0.00",
+                        "expenses": "500.00",
+                        "net": "-500.00",
+                        "accounts": {"Expenses:Rent": "500.00"},
+                    },
+                ],
+            })
+        );
+        // The USD of the rent could not be converted.
+        assert_eq!(body["warnings"].as_array().unwrap().len(), 1);
+
+        let (_, body) = testing::get(&state, "/api/income_expenses?negate=true").await;
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["data"]["series"][0]["income"], "-1000.00");
+        assert!(body["data"]["series"][0].get("accounts").is_none());
+        let (status, _) = testing::get(&state, "/api/income_expenses?currency=cny").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn sums_the_income_statement_per_period() {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|| async {
+                table(
+                    &["account", "year", "month", "balance"],
+                    &[
+                        &["Expenses:Food", "2024", "1", "120.50 CNY"],
+                        &["Expenses:Food", "2024", "2", "80 CNY"],
+                        &["Income:Salary", "2024", "1", "-1000.00 CNY"],
+                    ],
+                )
+            }),
+        );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        let state = AppState::new(config);
+        let (status, body) = testing::get(
+            &state,
+            "/api/income_statement?interval=month&time=2024&explain=true",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["data"]["periods"], json!(["2024-01", "2024-02"]));
+        assert_eq!(
+            body["data"]["roots"],
+            json!([
+                {
+                    "account": "Income",
+                    "balance": {},
+                    "total": {"CNY": "-1000.00"},
+                    "periods": {"2024-01": {"CNY": "-1000.00"}},
+                    "children": [{
+                        "account": "Income:Salary",
+                        "balance": {"CNY": "-1000.00"},
+                        "total": {"CNY": "-1000.00"},
+                        "periods": {"2024-01": {"CNY": "-1000.00"}},
+                    }],
+                },
+                {
+                    "account": "Expenses",
+                    "balance": {},
+                    "total": {"CNY": "200.50"},
+                    "periods": {
+                        "2024-01": {"CNY": "120.50"},
+                        "2024-02": {"CNY": "80.00"},
+                    },
+                    "children": [{
+                        "account": "Expenses:Food",
+                        "balance": {"CNY": "200.50"},
+                        "total": {"CNY": "200.50"},
+                        "periods": {
+                            "2024-01": {"CNY": "120.50"},
+                            "2024-02": {"CNY": "80.00"},
+                        },
+                    }],
+                },
+            ])
+        );
+        let generated = &body["meta"]["generated_queries"][0];
+        assert_eq!(
+            generated["query"],
+            "SELECT account, year, month, cost(sum(position)) AS balance \
+             WHERE account ~ '^(Income|Expenses)(:|$)' \
+             GROUP BY account, year, month ORDER BY account, year, month"
+        );
+        assert_eq!(generated["filters"], json!(["time=2024"]));
+
+        let (status, _) = testing::get(&state, "/api/income_statement?conversion=market").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn values_net_worth_at_the_last_price() {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|Query(params): Query<HashMap<String, String>>| async move {
+                match params["query_string"].as_str() {
+                    PRICES_QUERY => table(
+                        &["date", "currency", "amount"],
+                        &[
+                            &["2024-01-15", "AAPL", "150 USD"],
+                            &["2024-02-10", "AAPL", "160 USD"],
+                        ],
+                    ),
+                    _ => table(
+                        &["year", "month", "balance"],
+                        &[
+                            &["2024", "1", "1000 USD, 10 AAPL"],
+                            &["2024", "2", "5 VT"],
+                            &["2024", "3", "-200 USD"],
+                        ],
+                    ),
+                }
+            }),
+        );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        let state = AppState::new(config);
+        let (status, body) = testing::get(&state, "/api/net_worth?currency=USD&explain=true").await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["data"],
+            json!({
+                "currency": "USD",
+                "series": [
+                    {"period": "2024-01", "value": "2500"},
+                    {"period": "2024-02", "value": "2600"},
+                    {"period": "2024-03", "value": "2400"},
+                ],
+            })
+        );
+        // VT has no price but is only reported once.
+        assert_eq!(body["warnings"].as_array().unwrap().len(), 1);
+        let generated = &body["meta"]["generated_queries"];
+        assert_eq!(
+            generated[0]["query"],
+            "SELECT year, month, units(sum(position)) AS balance \
+             WHERE account ~ '^(Assets|Liabilities)(:|$)' \
+             GROUP BY year, month ORDER BY year, month"
+        );
+        assert_eq!(generated[1]["query"], PRICES_QUERY);
+
+        // Holdings before `from` still count towards its value.
+        let (_, body) = testing::get(&state, "/api/net_worth?currency=USD&from=2024-03").await;
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["data"]["series"],
+            json!([{"period": "2024-03", "value": "2400"}])
+        );
+
+        let (status, _) = testing::get(&state, "/api/net_worth").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
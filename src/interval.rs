@@ -0,0 +1,660 @@
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, time::SystemTime};
+
+use crate::{
+    amount::Scales,
+    balance_sheet::{self, described, fava_filters, Inventory},
+    empty_string_as_none,
+    i18n::{Lang, Message},
+    params::{QueryFields, StrictQuery},
+    AppState, ErrorResult, GeneratedQuery, Meta, Row,
+};
+
+/// Longest series a request may ask for, ten years of days.
+pub const MAX_PERIODS: usize = 3660;
+
+/// The length of the periods of a report, like fava's interval selector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Interval {
+    Day,
+    /// ISO weeks, from Monday.
+    Week,
+    Month,
+    Quarter,
+    Year,
+}
+
+impl Interval {
+    pub fn parse(text: &str) -> Result<Interval, ErrorResult> {
+        match text {
+            "day" => Ok(Interval::Day),
+            "week" => Ok(Interval::Week),
+            "month" => Ok(Interval::Month),
+            "quarter" => Ok(Interval::Quarter),
+            "year" => Ok(Interval::Year),
+            _ => Err(ErrorResult::bad_request(format!(
+                "invalid interval {}, expected day, week, month, quarter or year",
+                text
+            ))),
+        }
+    }
+
+    /// The BQL columns to group by so that rows fall into one period each.
+    pub fn columns(self) -> &'static str {
+        match self {
+            Interval::Day | Interval::Week => "year, month, day",
+            Interval::Month | Interval::Quarter => "year, month",
+            Interval::Year => "year",
+        }
+    }
+}
+
+/// A day, week, month, quarter or year, by its first day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Period {
+    interval: Interval,
+    /// Days since 1970-01-01.
+    start: i64,
+}
+
+impl Period {
+    /// The period of `interval` that `day` falls into.
+    fn containing(interval: Interval, day: i64) -> Period {
+        let (year, month, _) = civil(day);
+        let start = match interval {
+            Interval::Day => day,
+            Interval::Week => day - (day + 3).rem_euclid(7),
+            Interval::Month => days(year, month, 1),
+            Interval::Quarter => days(year, (month - 1) / 3 * 3 + 1, 1),
+            Interval::Year => days(year, 1, 1),
+        };
+        Period { interval, start }
+    }
+
+    /// The period of a row grouped by [`Interval::columns`].
+    pub fn from_row(row: &Row, interval: Interval) -> Option<Period> {
+        let column = |name: &str| match row.get(name) {
+            Some(value) => value.trim().parse().ok(),
+            None => Some(1),
+        };
+        let year = row.get("year")?.trim().parse().ok()?;
+        let month = column("month").filter(|month| (1..=12).contains(month))?;
+        let day = column("day").filter(|day| *day >= 1 && *day <= month_days(year, month))?;
+        Some(Period::containing(interval, days(year, month, day)))
+    }
+
+    pub fn next(self) -> Period {
+        let (year, month, _) = civil(self.start);
+        let start = match self.interval {
+            Interval::Day => self.start + 1,
+            Interval::Week => self.start + 7,
+            Interval::Month => add_months(year, month, 1),
+            Interval::Quarter => add_months(year, month, 3),
+            Interval::Year => days(year + 1, 1, 1),
+        };
+        Period { start, ..self }
+    }
+
+    /// The period of the same interval just before this one.
+    pub fn previous(self) -> Period {
+        Period::containing(self.interval, self.start - 1)
+    }
+
+    /// The first day of the period, as a BQL date.
+    pub fn start(self) -> String {
+        format_date(self.start)
+    }
+}
+
+impl fmt::Display for Period {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, _) = civil(self.start);
+        match self.interval {
+            Interval::Day => write!(f, "{}", self.start()),
+            Interval::Week => {
+                // The ISO year is the year of the week's Thursday.
+                let thursday = self.start + 3;
+                let (year, _, _) = civil(thursday);
+                let week = (thursday - days(year, 1, 1)) / 7 + 1;
+                write!(f, "{:04}-W{:02}", year, week)
+            }
+            Interval::Month => write!(f, "{:04}-{:02}", year, month),
+            Interval::Quarter => write!(f, "{:04}-Q{}", year, (month - 1) / 3 + 1),
+            Interval::Year => write!(f, "{:04}", year),
+        }
+    }
+}
+
+/// Reads `from` or `to` as `YYYY`, `YYYY-MM` or `YYYY-MM-DD`, as the period
+/// holding its first day, or its last day for the `end`.
+pub fn bound(
+    text: Option<&str>,
+    interval: Interval,
+    end: bool,
+) -> Result<Option<Period>, ErrorResult> {
+    let text = match text {
+        Some(text) => text,
+        None => return Ok(None),
+    };
+    let invalid = || {
+        ErrorResult::bad_request(format!(
+            "invalid period {}, expected YYYY, YYYY-MM or YYYY-MM-DD",
+            text
+        ))
+    };
+    let parts: Vec<&str> = text.split('-').collect();
+    let widths = [4, 2, 2];
+    if parts.len() > 3
+        || parts
+            .iter()
+            .zip(widths)
+            .any(|(part, width)| part.len() != width || !part.chars().all(|c| c.is_ascii_digit()))
+    {
+        return Err(invalid());
+    }
+    let number = |i: usize| parts.get(i).map(|part| part.parse::<i64>().unwrap_or(0));
+    let year = number(0).ok_or_else(invalid)?;
+    let month = match number(1) {
+        Some(month @ 1..=12) => month,
+        Some(_) => return Err(invalid()),
+        None if end => 12,
+        None => 1,
+    };
+    let day = match number(2) {
+        Some(day) if day >= 1 && day <= month_days(year, month) => day,
+        Some(_) => return Err(invalid()),
+        None if end => month_days(year, month),
+        None => 1,
+    };
+    Ok(Some(Period::containing(interval, days(year, month, day))))
+}
+
+/// The periods from `from` through `to`, stopping once there are more
+/// than [`MAX_PERIODS`].
+pub fn periods(from: Period, to: Period) -> Vec<Period> {
+    let mut periods = Vec::new();
+    let mut period = from;
+    while period <= to && periods.len() <= MAX_PERIODS {
+        periods.push(period);
+        period = period.next();
+    }
+    periods
+}
+
+/// Days since 1970-01-01 in UTC.
+pub fn today() -> i64 {
+    let elapsed = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    (elapsed.as_secs() / 86400) as i64
+}
+
+/// fava's time filter for a relative `time` such as `last_30_days`,
+/// `this_month`, `last_quarter` or `ytd`, counted from `today`. Other
+/// values are fava's own syntax and left to fava.
+///
+/// `this_` and `last_` take `week`, `month`, `quarter` or `year`;
+/// `last_N_days` runs through today, as do `wtd`, `mtd`, `qtd` and `ytd`.
+pub fn relative_time(text: &str, today: i64) -> Option<String> {
+    let current = |interval| Period::containing(interval, today);
+    let through_today = |start: i64| format!("{} - {}", format_date(start), format_date(today));
+    let interval = |name: &str| match name {
+        "week" => Some(Interval::Week),
+        "month" => Some(Interval::Month),
+        "quarter" => Some(Interval::Quarter),
+        "year" => Some(Interval::Year),
+        _ => None,
+    };
+    match text.trim() {
+        "today" => return Some(format_date(today)),
+        "yesterday" => return Some(format_date(today - 1)),
+        "wtd" => return Some(through_today(current(Interval::Week).start)),
+        "mtd" => return Some(through_today(current(Interval::Month).start)),
+        "qtd" => return Some(through_today(current(Interval::Quarter).start)),
+        "ytd" => return Some(through_today(current(Interval::Year).start)),
+        _ => {}
+    }
+    if let Some(name) = text.trim().strip_prefix("this_") {
+        return Some(current(interval(name)?).to_string());
+    }
+    let rest = text.trim().strip_prefix("last_")?;
+    if let Some(count) = rest.strip_suffix("_days") {
+        let count: i64 = count.parse().ok().filter(|count| *count >= 1)?;
+        return Some(through_today(today - count.min(MAX_PERIODS as i64) + 1));
+    }
+    Some(current(interval(rest)?).previous().to_string())
+}
+
+/// Days since 1970-01-01 of a `YYYY-MM-DD` date.
+pub fn parse_date(text: &str) -> Option<i64> {
+    let mut parts = text
+        .trim()
+        .splitn(3, '-')
+        .map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (parts.next()??, parts.next()??, parts.next()??);
+    if !(1..=12).contains(&month) || day < 1 || day > month_days(year, month) {
+        return None;
+    }
+    Some(days(year, month, day))
+}
+
+/// A day of [`parse_date`] as `YYYY-MM-DD`.
+pub fn format_date(day: i64) -> String {
+    let (year, month, day) = civil(day);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// The same day of the month `count` months later, or the last day of that
+/// month if it is shorter.
+pub fn shift_months(day: i64, count: i64) -> i64 {
+    let (year, month, day) = civil(day);
+    let first = add_months(year, month, count);
+    let (year, month, _) = civil(first);
+    first + day.min(month_days(year, month)) - 1
+}
+
+/// Days since 1970-01-01 of a date of the proleptic Gregorian calendar.
+fn days(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The year, month and day of [`days`].
+fn civil(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted + 2) / 5 + 1;
+    let month = if shifted < 10 {
+        shifted + 3
+    } else {
+        shifted - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn month_days(year: i64, month: i64) -> i64 {
+    let (next_year, next_month) = match month {
+        12 => (year + 1, 1),
+        month => (year, month + 1),
+    };
+    days(next_year, next_month, 1) - days(year, month, 1)
+}
+
+/// The first day of the month `count` months after `year`-`month`.
+fn add_months(year: i64, month: i64, count: i64) -> i64 {
+    let months = year * 12 + month - 1 + count;
+    days(months.div_euclid(12), months.rem_euclid(12) + 1, 1)
+}
+
+/// The postings of `account` and the accounts below it summed per period,
+/// every period from `from` through `to` included.
+///
+/// Without `from` and `to` the series runs from the first to the last
+/// period with a posting. `time` and `filter` are handed to fava.
+pub async fn interval_report(
+    State(state): State<AppState>,
+    StrictQuery(params): StrictQuery<IntervalParams>,
+    lang: Lang,
+) -> Result<IntervalResult, ErrorResult> {
+    if !balance_sheet::is_account(&params.account) {
+        return Err(ErrorResult::bad_request(format!(
+            "invalid account {}",
+            params.account
+        )));
+    }
+    let interval = params
+        .interval
+        .as_deref()
+        .map(Interval::parse)
+        .transpose()?
+        .unwrap_or(Interval::Month);
+    let from = bound(params.from.as_deref(), interval, false)?;
+    let to = bound(params.to.as_deref(), interval, true)?;
+    let value = balance_sheet::balance(params.currency.as_deref(), params.conversion.as_deref())?;
+    let mut filter = format!("account ~ '^{}(:|$)'", params.account);
+    if let Some(from) = from {
+        filter.push_str(&format!(" AND date >= {}", from.start()));
+    }
+    if let Some(to) = to {
+        filter.push_str(&format!(" AND date < {}", to.next().start()));
+    }
+    let query = format!(
+        "SELECT {columns}, {value} AS balance WHERE {filter} \
+         GROUP BY {columns} ORDER BY {columns}",
+        columns = interval.columns(),
+    );
+    let filters = fava_filters(params.time.as_deref(), params.filter.as_deref());
+    let parsed = state
+        .session()
+        .filtered(&filters)
+        .rows(&query)
+        .await
+        .map_err(|e| e.localize(lang))?;
+
+    let mut scales = Scales::default();
+    let mut sums: BTreeMap<Period, Inventory> = BTreeMap::new();
+    for row in &parsed.rows {
+        if let Some(period) = Period::from_row(row, interval) {
+            let text = row.get("balance").map_or("", String::as_str);
+            balance_sheet::add(sums.entry(period).or_default(), text, &mut scales);
+        }
+    }
+    let from = from.or_else(|| sums.keys().next().copied());
+    let to = to.or_else(|| sums.keys().next_back().copied());
+    let periods = match (from, to) {
+        (Some(from), Some(to)) => periods(from, to),
+        _ => Vec::new(),
+    };
+    if periods.len() > MAX_PERIODS {
+        return Err(ErrorResult::bad_request(format!(
+            "more than {} periods requested",
+            MAX_PERIODS
+        )));
+    }
+    let series = periods
+        .into_iter()
+        .map(|period| IntervalTotals {
+            period: period.to_string(),
+            start: period.start(),
+            totals: balance_sheet::render(&sums.remove(&period).unwrap_or_default(), &scales),
+        })
+        .collect();
+
+    let mut generated_filters = vec![filter];
+    generated_filters.extend(described(&filters));
+    let generated = GeneratedQuery {
+        query,
+        filters: generated_filters,
+        feeds: "data.series".into(),
+    };
+    Ok(IntervalResult {
+        success: true,
+        data: IntervalReport {
+            account: params.account,
+            series,
+        },
+        warnings: parsed
+            .warnings
+            .into_iter()
+            .map(|warning| warning.localize(lang))
+            .collect(),
+        meta: Meta::explain(params.explain, vec![generated]),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IntervalParams {
+    account: String,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    interval: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    from: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    to: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    currency: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    conversion: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    time: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    filter: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    explain: Option<bool>,
+}
+
+impl QueryFields for IntervalParams {
+    const FIELDS: &[&str] = &[
+        "account",
+        "interval",
+        "from",
+        "to",
+        "currency",
+        "conversion",
+        "time",
+        "filter",
+        "explain",
+    ];
+}
+
+#[derive(Debug, Serialize)]
+struct IntervalTotals {
+    /// Such as `2024-03-05`, `2024-W10`, `2024-03`, `2024-Q1` or `2024`.
+    period: String,
+    /// The first day of the period.
+    start: String,
+    /// Currency to the sum of the period's postings.
+    totals: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize)]
+struct IntervalReport {
+    account: String,
+    series: Vec<IntervalTotals>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IntervalResult {
+    success: bool,
+    data: IntervalReport,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<Meta>,
+}
+
+impl IntoResponse for IntervalResult {
+    fn into_response(self) -> Response {
+        let body = Json(self);
+        (StatusCode::OK, body).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::get, Router};
+
+    use super::*;
+    use crate::{backend::Backend, config::Config, testing, testing::table};
+
+    fn day(text: &str) -> i64 {
+        parse_date(text).unwrap()
+    }
+
+    fn period(interval: Interval, text: &str) -> Period {
+        Period::containing(interval, day(text))
+    }
+
+    fn end(text: &str, interval: Interval) -> String {
+        bound(Some(text), interval, true)
+            .unwrap()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn counts_leap_days() {
+        for (date, valid) in [
+            ("2024-02-29", true),
+            ("2023-02-29", false),
+            ("2000-02-29", true),
+            ("1900-02-29", false),
+            ("2100-02-29", false),
+            ("2024-04-31", false),
+        ] {
+            assert_eq!(parse_date(date).is_some(), valid, "{}", date);
+        }
+        assert_eq!(day("2024-03-01") - day("2024-02-28"), 2);
+        assert_eq!(day("2025-01-01") - day("2024-01-01"), 366);
+        assert_eq!(day("1970-01-01"), 0);
+        for days in day("1899-12-31")..day("2101-01-01") {
+            assert_eq!(day(&format_date(days)), days);
+        }
+    }
+
+    #[test]
+    fn numbers_iso_weeks_by_their_thursday() {
+        for (date, week, monday) in [
+            ("2019-12-30", "2020-W01", "2019-12-30"),
+            ("2020-12-31", "2020-W53", "2020-12-28"),
+            ("2021-01-03", "2020-W53", "2020-12-28"),
+            ("2021-01-04", "2021-W01", "2021-01-04"),
+            ("2024-12-29", "2024-W52", "2024-12-23"),
+            ("2024-12-30", "2025-W01", "2024-12-30"),
+            ("2026-12-31", "2026-W53", "2026-12-28"),
+        ] {
+            let period = period(Interval::Week, date);
+            assert_eq!(
+                (period.to_string(), period.start()),
+                (week.into(), monday.into()),
+                "{}",
+                date
+            );
+        }
+        assert_eq!(
+            period(Interval::Week, "2020-12-31").next().to_string(),
+            "2021-W01"
+        );
+    }
+
+    #[test]
+    fn starts_quarters_on_their_first_month() {
+        for (date, quarter, start) in [
+            ("2024-01-01", "2024-Q1", "2024-01-01"),
+            ("2024-03-31", "2024-Q1", "2024-01-01"),
+            ("2024-05-15", "2024-Q2", "2024-04-01"),
+            ("2024-09-30", "2024-Q3", "2024-07-01"),
+            ("2024-12-31", "2024-Q4", "2024-10-01"),
+        ] {
+            let period = period(Interval::Quarter, date);
+            assert_eq!(
+                (period.to_string(), period.start()),
+                (quarter.into(), start.into())
+            );
+        }
+        let fourth = period(Interval::Quarter, "2024-11-02");
+        assert_eq!(fourth.next().to_string(), "2025-Q1");
+        assert_eq!(
+            period(Interval::Quarter, "2024-02-02")
+                .previous()
+                .to_string(),
+            "2023-Q4"
+        );
+    }
+
+    #[test]
+    fn ends_bounds_on_the_last_day() {
+        assert_eq!(end("2024-02", Interval::Day), "2024-02-29");
+        assert_eq!(end("2023-02", Interval::Day), "2023-02-28");
+        assert_eq!(end("2024", Interval::Day), "2024-12-31");
+        assert_eq!(end("2024", Interval::Month), "2024-12");
+        assert_eq!(end("2024-02", Interval::Week), "2024-W09");
+        let start = bound(Some("2024-02"), Interval::Day, false)
+            .unwrap()
+            .unwrap();
+        assert_eq!(start.to_string(), "2024-02-01");
+        assert_eq!(bound(None, Interval::Day, true).unwrap(), None);
+        for text in [
+            "2023-02-29",
+            "2024-13",
+            "2024-00",
+            "24",
+            "2024-2",
+            "2024-02-01-01",
+            "x",
+        ] {
+            let error = bound(Some(text), Interval::Day, true).unwrap_err();
+            assert_eq!(error.status, StatusCode::BAD_REQUEST, "{}", text);
+        }
+    }
+
+    #[tokio::test]
+    async fn caps_the_series_at_max_periods() {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|| async { table(&["year", "month", "day", "balance"], &[]) }),
+        );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        let state = AppState::new(config);
+        let from = day("2000-01-01");
+        let uri = |days: i64| {
+            format!(
+                "/api/interval_report?account=Assets&interval=day&from=2000-01-01&to={}",
+                format_date(from + days - 1)
+            )
+        };
+
+        let (status, body) = testing::get(&state, &uri(MAX_PERIODS as i64)).await;
+        assert_eq!(status, StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let series = body["data"]["series"].as_array().unwrap();
+        assert_eq!(series.len(), MAX_PERIODS);
+        assert_eq!(series[0]["period"], "2000-01-01");
+
+        let (status, body) = testing::get(&state, &uri(MAX_PERIODS as i64 + 1)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(
+            body.contains("more than 3660 periods requested"),
+            "{}",
+            body
+        );
+    }
+
+    #[tokio::test]
+    async fn sums_every_quarter_of_the_series() {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|| async {
+                table(
+                    &["year", "month", "balance"],
+                    &[
+                        &["2024", "1", "10.00 CNY"],
+                        &["2024", "3", "5.50 CNY"],
+                        &["2024", "8", "-2.00 CNY"],
+                    ],
+                )
+            }),
+        );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        let state = AppState::new(config);
+        let uri = "/api/interval_report?account=Expenses&interval=quarter&time=2024&explain=true";
+        let (status, body) = testing::get(&state, uri).await;
+        assert_eq!(status, StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["data"],
+            serde_json::json!({
+                "account": "Expenses",
+                "series": [
+                    {"period": "2024-Q1", "start": "2024-01-01", "totals": {"CNY": "15.50"}},
+                    {"period": "2024-Q2", "start": "2024-04-01", "totals": {}},
+                    {"period": "2024-Q3", "start": "2024-07-01", "totals": {"CNY": "-2.00"}},
+                ],
+            })
+        );
+        assert_eq!(
+            body["meta"]["generated_queries"][0]["filters"],
+            serde_json::json!(["account ~ '^Expenses(:|$)'", "time=2024"])
+        );
+    }
+}
@@ -0,0 +1,176 @@
+use nipper::{Document, Selection};
+use serde::Serialize;
+
+use crate::detect::FavaVersion;
+
+/// Longest raw cell text kept, in characters.
+const RAW_LIMIT: usize = 200;
+
+/// Where fava's account journal keeps each part of an entry.
+#[derive(Debug)]
+pub struct Layout {
+    /// Reported by `/api/upstream`.
+    pub name: &'static str,
+    /// First fava version rendering its journal this way.
+    since: FavaVersion,
+    table: &'static str,
+    entry: &'static str,
+    date: &'static str,
+    flag: &'static str,
+    payee: &'static str,
+    description: &'static str,
+    change: &'static str,
+    balance: &'static str,
+    account: &'static str,
+}
+
+/// The journal layouts of the fava versions, oldest first. A template
+/// change in a new fava version gets an entry of its own here.
+const LAYOUTS: &[Layout] = &[Layout {
+    name: "flex-table",
+    since: FavaVersion([0, 0, 0]),
+    table: ".flex-table",
+    entry: ".transaction",
+    date: ".datecell",
+    flag: ".flag",
+    payee: ".payee",
+    description: ".description",
+    change: ".change",
+    balance: "span:nth-child(6)",
+    account: ".postings .account",
+}];
+
+/// The layout of `version`, or of the latest version if it is not known.
+pub fn layout(version: Option<FavaVersion>) -> &'static Layout {
+    let latest = &LAYOUTS[LAYOUTS.len() - 1];
+    match version {
+        Some(version) => LAYOUTS
+            .iter()
+            .rev()
+            .find(|layout| layout.since <= version)
+            .unwrap_or(latest),
+        None => latest,
+    }
+}
+
+/// One `.transaction` row of fava's account journal, as text.
+#[derive(Debug, Clone)]
+pub struct JournalEntry {
+    pub date: String,
+    pub flag: String,
+    pub payee: String,
+    pub narration: String,
+    pub change: String,
+    pub balance: String,
+    /// Accounts of the entry's postings, in page order.
+    pub accounts: Vec<String>,
+    pub raw: RawCells,
+}
+
+/// The untrimmed text the entry's numbers were read from, for debugging
+/// the parser from a client.
+#[derive(Debug, Clone, Serialize)]
+pub struct RawCells {
+    pub date: String,
+    pub change: String,
+    pub balance: String,
+    pub classes: Vec<String>,
+}
+
+pub fn parse_journal(html: &str, layout: &Layout) -> Vec<JournalEntry> {
+    let document = Document::from(html);
+    // fava puts a `<br>` between the positions of a cell holding several
+    // commodities, which would otherwise run together as `5 USD3.00 CNY`.
+    document.select("br").replace_with_html(" ");
+    let table = document.select(layout.table);
+    table
+        .select(layout.entry)
+        .iter()
+        .map(|line| JournalEntry {
+            raw: RawCells {
+                date: capped(&line.select(layout.date).text()),
+                change: capped(&line.select(layout.change).text()),
+                balance: capped(&line.select(layout.balance).text()),
+                classes: line
+                    .attr("class")
+                    .map(|classes| classes.split_whitespace().map(capped).collect())
+                    .unwrap_or_default(),
+            },
+            date: line.select(layout.date).text().trim().to_string(),
+            flag: first_text(&line, layout.flag),
+            payee: first_text(&line, layout.payee),
+            narration: narration(&line, layout),
+            change: line.select(layout.change).text().to_string(),
+            balance: line.select(layout.balance).text().to_string(),
+            accounts: line
+                .select(layout.account)
+                .iter()
+                .map(|account| account.text().trim().to_string())
+                .collect(),
+        })
+        .collect()
+}
+
+fn capped(text: &str) -> String {
+    text.chars().take(RAW_LIMIT).collect()
+}
+
+fn first_text(line: &Selection, selector: &str) -> String {
+    line.select(selector).first().text().trim().to_string()
+}
+
+/// The description cell holds the payee, the narration, and the entry's tags
+/// and links; the narration is whatever remains once the others are removed.
+fn narration(line: &Selection, layout: &Layout) -> String {
+    let description = line.select(layout.description).first();
+    let mut text = description.text().to_string();
+    for part in description.select(".payee, .tag, .link").iter() {
+        text = text.replacen(part.text().as_ref(), "", 1);
+    }
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{amount, detect, interval, testing::FixtureSet};
+
+    #[test]
+    fn reads_each_captured_journal_with_the_layout_of_its_version() {
+        for set in FixtureSet::all() {
+            let version = detect::parse_version(&format!("fava {}", set.fava_version));
+            let entries = parse_journal(&set.file("account"), layout(version));
+            assert!(!entries.is_empty(), "fava {}", set.fava_version);
+            for entry in entries {
+                assert!(
+                    interval::parse_date(&entry.date).is_some(),
+                    "fava {}",
+                    set.fava_version
+                );
+                assert!(
+                    entry.accounts.contains(&set.account),
+                    "fava {}",
+                    set.fava_version
+                );
+                assert!(amount::parse_positions(&entry.change).is_some());
+                assert!(amount::parse_positions(&entry.balance).is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_raw_cells_untrimmed_up_to_the_limit() {
+        let long = "9".repeat(RAW_LIMIT + 50);
+        let html = include_str!("../tests/fixtures/1.27/account.html")
+            .replace(
+                ">-300.00 CNY</span><span",
+                &format!(">{}</span><span", long),
+            )
+            .replace(">2024-01-03</a>", ">\n  2024-01-03 </a>");
+        let entries = parse_journal(&html, layout(None));
+        assert_eq!(entries[0].raw.date, "\n  2024-01-03 ");
+        assert_eq!(entries[0].date, "2024-01-03");
+        assert_eq!(entries[0].raw.change, long[..RAW_LIMIT]);
+        assert_eq!(entries[0].raw.classes, ["transaction", "cleared"]);
+    }
+}
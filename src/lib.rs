@@ -0,0 +1,2654 @@
+use axum::{
+    extract::{Path, State},
+    http::Uri,
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{any, get, post, put},
+    Json, Router,
+};
+use backend::JsonQueryResult;
+use capabilities::FeatureRoutes;
+use config::LocaleConfig;
+use groups::{group, EndpointGroup};
+use i18n::{Lang, Message};
+use nipper::Document;
+use params::{QueryFields, StrictJson, StrictQuery};
+use reqwest::{
+    header::{HeaderMap, CONTENT_DISPOSITION, CONTENT_TYPE, RETRY_AFTER},
+    StatusCode,
+};
+use rust_decimal::Decimal;
+use serde::{
+    de::{self},
+    ser::{SerializeMap, SerializeStruct},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fmt,
+    future::Future,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::task::JoinHandle;
+use tower::{service_fn, ServiceExt};
+
+mod accept;
+mod accounts;
+mod aggregate;
+mod alerts;
+mod amount;
+mod arrow;
+mod availability;
+mod backend;
+mod balance_sheet;
+mod batch;
+mod beancount;
+mod cache;
+mod capabilities;
+mod client;
+mod commodities;
+mod compression;
+mod config;
+mod csv;
+mod detect;
+mod directives;
+mod entries;
+mod errors;
+mod events;
+mod export;
+mod fingerprint;
+mod fragment;
+#[cfg(any(test, fuzzing))]
+#[doc(hidden)]
+pub mod fuzzing;
+mod graphql;
+mod groups;
+mod grpc;
+mod holdings;
+mod i18n;
+mod income;
+mod interval;
+mod journal;
+mod links;
+mod locale;
+mod merge;
+mod metrics;
+mod ndjson;
+mod options;
+mod paging;
+mod params;
+mod partial;
+mod pattern;
+mod protobuf;
+mod recurring;
+mod saved_queries;
+mod schedule;
+mod search;
+mod session;
+mod slugs;
+mod smoothing;
+mod sort;
+mod statistics;
+mod status;
+mod tags;
+mod templates;
+#[cfg(test)]
+mod testing;
+mod transform;
+mod typed;
+mod validate;
+mod views;
+mod xlsx;
+mod zip;
+
+pub use backend::Backend;
+pub use client::{Auth, Error, FavaClient};
+pub use config::Config;
+
+/// Builds the service's routes for `config`, with their state included, so
+/// they can be served directly or nested into another application.
+///
+/// Nothing runs in the background: views and alerts are only computed, and
+/// polled queries only refreshed ahead of time, when the router comes from
+/// [`router_with_background_tasks`].
+///
+/// The ledger at the config's url is served at the root, each further
+/// ledger of the config below `/ledgers/<name>`, and with `slugs` on the
+/// other ledgers of the same fava below `/slugs/<slug>`.
+pub fn router(config: Config) -> Router {
+    ledgers(config, |_| {})
+}
+
+/// Like [`router`], but also starts the tasks that keep views, alerts and
+/// smoothed polls up to date. Has to be called inside a tokio runtime.
+pub fn router_with_background_tasks(config: Config) -> Router {
+    ledgers(config, start_background_tasks)
+}
+
+/// Like [`router_with_background_tasks`], plus a handle that swaps the
+/// configuration of the running router. Requests already in flight finish
+/// with the old configuration.
+pub fn reloadable_router(config: Config) -> (Router, Reloader) {
+    let reloader = Reloader {
+        current: Arc::new(Mutex::new((Router::new(), Vec::new()))),
+    };
+    reloader.reload(config);
+    let current = reloader.current.clone();
+    let router = Router::new().fallback_service(service_fn(move |request| {
+        let router = current.lock().unwrap().0.clone();
+        router.oneshot(request)
+    }));
+    (router, reloader)
+}
+
+/// Replaces the configuration of a router from [`reloadable_router`].
+#[derive(Clone)]
+pub struct Reloader {
+    current: Arc<Mutex<(Router, Vec<AppState>)>>,
+}
+
+impl Reloader {
+    /// Starts serving `config`, with fresh caches, and stops the background
+    /// tasks of the previous configuration.
+    pub fn reload(&self, config: Config) {
+        let states = Mutex::new(Vec::new());
+        let router = ledgers(config, |state| {
+            start_background_tasks(state);
+            states.lock().unwrap().push(state.clone());
+        });
+        let (_, previous) = std::mem::replace(
+            &mut *self.current.lock().unwrap(),
+            (router, states.into_inner().unwrap()),
+        );
+        for state in previous {
+            state.stop();
+        }
+    }
+}
+
+fn start_background_tasks(state: &AppState) {
+    fingerprint::spawn(state);
+    detect::spawn(state);
+    views::spawn(state);
+    alerts::spawn(state);
+    smoothing::spawn(state);
+}
+
+/// Runs one query the way `/api/query_result` does, without serving
+/// anything, and renders the result as `json` or `csv`. Also returns the
+/// result's warnings, which the CSV has no place for.
+pub async fn query_once(
+    config: Config,
+    query_string: &str,
+    format: &str,
+) -> Result<(String, Vec<String>), String> {
+    if !matches!(format, "json" | "csv") {
+        return Err(Message::new("unsupported_format", vec![format.to_string()]).to_string());
+    }
+    let state = AppState::new(config);
+    let params = Params {
+        query_string: query_string.to_string(),
+        ..Params::default()
+    };
+    let locale = output_locale(&state, None).map_err(|e| e.error)?;
+    let result = query_rows(&state, &params).await.map_err(|e| e.error)?;
+    let warnings = result.warnings.iter().map(Message::to_string).collect();
+    let output = match format {
+        "json" => serde_json::to_string_pretty(&result).map_err(|e| e.to_string())?,
+        _ => result.csv(locale.as_ref()),
+    };
+    Ok((output, warnings))
+}
+
+/// Mounts the routes of every ledger, each with a state of its own that
+/// `start` is called with.
+fn ledgers(config: Config, start: impl Fn(&AppState)) -> Router {
+    let mut router = Router::new();
+    for (name, ledger) in &config.ledgers {
+        let state = AppState::new(config.for_ledger(name, ledger));
+        start(&state);
+        router = router.nest(&format!("/ledgers/{}", name), routes(state));
+    }
+    let base_path = config.base_path.clone();
+    if !config.url.is_empty() {
+        let state = AppState::new(config);
+        start(&state);
+        router = router.merge(routes(state));
+    }
+    match base_path.is_empty() {
+        true => router,
+        false => Router::new().nest(&base_path, router),
+    }
+}
+
+fn routes(state: AppState) -> Router {
+    let router = match state.config.slugs {
+        true => group(
+            &state,
+            EndpointGroup::Passthrough,
+            Router::new().feature_route("/slugs/:slug/*route", any(slugs::forward)),
+        ),
+        false => Router::new(),
+    };
+    router
+        .merge(group(
+            &state,
+            EndpointGroup::Query,
+            Router::new()
+                .feature_route("/api/query_result", get(query).post(query_post))
+                .feature_route("/api/query_batch", post(batch::query_batch))
+                .feature_route("/api/query_validate", get(validate::query_validate))
+                .feature_route("/api/template/:name", get(templates::template))
+                .feature_route("/api/templates", get(templates::templates))
+                .feature_route("/api/saved_queries", get(saved_queries::list))
+                .feature_route("/api/saved_queries/:id", get(saved_queries::get))
+                .feature_route("/api/saved_queries/:id/run", get(saved_queries::run))
+                .feature_route("/api/from_link", get(links::from_link))
+                .feature_route("/api/journal", get(entries::journal))
+                .feature_route(
+                    "/api/graphql",
+                    get(graphql::graphql_get).post(graphql::graphql_post),
+                )
+                .feature_route(
+                    "/fava_query.v1.FavaQuery/QueryResult",
+                    post(grpc::query_result),
+                ),
+        ))
+        .merge(group(
+            &state,
+            EndpointGroup::Account,
+            Router::new()
+                .feature_route("/api/account/:account", get(account))
+                .feature_route(
+                    "/api/account/:account/balance",
+                    get(balance_sheet::account_balance),
+                )
+                .feature_route(
+                    "/api/account/:account/transactions",
+                    get(entries::account_transactions),
+                )
+                .feature_route("/api/accounts", get(accounts::accounts))
+                .feature_route("/balance", get(balance))
+                .feature_route(
+                    "/fava_query.v1.FavaQuery/AccountSeries",
+                    post(grpc::account_series),
+                ),
+        ))
+        .merge(group(
+            &state,
+            EndpointGroup::Aggregate,
+            Router::new()
+                .feature_route("/api/tag/:tag", get(tags::tag))
+                .feature_route("/api/tags", get(tags::tags))
+                .feature_route("/api/links", get(tags::links))
+                .feature_route("/api/payees", get(tags::payees))
+                .feature_route("/api/recurring", get(recurring::recurring))
+                .feature_route("/api/uncleared", get(entries::uncleared))
+                .feature_route("/api/view/:name", get(views::view))
+                .feature_route("/api/export.zip", get(export::export))
+                .feature_route("/api/income_expenses", get(income::income_expenses))
+                .feature_route("/api/balance_sheet", get(balance_sheet::balance_sheet))
+                .feature_route("/api/income_statement", get(income::income_statement))
+                .feature_route("/api/net_worth", get(income::net_worth))
+                .feature_route("/api/holdings", get(holdings::holdings))
+                .feature_route("/api/statistics", get(statistics::statistics))
+                .feature_route("/api/interval_report", get(interval::interval_report))
+                .feature_route("/api/trial_balance", get(balance_sheet::trial_balance))
+                .feature_route("/api/commodities", get(commodities::commodities))
+                .feature_route("/api/prices", get(commodities::prices))
+                .feature_route("/api/events", get(directives::events))
+                .feature_route("/api/budgets", get(directives::budgets))
+                .feature_route("/api/documents", get(directives::documents))
+                .feature_route(
+                    "/fava_query.v1.FavaQuery/BalanceSheet",
+                    post(grpc::balance_sheet),
+                ),
+        ))
+        .merge(group(
+            &state,
+            EndpointGroup::Write,
+            Router::new()
+                .feature_route("/api/saved_queries", post(saved_queries::create))
+                .feature_route(
+                    "/api/saved_queries/:id",
+                    put(saved_queries::replace).delete(saved_queries::delete),
+                ),
+        ))
+        .merge(group(
+            &state,
+            EndpointGroup::Passthrough,
+            Router::new().feature_route("/api/documents/download", get(directives::download)),
+        ))
+        .merge(group(
+            &state,
+            EndpointGroup::Streaming,
+            Router::new().feature_route("/api/events/stream", get(events::stream)),
+        ))
+        .merge(group(
+            &state,
+            EndpointGroup::Admin,
+            Router::new()
+                .feature_route("/api/alerts", get(alerts::alerts))
+                .feature_route("/api/cache/status", get(cache_status))
+                .feature_route("/metrics", get(metrics::metrics))
+                .feature_route("/api/errors", get(errors::errors)),
+        ))
+        .feature_route("/api/status", get(status::status))
+        .feature_route("/healthz", get(status::healthz))
+        .feature_route("/readyz", get(status::readyz))
+        .feature_route("/api/upstream", get(detect::upstream))
+        .feature_route("/api/options", get(options::options))
+        .feature_route("/api/version", get(capabilities::version))
+        .fallback(not_found)
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            metrics::track,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            compression::compress,
+        ))
+        .with_state(state)
+}
+
+/// Answers a path no route matches in the same envelope as other errors.
+async fn not_found(uri: Uri) -> ErrorResult {
+    ErrorResult {
+        error_code: Some("not_found".into()),
+        status: StatusCode::NOT_FOUND,
+        ..ErrorResult::new(format!("no route for {}", uri.path()))
+    }
+}
+
+#[derive(Clone)]
+struct AppState {
+    config: Arc<config::Config>,
+    client: FavaClient,
+    version: Arc<cache::LedgerVersion>,
+    queries: Arc<cache::VersionedCache<QueryResult>>,
+    accounts: Arc<cache::VersionedCache<String>>,
+    views: Arc<views::ViewStore>,
+    availability: Arc<availability::Availability>,
+    alerts: Arc<alerts::AlertStore>,
+    smoother: Arc<smoothing::PollSmoother>,
+    fingerprint: Arc<fingerprint::Fingerprint>,
+    fallback: Arc<cache::Fallback<QueryResult>>,
+    /// Background tasks working for this state.
+    tasks: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    /// States of the other ledgers of the fava instance.
+    slugs: Arc<Mutex<slugs::Slugs>>,
+    backend: Arc<backend::Detected>,
+    detection: Arc<detect::Detection>,
+    events: Arc<events::Events>,
+    metrics: Arc<metrics::Metrics>,
+    saved_queries: Arc<saved_queries::Store>,
+}
+
+impl AppState {
+    fn new(config: config::Config) -> AppState {
+        let mut client = config
+            .resolve
+            .iter()
+            .fold(FavaClient::new(&config.url), |client, (host, ip)| {
+                client.resolve(host, *ip)
+            });
+        if let Some(timeout) = config.upstream.connect_timeout {
+            client = client.connect_timeout(timeout);
+        }
+        if let Some(timeout) = config.upstream.timeout {
+            client = client.timeout(timeout);
+        }
+        if let Some(user_agent) = &config.upstream.user_agent {
+            client = client.user_agent(user_agent);
+        }
+        for certificate in &config.upstream.roots {
+            client = client.add_root_certificate(certificate.clone());
+        }
+        match config.upstream.proxy.as_deref() {
+            Some("none") => client = client.no_proxy(),
+            Some(proxy) => client = client.proxy(proxy),
+            None => {}
+        }
+        if config.upstream.accept_invalid_certs {
+            println!("not verifying the certificate of {}", config.url);
+            client = client.danger_accept_invalid_certs(true);
+        }
+        if let Some(auth) = &config.upstream.auth {
+            client = client.auth(auth.clone());
+        }
+        for (name, value) in &config.upstream.headers {
+            client = client.header(name.clone(), value.clone());
+        }
+        AppState {
+            client,
+            config: Arc::new(config),
+            version: Default::default(),
+            queries: Default::default(),
+            accounts: Default::default(),
+            views: Default::default(),
+            availability: Default::default(),
+            alerts: Default::default(),
+            smoother: Default::default(),
+            fingerprint: Default::default(),
+            fallback: Default::default(),
+            tasks: Default::default(),
+            slugs: Default::default(),
+            backend: Default::default(),
+            detection: Default::default(),
+            events: Default::default(),
+            metrics: Default::default(),
+            saved_queries: Default::default(),
+        }
+    }
+
+    /// Spawns a task that lives as long as this state serves requests.
+    fn spawn_background(&self, task: impl Future<Output = ()> + Send + 'static) {
+        let handle = tokio::spawn(task);
+        self.tasks.lock().unwrap().push(handle);
+    }
+
+    /// Stops the background tasks, once the state was replaced.
+    fn stop(&self) {
+        for task in self.tasks.lock().unwrap().drain(..) {
+            task.abort();
+        }
+        for state in self.slugs.lock().unwrap().states() {
+            state.stop();
+        }
+    }
+}
+
+async fn cache_status(State(state): State<AppState>) -> Json<CacheStatus> {
+    Json(CacheStatus {
+        success: true,
+        queries: state.queries.len(),
+        accounts: state.accounts.len(),
+        poll_smoothing: state.config.poll_smoothing.is_some(),
+        schedule: state.smoother.status(),
+    })
+}
+
+#[derive(Debug, Serialize)]
+struct CacheStatus {
+    success: bool,
+    queries: usize,
+    accounts: usize,
+    poll_smoothing: bool,
+    schedule: Vec<smoothing::ScheduleStatus>,
+}
+
+async fn balance(State(state): State<AppState>, lang: Lang) -> Result<String, ErrorResult> {
+    query_balance(&state)
+        .await
+        .map_err(|e| ErrorResult::from(e).localize(lang))
+}
+
+async fn account(
+    State(state): State<AppState>,
+    Path(account): Path<String>,
+    StrictQuery(params): StrictQuery<AccountParams>,
+    lang: Lang,
+    headers: HeaderMap,
+) -> Response {
+    accept::vary(account_response(&state, account, &params, lang, &headers).await)
+}
+
+async fn account_response(
+    state: &AppState,
+    account: String,
+    params: &AccountParams,
+    lang: Lang,
+    headers: &HeaderMap,
+) -> Response {
+    let accounts: Vec<&str> = account
+        .split(',')
+        .map(str::trim)
+        .filter(|account| !account.is_empty())
+        .collect();
+    let locale = match output_locale(state, params.output_locale.as_deref()) {
+        Ok(locale) => locale,
+        Err(e) => return e.localize(lang).into_response(),
+    };
+    if accounts.len() > 1 {
+        let csv = match params.format.as_deref() {
+            None => accept::format(headers, &["json", "csv"]) == "csv",
+            format => format == Some("csv"),
+        };
+        return match combined_account(state, &accounts, params).await {
+            Ok(result) if csv => result.into_csv_response(locale.as_ref()),
+            Ok(result) => result.localize(lang).into_response(),
+            Err(e) => e.localize(lang).into_response(),
+        };
+    }
+    let journal = match state
+        .session()
+        .refresh_path(params.refresh_path.as_deref())
+        .refresh(params.refresh)
+        .account_journal(&account)
+        .await
+    {
+        Ok(journal) => journal,
+        Err(e) => return e.localize(lang).into_response(),
+    };
+    let entries = journal.entries;
+    let format = match params.format.as_deref() {
+        Some(format) => format,
+        None => accept::format(headers, &["json", "csv", "beancount"]),
+    };
+    match format {
+        "beancount" => {
+            let balancing_account = params
+                .balancing_account
+                .as_deref()
+                .unwrap_or(&state.config.balancing_account);
+            let mut warnings = Vec::new();
+            let text = beancount::render(&account, &entries, balancing_account, &mut warnings);
+            let mut output = String::new();
+            for warning in warnings {
+                output.push_str(&format!("; {}\n", warning.localize(lang)));
+            }
+            output.push_str(&text);
+            ([(CONTENT_TYPE, "text/plain; charset=utf-8")], output).into_response()
+        }
+        "json" | "csv" => {
+            let mut parsed = get_account_data(&entries, params);
+            if let Some(columns) = &params.invert_columns {
+                match transform::invert(parsed.rows, columns) {
+                    Ok(rows) => parsed.rows = rows,
+                    Err(e) => return ErrorResult::bad_request(e).localize(lang).into_response(),
+                }
+            }
+            let (offset, limit) = (params.offset, params.limit);
+            let raw = paginate(std::mem::take(&mut parsed.raw), offset, limit, &mut None);
+            let mut meta = None;
+            parsed.rows = paginate(parsed.rows, offset, limit, &mut meta);
+            let mut result = SuccessResult::from(parsed).localize(lang);
+            result.meta = meta;
+            result.typed = params.typed == Some(true);
+            if state.config.journal_by_year {
+                result.meta = Some(Meta {
+                    upstream_pages: Some(journal.pages),
+                    ..result.meta.unwrap_or_default()
+                });
+            }
+            if format == "csv" {
+                return result.into_csv_response(locale.as_ref());
+            }
+            if raw.is_empty() {
+                return result.into_response();
+            }
+            // Raw cells go next to the parsed values of each row, which
+            // only hold strings otherwise.
+            let mut body = serde_json::to_value(&result).unwrap_or_default();
+            if let Some(rows) = body["data"].as_array_mut() {
+                for (row, raw) in rows.iter_mut().zip(raw) {
+                    row["_raw"] = serde_json::to_value(raw).unwrap_or_default();
+                }
+            }
+            Json(body).into_response()
+        }
+        format => ErrorResult {
+            status: StatusCode::BAD_REQUEST,
+            ..ErrorResult::message(Message::new("unsupported_format", vec![format.to_string()]))
+        }
+        .localize(lang)
+        .into_response(),
+    }
+}
+
+async fn query(
+    State(state): State<AppState>,
+    StrictQuery(params): StrictQuery<Params>,
+    lang: Lang,
+    headers: HeaderMap,
+) -> Result<Response, ErrorResult> {
+    respond(&state, &params, lang, &headers).await
+}
+
+/// `POST /api/query_result`, with the parameters as a JSON object, for
+/// queries that are awkward to put into a url.
+async fn query_post(
+    State(state): State<AppState>,
+    lang: Lang,
+    headers: HeaderMap,
+    StrictJson(params): StrictJson<Params>,
+) -> Result<Response, ErrorResult> {
+    respond(&state, &params, lang, &headers).await
+}
+
+/// Answers a query as JSON, or as CSV, an Excel workbook, an Arrow stream
+/// or a line of JSON per row when `format` or the `Accept` header asks for
+/// it. An HTML fragment is only ever asked for by `format`, as browsers
+/// accept HTML before anything else.
+async fn respond(
+    state: &AppState,
+    params: &Params,
+    lang: Lang,
+    headers: &HeaderMap,
+) -> Result<Response, ErrorResult> {
+    let format = match params.format.as_deref() {
+        None => accept::format(headers, &["json", "csv", "xlsx", "arrow", "ndjson"]),
+        Some(format @ ("json" | "csv" | "xlsx" | "arrow" | "ndjson" | "html_fragment")) => format,
+        Some(format) => {
+            return Err(ErrorResult {
+                status: StatusCode::BAD_REQUEST,
+                ..ErrorResult::message(Message::new("unsupported_format", vec![format.to_string()]))
+            }
+            .localize(lang))
+        }
+    };
+    let locale =
+        output_locale(state, params.output_locale.as_deref()).map_err(|e| e.localize(lang))?;
+    if format == "ndjson" {
+        return ndjson::respond(state, params)
+            .await
+            .map(accept::vary)
+            .map_err(|e| e.localize(lang));
+    }
+    let result = query_rows(state, params)
+        .await
+        .map(|result| result.localize(lang))
+        .map_err(|e| e.localize(lang))?;
+    Ok(accept::vary(match format {
+        "csv" => result.into_csv_response(locale.as_ref()),
+        "xlsx" => result.into_xlsx_response(locale.as_ref()),
+        "arrow" => result.into_arrow_response(),
+        "html_fragment" => {
+            // Without a profile asked for, the one of the language if there
+            // is one, so that the whole fragment reads in it.
+            let locale = locale.or_else(|| {
+                locale::profile(&state.config.locales, lang.tag()).map(Cow::into_owned)
+            });
+            result.into_fragment_response(locale.as_ref(), lang)
+        }
+        _ => result.into_response(),
+    }))
+}
+
+async fn query_rows(state: &AppState, params: &Params) -> Result<SuccessResult, ErrorResult> {
+    let filters = params.filters();
+    // The schedule keeps results of the configured refresh, so a forced
+    // refresh or another refresh page goes to fava on its own.
+    let smoothed = filters.is_empty()
+        && params.refresh != Some(true)
+        && params
+            .refresh_path
+            .as_deref()
+            .is_none_or(|path| path == state.config.refresh_path);
+    let result = match (params.budget_ms, &state.config.poll_smoothing) {
+        (Some(budget_ms), _) => query_within_budget(state, params, budget_ms).await,
+        (None, Some(smoothing)) if smoothed => {
+            table_rows(smoothing::query(state, smoothing, &params.query_string).await)
+                .inspect(|parsed| state.metrics.rows_parsed(parsed.rows.len()))
+                .map(SuccessResult::from)
+        }
+        (None, _) => state
+            .session()
+            .refresh_path(params.refresh_path.as_deref())
+            .refresh(params.refresh)
+            .filtered(&filters)
+            .rows(&params.query_string)
+            .await
+            .map(SuccessResult::from),
+    }?;
+    // Rows are transformed first, so a search sees the renamed and computed
+    // columns.
+    let data = apply_transform(state, params.transform.as_deref(), result.data)?;
+    let columns = match params
+        .transform
+        .as_deref()
+        .and_then(|name| state.config.transforms.get(name))
+    {
+        Some(transform) => transform::rename_columns(transform, result.columns),
+        None => result.columns,
+    };
+    let data = match &params.invert_columns {
+        Some(columns) => transform::invert(data, columns).map_err(ErrorResult::bad_request)?,
+        None => data,
+    };
+    let mut meta = result.meta;
+    let unfiltered = data.len();
+    let data = match &params.row_filter {
+        Some(row_filter) => {
+            search::row_filter(data, row_filter).map_err(ErrorResult::bad_request)?
+        }
+        None => data,
+    };
+    let data = match &params.search {
+        Some(search) => search::filter(data, search, params.search_columns.as_deref()),
+        None => data,
+    };
+    if params.row_filter.is_some() || params.search.is_some() {
+        meta = Some(Meta {
+            total_rows: Some(TotalRows {
+                unfiltered,
+                filtered: data.len(),
+            }),
+            ..meta.unwrap_or_default()
+        });
+    }
+    let data = match (params.group_by.as_deref(), params.aggregate.as_deref()) {
+        (None, None) => data,
+        (group_by, aggregate) => {
+            let aggregate = match aggregate {
+                Some(aggregate) => {
+                    aggregate::Aggregate::parse(aggregate).map_err(ErrorResult::bad_request)?
+                }
+                None => aggregate::Aggregate::Count,
+            };
+            aggregate::aggregate(data, group_by.unwrap_or_default(), aggregate)
+                .map_err(ErrorResult::bad_request)?
+        }
+    };
+    let data = match &params.sort {
+        Some(order) => sort::sort(data, order).map_err(ErrorResult::bad_request)?,
+        None => data,
+    };
+    let data = match &params.columns {
+        Some(columns) => keep_columns(data, columns).map_err(ErrorResult::bad_request)?,
+        None => data,
+    };
+    // Pages are cut last, so that they count the rows a search kept.
+    let data = paginate(data, params.offset, params.limit, &mut meta);
+    // CSV columns come in the order `columns` asks for.
+    let columns = match &params.columns {
+        Some(columns) => columns
+            .split(',')
+            .map(|column| column.trim().to_string())
+            .filter(|column| !column.is_empty())
+            .collect(),
+        None => columns,
+    };
+    Ok(SuccessResult {
+        data,
+        meta,
+        typed: params.typed == Some(true),
+        columns,
+        ..result
+    })
+}
+
+/// Drops every column of `rows` but the comma separated `columns`, which
+/// a search or a sort may still have used.
+fn keep_columns(rows: Vec<Row>, columns: &str) -> Result<Vec<Row>, String> {
+    let columns: Vec<&str> = columns
+        .split(',')
+        .map(str::trim)
+        .filter(|column| !column.is_empty())
+        .collect();
+    if let Some(unknown) = columns
+        .iter()
+        .find(|column| !rows.is_empty() && !rows.iter().any(|row| row.contains_key(**column)))
+    {
+        return Err(format!("unknown column {}", unknown));
+    }
+    Ok(rows
+        .into_iter()
+        .map(|mut row| {
+            row.retain(|column, _| columns.contains(&column.as_str()));
+            row
+        })
+        .collect())
+}
+
+/// The `limit` rows from `offset` on, noting the page in `meta` when either
+/// is given.
+fn paginate<T>(
+    rows: Vec<T>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+    meta: &mut Option<Meta>,
+) -> Vec<T> {
+    if offset.is_none() && limit.is_none() {
+        return rows;
+    }
+    let total = rows.len();
+    let offset = offset.unwrap_or(0);
+    let rows: Vec<T> = rows
+        .into_iter()
+        .skip(offset)
+        .take(limit.unwrap_or(usize::MAX))
+        .collect();
+    let end = offset.saturating_add(rows.len());
+    *meta = Some(Meta {
+        page: Some(Page {
+            offset,
+            limit,
+            total,
+            next_offset: (end < total).then_some(end),
+        }),
+        ..meta.take().unwrap_or_default()
+    });
+    rows
+}
+
+/// Applies the named transform from the config, if any, to parsed rows.
+fn apply_transform(
+    state: &AppState,
+    name: Option<&str>,
+    rows: Vec<Row>,
+) -> Result<Vec<Row>, ErrorResult> {
+    let name = match name {
+        Some(name) => name,
+        None => return Ok(rows),
+    };
+    match state.config.transforms.get(name) {
+        Some(transform) => transform::apply(name, transform, rows).map_err(ErrorResult::new),
+        None => Err(ErrorResult {
+            status: StatusCode::BAD_REQUEST,
+            ..ErrorResult::message(Message::new("unknown_transform", vec![name.to_string()]))
+        }),
+    }
+}
+
+/// The formatting profile `name` of a request, or else the config's, for
+/// the outputs meant to be read by people.
+fn output_locale(
+    state: &AppState,
+    name: Option<&str>,
+) -> Result<Option<LocaleConfig>, ErrorResult> {
+    let name = match name.or(state.config.output_locale.as_deref()) {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+    match locale::profile(&state.config.locales, name) {
+        Some(locale) => Ok(Some(locale.into_owned())),
+        None => Err(ErrorResult {
+            status: StatusCode::BAD_REQUEST,
+            ..ErrorResult::message(Message::new("unknown_locale", vec![name.to_string()]))
+        }),
+    }
+}
+
+/// Races the query against `budget_ms`. When the budget runs out the
+/// upstream request is dropped, and the rows that arrived completely so far,
+/// of the HTML table or of the JSON API's rows, are returned as a partial
+/// result that is never cached.
+async fn query_within_budget(
+    state: &AppState,
+    params: &Params,
+    budget_ms: u64,
+) -> Result<SuccessResult, ErrorResult> {
+    let received = Mutex::new(partial::Received::default());
+    let filters = params.filters();
+    let session = state
+        .session()
+        .refresh_path(params.refresh_path.as_deref())
+        .refresh(params.refresh)
+        .filtered(&filters)
+        .streaming_into(&received);
+    let pipeline = session.query(&params.query_string);
+    match tokio::time::timeout(Duration::from_millis(budget_ms), pipeline).await {
+        Ok(result) => table_rows(result)
+            .inspect(|parsed| state.metrics.rows_parsed(parsed.rows.len()))
+            .map(SuccessResult::from),
+        Err(_) => {
+            let received = received.into_inner().unwrap();
+            let parsed = match partial::partial_table(&received.body) {
+                Some(table) => get_table_data(table),
+                None => partial::partial_rows(&received.body)
+                    .and_then(|body| serde_json::from_slice::<JsonQueryResult>(&body).ok())
+                    .and_then(|json| QueryResult::from(json).data)
+                    .map(QueryResultData::parse)
+                    .unwrap_or_default(),
+            };
+            state.metrics.rows_parsed(parsed.rows.len());
+            if parsed.rows.is_empty() {
+                return Err(ErrorResult {
+                    status: StatusCode::GATEWAY_TIMEOUT,
+                    ..ErrorResult::message(Message::new(
+                        "budget_exceeded",
+                        vec![budget_ms.to_string()],
+                    ))
+                });
+            }
+            let meta = Meta {
+                partial: Some(true),
+                parsed_rows: Some(parsed.rows.len()),
+                estimated_total_rows: received.estimate_total(parsed.rows.len()),
+                ..Meta::default()
+            };
+            Ok(SuccessResult {
+                meta: Some(meta),
+                ..SuccessResult::from(parsed)
+            })
+        }
+    }
+}
+
+fn table_rows(query_result: Result<QueryResult, UpstreamError>) -> Result<ParsedRows, ErrorResult> {
+    let result = successful(query_result)?;
+    let mut parsed = match result.data {
+        Some(data) => data.parse(),
+        None => ParsedRows::default(),
+    };
+    if let Some(age) = result.stale_for {
+        parsed.warnings.push(Message::new(
+            "stale_fallback",
+            vec![age.as_secs().to_string()],
+        ));
+        parsed.stale = true;
+    }
+    Ok(parsed)
+}
+
+/// The result of a query fava ran, or the error it gave.
+fn successful(
+    query_result: Result<QueryResult, UpstreamError>,
+) -> Result<QueryResult, ErrorResult> {
+    match query_result {
+        Ok(result) if result.success => Ok(result),
+        // Fava answers a query it could not run with its error.
+        Ok(result) => Err(match result.error {
+            Some(error) => ErrorResult {
+                error_code: Some("query_error".into()),
+                status: StatusCode::BAD_REQUEST,
+                ..ErrorResult::new(error)
+            },
+            None => ErrorResult {
+                status: StatusCode::BAD_GATEWAY,
+                ..ErrorResult::message(Message::new("something_went_wrong", Vec::new()))
+            },
+        }),
+        Err(e) => Err(ErrorResult::from(e)),
+    }
+}
+
+async fn query_balance(state: &AppState) -> Result<String, UpstreamError> {
+    let mut balances: Vec<String> = vec![];
+    let text = state
+        .session()
+        .page("/statistics/", &[("interval", "day")])
+        .await?;
+    let document = Document::from(text.as_str());
+    let balance_item = document
+        .select(".statistics-update-activity")
+        .select("tbody")
+        .select("tr");
+    balance_item.iter().for_each(|node| {
+        let account = node.select(".account").text().trim().to_string();
+        let balance = node.select("td.num").text().trim().to_string();
+        balances.push(format!("date balance {} {}", account, balance));
+    });
+    balances.sort();
+    Ok(balances.join("\r\n"))
+}
+
+/// One parsed row keyed by column name, ordered so that responses serialize
+/// the same way every time.
+type Row = BTreeMap<String, String>;
+
+/// Rows parsed from an upstream page, plus notes about markup that had to be
+/// skipped or patched up while parsing it.
+#[derive(Debug, Default)]
+struct ParsedRows {
+    rows: Vec<Row>,
+    /// The column names in the order of the query.
+    columns: Vec<String>,
+    warnings: Vec<Message>,
+    /// Set when the rows are an older result served because fava failed.
+    stale: bool,
+    /// Source text of each row, only collected with `include_raw`.
+    raw: Vec<journal::RawCells>,
+}
+
+fn get_table_data(table_str: String) -> ParsedRows {
+    let mut parsed = ParsedRows::default();
+    let mut rows = Vec::new();
+    parsed.columns = each_table_row(&table_str, &mut parsed.warnings, |_, row| {
+        rows.push(row);
+        true
+    });
+    parsed.rows = rows;
+    parsed
+}
+
+/// Parses the rows of an HTML table one by one, handing each to `emit`
+/// with the column names until it returns `false`, and returns the names.
+fn each_table_row(
+    table_str: &str,
+    warnings: &mut Vec<Message>,
+    mut emit: impl FnMut(&[String], Row) -> bool,
+) -> Vec<String> {
+    let document = Document::from(table_str);
+    let table_title = document.select("thead").select("tr").select("th");
+    let table_lines = document.select("tbody").select("tr");
+    let mut titles = Vec::new();
+    table_title.iter().enumerate().for_each(|(i, node)| {
+        let title = node.text().to_string();
+        if title.trim().is_empty() {
+            warnings.push(Message::new(
+                "column_without_header",
+                vec![(i + 1).to_string(), format!("column_{}", i + 1)],
+            ));
+            titles.push(format!("column_{}", i + 1));
+        } else {
+            titles.push(title);
+        }
+    });
+
+    for (row, node) in table_lines.iter().enumerate() {
+        let mut line = Row::new();
+
+        let cells = node.select("td");
+        if cells.length() > titles.len() {
+            warnings.push(Message::new(
+                "extra_cells",
+                vec![
+                    (row + 1).to_string(),
+                    cells.length().to_string(),
+                    titles.len().to_string(),
+                ],
+            ));
+        }
+        for (title, el) in titles.iter().zip(cells.iter()) {
+            let value = el.text().trim().to_string();
+            line.insert(title.to_string(), value);
+        }
+        if !emit(&titles, line) {
+            break;
+        }
+    }
+    titles
+}
+
+/// `/api/account/A,B`: the journals of several accounts fetched at once
+/// and combined into one series, see [`merge::series`].
+async fn combined_account(
+    state: &AppState,
+    accounts: &[&str],
+    params: &AccountParams,
+) -> Result<SuccessResult, ErrorResult> {
+    if accounts.len() > merge::MAX_ACCOUNTS {
+        return Err(ErrorResult::bad_request(format!(
+            "more than {} accounts requested",
+            merge::MAX_ACCOUNTS
+        )));
+    }
+    if let Some(format) = params
+        .format
+        .as_deref()
+        .filter(|format| !matches!(*format, "json" | "csv"))
+    {
+        return Err(ErrorResult::bad_request(format!(
+            "format {} takes a single account",
+            format
+        )));
+    }
+    let tasks: Vec<_> = accounts
+        .iter()
+        .map(|account| {
+            let state = state.clone();
+            let account = account.to_string();
+            let refresh_path = params.refresh_path.clone();
+            let refresh = params.refresh;
+            tokio::spawn(async move {
+                state
+                    .session()
+                    .refresh_path(refresh_path.as_deref())
+                    .refresh(refresh)
+                    .account_journal(&account)
+                    .await
+            })
+        })
+        .collect();
+    let mut series = Vec::new();
+    let mut warnings = Vec::new();
+    let mut pages = 0;
+    for task in tasks {
+        let journal = task.await.map_err(|e| ErrorResult::new(e.to_string()))??;
+        let parsed = get_account_data(&journal.entries, params);
+        warnings.extend(parsed.warnings);
+        series.push(parsed.rows);
+        pages += journal.pages;
+    }
+    let mut rows = merge::series(series);
+    if let Some(columns) = &params.invert_columns {
+        rows = transform::invert(rows, columns).map_err(ErrorResult::bad_request)?;
+    }
+    let mut meta = None;
+    let rows = paginate(rows, params.offset, params.limit, &mut meta);
+    if state.config.journal_by_year {
+        meta = Some(Meta {
+            upstream_pages: Some(pages),
+            ..meta.unwrap_or_default()
+        });
+    }
+    Ok(SuccessResult {
+        meta,
+        typed: params.typed == Some(true),
+        ..SuccessResult::from(ParsedRows {
+            rows,
+            columns: vec!["date".into(), "changed".into(), "balance".into()],
+            warnings,
+            ..ParsedRows::default()
+        })
+    })
+}
+
+fn get_account_data(entries: &[journal::JournalEntry], params: &AccountParams) -> ParsedRows {
+    let mut parsed = ParsedRows::default();
+    let mut scales = amount::Scales::default();
+    let mut positions = Vec::new();
+    for entry in entries {
+        let change = amount::parse_positions(&entry.change);
+        let balance = amount::parse_positions(&entry.balance);
+        for (number, currency) in change.iter().chain(balance.iter()).flatten() {
+            scales.observe(currency.as_deref(), *number);
+        }
+        positions.push((change, balance));
+    }
+
+    entries
+        .iter()
+        .zip(positions)
+        .enumerate()
+        .for_each(|(row, (entry, (change, balance)))| {
+            let mut result_item = Row::new();
+            let date = entry.date.clone();
+            if date.is_empty() {
+                parsed.warnings.push(Message::new(
+                    "transaction_without_date",
+                    vec![(row + 1).to_string()],
+                ));
+                return;
+            }
+            if parsed
+                .rows
+                .iter()
+                .any(|item| item.get("date") == Some(&date))
+            {
+                return;
+            }
+            // Cells missing from the page are zero in the commodity of the
+            // other, at its usual scale.
+            let currency = change
+                .iter()
+                .chain(balance.iter())
+                .flatten()
+                .next()
+                .and_then(|(_, currency)| currency.clone());
+            let zero = Decimal::new(0, scales.common(currency.as_deref()));
+            let mut cell = |key: &'static str, text: &str, positions: Option<Vec<_>>| {
+                positions.unwrap_or_else(|| {
+                    if !text.trim().is_empty() {
+                        parsed.warnings.push(Message::new(
+                            key,
+                            vec![(row + 1).to_string(), text.trim().to_string()],
+                        ));
+                    }
+                    vec![(zero, currency.clone())]
+                })
+            };
+            let mut changed = cell("unreadable_change", &entry.change, change);
+            let mut balance = cell("unreadable_balance", &entry.balance, balance);
+
+            if Some(true) == params.negate {
+                for (number, _) in changed.iter_mut().chain(balance.iter_mut()) {
+                    *number = -*number;
+                }
+            }
+            result_item.insert("date".into(), date);
+            result_item.insert("changed".into(), amount::format_positions(&changed));
+            result_item.insert("balance".into(), amount::format_positions(&balance));
+            parsed.rows.push(result_item);
+            if Some(true) == params.include_raw {
+                parsed.raw.push(entry.raw.clone());
+            }
+        });
+    parsed.rows.reverse();
+    parsed.raw.reverse();
+    parsed.columns = vec!["date".into(), "changed".into(), "balance".into()];
+    parsed
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Params {
+    query_string: String,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    account: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    filter: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    time: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    refresh_path: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    refresh: Option<bool>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    budget_ms: Option<u64>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    transform: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    invert_columns: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    row_filter: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    search: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    search_columns: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    group_by: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    aggregate: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    sort: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    columns: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    offset: Option<usize>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    limit: Option<usize>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    typed: Option<bool>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    format: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    output_locale: Option<String>,
+}
+
+impl Params {
+    /// fava's `account`, `filter` and `time` filters the query runs with.
+    fn filters(&self) -> Vec<(&str, &str)> {
+        [
+            ("account", &self.account),
+            ("filter", &self.filter),
+            ("time", &self.time),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| Some((name, value.as_deref()?)))
+        .collect()
+    }
+}
+
+impl QueryFields for Params {
+    const FIELDS: &[&str] = &[
+        "query_string",
+        "account",
+        "filter",
+        "time",
+        "refresh_path",
+        "refresh",
+        "budget_ms",
+        "transform",
+        "invert_columns",
+        "row_filter",
+        "search",
+        "search_columns",
+        "group_by",
+        "aggregate",
+        "sort",
+        "columns",
+        "offset",
+        "limit",
+        "typed",
+        "format",
+        "output_locale",
+    ];
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[allow(dead_code)]
+struct AccountParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    negate: Option<bool>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    invert_columns: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    refresh_path: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    refresh: Option<bool>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    format: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    balancing_account: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    include_raw: Option<bool>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    offset: Option<usize>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    limit: Option<usize>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    typed: Option<bool>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    output_locale: Option<String>,
+}
+
+impl QueryFields for AccountParams {
+    const FIELDS: &[&str] = &[
+        "negate",
+        "invert_columns",
+        "refresh_path",
+        "refresh",
+        "format",
+        "balancing_account",
+        "include_raw",
+        "offset",
+        "limit",
+        "typed",
+        "output_locale",
+    ];
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct QueryResult {
+    error: Option<String>,
+    success: bool,
+    data: Option<QueryResultData>,
+    /// Age of a result served because fava failed.
+    #[serde(skip)]
+    stale_for: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct QueryResultData {
+    /// The HTML table of `/api/query_result`.
+    #[serde(default)]
+    table: String,
+    /// The table of `/api/query`, in place of the HTML one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    json: Option<backend::JsonTable>,
+}
+
+impl QueryResultData {
+    fn parse(self) -> ParsedRows {
+        match self.json {
+            Some(table) => table.parse(),
+            None => get_table_data(self.table),
+        }
+    }
+
+    /// Parses the rows one by one, see [`each_table_row`].
+    fn each_row(
+        self,
+        warnings: &mut Vec<Message>,
+        emit: impl FnMut(&[String], Row) -> bool,
+    ) -> Vec<String> {
+        match self.json {
+            Some(table) => table.each_row(warnings, emit),
+            None => each_table_row(&self.table, warnings, emit),
+        }
+    }
+
+    /// The result's columns and, from the JSON API, their types.
+    fn schema(&self) -> Vec<(String, Option<String>)> {
+        match &self.json {
+            Some(table) => table.schema(),
+            None => Document::from(self.table.as_str())
+                .select("thead tr th")
+                .iter()
+                .map(|title| (title.text().trim().to_string(), None))
+                .collect(),
+        }
+    }
+}
+
+/// Serde deserialization decorator to map empty Strings to None,
+fn empty_string_as_none<'de, D, T>(de: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    let opt = Option::<String>::deserialize(de)?;
+    match opt.as_deref() {
+        None | Some("") => Ok(None),
+        Some(s) => FromStr::from_str(s).map_err(de::Error::custom).map(Some),
+    }
+}
+
+/// Errors of an upstream call whose body is decoded separately.
+#[derive(Debug)]
+enum UpstreamError {
+    Http(reqwest::Error),
+    Decode(serde_json::Error),
+    /// Fava is down for maintenance and expects to be back after this long.
+    Unavailable(Duration),
+    /// The configured url answers, but not like fava does.
+    NotFava(String),
+    /// Fava has no page for this account.
+    UnknownAccount(String),
+}
+
+impl UpstreamError {
+    /// Whether the response arrived but could not be read as fava's API.
+    fn is_decode(&self) -> bool {
+        match self {
+            UpstreamError::Http(e) => e.is_decode(),
+            UpstreamError::Decode(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether fava could not be reached or is down for maintenance.
+    fn is_unreachable(&self) -> bool {
+        match self {
+            UpstreamError::Http(e) => !e.is_decode(),
+            UpstreamError::Unavailable(_) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for UpstreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpstreamError::Http(e) => e.fmt(f),
+            UpstreamError::Decode(e) => write!(f, "error decoding response body: {}", e),
+            UpstreamError::Unavailable(retry_after) => write!(
+                f,
+                "fava is temporarily unavailable, retry after {} seconds",
+                retry_after_secs(*retry_after)
+            ),
+            UpstreamError::NotFava(mismatch) => mismatch.fmt(f),
+            UpstreamError::UnknownAccount(account) => write!(f, "unknown account {}", account),
+        }
+    }
+}
+
+impl From<reqwest::Error> for UpstreamError {
+    fn from(e: reqwest::Error) -> UpstreamError {
+        UpstreamError::Http(e)
+    }
+}
+
+impl From<serde_json::Error> for UpstreamError {
+    fn from(e: serde_json::Error) -> UpstreamError {
+        UpstreamError::Decode(e)
+    }
+}
+
+fn retry_after_secs(retry_after: Duration) -> u64 {
+    retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0)
+}
+
+#[derive(Debug)]
+struct ErrorResult {
+    error: String,
+    success: bool,
+    error_code: Option<String>,
+    status: StatusCode,
+    retry_after: Option<u64>,
+    /// The generated error text, kept to render it in the client's language.
+    message: Option<Box<Message>>,
+}
+
+impl ErrorResult {
+    fn new(error: String) -> ErrorResult {
+        ErrorResult {
+            error,
+            success: false,
+            error_code: None,
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            retry_after: None,
+            message: None,
+        }
+    }
+
+    /// An error with a generated text, coded by the text's key.
+    fn message(message: Message) -> ErrorResult {
+        ErrorResult {
+            error_code: Some(message.key().to_string()),
+            message: Some(Box::new(message.clone())),
+            ..ErrorResult::new(message.to_string())
+        }
+    }
+
+    fn localize(self, lang: Lang) -> ErrorResult {
+        match self.message {
+            Some(message) => {
+                let message = message.localize(lang);
+                ErrorResult {
+                    error: message.to_string(),
+                    message: Some(Box::new(message)),
+                    ..self
+                }
+            }
+            None => self,
+        }
+    }
+
+    /// The machine-readable code of the error, the same in every language:
+    /// its own, or else one for its status.
+    fn code(&self) -> &str {
+        if let Some(code) = &self.error_code {
+            return code;
+        }
+        match self.status {
+            StatusCode::BAD_REQUEST => "bad_request",
+            StatusCode::NOT_FOUND => "not_found",
+            StatusCode::BAD_GATEWAY => "upstream_error",
+            StatusCode::SERVICE_UNAVAILABLE => "unavailable",
+            StatusCode::GATEWAY_TIMEOUT => "timeout",
+            _ => "internal_error",
+        }
+    }
+
+    fn bad_request(error: String) -> ErrorResult {
+        ErrorResult {
+            error_code: Some("bad_request".into()),
+            status: StatusCode::BAD_REQUEST,
+            ..ErrorResult::new(error)
+        }
+    }
+}
+
+impl From<UpstreamError> for ErrorResult {
+    fn from(e: UpstreamError) -> ErrorResult {
+        match e {
+            UpstreamError::Unavailable(retry_after) => ErrorResult {
+                error_code: Some("upstream_unavailable".into()),
+                status: StatusCode::SERVICE_UNAVAILABLE,
+                retry_after: Some(retry_after_secs(retry_after)),
+                ..ErrorResult::new(e.to_string())
+            },
+            UpstreamError::NotFava(_) => ErrorResult {
+                error_code: Some("upstream_not_fava".into()),
+                status: StatusCode::BAD_GATEWAY,
+                ..ErrorResult::new(e.to_string())
+            },
+            UpstreamError::UnknownAccount(_) => ErrorResult {
+                error_code: Some("unknown_account".into()),
+                status: StatusCode::NOT_FOUND,
+                ..ErrorResult::new(e.to_string())
+            },
+            UpstreamError::Http(ref http) if http.is_timeout() => ErrorResult {
+                error_code: Some("upstream_timeout".into()),
+                status: StatusCode::GATEWAY_TIMEOUT,
+                ..ErrorResult::new(e.to_string())
+            },
+            e if e.is_decode() => ErrorResult {
+                error_code: Some("upstream_invalid_response".into()),
+                status: StatusCode::BAD_GATEWAY,
+                ..ErrorResult::new(e.to_string())
+            },
+            e => ErrorResult {
+                error_code: Some("upstream_unreachable".into()),
+                status: StatusCode::BAD_GATEWAY,
+                ..ErrorResult::new(e.to_string())
+            },
+        }
+    }
+}
+
+/// `{success, error, code}`, and `error_code` as before `code` was given
+/// to every error.
+impl Serialize for ErrorResult {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut result = serializer.serialize_struct("ErrorResult", 4)?;
+        result.serialize_field("error", &self.error)?;
+        result.serialize_field("success", &self.success)?;
+        result.serialize_field("code", self.code())?;
+        if let Some(error_code) = &self.error_code {
+            result.serialize_field("error_code", error_code)?;
+        }
+        result.end()
+    }
+}
+
+impl IntoResponse for ErrorResult {
+    fn into_response(self) -> Response {
+        let status = self.status;
+        let retry_after = self.retry_after;
+        let mut response = (status, Json(self)).into_response();
+        if let Some(retry_after) = retry_after {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, retry_after.into());
+        }
+        response
+    }
+}
+
+#[derive(Debug)]
+struct SuccessResult {
+    success: bool,
+    data: Vec<Row>,
+    warnings: Vec<Message>,
+    meta: Option<Meta>,
+    /// Renders `data` with JSON types, see [`typed::cell`].
+    typed: bool,
+    /// The column order for CSV, which the rows do not keep.
+    columns: Vec<String>,
+}
+
+impl Serialize for SuccessResult {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut result = serializer.serialize_struct("SuccessResult", 4)?;
+        result.serialize_field("success", &self.success)?;
+        let columns = self.ordered_columns();
+        let data: Vec<OrderedRow<&str>> = self
+            .data
+            .iter()
+            .map(|row| OrderedRow {
+                row,
+                columns: &columns,
+                typed: self.typed,
+            })
+            .collect();
+        result.serialize_field("data", &data)?;
+        if !self.warnings.is_empty() {
+            result.serialize_field("warnings", &self.warnings)?;
+        }
+        if let Some(meta) = &self.meta {
+            result.serialize_field("meta", meta)?;
+        }
+        result.end()
+    }
+}
+
+/// A row with its cells in the order of `columns`, as in the query's
+/// `SELECT`, which a `Row` does not keep. Cells of other columns follow.
+struct OrderedRow<'a, C> {
+    row: &'a Row,
+    columns: &'a [C],
+    /// Renders the cells with JSON types, see [`typed::cell`].
+    typed: bool,
+}
+
+impl<C: AsRef<str>> Serialize for OrderedRow<'_, C> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.row.len()))?;
+        let mut entry = |column: &str, text: &String| match self.typed {
+            true => map.serialize_entry(column, &typed::cell(text)),
+            false => map.serialize_entry(column, text),
+        };
+        for column in self.columns {
+            if let Some(text) = self.row.get(column.as_ref()) {
+                entry(column.as_ref(), text)?;
+            }
+        }
+        for (column, text) in self.row {
+            if !self.columns.iter().any(|known| known.as_ref() == column) {
+                entry(column, text)?;
+            }
+        }
+        map.end()
+    }
+}
+
+/// Extra information about how a response was produced.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Meta {
+    /// Set when the response only holds the rows parsed before a deadline.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    partial: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parsed_rows: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    estimated_total_rows: Option<usize>,
+    /// How the response came from a cache, `stale_error_fallback` for an
+    /// older result served because fava failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cached: Option<String>,
+    /// Account pages fetched from fava for a journal split by year.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upstream_pages: Option<usize>,
+    /// Row counts before and after the `search` parameter was applied.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total_rows: Option<TotalRows>,
+    /// The rows returned with `offset` and `limit`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page: Option<Page>,
+    /// The BQL an endpoint generated, with `explain=true`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    generated_queries: Option<Vec<GeneratedQuery>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TotalRows {
+    unfiltered: usize,
+    filtered: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Page {
+    offset: usize,
+    limit: Option<usize>,
+    /// Rows before the page was cut.
+    total: usize,
+    /// The `offset` of the next page, unless this one is the last.
+    next_offset: Option<usize>,
+}
+
+/// A query built by an endpoint, as it can be pasted into fava's query box.
+#[derive(Debug, Serialize, Deserialize)]
+struct GeneratedQuery {
+    query: String,
+    /// The conditions of the `WHERE` clause.
+    filters: Vec<String>,
+    /// The part of the response computed from the query's rows.
+    feeds: String,
+}
+
+impl Meta {
+    /// Meta listing `queries`, or none if the client did not ask to explain.
+    fn explain(explain: Option<bool>, queries: Vec<GeneratedQuery>) -> Option<Meta> {
+        if explain != Some(true) {
+            return None;
+        }
+        Some(Meta {
+            generated_queries: Some(queries),
+            ..Meta::default()
+        })
+    }
+}
+
+impl SuccessResult {
+    fn new(data: Vec<Row>) -> SuccessResult {
+        SuccessResult {
+            success: true,
+            data,
+            warnings: Vec::new(),
+            meta: None,
+            typed: false,
+            columns: Vec::new(),
+        }
+    }
+
+    /// The columns of `data`, in the order of `columns` and then of any
+    /// columns a transform or an aggregate added.
+    fn ordered_columns(&self) -> Vec<&str> {
+        let mut columns: Vec<&str> = self
+            .columns
+            .iter()
+            .map(String::as_str)
+            .filter(|column| {
+                self.data.is_empty() || self.data.iter().any(|row| row.contains_key(*column))
+            })
+            .collect();
+        for row in &self.data {
+            for column in row.keys() {
+                if !columns.contains(&column.as_str()) {
+                    columns.push(column);
+                }
+            }
+        }
+        columns
+    }
+
+    fn csv(&self, locale: Option<&LocaleConfig>) -> String {
+        csv::write(&self.ordered_columns(), &self.data, locale)
+    }
+
+    fn into_csv_response(self, locale: Option<&LocaleConfig>) -> Response {
+        (
+            [(CONTENT_TYPE, "text/csv; charset=utf-8")],
+            self.csv(locale),
+        )
+            .into_response()
+    }
+
+    fn into_fragment_response(self, locale: Option<&LocaleConfig>, lang: Lang) -> Response {
+        let fragment = fragment::write(
+            &self.ordered_columns(),
+            &self.data,
+            &self.warnings,
+            locale,
+            lang,
+        );
+        ([(CONTENT_TYPE, "text/html; charset=utf-8")], fragment).into_response()
+    }
+
+    fn into_arrow_response(self) -> Response {
+        let stream = arrow::write(&self.ordered_columns(), &self.data);
+        (
+            [
+                (CONTENT_TYPE, "application/vnd.apache.arrow.stream"),
+                (CONTENT_DISPOSITION, "attachment; filename=\"query.arrows\""),
+            ],
+            stream,
+        )
+            .into_response()
+    }
+
+    fn into_xlsx_response(self, locale: Option<&LocaleConfig>) -> Response {
+        let workbook = xlsx::write(&self.ordered_columns(), &self.data, locale);
+        (
+            [
+                (
+                    CONTENT_TYPE,
+                    "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+                ),
+                (CONTENT_DISPOSITION, "attachment; filename=\"query.xlsx\""),
+            ],
+            workbook,
+        )
+            .into_response()
+    }
+
+    /// Renders the warnings in `lang`.
+    fn localize(self, lang: Lang) -> SuccessResult {
+        SuccessResult {
+            warnings: self
+                .warnings
+                .into_iter()
+                .map(|warning| warning.localize(lang))
+                .collect(),
+            ..self
+        }
+    }
+}
+
+impl From<ParsedRows> for SuccessResult {
+    fn from(parsed: ParsedRows) -> SuccessResult {
+        let meta = parsed.stale.then(|| Meta {
+            cached: Some("stale_error_fallback".into()),
+            ..Meta::default()
+        });
+        SuccessResult {
+            warnings: parsed.warnings,
+            meta,
+            columns: parsed.columns,
+            ..SuccessResult::new(parsed.rows)
+        }
+    }
+}
+
+impl IntoResponse for SuccessResult {
+    fn into_response(self) -> Response {
+        let body = Json(self);
+        (StatusCode::OK, body).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{boxed, Body},
+        extract::Query,
+        http::{Request, StatusCode},
+        routing::get,
+        Router,
+    };
+    use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+    use std::{collections::BTreeMap, time::Duration};
+
+    use crate::{
+        backend::Backend,
+        config::Config,
+        fuzzing, get_table_data,
+        testing::{self, FixtureSet, Hits},
+        AppState,
+    };
+
+    /// Cases each property is checked with, from a fixed seed so that a
+    /// failure comes back on every run.
+    const CASES: usize = 300;
+
+    /// Bits of cell text: entities, nested and stray markup, numbers and
+    /// amounts, other scripts and one huge run.
+    const PIECES: &[&str] = &[
+        "",
+        " ",
+        "Assets:Cash",
+        "&amp;",
+        "&lt;td&gt;",
+        "&#x1F600;",
+        "&bogus;",
+        "<b>bold</b>",
+        "<span><i>nested <em>deep</em></i></span>",
+        "<br>",
+        "</td>",
+        "</tr><tr>",
+        "<!--",
+        "<table><tr><td>inner</td></tr></table>",
+        "1,234.56 CNY",
+        "-0.00 USD",
+        "5 USD<br>3.00 CNY",
+        "12 ABC {1.00 USD}",
+        "2024-01-02",
+        "2024-02-30",
+        "中文",
+        "\"quoted\"",
+    ];
+
+    fn text(rng: &mut StdRng) -> String {
+        if rng.gen_ratio(1, 50) {
+            return "x".repeat(rng.gen_range(1_000..20_000));
+        }
+        (0..rng.gen_range(0..4))
+            .map(|_| *PIECES.choose(rng).unwrap())
+            .collect()
+    }
+
+    /// Closes `tag` most of the time.
+    fn close(rng: &mut StdRng, tag: &str) -> String {
+        match rng.gen_ratio(9, 10) {
+            true => format!("</{}>", tag),
+            false => String::new(),
+        }
+    }
+
+    /// Cuts `html` short at a random character now and then, like a
+    /// response that ended early.
+    fn truncated(rng: &mut StdRng, html: String) -> String {
+        if html.is_empty() || !rng.gen_ratio(1, 5) {
+            return html;
+        }
+        let cut = rng.gen_range(0..html.chars().count());
+        html.chars().take(cut).collect()
+    }
+
+    fn table(rng: &mut StdRng) -> String {
+        let mut html = String::from("<table class=\"queryresults\">");
+        if rng.gen_ratio(9, 10) {
+            html.push_str("<thead><tr>");
+            for _ in 0..rng.gen_range(0..7) {
+                html.push_str(&format!("<th>{}{}", text(rng), close(rng, "th")));
+            }
+            html.push_str("</tr></thead>");
+        }
+        html.push_str("<tbody>");
+        for _ in 0..rng.gen_range(0..10) {
+            html.push_str("<tr>");
+            for _ in 0..rng.gen_range(0..9) {
+                html.push_str(&format!("<td>{}{}", text(rng), close(rng, "td")));
+            }
+            html.push_str(&close(rng, "tr"));
+        }
+        html.push_str("</tbody></table>");
+        truncated(rng, html)
+    }
+
+    fn journal(rng: &mut StdRng) -> String {
+        const DATES: &[&str] = &["2024-01-01", "2024-01-02", "", " ", "not a date"];
+        let mut html = String::from("<ol class=\"flex-table\">");
+        for _ in 0..rng.gen_range(0..12) {
+            html.push_str(&format!(
+                "<li class=\"transaction {}\"><p>\
+                 <span class=\"datecell\">{}</span><span class=\"flag\">*</span>\
+                 <span class=\"description\"><span class=\"payee\">{}</span>{}</span>\
+                 <span class=\"change num\">{}</span><span class=\"num\"></span>\
+                 <span class=\"num\">{}</span></p>\
+                 <ul class=\"postings\"><li><span class=\"account\">{}</span></li></ul>{}",
+                text(rng),
+                DATES.choose(rng).unwrap(),
+                text(rng),
+                text(rng),
+                text(rng),
+                text(rng),
+                text(rng),
+                close(rng, "li"),
+            ));
+        }
+        html.push_str("</ol>");
+        truncated(rng, html)
+    }
+
+    #[test]
+    fn table_rows_only_have_keys_of_the_columns() {
+        let mut rng = StdRng::seed_from_u64(207);
+        for _ in 0..CASES {
+            fuzzing::table_data(&table(&mut rng));
+        }
+    }
+
+    #[test]
+    fn account_rows_read_back_as_positions() {
+        let mut rng = StdRng::seed_from_u64(207);
+        for _ in 0..CASES {
+            fuzzing::account_data(&journal(&mut rng));
+        }
+    }
+
+    #[test]
+    fn parsers_take_any_text() {
+        let mut rng = StdRng::seed_from_u64(207);
+        for _ in 0..CASES {
+            let length = rng.gen_range(0..200);
+            let bytes: Vec<u8> = (0..length).map(|_| rng.gen()).collect();
+            let html = String::from_utf8_lossy(&bytes);
+            fuzzing::table_data(&html);
+            fuzzing::account_data(&html);
+        }
+    }
+
+    #[test]
+    fn reads_the_columns_of_each_captured_query() {
+        for set in FixtureSet::all() {
+            let envelope: serde_json::Value =
+                serde_json::from_str(&set.file("query_result")).unwrap();
+            let parsed = get_table_data(envelope["data"]["table"].as_str().unwrap().to_string());
+            let select = set.query["SELECT ".len()..].split(" where").next().unwrap();
+            let columns: Vec<&str> = select.split(", ").collect();
+            assert_eq!(parsed.columns, columns, "fava {}", set.fava_version);
+            assert!(!parsed.rows.is_empty(), "fava {}", set.fava_version);
+            assert!(parsed.warnings.is_empty(), "fava {}", set.fava_version);
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_each_captured_fava() {
+        for set in FixtureSet::all() {
+            let config = Config::new(set.fava().await).backend(Backend::Html);
+            let state = AppState::new(config);
+            let query = percent_encoding::utf8_percent_encode(
+                &set.query,
+                percent_encoding::NON_ALPHANUMERIC,
+            );
+            let uri = format!("/api/query_result?query_string={}", query);
+            let (status, body) = testing::get(&state, &uri).await;
+            assert_eq!(status, 200, "fava {}: {}", set.fava_version, body);
+            let (status, body) =
+                testing::get(&state, &format!("/api/account/{}", set.account)).await;
+            assert_eq!(status, 200, "fava {}: {}", set.fava_version, body);
+            let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert!(
+                !body["data"].as_array().unwrap().is_empty(),
+                "fava {}",
+                set.fava_version
+            );
+        }
+    }
+
+    /// The text of the span whose opening tag starts with the first
+    /// `marker` in `html`, its tags left out.
+    fn span_text(html: &str, marker: &str) -> String {
+        let start = html.find(marker).unwrap() + marker.len();
+        let start = start + html[start..].find('>').unwrap() + 1;
+        let span = &html[start..start + html[start..].find("</span>").unwrap()];
+        let mut text = String::new();
+        let mut in_tag = false;
+        for c in span.chars() {
+            match c {
+                '<' => in_tag = true,
+                '>' => in_tag = false,
+                c if !in_tag => text.push(c),
+                _ => {}
+            }
+        }
+        text
+    }
+
+    #[tokio::test]
+    async fn includes_the_raw_text_of_each_captured_row() {
+        for set in FixtureSet::all() {
+            let config = Config::new(set.fava().await).backend(Backend::Html);
+            let state = AppState::new(config);
+            let account = format!("/api/account/{}", set.account);
+            let (_, body) = testing::get(&state, &account).await;
+            assert!(!body.contains("_raw"), "fava {}", set.fava_version);
+
+            let (_, body) = testing::get(&state, &format!("{}?include_raw=true", account)).await;
+            let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+            let rows = body["data"].as_array().unwrap();
+            // The rows come oldest first, fava's journal newest first.
+            let html = set.file("account");
+            let mut lines: Vec<&str> = html.split("<li class=\"transaction ").skip(1).collect();
+            lines.reverse();
+            assert_eq!(rows.len(), lines.len(), "fava {}", set.fava_version);
+            for (row, line) in rows.iter().zip(lines) {
+                let raw = &row["_raw"];
+                let classes = &line[..line.find('"').unwrap()];
+                let change = span_text(line, "<span class=\"num change\"");
+                let balance = span_text(line, &format!("{}</span><span class=\"num\"", change));
+                let classes: Vec<String> = ["transaction"]
+                    .into_iter()
+                    .chain(classes.split_whitespace())
+                    .map(str::to_string)
+                    .collect();
+                assert_eq!(raw["date"], span_text(line, "<span class=\"datecell\""));
+                assert_eq!(raw["change"], change);
+                assert_eq!(raw["balance"], balance);
+                assert_eq!(raw["classes"], serde_json::json!(classes));
+                assert_eq!(row["date"], raw["date"]);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn answers_each_captured_fava_like_its_snapshots() {
+        for set in FixtureSet::all() {
+            let config = Config::new(set.fava().await).backend(Backend::Html);
+            let state = AppState::new(config);
+            let query = percent_encoding::utf8_percent_encode(
+                &set.query,
+                percent_encoding::NON_ALPHANUMERIC,
+            );
+            let query = format!("/api/query_result?query_string={}", query);
+            let account = format!("/api/account/{}", set.account);
+            let cases = [
+                ("query_result.json", format!("{}&format=json", query)),
+                ("query_result.typed.json", format!("{}&typed=true", query)),
+                ("query_result.csv", format!("{}&format=csv", query)),
+                ("query_result.ndjson", format!("{}&format=ndjson", query)),
+                ("query_result.arrow", format!("{}&format=arrow", query)),
+                ("query_result.xlsx", format!("{}&format=xlsx", query)),
+                (
+                    "query_result.html",
+                    format!("{}&format=html_fragment", query),
+                ),
+                (
+                    "query_result.zh.html",
+                    format!("{}&format=html_fragment&lang=zh", query),
+                ),
+                ("account.json", account.clone()),
+                ("account.typed.json", format!("{}?typed=true", account)),
+                ("account.csv", format!("{}?format=csv", account)),
+                ("account.beancount", format!("{}?format=beancount", account)),
+                ("errors.json", "/api/errors".to_string()),
+                ("version.json", "/api/version".to_string()),
+            ];
+            let mut changed = Vec::new();
+            for (name, uri) in cases {
+                let request = Request::get(&uri).body(Body::empty()).unwrap();
+                let (status, _, body) = testing::send(&state, request).await;
+                assert_eq!(status, 200, "fava {} {}", set.fava_version, uri);
+                if !set.matches_snapshot(name, &body) {
+                    changed.push(name);
+                }
+            }
+            assert!(
+                changed.is_empty(),
+                "fava {}: {:?} differ from their snapshots, see testing.rs to update them",
+                set.fava_version,
+                changed
+            );
+        }
+    }
+
+    /// A fava whose first answer to `SELECT n` stops after `first`, and
+    /// whose other ones are `whole`, with a `changed` API so results are
+    /// cached.
+    async fn stalling(backend: Backend, first: &'static str, whole: &'static str) -> AppState {
+        let path = match backend {
+            Backend::Json => "/api/query",
+            _ => "/api/query_result",
+        };
+        let hits = Hits::default();
+        let fava = Router::new()
+            .route(
+                path,
+                get(
+                    move |Query(query): Query<BTreeMap<String, String>>| async move {
+                        // The digest of the cache has to answer.
+                        if query["query_string"] != "SELECT n" {
+                            return boxed(Body::from(whole));
+                        }
+                        hits.hit();
+                        if hits.count() > 1 {
+                            return boxed(Body::from(whole));
+                        }
+                        let (mut sender, body) = Body::channel();
+                        tokio::spawn(async move {
+                            sender.send_data(first.into()).await.unwrap();
+                            tokio::time::sleep(Duration::from_secs(60)).await;
+                            drop(sender);
+                        });
+                        boxed(body)
+                    },
+                ),
+            )
+            .route(
+                "/api/changed",
+                get(|| async { "{\"success\": true, \"data\": false}" }),
+            );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(backend)
+            .refresh_path("none");
+        AppState::new(config)
+    }
+
+    const TABLE: &str = "{\"success\": true, \"data\": {\"table\": \"<table><thead><tr><th>n</th></tr></thead><tbody><tr><td>1</td></tr><tr><td>2</td></tr></tbody></table>\"}}";
+    const ROWS: &str = r#"{"success": true, "data": {"t": "table", "types": [{"name": "n", "dtype": "int"}], "rows": [[1], [2]]}}"#;
+
+    async fn query(state: &AppState, budget: &str) -> (StatusCode, serde_json::Value) {
+        let uri = format!("/api/query_result?query_string=SELECT%20n{}", budget);
+        let (status, body) = testing::get(state, &uri).await;
+        (status, serde_json::from_str(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn answers_with_the_rows_parsed_within_the_budget_but_never_caches_them() {
+        for (backend, whole) in [(Backend::Html, TABLE), (Backend::Json, ROWS)] {
+            let first = &whole[..whole.find("2").unwrap()];
+            let state = stalling(backend, first, whole).await;
+            let (status, body) = query(&state, "&budget_ms=200").await;
+            assert_eq!(status, StatusCode::OK, "{:?}", backend);
+            assert_eq!(
+                body["data"],
+                serde_json::json!([{ "n": "1" }]),
+                "{:?}",
+                backend
+            );
+            assert_eq!(body["meta"]["partial"], true);
+            assert_eq!(body["meta"]["parsed_rows"], 1);
+            // The next request asks fava again instead of taking the part.
+            let (_, body) = query(&state, "").await;
+            assert_eq!(body["data"].as_array().unwrap().len(), 2, "{:?}", backend);
+            assert!(body.get("meta").is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn times_out_without_any_complete_row() {
+        let state = stalling(Backend::Html, &TABLE[..TABLE.find("<td>1").unwrap()], TABLE).await;
+        let (status, body) = query(&state, "&budget_ms=200").await;
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(body["error"], "query did not finish within 200 ms");
+    }
+
+    #[tokio::test]
+    async fn answers_balance_failures_as_errors() {
+        let statistics = "<table class=\"statistics-update-activity\"><tbody>\
+            <tr><td class=\"account\">Assets:Bank</td><td class=\"num\">2024-01-02</td></tr>\
+            </tbody></table>";
+        let fava = Router::new().route("/statistics/", get(move || async move { statistics }));
+        let state = AppState::new(Config::new(testing::fava(fava).await).refresh_path("none"));
+        let (status, body) = testing::get(&state, "/balance").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "date balance Assets:Bank 2024-01-02");
+
+        let state = AppState::new(Config::new("http://127.0.0.1:9").refresh_path("none"));
+        let (status, body) = testing::get(&state, "/balance").await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["success"], false);
+        assert_eq!(body["code"], "upstream_unreachable");
+    }
+
+    #[tokio::test]
+    async fn answers_bad_ids_and_unknown_routes_as_errors() {
+        let state = AppState::new(Config::new("http://127.0.0.1:9").refresh_path("none"));
+        for (uri, status, code) in [
+            (
+                "/api/saved_queries/abc",
+                StatusCode::BAD_REQUEST,
+                "bad_request",
+            ),
+            (
+                "/api/saved_queries/abc/run",
+                StatusCode::BAD_REQUEST,
+                "bad_request",
+            ),
+            (
+                "/api/saved_queries/7",
+                StatusCode::NOT_FOUND,
+                "unknown_saved_query",
+            ),
+            ("/api/nope", StatusCode::NOT_FOUND, "not_found"),
+        ] {
+            let (answered, body) = testing::get(&state, uri).await;
+            assert_eq!(answered, status, "{}", uri);
+            let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(
+                (body["success"].as_bool(), body["code"].as_str()),
+                (Some(false), Some(code))
+            );
+        }
+        let (_, body) = testing::get(&state, "/api/nope").await;
+        assert!(body.contains("no route for /api/nope"));
+    }
+
+    #[tokio::test]
+    async fn sends_forced_refreshes_past_the_poll_schedule() {
+        let (queries, refreshes, others) = (Hits::default(), Hits::default(), Hits::default());
+        let (hits, refreshed, other) = (queries.clone(), refreshes.clone(), others.clone());
+        let fava = Router::new()
+            .route(
+                "/api/query_result",
+                get(move || async move {
+                    hits.hit();
+                    testing::table(&["n"], &[&["1"]])
+                }),
+            )
+            .route("/refresh", get(move || async move { refreshed.hit() }))
+            .route("/other", get(move || async move { other.hit() }));
+        let mut config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("/refresh");
+        config.poll_smoothing = Some(crate::config::PollSmoothingConfig {
+            interval: 3600,
+            idle: 3600,
+            jitter: 0.0,
+        });
+        let state = AppState::new(config);
+        let uri = "/api/query_result?query_string=SELECT%201";
+        for (query, expected) in [
+            ("", (1, 1, 0)),
+            ("", (1, 1, 0)),
+            ("&refresh=true", (2, 2, 0)),
+            ("&refresh_path=/other", (3, 2, 1)),
+            ("&refresh_path=/refresh", (3, 2, 1)),
+        ] {
+            let (status, _) = testing::get(&state, &format!("{}{}", uri, query)).await;
+            assert_eq!(status, StatusCode::OK);
+            let counts = (queries.count(), refreshes.count(), others.count());
+            assert_eq!(counts, expected, "{}", query);
+        }
+    }
+
+    #[tokio::test]
+    async fn nests_into_another_app_and_queries_without_serving() {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|| async { testing::table(&["account"], &[&["Assets:Bank"]]) }),
+        );
+        let url = testing::fava(fava).await;
+        let config = Config::new(url.clone())
+            .backend(Backend::Html)
+            .refresh_path("none");
+        let app = Router::new().nest("/ledger", crate::router(config));
+        let request = Request::get("/ledger/api/query_result?query_string=SELECT%20account")
+            .body(Body::empty())
+            .unwrap();
+        let response = tower::ServiceExt::oneshot(app, request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["data"][0]["account"], "Assets:Bank");
+
+        let rows = crate::FavaClient::new(url)
+            .query("SELECT account")
+            .await
+            .unwrap();
+        assert_eq!(
+            rows,
+            [BTreeMap::from([(
+                "account".to_string(),
+                "Assets:Bank".to_string()
+            )])]
+        );
+    }
+
+    /// A fava whose every query answers `name` in the column `ledger`.
+    async fn ledger(name: &'static str) -> String {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(move || async move { testing::table(&["ledger"], &[&[name]]) }),
+        );
+        testing::fava(fava).await
+    }
+
+    /// The `ledger` cell `app` answers a query at `path` with.
+    async fn answered_by(app: &Router, path: &str) -> (StatusCode, String) {
+        let uri = format!("{}/api/query_result?query_string=SELECT%20ledger", path);
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        let response = tower::ServiceExt::oneshot(app.clone(), request)
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+        (
+            status,
+            body["data"][0]["ledger"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+        )
+    }
+
+    #[tokio::test]
+    async fn serves_each_named_ledger_below_its_name() {
+        let config = Config::new(ledger("main").await)
+            .ledger("family", ledger("family").await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        let app = crate::router(config);
+        assert_eq!(answered_by(&app, "").await, (StatusCode::OK, "main".into()));
+        assert_eq!(
+            answered_by(&app, "/ledgers/family").await,
+            (StatusCode::OK, "family".into())
+        );
+        assert_eq!(
+            answered_by(&app, "/ledgers/work").await.0,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn swaps_the_config_of_a_running_router() {
+        let config = |url| Config::new(url).backend(Backend::Html).refresh_path("none");
+        let (app, reloader) = crate::reloadable_router(config(ledger("old").await));
+        assert_eq!(answered_by(&app, "").await.1, "old");
+        reloader.reload(config(ledger("new").await));
+        assert_eq!(answered_by(&app, "").await.1, "new");
+    }
+
+    #[tokio::test]
+    async fn mounts_every_ledger_below_the_base_path() {
+        let config = Config::new(ledger("main").await)
+            .ledger("family", ledger("family").await)
+            .base_path("/fava-query/")
+            .backend(Backend::Html)
+            .refresh_path("none");
+        let app = crate::router(config);
+        assert_eq!(answered_by(&app, "/fava-query").await.1, "main");
+        assert_eq!(
+            answered_by(&app, "/fava-query/ledgers/family").await.1,
+            "family"
+        );
+        assert_eq!(answered_by(&app, "").await.0, StatusCode::NOT_FOUND);
+    }
+
+    /// The query of `POST /api/query_result`, quotes and newlines included.
+    const POSTED: &str = "SELECT account\nWHERE payee = \"Shop & Co\" AND narration ~ 'tea?'";
+
+    #[tokio::test]
+    async fn takes_the_parameters_of_a_posted_json_object() {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|Query(query): Query<BTreeMap<String, String>>| async move {
+                match (query["query_string"].as_str(), query.get("time")) {
+                    (POSTED, Some(time)) if time == "2024" => (
+                        StatusCode::OK,
+                        testing::table(&["account"], &[&["Assets:Bank"]]),
+                    ),
+                    _ => (StatusCode::BAD_REQUEST, format!("{:?}", query)),
+                }
+            }),
+        );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        let state = AppState::new(config);
+        let post = |body: serde_json::Value| {
+            Request::post("/api/query_result")
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        let request = post(serde_json::json!({ "query_string": POSTED, "time": "2024" }));
+        let (status, _, body) = testing::send(&state, request).await;
+        assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body["data"],
+            serde_json::json!([{ "account": "Assets:Bank" }])
+        );
+
+        let request = post(serde_json::json!({ "query_string": POSTED, "tme": "2024" }));
+        assert_eq!(
+            testing::send(&state, request).await.0,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn hands_account_filter_and_time_to_fava() {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|Query(query): Query<BTreeMap<String, String>>| async move {
+                let asked = |name: &str| query.get(name).cloned().unwrap_or_default();
+                let (account, filter, time) = (asked("account"), asked("filter"), asked("time"));
+                testing::table(
+                    &["account", "filter", "time"],
+                    &[&[&account, &filter, &time]],
+                )
+            }),
+        );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        let state = AppState::new(config);
+        let uri = "/api/query_result?query_string=SELECT%20n\
+            &account=Assets%3ABank&filter=%23trip%20payee%3A%22Shop%22&time=2024-Q1";
+        let (status, body) = testing::get(&state, uri).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["data"],
+            serde_json::json!([{ "account": "Assets:Bank", "filter": "#trip payee:\"Shop\"", "time": "2024-Q1" }])
+        );
+    }
+
+    /// A state whose every query answers the ten rows `n` of 1 to 10.
+    async fn counting() -> AppState {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|| async {
+                let numbers: Vec<String> = (1..=10).map(|n| n.to_string()).collect();
+                let rows: Vec<[&str; 1]> = numbers.iter().map(|n| [n.as_str()]).collect();
+                let rows: Vec<&[&str]> = rows.iter().map(|row| &row[..]).collect();
+                testing::table(&["n"], &rows)
+            }),
+        );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        AppState::new(config)
+    }
+
+    #[tokio::test]
+    async fn cuts_pages_of_the_rows_and_counts_them() {
+        let state = counting().await;
+        let uri = "/api/query_result?query_string=SELECT%20n";
+        for (page, numbers, meta) in [
+            (
+                "&limit=4",
+                vec!["1", "2", "3", "4"],
+                serde_json::json!({"offset": 0, "limit": 4, "total": 10, "next_offset": 4}),
+            ),
+            (
+                "&offset=8&limit=4",
+                vec!["9", "10"],
+                serde_json::json!({"offset": 8, "limit": 4, "total": 10, "next_offset": null}),
+            ),
+            (
+                "&offset=12",
+                vec![],
+                serde_json::json!({"offset": 12, "limit": null, "total": 10, "next_offset": null}),
+            ),
+        ] {
+            let (status, body) = testing::get(&state, &format!("{}{}", uri, page)).await;
+            assert_eq!(status, StatusCode::OK, "{}", body);
+            let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+            let rows: Vec<&str> = body["data"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|row| row["n"].as_str().unwrap())
+                .collect();
+            assert_eq!(rows, numbers, "{}", page);
+            assert_eq!(body["meta"]["page"], meta, "{}", page);
+        }
+        let (_, body) = testing::get(&state, uri).await;
+        assert!(!body.contains("\"meta\""));
+        let (status, _) = testing::get(&state, &format!("{}&limit=-1", uri)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn keeps_only_the_asked_columns_in_their_order() {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|| async {
+                testing::table(
+                    &["date", "account", "position"],
+                    &[&["2024-01-02", "Assets:Bank", "10.00 CNY"]],
+                )
+            }),
+        );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        let state = AppState::new(config);
+        let uri = "/api/query_result?query_string=SELECT%201&columns=position,%20account";
+        let (status, json) = testing::get(&state, uri).await;
+        assert_eq!(status, StatusCode::OK, "{}", json);
+        assert_eq!(
+            json,
+            r#"{"success":true,"data":[{"position":"10.00 CNY","account":"Assets:Bank"}]}"#
+        );
+        let (_, csv) = testing::get(&state, &format!("{}&format=csv", uri)).await;
+        assert_eq!(csv, "position,account\r\n10.00 CNY,Assets:Bank\r\n");
+        let (status, body) = testing::get(&state, &format!("{}%2Cpayee", uri)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("unknown column payee"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn keeps_the_select_order_of_the_columns() {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|| async {
+                testing::table(
+                    &["position", "date", "account"],
+                    &[
+                        &["10.00 CNY", "2024-01-02", "Assets:Bank"],
+                        &["-3 USD", "2024-01-03", "Assets:Cash"],
+                    ],
+                )
+            }),
+        );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        let state = AppState::new(config);
+        let uri = "/api/query_result?query_string=SELECT%201";
+        let (_, json) = testing::get(&state, uri).await;
+        assert_eq!(
+            json,
+            "{\"success\":true,\"data\":[\
+             {\"position\":\"10.00 CNY\",\"date\":\"2024-01-02\",\"account\":\"Assets:Bank\"},\
+             {\"position\":\"-3 USD\",\"date\":\"2024-01-03\",\"account\":\"Assets:Cash\"}]}"
+        );
+        let (_, typed) = testing::get(&state, &format!("{}&typed=true&limit=1", uri)).await;
+        let at = |column: &str| typed.find(&format!("\"{}\":", column)).unwrap();
+        assert!(
+            at("position") < at("date") && at("date") < at("account"),
+            "{}",
+            typed
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_account_amounts_of_any_commodity() {
+        let entry = |date: &str, change: &str, balance: &str| {
+            format!(
+                "<li class=\"transaction\"><p><span class=\"datecell\">{}</span>\
+                 <span class=\"flag\">*</span><span class=\"description\">\
+                 <strong class=\"payee\">Broker</strong></span><span class=\"indicators\">\
+                 </span><span class=\"num change\">{}</span><span class=\"num\">{}</span>\
+                 </p><ul class=\"postings\"><li class=\"posting\"><p>\
+                 <a class=\"account\">Assets:Broker</a></p></li></ul></li>",
+                date, change, balance
+            )
+        };
+        let page = format!(
+            "<html><body><ol class=\"flex-table journal\">{}{}</ol></body></html>",
+            entry("2024-01-03", "-10.00 USD", "90.00 USD, 0.5 BTC"),
+            entry("2024-01-02", "0.5 BTC", "100.00 USD, 0.5 BTC"),
+        );
+        let fava = Router::new().route("/account/*account", get(move || async move { page }));
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        let state = AppState::new(config);
+        let (status, body) = testing::get(&state, "/api/account/Assets:Broker?negate=true").await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["data"],
+            serde_json::json!([
+                {"date": "2024-01-02", "changed": "-0.5 BTC", "balance": "-100.00 USD, -0.5 BTC"},
+                {"date": "2024-01-03", "changed": "10.00 USD", "balance": "-90.00 USD, -0.5 BTC"},
+            ])
+        );
+    }
+}
@@ -0,0 +1,189 @@
+use axum::extract::State;
+use percent_encoding::percent_decode_str;
+use reqwest::Url;
+use serde::Deserialize;
+
+use crate::{
+    get_account_data,
+    i18n::Lang,
+    params::{QueryFields, StrictQuery},
+    AccountParams, AppState, ErrorResult, SuccessResult,
+};
+
+const EXPECTED: &str = "expected a fava query or account link, such as \
+    http://fava/<ledger>/query/?query_string=... or http://fava/<ledger>/account/<name>/";
+
+/// The request a fava link stands for.
+#[derive(Debug)]
+enum FavaLink {
+    Query { query_string: String },
+    Account { account: String },
+}
+
+/// Parses a link copied from fava's web interface. The link must point at
+/// the configured fava host; its path may carry the same base path and
+/// ledger slug as the configured url, or no slug at all for single-ledger
+/// fava versions.
+fn parse_link(link: &str, upstream: &str) -> Result<FavaLink, String> {
+    let link = Url::parse(link).map_err(|e| format!("invalid url: {}, {}", e, EXPECTED))?;
+    let upstream = Url::parse(upstream).map_err(|e| format!("invalid upstream url: {}", e))?;
+    if link.host_str() != upstream.host_str()
+        || link.port_or_known_default() != upstream.port_or_known_default()
+    {
+        return Err(format!(
+            "link host {} is not the configured fava host {}",
+            link.host_str().unwrap_or_default(),
+            upstream.host_str().unwrap_or_default()
+        ));
+    }
+
+    let segments = path_segments(&link);
+    let base = path_segments(&upstream);
+    let report = match segments
+        .iter()
+        .position(|segment| segment == "query" || segment == "account")
+    {
+        Some(report) => report,
+        None => return Err(EXPECTED.into()),
+    };
+    let prefix = &segments[..report];
+    if !prefix.is_empty() && prefix != base.as_slice() {
+        return Err(format!(
+            "link is for ledger /{}/ but this server queries /{}/",
+            prefix.join("/"),
+            base.join("/")
+        ));
+    }
+
+    match (segments[report].as_str(), segments.get(report + 1)) {
+        ("query", _) => link
+            .query_pairs()
+            .find(|(key, value)| key == "query_string" && !value.is_empty())
+            .map(|(_, value)| FavaLink::Query {
+                query_string: value.into_owned(),
+            })
+            .ok_or_else(|| "query link has no query_string".into()),
+        ("account", Some(account)) => Ok(FavaLink::Account {
+            account: account.clone(),
+        }),
+        _ => Err(EXPECTED.into()),
+    }
+}
+
+fn path_segments(url: &Url) -> Vec<String> {
+    url.path_segments()
+        .map(|segments| {
+            segments
+                .filter(|segment| !segment.is_empty())
+                .map(|segment| percent_decode_str(segment).decode_utf8_lossy().into_owned())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub async fn from_link(
+    State(state): State<AppState>,
+    StrictQuery(params): StrictQuery<FromLinkParams>,
+    lang: Lang,
+) -> Result<SuccessResult, ErrorResult> {
+    from_link_rows(&state, &params)
+        .await
+        .map(|result| result.localize(lang))
+        .map_err(|e| e.localize(lang))
+}
+
+async fn from_link_rows(
+    state: &AppState,
+    params: &FromLinkParams,
+) -> Result<SuccessResult, ErrorResult> {
+    let link = parse_link(&params.url, &state.config.url).map_err(ErrorResult::bad_request)?;
+    match link {
+        FavaLink::Query { query_string } => state
+            .session()
+            .rows(&query_string)
+            .await
+            .map(SuccessResult::from),
+        FavaLink::Account { account } => {
+            let journal = state.session().account_journal(&account).await?;
+            Ok(SuccessResult::from(get_account_data(
+                &journal.entries,
+                &AccountParams::default(),
+            )))
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FromLinkParams {
+    url: String,
+}
+
+impl QueryFields for FromLinkParams {
+    const FIELDS: &[&str] = &["url"];
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::get, Router};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{backend::Backend, config::Config, testing, testing::table};
+
+    const FAVA: &str = "http://fava:5000/beancount";
+
+    #[test]
+    fn reads_query_and_account_links() {
+        let link = parse_link(
+            "http://fava:5000/beancount/query/?query_string=SELECT%20account",
+            FAVA,
+        );
+        assert!(
+            matches!(link, Ok(FavaLink::Query { query_string }) if query_string == "SELECT account")
+        );
+        let link = parse_link("http://fava:5000/account/Assets:Bank%20X/", FAVA);
+        assert!(matches!(link, Ok(FavaLink::Account { account }) if account == "Assets:Bank X"));
+    }
+
+    #[test]
+    fn refuses_links_to_other_favas() {
+        assert_eq!(
+            parse_link("http://other:5000/beancount/account/Assets/", FAVA).unwrap_err(),
+            "link host other is not the configured fava host fava"
+        );
+        assert_eq!(
+            parse_link("http://fava:5000/family/account/Assets/", FAVA).unwrap_err(),
+            "link is for ledger /family/ but this server queries /beancount/"
+        );
+        assert_eq!(
+            parse_link("http://fava:5000/beancount/query/", FAVA).unwrap_err(),
+            "query link has no query_string"
+        );
+        assert_eq!(
+            parse_link("http://fava:5000/beancount/income_statement/", FAVA).unwrap_err(),
+            EXPECTED
+        );
+    }
+
+    #[tokio::test]
+    async fn runs_the_query_of_a_link() {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|| async { table(&["account"], &[&["Assets:Bank"]]) }),
+        );
+        let url = testing::fava(fava).await;
+        let config = Config::new(url.clone())
+            .backend(Backend::Html)
+            .refresh_path("none");
+        let state = AppState::new(config);
+        let link = format!("{}/query/?query_string=SELECT%2520account", url);
+        let uri = format!(
+            "/api/from_link?url={}",
+            link.replace('/', "%2F").replace('?', "%3F")
+        );
+        let (status, body) = testing::get(&state, &uri).await;
+        assert_eq!(status, 200, "{}", body);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["data"], json!([{"account": "Assets:Bank"}]));
+    }
+}
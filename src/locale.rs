@@ -0,0 +1,250 @@
+use rust_decimal::Decimal;
+use std::{borrow::Cow, collections::BTreeMap};
+
+use crate::{amount, config::LocaleConfig, interval};
+
+/// The profiles `output_locale` knows without any configured.
+const BUILT_IN: &[(&str, &str, &str, &str)] = &[
+    ("en", ",", ".", "MM/DD/YYYY"),
+    ("de", ".", ",", "DD.MM.YYYY"),
+    ("zh", ",", ".", "YYYY年M月D日"),
+];
+
+/// The parts of a date pattern, longest first.
+const DATE_PARTS: [&str; 5] = ["YYYY", "MM", "DD", "M", "D"];
+
+/// The profile called `name`, one of the config before a built-in one.
+pub fn profile<'a>(
+    locales: &'a BTreeMap<String, LocaleConfig>,
+    name: &str,
+) -> Option<Cow<'a, LocaleConfig>> {
+    if let Some(locale) = locales.get(name) {
+        return Some(Cow::Borrowed(locale));
+    }
+    BUILT_IN
+        .iter()
+        .find(|(built_in, ..)| *built_in == name)
+        .map(|(_, thousands, decimal, date)| {
+            Cow::Owned(LocaleConfig {
+                thousands: thousands.to_string(),
+                decimal: decimal.to_string(),
+                date: date.to_string(),
+            })
+        })
+}
+
+impl LocaleConfig {
+    /// A cell for display: dates in the profile's pattern, and numbers and
+    /// amounts with its separators. Anything else stays as it is.
+    pub fn cell(&self, text: &str) -> String {
+        if let Some((year, month, day)) = date(text) {
+            return self.date(year, month, day);
+        }
+        match amount::parse_positions(text) {
+            Some(positions) => positions
+                .iter()
+                .map(|(number, currency)| match currency {
+                    Some(currency) => format!("{} {}", self.number(*number, true), currency),
+                    None => self.number(*number, false),
+                })
+                .collect::<Vec<_>>()
+                .join(", "),
+            None => text.to_string(),
+        }
+    }
+
+    /// A number with the profile's decimal mark, its integer digits grouped
+    /// by thousands if `grouped`. Bare numbers are not, as they may be years
+    /// or ids.
+    fn number(&self, number: Decimal, grouped: bool) -> String {
+        let text = amount::format_number(number);
+        let (sign, digits) = match text.strip_prefix('-') {
+            Some(digits) => ("-", digits),
+            None => ("", text.as_str()),
+        };
+        let (integer, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        let mut output = sign.to_string();
+        for (i, digit) in integer.chars().enumerate() {
+            if grouped && i > 0 && (integer.len() - i) % 3 == 0 {
+                output.push_str(&self.thousands);
+            }
+            output.push(digit);
+        }
+        if !fraction.is_empty() {
+            output.push_str(&self.decimal);
+            output.push_str(fraction);
+        }
+        output
+    }
+
+    /// A date in the profile's pattern, where `YYYY`, `MM`, `DD` are the
+    /// zero-padded year, month and day, `M` and `D` the unpadded ones.
+    fn date(&self, year: i64, month: i64, day: i64) -> String {
+        self.date_parts(|part| match part {
+            Ok("YYYY") => format!("{:04}", year),
+            Ok("MM") => format!("{:02}", month),
+            Ok("DD") => format!("{:02}", day),
+            Ok("M") => month.to_string(),
+            Ok(_) => day.to_string(),
+            Err(c) => c.to_string(),
+        })
+    }
+
+    /// The profile's date pattern as an Excel number format code, with the
+    /// text between the parts quoted.
+    pub fn excel_date(&self) -> String {
+        self.date_parts(|part| match part {
+            Ok(part) => part.to_lowercase(),
+            Err(c) => format!("\"{}\"", c),
+        })
+    }
+
+    /// The date pattern with each of its [`DATE_PARTS`], and each character
+    /// between them as `Err`, replaced by `render`.
+    fn date_parts(&self, mut render: impl FnMut(Result<&str, char>) -> String) -> String {
+        let mut output = String::new();
+        let mut rest = self.date.as_str();
+        while let Some(c) = rest.chars().next() {
+            match DATE_PARTS.into_iter().find(|part| rest.starts_with(part)) {
+                Some(part) => {
+                    output.push_str(&render(Ok(part)));
+                    rest = &rest[part.len()..];
+                }
+                None => {
+                    output.push_str(&render(Err(c)));
+                    rest = &rest[c.len_utf8()..];
+                }
+            }
+        }
+        output
+    }
+}
+
+/// The year, month and day of a `YYYY-MM-DD` cell.
+pub fn date(text: &str) -> Option<(i64, i64, i64)> {
+    let text = text.trim();
+    let is_date = text.len() == 10
+        && text.char_indices().all(|(i, c)| {
+            matches!((i, c), (4 | 7, '-')) || (i != 4 && i != 7 && c.is_ascii_digit())
+        });
+    interval::parse_date(text).filter(|_| is_date)?;
+    let part = |range: std::ops::Range<usize>| text[range].parse().ok();
+    Some((part(0..4)?, part(5..7)?, part(8..10)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::get, Router};
+
+    use super::*;
+    use crate::{
+        backend::Backend,
+        config::Config,
+        csv,
+        testing::{self, table},
+        AppState, Row,
+    };
+
+    fn built_in(name: &str) -> LocaleConfig {
+        profile(&BTreeMap::new(), name).unwrap().into_owned()
+    }
+
+    fn rows() -> Vec<Row> {
+        let row = [
+            ("date", "2024-01-02"),
+            ("amount", "-1234.56 CNY, 3 USD"),
+            ("year", "2024"),
+            ("payee", "Shop, Inc."),
+        ];
+        vec![row
+            .iter()
+            .map(|(column, cell)| (column.to_string(), cell.to_string()))
+            .collect()]
+    }
+
+    fn written(name: Option<&str>) -> String {
+        let locale = name.map(built_in);
+        csv::write(
+            &["date", "amount", "year", "payee"],
+            &rows(),
+            locale.as_ref(),
+        )
+    }
+
+    #[test]
+    fn renders_the_same_rows_for_each_locale() {
+        let header = "date,amount,year,payee\r\n";
+        assert_eq!(
+            written(Some("de")),
+            format!(
+                "{}02.01.2024,\"-1.234,56 CNY, 3 USD\",2024,\"Shop, Inc.\"\r\n",
+                header
+            )
+        );
+        assert_eq!(
+            written(Some("zh")),
+            format!(
+                "{}2024年1月2日,\"-1,234.56 CNY, 3 USD\",2024,\"Shop, Inc.\"\r\n",
+                header
+            )
+        );
+        assert_eq!(
+            written(None),
+            format!(
+                "{}2024-01-02,\"-1234.56 CNY, 3 USD\",2024,\"Shop, Inc.\"\r\n",
+                header
+            )
+        );
+    }
+
+    #[test]
+    fn prefers_a_configured_profile_to_a_built_in_one() {
+        let mut locales = BTreeMap::new();
+        let custom = LocaleConfig {
+            thousands: "'".to_string(),
+            decimal: ".".to_string(),
+            date: "DD/MM/YYYY".to_string(),
+        };
+        locales.insert("de".to_string(), custom.clone());
+        assert_eq!(profile(&locales, "de").as_deref(), Some(&custom));
+        assert_eq!(custom.cell("1234567.5 CHF"), "1'234'567.5 CHF");
+        assert!(profile(&locales, "fr").is_none());
+    }
+
+    #[test]
+    fn quotes_the_text_of_excel_date_codes() {
+        let zh = built_in("zh");
+        assert_eq!(zh.excel_date(), "yyyy\"年\"m\"月\"d\"日\"");
+        let de = built_in("de");
+        assert_eq!(de.excel_date(), "dd\".\"mm\".\"yyyy");
+    }
+
+    async fn exported(configure: impl FnOnce(Config) -> Config, query: &str) -> (u16, String) {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|| async { table(&["date", "amount"], &[&["2024-01-02", "1234.5 CNY"]]) }),
+        );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        let state = AppState::new(configure(config));
+        let uri = format!(
+            "/api/query_result?query_string=SELECT%20date&format=csv{}",
+            query
+        );
+        let (status, body) = testing::get(&state, &uri).await;
+        (status.as_u16(), body)
+    }
+
+    #[tokio::test]
+    async fn exports_in_the_asked_or_configured_locale() {
+        let (_, body) = exported(|config| config, "&output_locale=de").await;
+        assert_eq!(body, "date,amount\r\n02.01.2024,\"1.234,5 CNY\"\r\n");
+        let (_, body) = exported(|config| config.output_locale("zh"), "").await;
+        assert_eq!(body, "date,amount\r\n2024年1月2日,\"1,234.5 CNY\"\r\n");
+        let (_, body) = exported(|config| config.output_locale("zh"), "&output_locale=en").await;
+        assert_eq!(body, "date,amount\r\n01/02/2024,\"1,234.5 CNY\"\r\n");
+        let (status, _) = exported(|config| config, "&output_locale=xx").await;
+        assert_eq!(status, 400);
+    }
+}
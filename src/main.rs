@@ -1,282 +1,546 @@
-use axum::{
-    extract::{Path, Query},
-    response::{IntoResponse, Response},
-    routing::get,
-    Json, Router,
+use axum::{extract::Host, http::Uri, response::Redirect, Router};
+use std::{
+    env, fs,
+    net::{IpAddr, SocketAddr},
+    process,
+    time::Duration,
 };
-use nipper::Document;
-use reqwest::StatusCode;
-use serde::{
-    de::{self},
-    Deserialize, Deserializer, Serialize,
+use tokio::{
+    net::TcpListener,
+    sync::{mpsc, watch},
 };
-use std::{collections::HashMap, env, fmt, net::SocketAddr, str::FromStr};
 
 // Use Jemalloc only for musl-64 bits platforms
 #[cfg(all(target_env = "musl", target_pointer_width = "64"))]
 #[global_allocator]
 static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
+/// Default seconds that requests in flight get to finish on shutdown.
+const DEFAULT_DRAIN_TIMEOUT: u64 = 30;
+
+const USAGE: &str = "usage: fava-query [serve]
+       fava-query query <BQL> [--format json|csv]
+       fava-query healthcheck
+
+Settings come from environment variables such as url, port and config.";
+
 #[tokio::main]
 async fn main() {
-    let _ = match env::var("url") {
-        Ok(val) => val,
-        Err(_) => panic!("url not set"),
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let code = match args.as_slice() {
+        [] | ["serve"] => {
+            serve().await;
+            0
+        }
+        ["query", query_string] => query(query_string, "json").await,
+        ["query", query_string, "--format", format]
+        | ["query", "--format", format, query_string] => query(query_string, format).await,
+        ["healthcheck"] => healthcheck().await,
+        ["help" | "--help" | "-h"] => {
+            println!("{}", USAGE);
+            0
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            2
+        }
     };
-    let app = Router::new()
-        .route("/api/query_result", get(query))
-        .route("/api/account/:account", get(account))
-        .route("/balance", get(balance));
-    let addr = SocketAddr::from(([0, 0, 0, 0], 80));
-    println!("listening on {}", addr);
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
-}
-
-async fn balance() -> String {
-    let query_result = query_balance().await;
-    match query_result {
-        Ok(result) => return result,
-        Err(e) => e.to_string(),
-    }
+    process::exit(code);
 }
 
-async fn account(
-    Path(account): Path<String>,
-    Query(params): Query<AccountParams>,
-) -> Result<SuccessResult, ErrorResult> {
-    let query_result = query_account(account).await;
-    match query_result {
-        Ok(result) => return Ok(SuccessResult::new(get_account_data(result, params))),
-        Err(e) => Err(ErrorResult::new(e.to_string())),
+/// Prints the result of one query, reading fava the same way the server
+/// does.
+async fn query(query_string: &str, format: &str) -> i32 {
+    let config = fava_query::Config::from_env();
+    match fava_query::query_once(config, query_string, format).await {
+        Ok((output, warnings)) => {
+            for warning in warnings {
+                eprintln!("warning: {}", warning);
+            }
+            println!("{}", output.trim_end());
+            0
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
     }
 }
 
-async fn query(Query(params): Query<Params>) -> Result<SuccessResult, ErrorResult> {
-    let query_result = query_table(params).await;
-    match query_result {
-        Ok(result) => {
-            if result.success {
-                let data = result.data;
-                if let Some(data) = data {
-                    let table_str = data.table;
-                    return Ok(SuccessResult::new(get_table_data(table_str)));
+/// Asks the running server for its status, for container health probes.
+async fn healthcheck() -> i32 {
+    let healthy = match env::var("unix_socket") {
+        Ok(path) => unix_status(&path).await,
+        Err(_) => {
+            let https = env::var("tls_cert").is_ok();
+            let ip = match listen_addr() {
+                ip if ip.is_unspecified() && ip.is_ipv4() => IpAddr::from([127, 0, 0, 1]),
+                ip if ip.is_unspecified() => IpAddr::from([0, 0, 0, 0, 0, 0, 0, 1]),
+                ip => ip,
+            };
+            let url = format!(
+                "{}://{}/healthz",
+                if https { "https" } else { "http" },
+                SocketAddr::new(ip, port(if https { 443 } else { 80 }))
+            );
+            // The certificate names the public host, not the loopback address.
+            let client = reqwest::Client::builder()
+                .danger_accept_invalid_certs(true)
+                .timeout(Duration::from_secs(5))
+                .build()
+                .expect("can not create http client");
+            match client.get(&url).send().await {
+                Ok(response) => response.status().is_success(),
+                Err(e) => {
+                    eprintln!("{}: {}", url, e);
+                    false
                 }
-                Ok(SuccessResult::default())
-            } else {
-                Err(ErrorResult::new(
-                    result.error.unwrap_or("Something went wrong".into()),
-                ))
             }
         }
-        Err(e) => Err(ErrorResult::new(e.to_string())),
+    };
+    match healthy {
+        true => 0,
+        false => 1,
     }
 }
 
-async fn query_balance() -> Result<String, reqwest::Error> {
-    let mut balances: Vec<String> = vec![];
-    let url = env::var("url").unwrap_or_default();
-    let query_url = format!("{}/statistics/?interval=day", url);
-    let text = reqwest::get(query_url).await?.text().await?;
-    let document = Document::from(text.as_str());
-    let balance_item = document
-        .select(".statistics-update-activity")
-        .select("tbody")
-        .select("tr");
-    balance_item.iter().for_each(|node| {
-        let account = node.select(".account").text().trim().to_string();
-        let balance = node.select("td.num").text().trim().to_string();
-        balances.push(format!("date balance {} {}", account, balance));
-    });
-    balances.sort();
-    Ok(balances.join("\r\n"))
-}
+#[cfg(unix)]
+async fn unix_status(path: &str) -> bool {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::UnixStream,
+    };
 
-async fn query_table(params: Params) -> Result<QueryResult, reqwest::Error> {
-    let url = env::var("url").unwrap_or_default();
-
-    let query_url = format!(
-        "{}/api/query_result?query_string={}",
-        url, params.query_string
-    );
-    // 先请求页面以刷新数据
-    let _ = reqwest::get(format!("{}/income_statement/", url))
-        .await?
-        .text()
-        .await?;
-    let result = reqwest::get(query_url).await?.json::<QueryResult>().await?;
-
-    Ok(result)
+    let check = async {
+        let mut stream = UnixStream::connect(path).await?;
+        stream
+            .write_all(b"GET /healthz HTTP/1.0\r\nHost: localhost\r\n\r\n")
+            .await?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        Ok::<_, std::io::Error>(
+            response.starts_with(b"HTTP/1.0 200") || response.starts_with(b"HTTP/1.1 200"),
+        )
+    };
+    match tokio::time::timeout(Duration::from_secs(5), check).await {
+        Ok(Ok(healthy)) => healthy,
+        Ok(Err(e)) => {
+            eprintln!("{}: {}", path, e);
+            false
+        }
+        Err(_) => {
+            eprintln!("{}: timed out", path);
+            false
+        }
+    }
 }
 
-async fn query_account(account: String) -> Result<String, reqwest::Error> {
-    let url = env::var("url").unwrap_or_default();
-    let _ = reqwest::get(format!("{}/income_statement/", url))
-        .await?
-        .text()
-        .await?;
-    let url = format!("{}/account/{}", url, account);
-    // 先请求页面以刷新数据
-    let result = reqwest::get(url).await?.text().await?;
-    Ok(result)
+#[cfg(not(unix))]
+async fn unix_status(_path: &str) -> bool {
+    false
 }
 
-fn get_table_data(table_str: String) -> Vec<HashMap<String, String>> {
-    let document = Document::from(table_str.as_str());
-    let table_title = document.select("thead").select("tr").select("th");
-    let table_lines = document.select("tbody").select("tr");
-    let mut titles = Vec::new();
-    table_title.iter().for_each(|node| {
-        titles.push(node.text().to_string());
-    });
-
-    let mut result: Vec<HashMap<String, String>> = Vec::new();
-    table_lines.iter().for_each(|node| {
-        let mut line: HashMap<String, String> = HashMap::new();
+async fn serve() {
+    let config = fava_query::Config::from_env();
+    let (app, reloader) = fava_query::reloadable_router(config);
+    tokio::spawn(reload_on_sighup(reloader));
+    let tls = match (env::var("tls_cert"), env::var("tls_key")) {
+        (Ok(cert), Ok(key)) => Some((cert, key)),
+        (Err(_), Err(_)) => None,
+        _ => panic!("tls_cert and tls_key have to be set together"),
+    };
 
-        for (i, el) in node.select("td").iter().enumerate() {
-            let title = titles.get(i).unwrap();
-            let value = el.text().trim().to_string();
-            line.insert(title.to_string(), value);
-        }
-        result.push(line);
+    // On SIGTERM or SIGINT the listeners close, and requests in flight get
+    // `drain_timeout` seconds to finish before the process exits anyway.
+    let drain_timeout = drain_timeout();
+    let (stop, stopped) = watch::channel(false);
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        println!(
+            "shutting down, waiting up to {}s for requests in flight",
+            drain_timeout.as_secs()
+        );
+        let _ = stop.send(true);
     });
-    result
+    let deadline = {
+        let stopped = stopped.clone();
+        async move {
+            wait(stopped).await;
+            tokio::time::sleep(drain_timeout).await;
+        }
+    };
+    let unix_socket = env::var("unix_socket").ok();
+    if unix_socket.is_some() && tls.is_some() {
+        panic!("unix_socket can not be combined with tls_cert and tls_key");
+    }
+    let server = async move {
+        match (unix_socket, tls) {
+            (Some(path), _) => serve_unix(app, &path, stopped).await,
+            (None, Some((cert, key))) => {
+                let addr = SocketAddr::new(listen_addr(), port(443));
+                // `http_port=none` turns the redirect off.
+                let http_port = match env::var("http_port").as_deref() {
+                    Ok("none") => None,
+                    Ok(val) => Some(
+                        val.parse()
+                            .unwrap_or_else(|_| panic!("invalid http_port {}", val)),
+                    ),
+                    Err(_) => Some(80),
+                };
+                if let Some(http_port) = http_port {
+                    tokio::spawn(redirect_to_https(
+                        SocketAddr::new(addr.ip(), http_port),
+                        addr.port(),
+                        stopped.clone(),
+                    ));
+                }
+                println!("listening on {} (https)", addr);
+                serve_tls(app, addr, &cert, &key, stopped).await;
+            }
+            (None, None) => {
+                let addr = SocketAddr::new(listen_addr(), port(80));
+                println!("listening on {}", addr);
+                axum::Server::bind(&addr)
+                    .serve(app.into_make_service())
+                    .with_graceful_shutdown(wait(stopped))
+                    .await
+                    .unwrap();
+            }
+        }
+    };
+    tokio::select! {
+        _ = server => println!("all requests finished"),
+        _ = deadline => println!("drain timeout passed, dropping the remaining requests"),
+    }
 }
 
-fn get_account_data(html: String, params: AccountParams) -> Vec<HashMap<String, String>> {
-    let document = Document::from(html.as_str());
-    let table = document.select(".flex-table");
-    let data_lines = table.select(".transaction");
-    let mut result: Vec<HashMap<String, String>> = Vec::new();
-    data_lines.iter().for_each(|line| {
-        let mut result_item: HashMap<String, String> = HashMap::new();
-        let date = line.select(".datecell").text().to_string();
-        if result.iter().any(|item| item.get("date") == Some(&date)) {
-            return;
-        }
-        let mut changed: f32 = line
-            .select(".change")
-            .text()
-            .replace("CNY", "")
-            .trim()
-            .parse()
-            .unwrap_or_default();
-        let mut balance: f32 = line
-            .select("span:nth-child(6)")
-            .text()
-            .replace("CNY", "")
-            .trim()
+/// Address to listen on, `listen_addr`, all interfaces by default.
+fn listen_addr() -> IpAddr {
+    match env::var("listen_addr") {
+        Ok(val) => val
             .parse()
-            .unwrap_or_default();
+            .unwrap_or_else(|_| panic!("invalid listen_addr {}", val)),
+        Err(_) => IpAddr::from([0, 0, 0, 0]),
+    }
+}
 
-        if Some(true) == params.negate {
-            changed = 0.0 - changed;
-            balance = 0.0 - balance;
-        }
-        result_item.insert("date".into(), date);
-        result_item.insert("changed".into(), changed.to_string());
-        result_item.insert("balance".into(), balance.to_string());
-        result.push(result_item);
-    });
-    result.reverse();
-    result
+/// Port to listen on, `port`, else `default`.
+fn port(default: u16) -> u16 {
+    match env::var("port") {
+        Ok(val) => val
+            .parse()
+            .unwrap_or_else(|_| panic!("invalid port {}", val)),
+        Err(_) => default,
+    }
 }
 
-#[derive(Debug, Deserialize)]
-#[allow(dead_code)]
-struct Params {
-    query_string: String,
-    #[serde(default, deserialize_with = "empty_string_as_none")]
-    account: Option<String>,
-    #[serde(default, deserialize_with = "empty_string_as_none")]
-    filter: Option<String>,
-    #[serde(default, deserialize_with = "empty_string_as_none")]
-    time: Option<String>,
+/// Seconds given to requests in flight on shutdown, `drain_timeout`.
+fn drain_timeout() -> Duration {
+    match env::var("drain_timeout") {
+        Ok(val) => Duration::from_secs(
+            val.parse()
+                .unwrap_or_else(|_| panic!("invalid drain_timeout {}", val)),
+        ),
+        Err(_) => Duration::from_secs(DEFAULT_DRAIN_TIMEOUT),
+    }
 }
 
-#[derive(Debug, Deserialize)]
-#[allow(dead_code)]
-struct AccountParams {
-    #[serde(default, deserialize_with = "empty_string_as_none")]
-    negate: Option<bool>,
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate()).expect("can not listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct QueryResult {
-    error: Option<String>,
-    success: bool,
-    data: Option<QueryResultData>,
+/// Reads the environment and config file again on every SIGHUP. A config
+/// that does not load leaves the running one in place; the listener
+/// settings only apply on restart.
+#[cfg(unix)]
+async fn reload_on_sighup(reloader: fava_query::Reloader) {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hangup = signal(SignalKind::hangup()).expect("can not listen for SIGHUP");
+    while hangup.recv().await.is_some() {
+        match fava_query::Config::try_from_env() {
+            Ok(config) => {
+                reloader.reload(config);
+                println!("reloaded config");
+            }
+            Err(e) => println!("config reload failed, keeping the old config: {}", e),
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct QueryResultData {
-    table: String,
+#[cfg(not(unix))]
+async fn reload_on_sighup(_reloader: fava_query::Reloader) {}
+
+/// Resolves once shutdown started.
+async fn wait(mut stopped: watch::Receiver<bool>) {
+    while !*stopped.borrow_and_update() {
+        if stopped.changed().await.is_err() {
+            return;
+        }
+    }
 }
 
-/// Serde deserialization decorator to map empty Strings to None,
-fn empty_string_as_none<'de, D, T>(de: D) -> Result<Option<T>, D::Error>
-where
-    D: Deserializer<'de>,
-    T: FromStr,
-    T::Err: fmt::Display,
-{
-    let opt = Option::<String>::deserialize(de)?;
-    match opt.as_deref() {
-        None | Some("") => Ok(None),
-        Some(s) => FromStr::from_str(s).map_err(de::Error::custom).map(Some),
+/// Serves `app` on a unix socket at `path`, replacing a socket left behind
+/// by an earlier run.
+#[cfg(unix)]
+async fn serve_unix(app: Router, path: &str, stopped: watch::Receiver<bool>) {
+    use std::os::unix::fs::FileTypeExt;
+    use tokio::net::UnixListener;
+
+    if fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        let _ = fs::remove_file(path);
     }
+    let listener =
+        UnixListener::bind(path).unwrap_or_else(|e| panic!("can not listen on {}: {}", path, e));
+    println!("listening on {}", path);
+    let accept = hyper::server::accept::poll_fn(move |cx| {
+        listener
+            .poll_accept(cx)
+            .map(|accepted| Some(accepted.map(|(stream, _)| stream)))
+    });
+    axum::Server::builder(accept)
+        .serve(app.into_make_service())
+        .with_graceful_shutdown(wait(stopped))
+        .await
+        .unwrap();
+    let _ = fs::remove_file(path);
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct ErrorResult {
-    error: String,
-    success: bool,
+#[cfg(not(unix))]
+async fn serve_unix(_app: Router, _path: &str, _stopped: watch::Receiver<bool>) {
+    panic!("unix_socket is only supported on unix");
 }
 
-impl ErrorResult {
-    fn new(error: String) -> ErrorResult {
-        ErrorResult {
-            error: error,
-            success: false,
-        }
+/// Serves `app` over TLS with the PEM certificate chain and PKCS#8 key at
+/// `cert` and `key`, until shutdown started and the open connections are done.
+async fn serve_tls(
+    app: Router,
+    addr: SocketAddr,
+    cert: &str,
+    key: &str,
+    stopped: watch::Receiver<bool>,
+) {
+    let read =
+        |path: &str| fs::read(path).unwrap_or_else(|e| panic!("can not read {}: {}", path, e));
+    let identity = native_tls::Identity::from_pkcs8(&read(cert), &read(key))
+        .unwrap_or_else(|e| panic!("invalid tls_cert or tls_key: {}", e));
+    let acceptor = native_tls::TlsAcceptor::new(identity)
+        .map(tokio_native_tls::TlsAcceptor::from)
+        .unwrap_or_else(|e| panic!("can not set up tls: {}", e));
+    let listener = TcpListener::bind(addr)
+        .await
+        .unwrap_or_else(|e| panic!("can not listen on {}: {}", addr, e));
+    // Every connection holds a sender, so the receiver sees the channel
+    // close once the last one is done.
+    let (open, mut closed) = mpsc::channel::<()>(1);
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    println!("accept failed: {}", e);
+                    continue;
+                }
+            },
+            _ = wait(stopped.clone()) => break,
+        };
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+        let stopped = stopped.clone();
+        let open = open.clone();
+        tokio::spawn(async move {
+            let _open = open;
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    println!("tls handshake failed: {}", e);
+                    return;
+                }
+            };
+            let connection = hyper::server::conn::Http::new().serve_connection(stream, app);
+            tokio::pin!(connection);
+            tokio::select! {
+                _ = connection.as_mut() => {}
+                _ = wait(stopped) => {
+                    connection.as_mut().graceful_shutdown();
+                    let _ = connection.await;
+                }
+            }
+        });
     }
+    drop(open);
+    let _ = closed.recv().await;
 }
 
-impl IntoResponse for ErrorResult {
-    fn into_response(self) -> Response {
-        let body = Json(self);
-        (StatusCode::OK, body).into_response()
-    }
+/// Answers every plain http request with a redirect to the same url on
+/// `https_port`.
+async fn redirect_to_https(addr: SocketAddr, https_port: u16, stopped: watch::Receiver<bool>) {
+    let redirect = move |Host(host): Host, uri: Uri| async move {
+        let path = uri.path_and_query().map_or("/", |path| path.as_str());
+        Redirect::permanent(&https_location(&host, https_port, path))
+    };
+    println!("redirecting http on {} to https", addr);
+    axum::Server::bind(&addr)
+        .serve(Router::new().fallback(redirect).into_make_service())
+        .with_graceful_shutdown(wait(stopped))
+        .await
+        .unwrap();
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct SuccessResult {
-    success: bool,
-    data: Vec<HashMap<String, String>>,
+/// The https url of `path` on `host`, whose port, if any, gives way to
+/// `https_port`. An IPv6 host keeps its brackets, or gets them when a
+/// client sent it bare.
+fn https_location(host: &str, https_port: u16, path: &str) -> String {
+    let host = match host.strip_prefix('[') {
+        Some(rest) => match rest.split_once(']') {
+            Some((ip, _)) => format!("[{}]", ip),
+            None => host.to_string(),
+        },
+        None => match host.split_once(':') {
+            Some((name, port)) if !port.contains(':') => name.to_string(),
+            Some(_) => format!("[{}]", host),
+            None => host.to_string(),
+        },
+    };
+    let port = match https_port {
+        443 => String::new(),
+        port => format!(":{}", port),
+    };
+    format!("https://{}{}{}", host, port, path)
 }
 
-impl SuccessResult {
-    fn new(data: Vec<HashMap<String, String>>) -> SuccessResult {
-        SuccessResult {
-            success: true,
-            data,
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redirects_to_the_https_port_of_the_same_host() {
+        let location = |host| https_location(host, 8443, "/api/version?lang=zh");
+        assert_eq!(
+            location("fava.example:8080"),
+            "https://fava.example:8443/api/version?lang=zh"
+        );
+        assert_eq!(
+            location("fava.example"),
+            "https://fava.example:8443/api/version?lang=zh"
+        );
+        assert_eq!(
+            location("10.0.0.2:80"),
+            "https://10.0.0.2:8443/api/version?lang=zh"
+        );
+        assert_eq!(
+            https_location("fava.example:80", 443, "/"),
+            "https://fava.example/"
+        );
+    }
+
+    #[test]
+    fn keeps_ipv6_hosts_whole() {
+        assert_eq!(https_location("[::1]", 443, "/"), "https://[::1]/");
+        assert_eq!(
+            https_location("[::1]:8080", 8443, "/"),
+            "https://[::1]:8443/"
+        );
+        assert_eq!(
+            https_location("[2001:db8::7]:80", 443, "/a"),
+            "https://[2001:db8::7]/a"
+        );
+        assert_eq!(
+            https_location("2001:db8::7", 443, "/"),
+            "https://[2001:db8::7]/"
+        );
+    }
+
+    #[test]
+    fn reads_the_listen_address_and_port() {
+        assert_eq!(listen_addr(), IpAddr::from([0, 0, 0, 0]));
+        assert_eq!(port(80), 80);
+        env::set_var("listen_addr", "::1");
+        env::set_var("port", "8080");
+        assert_eq!(listen_addr(), IpAddr::from([0, 0, 0, 0, 0, 0, 0, 1]));
+        assert_eq!(port(80), 8080);
+        env::remove_var("listen_addr");
+        env::remove_var("port");
     }
 
-    fn default() -> SuccessResult {
-        SuccessResult {
-            success: true,
-            data: Vec::new(),
+    #[tokio::test]
+    async fn waits_until_shutdown_started() {
+        assert_eq!(drain_timeout(), Duration::from_secs(DEFAULT_DRAIN_TIMEOUT));
+        let (stop, stopped) = watch::channel(false);
+        let waiting = tokio::spawn(wait(stopped.clone()));
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+        stop.send(true).unwrap();
+        waiting.await.unwrap();
+        // Started before the wait began.
+        wait(stopped).await;
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn serves_on_a_unix_socket_and_removes_it() {
+        let path = env::temp_dir().join(format!("fava-query-{}.sock", process::id()));
+        let path = path.to_str().unwrap().to_string();
+        // A socket left behind by an earlier run is replaced.
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        let app = Router::new().route("/healthz", axum::routing::get(|| async { "ok" }));
+        let (stop, stopped) = watch::channel(false);
+        let server = tokio::spawn({
+            let path = path.clone();
+            async move { serve_unix(app, &path, stopped).await }
+        });
+        let mut healthy = false;
+        for _ in 0..50 {
+            healthy = unix_status(&path).await;
+            if healthy {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
         }
+        assert!(healthy);
+        stop.send(true).unwrap();
+        server.await.unwrap();
+        assert!(fs::metadata(&path).is_err());
+        assert!(!unix_status(&path).await);
     }
-}
 
-impl IntoResponse for SuccessResult {
-    fn into_response(self) -> Response {
-        let body = Json(self);
-        (StatusCode::OK, body).into_response()
+    #[tokio::test]
+    async fn runs_one_query_and_checks_the_health() {
+        let fava = Router::new().route(
+            "/api/query_result",
+            axum::routing::get(|| async {
+                "{\"success\": true, \"data\": {\"table\": \"<table><thead><tr><th>n</th></tr>\
+                 </thead><tbody><tr><td>1</td></tr></tbody></table>\"}}"
+            }),
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(fava.into_make_service()),
+        );
+        env::set_var("url", &url);
+        env::set_var("fava_backend", "html");
+        env::set_var("refresh_path", "none");
+        assert_eq!(query("SELECT n", "csv").await, 0);
+        assert_eq!(query("SELECT n", "xml").await, 1);
+        env::remove_var("url");
+        env::remove_var("fava_backend");
+        env::remove_var("refresh_path");
+
+        env::set_var("unix_socket", "/nonexistent/fava-query.sock");
+        assert_eq!(healthcheck().await, 1);
+        env::remove_var("unix_socket");
     }
 }
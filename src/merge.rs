@@ -0,0 +1,133 @@
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+
+use crate::{amount, Row};
+
+/// Most accounts `/api/account/A,B` may combine, as each is its own
+/// journal fetched from fava.
+pub const MAX_ACCOUNTS: usize = 16;
+
+/// Numbers by commodity, with bare numbers under `None`.
+type Inventory = BTreeMap<Option<String>, Decimal>;
+
+/// Combines the daily series of several accounts, each with its `date`,
+/// `changed` and `balance` and ascending by date, into one over every day
+/// any of them has.
+///
+/// The change of a day sums the changes of the accounts on that day, and
+/// its balance sums the balance each account had by the end of it, each
+/// commodity on its own.
+pub fn series(accounts: Vec<Vec<Row>>) -> Vec<Row> {
+    let inventory = |row: &Row, column: &str| -> Inventory {
+        let mut inventory = Inventory::new();
+        let positions = row
+            .get(column)
+            .and_then(|text| amount::parse_positions(text));
+        for (number, currency) in positions.into_iter().flatten() {
+            *inventory.entry(currency).or_default() += number;
+        }
+        inventory
+    };
+    let count = accounts.len();
+    let mut days: BTreeMap<String, Vec<Option<(Inventory, Inventory)>>> = BTreeMap::new();
+    for (i, rows) in accounts.iter().enumerate() {
+        for row in rows {
+            let date = match row.get("date") {
+                Some(date) => date.clone(),
+                None => continue,
+            };
+            days.entry(date).or_insert_with(|| vec![None; count])[i] =
+                Some((inventory(row, "changed"), inventory(row, "balance")));
+        }
+    }
+
+    let mut balances = vec![Inventory::new(); count];
+    days.into_iter()
+        .map(|(date, inventories)| {
+            let mut changed = Inventory::new();
+            for (i, day) in inventories.into_iter().enumerate() {
+                if let Some((change, balance)) = day {
+                    for (currency, number) in change {
+                        *changed.entry(currency).or_default() += number;
+                    }
+                    balances[i] = balance;
+                }
+            }
+            let mut balance = Inventory::new();
+            for (currency, number) in balances.iter().flatten() {
+                *balance.entry(currency.clone()).or_default() += *number;
+            }
+            let mut row = Row::new();
+            row.insert("date".into(), date);
+            row.insert("changed".into(), format(changed));
+            row.insert("balance".into(), format(balance));
+            row
+        })
+        .collect()
+}
+
+fn format(inventory: Inventory) -> String {
+    let positions: Vec<_> = inventory
+        .into_iter()
+        .map(|(currency, number)| (number, currency))
+        .collect();
+    match positions.is_empty() {
+        true => amount::format_number(Decimal::ZERO),
+        false => amount::format_positions(&positions),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn journal(days: &[(&str, &str, &str)]) -> Vec<Row> {
+        days.iter()
+            .map(|(date, changed, balance)| {
+                Row::from([
+                    ("date".to_string(), date.to_string()),
+                    ("changed".to_string(), changed.to_string()),
+                    ("balance".to_string(), balance.to_string()),
+                ])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn carries_each_balance_over_the_days_of_the_others() {
+        let bank = journal(&[
+            ("2024-01-01", "100.00 CNY", "100.00 CNY"),
+            ("2024-01-03", "-20.00 CNY", "80.00 CNY"),
+        ]);
+        let cash = journal(&[
+            ("2024-01-02", "5 USD", "5 USD"),
+            ("2024-01-03", "1.50 CNY", "1.50 CNY, 5 USD"),
+        ]);
+        let rows = series(vec![bank, cash]);
+        let days: Vec<(&str, &str, &str)> = rows
+            .iter()
+            .map(|row| {
+                (
+                    row["date"].as_str(),
+                    row["changed"].as_str(),
+                    row["balance"].as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            days,
+            [
+                ("2024-01-01", "100.00 CNY", "100.00 CNY"),
+                ("2024-01-02", "5 USD", "100.00 CNY, 5 USD"),
+                ("2024-01-03", "-18.50 CNY", "81.50 CNY, 5 USD"),
+            ]
+        );
+    }
+
+    #[test]
+    fn writes_zero_for_an_empty_day() {
+        let rows = series(vec![journal(&[("2024-01-01", "", "")])]);
+        assert_eq!(rows[0]["changed"], "0");
+        assert_eq!(rows[0]["balance"], "0");
+    }
+}
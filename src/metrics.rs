@@ -0,0 +1,279 @@
+use axum::{
+    extract::{MatchedPath, State},
+    http::{header::CONTENT_TYPE, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Instant,
+};
+
+use crate::AppState;
+
+/// Upper bounds of the request latency buckets, in seconds.
+const BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Counters of one ledger's state, served by `/metrics` in the Prometheus
+/// text format.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Responses per route and status code.
+    requests: Mutex<BTreeMap<(String, u16), u64>>,
+    latencies: Mutex<BTreeMap<String, Histogram>>,
+    upstream_requests: AtomicU64,
+    upstream_errors: AtomicU64,
+    rows_parsed: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Observations per bucket of [`BUCKETS`], not cumulative.
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Metrics {
+    /// Counts a request to fava that answered, or failed with `error`.
+    pub fn upstream(&self, error: bool) {
+        self.upstream_requests.fetch_add(1, Ordering::Relaxed);
+        if error {
+            self.upstream_errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn rows_parsed(&self, rows: usize) {
+        self.rows_parsed.fetch_add(rows as u64, Ordering::Relaxed);
+    }
+
+    fn request(&self, route: &str, status: u16, seconds: f64) {
+        *self
+            .requests
+            .lock()
+            .unwrap()
+            .entry((route.to_string(), status))
+            .or_default() += 1;
+        let mut latencies = self.latencies.lock().unwrap();
+        let histogram = latencies.entry(route.to_string()).or_default();
+        if let Some(bucket) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            histogram.buckets[bucket] += 1;
+        }
+        histogram.count += 1;
+        histogram.sum += seconds;
+    }
+}
+
+/// Times every routed request, by the route it matched.
+pub async fn track<B>(
+    State(state): State<AppState>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let route = match request.extensions().get::<MatchedPath>() {
+        Some(path) => path.as_str().to_string(),
+        None => request.uri().path().to_string(),
+    };
+    let started = Instant::now();
+    let response = next.run(request).await;
+    state.metrics.request(
+        &route,
+        response.status().as_u16(),
+        started.elapsed().as_secs_f64(),
+    );
+    response
+}
+
+/// `GET /metrics`: the counters in the Prometheus text format.
+pub async fn metrics(State(state): State<AppState>) -> Response {
+    let metrics = &state.metrics;
+    let mut out = String::new();
+
+    header(
+        &mut out,
+        "fava_query_requests_total",
+        "counter",
+        "Requests served, by route and status code.",
+    );
+    for ((route, status), count) in metrics.requests.lock().unwrap().iter() {
+        let _ = writeln!(
+            out,
+            "fava_query_requests_total{{route=\"{}\",status=\"{}\"}} {}",
+            escape(route),
+            status,
+            count
+        );
+    }
+
+    header(
+        &mut out,
+        "fava_query_request_duration_seconds",
+        "histogram",
+        "Time to answer a request, by route.",
+    );
+    for (route, histogram) in metrics.latencies.lock().unwrap().iter() {
+        let route = escape(route);
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(histogram.buckets) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "fava_query_request_duration_seconds_bucket{{route=\"{}\",le=\"{}\"}} {}",
+                route, bound, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "fava_query_request_duration_seconds_bucket{{route=\"{}\",le=\"+Inf\"}} {}",
+            route, histogram.count
+        );
+        let _ = writeln!(
+            out,
+            "fava_query_request_duration_seconds_sum{{route=\"{}\"}} {}",
+            route, histogram.sum
+        );
+        let _ = writeln!(
+            out,
+            "fava_query_request_duration_seconds_count{{route=\"{}\"}} {}",
+            route, histogram.count
+        );
+    }
+
+    let counters = [
+        (
+            "fava_query_upstream_requests_total",
+            "Requests to fava, retries included.",
+            &metrics.upstream_requests,
+        ),
+        (
+            "fava_query_upstream_errors_total",
+            "Requests to fava that failed or found it in maintenance.",
+            &metrics.upstream_errors,
+        ),
+        (
+            "fava_query_rows_parsed_total",
+            "Rows parsed from fava's query results.",
+            &metrics.rows_parsed,
+        ),
+    ];
+    for (name, help, counter) in counters {
+        header(&mut out, name, "counter", help);
+        let _ = writeln!(out, "{} {}", name, counter.load(Ordering::Relaxed));
+    }
+
+    let caches = [
+        ("queries", state.queries.stats()),
+        ("accounts", state.accounts.stats()),
+    ];
+    header(
+        &mut out,
+        "fava_query_cache_requests_total",
+        "counter",
+        "Cache lookups, by cache and whether they hit.",
+    );
+    for (cache, (hits, misses)) in caches {
+        let _ = writeln!(
+            out,
+            "fava_query_cache_requests_total{{cache=\"{}\",result=\"hit\"}} {}",
+            cache, hits
+        );
+        let _ = writeln!(
+            out,
+            "fava_query_cache_requests_total{{cache=\"{}\",result=\"miss\"}} {}",
+            cache, misses
+        );
+    }
+    header(
+        &mut out,
+        "fava_query_cache_hit_ratio",
+        "gauge",
+        "Share of the cache lookups that hit, 0 before the first lookup.",
+    );
+    for (cache, (hits, misses)) in caches {
+        let ratio = match hits + misses {
+            0 => 0.0,
+            total => hits as f64 / total as f64,
+        };
+        let _ = writeln!(
+            out,
+            "fava_query_cache_hit_ratio{{cache=\"{}\"}} {}",
+            cache, ratio
+        );
+    }
+
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")],
+        out,
+    )
+        .into_response()
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Escapes a label value of the text format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::get, Router};
+
+    use super::*;
+    use crate::{backend::Backend, config::Config, testing};
+
+    #[tokio::test]
+    async fn counts_requests_rows_and_cache_hits() {
+        let fava = Router::new()
+            .route(
+                "/api/query_result",
+                get(|| async { testing::table(&["n"], &[&["1"], &["2"]]) }),
+            )
+            .route(
+                "/api/changed",
+                get(|| async { "{\"success\": true, \"data\": false}" }),
+            );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        let state = AppState::new(config);
+        for _ in 0..2 {
+            testing::get(&state, "/api/query_result?query_string=SELECT%20n").await;
+        }
+        let (status, metrics) = testing::get(&state, "/metrics").await;
+        assert_eq!(status, 200);
+        for line in [
+            "# TYPE fava_query_requests_total counter",
+            "fava_query_requests_total{route=\"/api/query_result\",status=\"200\"} 2",
+            "fava_query_request_duration_seconds_bucket{route=\"/api/query_result\",le=\"+Inf\"} 2",
+            "fava_query_request_duration_seconds_count{route=\"/api/query_result\"} 2",
+            "fava_query_rows_parsed_total 4",
+            "fava_query_upstream_errors_total 0",
+            "fava_query_cache_requests_total{cache=\"queries\",result=\"hit\"} 1",
+            "fava_query_cache_requests_total{cache=\"queries\",result=\"miss\"} 1",
+            "fava_query_cache_hit_ratio{cache=\"queries\"} 0.5",
+            "fava_query_cache_hit_ratio{cache=\"accounts\"} 0",
+        ] {
+            assert!(
+                metrics.lines().any(|got| got == line),
+                "{}\n{}",
+                line,
+                metrics
+            );
+        }
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
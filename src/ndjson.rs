@@ -0,0 +1,334 @@
+use axum::{
+    body::{boxed, Body, Bytes},
+    response::{IntoResponse, Response},
+};
+use reqwest::header::CONTENT_TYPE;
+use tokio::sync::mpsc;
+
+use crate::{
+    each_table_row, partial::TableChunks, query_rows, successful, AppState, ErrorResult,
+    OrderedRow, Params, QueryResult, Row, UpstreamError,
+};
+
+/// Lines parsed ahead of a slow client before parsing waits for it.
+const BUFFERED_LINES: usize = 1024;
+/// Row tables cut from fava's body ahead of parsing them.
+const BUFFERED_TABLES: usize = 64;
+/// Largest chunk lines are gathered into before they are sent.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// `format=ndjson`: one JSON object per row and line. Without anything to
+/// transform, filter, group or sort the rows by, each is sent as soon as
+/// it is parsed, and with fava's HTML table API parsing starts with the
+/// first rows to arrive, so the rows are never all in memory at once. The
+/// JSON API's result is read in full before its rows go out. Warnings and
+/// meta are left out, as the lines hold only rows.
+pub async fn respond(state: &AppState, params: &Params) -> Result<Response, ErrorResult> {
+    let (lines, received) = mpsc::channel::<String>(BUFFERED_LINES);
+    let emitter = Emitter {
+        lines,
+        offset: params.offset.unwrap_or(0),
+        left: params.limit,
+        typed: params.typed == Some(true),
+        parsed: 0,
+        done: false,
+    };
+    if streamable(state, params) {
+        let filters = params.filters();
+        let session = state
+            .session()
+            .refresh_path(params.refresh_path.as_deref())
+            .refresh(params.refresh)
+            .filtered(&filters);
+        let upstream = match session.query_stream(&params.query_string).await {
+            // The last good result may stand in, which only `query` serves.
+            Err(e) if e.is_unreachable() && state.config.stale_fallback.is_some() => None,
+            upstream => upstream?,
+        };
+        let result = match upstream {
+            Some(upstream) => stream(state, upstream, emitter).await?,
+            None => Some((
+                successful(session.query(&params.query_string).await)?,
+                emitter,
+            )),
+        };
+        if let Some((result, mut emitter)) = result {
+            let state = state.clone();
+            // The HTML table is parsed by nipper, whose documents can not be
+            // sent between threads.
+            tokio::task::spawn_blocking(move || {
+                if let Some(data) = result.data {
+                    data.each_row(&mut Vec::new(), |columns, row| emitter.emit(columns, row));
+                }
+                state.metrics.rows_parsed(emitter.parsed);
+            });
+        }
+    } else {
+        let result = query_rows(state, params).await?;
+        let typed = emitter.typed;
+        let lines = emitter.lines;
+        tokio::spawn(async move {
+            let columns = result.ordered_columns();
+            for row in &result.data {
+                if lines.send(line(row, &columns, typed)).await.is_err() {
+                    return;
+                }
+            }
+        });
+    }
+
+    Ok((
+        [(CONTENT_TYPE, "application/x-ndjson")],
+        boxed(forward(received)),
+    )
+        .into_response())
+}
+
+/// Parses the rows of fava's HTML table as its body arrives, once it
+/// turned out to hold a table. A body without one, such as fava's error,
+/// is returned whole with the emitter instead.
+async fn stream(
+    state: &AppState,
+    mut upstream: reqwest::Response,
+    mut emitter: Emitter,
+) -> Result<Option<(QueryResult, Emitter)>, ErrorResult> {
+    let mut chunks = TableChunks::default();
+    let mut first = Vec::new();
+    while !chunks.started() {
+        match upstream.chunk().await.map_err(UpstreamError::from)? {
+            Some(chunk) => first.extend(chunks.push(&chunk)),
+            None => {
+                let result = serde_json::from_slice(&chunks.into_body());
+                return Ok(Some((
+                    successful(result.map_err(UpstreamError::from))?,
+                    emitter,
+                )));
+            }
+        }
+    }
+
+    let (tables, mut received) = mpsc::channel::<String>(BUFFERED_TABLES);
+    tokio::spawn(async move {
+        while let Ok(Some(chunk)) = upstream.chunk().await {
+            if let Some(table) = chunks.push(&chunk) {
+                if tables.send(table).await.is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let mut next = first.into_iter();
+        while let Some(table) = next.next().or_else(|| received.blocking_recv()) {
+            each_table_row(&table, &mut Vec::new(), |columns, row| {
+                emitter.emit(columns, row)
+            });
+            if emitter.done {
+                break;
+            }
+        }
+        state.metrics.rows_parsed(emitter.parsed);
+    });
+    Ok(None)
+}
+
+/// Sends rows as lines, but for the first `offset` and any after `left`
+/// ran out.
+struct Emitter {
+    lines: mpsc::Sender<String>,
+    offset: usize,
+    left: Option<usize>,
+    typed: bool,
+    parsed: usize,
+    /// Set once no further row is wanted, or the client went away.
+    done: bool,
+}
+
+impl Emitter {
+    /// Takes a row from a blocking thread, and tells whether to go on.
+    fn emit(&mut self, columns: &[String], row: Row) -> bool {
+        self.parsed += 1;
+        if self.offset > 0 {
+            self.offset -= 1;
+            return true;
+        }
+        match &mut self.left {
+            Some(0) => {
+                self.done = true;
+                return false;
+            }
+            Some(left) => *left -= 1,
+            None => {}
+        }
+        let sent = self
+            .lines
+            .blocking_send(line(&row, columns, self.typed))
+            .is_ok();
+        self.done = !sent || self.left == Some(0);
+        !self.done
+    }
+}
+
+/// Whether the rows go out as fava gave them, but for `offset` and
+/// `limit`, so that they can be sent as they are parsed.
+fn streamable(state: &AppState, params: &Params) -> bool {
+    let smoothed = state.config.poll_smoothing.is_some() && params.filters().is_empty();
+    !smoothed
+        && params.budget_ms.is_none()
+        && params.transform.is_none()
+        && params.invert_columns.is_none()
+        && params.row_filter.is_none()
+        && params.search.is_none()
+        && params.group_by.is_none()
+        && params.aggregate.is_none()
+        && params.sort.is_none()
+        && params.columns.is_none()
+}
+
+/// A row as a line, its cells in the order of `columns`.
+fn line(row: &Row, columns: &[impl AsRef<str>], typed: bool) -> String {
+    let mut line = serde_json::to_string(&OrderedRow {
+        row,
+        columns,
+        typed,
+    })
+    .unwrap_or_default();
+    line.push('\n');
+    line
+}
+
+/// A body of the lines from `received`, gathering those already waiting
+/// into one chunk. Parsing stops once the client goes away, as the lines
+/// can no longer be sent.
+fn forward(mut received: mpsc::Receiver<String>) -> Body {
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        while let Some(mut chunk) = received.recv().await {
+            while chunk.len() < CHUNK_SIZE {
+                match received.try_recv() {
+                    Ok(line) => chunk.push_str(&line),
+                    Err(_) => break,
+                }
+            }
+            if sender.send_data(Bytes::from(chunk)).await.is_err() {
+                return;
+            }
+        }
+    });
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{boxed, Body, HttpBody},
+        http::Request,
+        routing::get,
+        Router,
+    };
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+    use tokio::sync::oneshot;
+    use tower::ServiceExt;
+
+    use crate::{backend::Backend, config::Config, routes, testing, AppState};
+
+    /// A fava that sends the head and first row of its table, then the
+    /// rest only once `release` fires.
+    async fn trickling(release: oneshot::Receiver<()>) -> AppState {
+        let release = Arc::new(Mutex::new(Some(release)));
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(move || {
+                let release = release.lock().unwrap().take();
+                async move {
+                    let (mut sender, body) = Body::channel();
+                    tokio::spawn(async move {
+                        let head =
+                            "{\"data\": {\"table\": \"<table><thead><tr><th>n</th></tr></thead>\
+                            <tbody><tr><td>1</td></tr>";
+                        sender.send_data(head.into()).await.unwrap();
+                        if let Some(release) = release {
+                            let _ = release.await;
+                        }
+                        let rest = "<tr><td>2</td></tr></tbody></table>\"}, \"success\": true}";
+                        let _ = sender.send_data(rest.into()).await;
+                    });
+                    boxed(body)
+                }
+            }),
+        );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        AppState::new(config)
+    }
+
+    #[tokio::test]
+    async fn sends_the_first_rows_before_fava_finished() {
+        let (release, released) = oneshot::channel();
+        let state = trickling(released).await;
+        let request = Request::get("/api/query_result?query_string=SELECT%20n&format=ndjson")
+            .body(Body::empty())
+            .unwrap();
+        let response = routes(state).oneshot(request).await.unwrap();
+        let mut body = response.into_body();
+        let first = tokio::time::timeout(Duration::from_secs(5), body.data())
+            .await
+            .expect("the first row waited for the whole table")
+            .unwrap()
+            .unwrap();
+        assert_eq!(first, "{\"n\":\"1\"}\n");
+        release.send(()).unwrap();
+        let rest = hyper::body::to_bytes(body).await.unwrap();
+        assert_eq!(rest, "{\"n\":\"2\"}\n");
+    }
+
+    #[tokio::test]
+    async fn answers_fava_errors_with_their_status() {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|| async { "{\"error\": \"Syntax error\", \"success\": false}" }),
+        );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        let state = AppState::new(config);
+        let (status, body) =
+            testing::get(&state, "/api/query_result?query_string=bogus&format=ndjson").await;
+        assert_eq!(status, 400);
+        assert!(body.contains("Syntax error"));
+    }
+
+    #[tokio::test]
+    async fn writes_the_page_of_rows_a_line_each() {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|| async {
+                testing::table(
+                    &["n", "account"],
+                    &[
+                        &["1", "Assets:Bank"],
+                        &["3", "Assets:Cash"],
+                        &["2", "Income:Salary"],
+                    ],
+                )
+            }),
+        );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        let state = AppState::new(config);
+        let uri = "/api/query_result?query_string=SELECT%20n&format=ndjson";
+        let (status, body) = testing::get(&state, &format!("{}&offset=1&limit=1", uri)).await;
+        assert_eq!(status, 200);
+        assert_eq!(body, "{\"n\":\"3\",\"account\":\"Assets:Cash\"}\n");
+        // Sorted rows are all parsed before the first goes out.
+        let (_, body) = testing::get(&state, &format!("{}&sort=n:desc", uri)).await;
+        let numbers: Vec<&str> = body.lines().map(|line| &line[6..7]).collect();
+        assert_eq!(numbers, ["3", "2", "1"]);
+    }
+}
@@ -0,0 +1,188 @@
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
+use nipper::Document;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{i18n::Lang, AppState, ErrorResult};
+
+/// The beancount options of the ledger that matter to clients formatting
+/// its numbers and accounts.
+///
+/// Read from fava's `/api/ledger_data`, or on fava versions without it from
+/// the ledger data its pages embed.
+pub async fn options(
+    State(state): State<AppState>,
+    lang: Lang,
+) -> Result<OptionsResult, ErrorResult> {
+    let session = state.session();
+    let response = session
+        .download("/api/ledger_data", &[])
+        .await
+        .map_err(|e| ErrorResult::from(e).localize(lang))?;
+    let from_api = match response.status().is_success() {
+        true => response
+            .json::<LedgerDataResult>()
+            .await
+            .ok()
+            .map(|result| result.data),
+        false => None,
+    };
+    let data = match from_api {
+        Some(data) => data,
+        None => {
+            let page = session
+                .page("/income_statement/", &[])
+                .await
+                .map_err(|e| ErrorResult::from(e).localize(lang))?;
+            embedded(&page).ok_or_else(|| ErrorResult {
+                status: StatusCode::BAD_GATEWAY,
+                ..ErrorResult::new("fava did not return its ledger data".into())
+            })?
+        }
+    };
+    let options = data.options;
+    let text = |name: &str| {
+        options
+            .get(name)
+            .and_then(Value::as_str)
+            .map(str::to_string)
+    };
+    Ok(OptionsResult {
+        success: true,
+        data: LedgerOptions {
+            title: text("title"),
+            operating_currency: options
+                .get("operating_currency")
+                .and_then(Value::as_array)
+                .map(|currencies| {
+                    currencies
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            name_assets: text("name_assets"),
+            name_liabilities: text("name_liabilities"),
+            name_equity: text("name_equity"),
+            name_income: text("name_income"),
+            name_expenses: text("name_expenses"),
+            booking_method: text("booking_method"),
+            render_commas: options.get("render_commas").and_then(Value::as_bool),
+            locale: data
+                .fava_options
+                .get("locale")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+        },
+    })
+}
+
+/// The JSON of `<script id="ledger-data">` in a fava page.
+fn embedded(html: &str) -> Option<LedgerData> {
+    let document = Document::from(html);
+    let script = document.select("script#ledger-data").text().to_string();
+    serde_json::from_str(script.trim()).ok()
+}
+
+#[derive(Debug, Deserialize)]
+struct LedgerDataResult {
+    data: LedgerData,
+}
+
+#[derive(Debug, Deserialize)]
+struct LedgerData {
+    #[serde(default)]
+    options: serde_json::Map<String, Value>,
+    #[serde(default)]
+    fava_options: serde_json::Map<String, Value>,
+}
+
+#[derive(Debug, Serialize)]
+struct LedgerOptions {
+    title: Option<String>,
+    operating_currency: Vec<String>,
+    name_assets: Option<String>,
+    name_liabilities: Option<String>,
+    name_equity: Option<String>,
+    name_income: Option<String>,
+    name_expenses: Option<String>,
+    booking_method: Option<String>,
+    render_commas: Option<bool>,
+    /// fava's `locale` option, for formatting numbers like fava does.
+    locale: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OptionsResult {
+    success: bool,
+    data: LedgerOptions,
+}
+
+impl IntoResponse for OptionsResult {
+    fn into_response(self) -> Response {
+        let body = Json(self);
+        (StatusCode::OK, body).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::get, Router};
+    use serde_json::json;
+
+    use super::*;
+    use crate::{config::Config, testing};
+
+    const DATA: &str = r#"{"options": {"title": "Home", "operating_currency": ["CNY", "USD"], "name_assets": "Assets", "booking_method": "STRICT", "render_commas": true}, "fava_options": {"locale": "zh_CN"}}"#;
+
+    #[tokio::test]
+    async fn reads_the_options_of_the_api_or_of_a_page() {
+        let api = format!("{{\"success\": true, \"data\": {}}}", DATA);
+        let page = format!(
+            "<html><script type=\"application/json\" id=\"ledger-data\">\n{}\n</script></html>",
+            DATA
+        );
+        let new = Router::new().route("/api/ledger_data", get(move || async move { api }));
+        let old = Router::new().route("/income_statement/", get(move || async move { page }));
+        for fava in [new, old] {
+            let config = Config::new(testing::fava(fava).await).refresh_path("none");
+            let (status, body) = testing::get(&AppState::new(config), "/api/options").await;
+            assert_eq!(status, StatusCode::OK, "{}", body);
+            let body: Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(
+                body["data"],
+                json!({
+                    "title": "Home",
+                    "operating_currency": ["CNY", "USD"],
+                    "name_assets": "Assets",
+                    "name_liabilities": null,
+                    "name_equity": null,
+                    "name_income": null,
+                    "name_expenses": null,
+                    "booking_method": "STRICT",
+                    "render_commas": true,
+                    "locale": "zh_CN",
+                })
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn fails_on_pages_without_ledger_data() {
+        let fava = Router::new().route("/income_statement/", get(|| async { "<html></html>" }));
+        let config = Config::new(testing::fava(fava).await).refresh_path("none");
+        let (status, body) = testing::get(&AppState::new(config), "/api/options").await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert!(
+            body.contains("fava did not return its ledger data"),
+            "{}",
+            body
+        );
+    }
+}
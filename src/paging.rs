@@ -0,0 +1,273 @@
+use std::{
+    collections::HashSet,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::Arc,
+};
+use tokio::sync::Semaphore;
+
+use crate::{
+    journal::{self, JournalEntry},
+    AppState, ErrorResult,
+};
+
+/// Year pages of one journal fetched at the same time.
+const CONCURRENCY: usize = 2;
+
+/// Flags of the entries fava adds for a time filter: the summary of
+/// everything before the period and the conversions at its end.
+const FILTER_FLAGS: [&str; 2] = ["S", "C"];
+
+/// The journal of an account, newest entry first.
+#[derive(Debug)]
+pub struct Journal {
+    pub entries: Vec<JournalEntry>,
+    /// Account pages requested from fava.
+    pub pages: usize,
+}
+
+/// Fetches the journal of `account`, in one page per year when the config
+/// sets `journal_by_year`, so that long journals do not have to be rendered
+/// in one go.
+pub async fn account_journal(
+    state: &AppState,
+    account: &str,
+    refresh_path: Option<&str>,
+    refresh: Option<bool>,
+) -> Result<Journal, ErrorResult> {
+    let years = match state.config.journal_by_year {
+        true => journal_years(state, account).await?,
+        false => Vec::new(),
+    };
+    if years.len() <= 1 {
+        let html = state
+            .session()
+            .refresh_path(refresh_path)
+            .refresh(refresh)
+            .account_page(account, None)
+            .await?;
+        return Ok(Journal {
+            entries: journal::parse_journal(&html, state.detection.layout()),
+            pages: 1,
+        });
+    }
+
+    // Only the first page refreshes fava's data.
+    let permits = Arc::new(Semaphore::new(CONCURRENCY));
+    let mut pages = Vec::new();
+    for (i, year) in years.iter().rev().enumerate() {
+        let state = state.clone();
+        let account = account.to_string();
+        let year = year.clone();
+        let refresh_path = match i {
+            0 => refresh_path.map(str::to_string),
+            _ => Some("none".to_string()),
+        };
+        let permits = permits.clone();
+        pages.push(tokio::spawn(async move {
+            let _permit = permits.acquire_owned().await;
+            state
+                .session()
+                .refresh_path(refresh_path.as_deref())
+                .refresh(refresh)
+                .account_page(&account, Some(&year))
+                .await
+                .map(|html| journal::parse_journal(&html, state.detection.layout()))
+        }));
+    }
+
+    let mut journal = Journal {
+        entries: Vec::new(),
+        pages: pages.len(),
+    };
+    let mut previous = HashSet::new();
+    for page in pages {
+        let entries = page.await.map_err(|e| ErrorResult::new(e.to_string()))??;
+        let entries: Vec<JournalEntry> = entries
+            .into_iter()
+            .filter(|entry| !FILTER_FLAGS.contains(&entry.flag.as_str()))
+            .collect();
+        // Neighbouring years may both list an entry on their boundary.
+        let hashes: HashSet<u64> = entries.iter().map(entry_hash).collect();
+        journal.entries.extend(
+            entries
+                .into_iter()
+                .filter(|entry| !previous.contains(&entry_hash(entry))),
+        );
+        previous = hashes;
+    }
+    Ok(journal)
+}
+
+/// The years with postings to `account` or its children, oldest first.
+async fn journal_years(state: &AppState, account: &str) -> Result<Vec<String>, ErrorResult> {
+    let query = format!(
+        "SELECT year, count(position) WHERE account ~ '^{}(:|$)' GROUP BY year ORDER BY year",
+        account.replace('\'', "")
+    );
+    let parsed = state
+        .session()
+        .refresh_path(Some("none"))
+        .rows(&query)
+        .await?;
+    Ok(parsed
+        .rows
+        .into_iter()
+        .filter_map(|mut row| row.remove("year"))
+        .filter(|year| !year.is_empty() && year.chars().all(|c| c.is_ascii_digit()))
+        .collect())
+}
+
+fn entry_hash(entry: &JournalEntry) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (
+        &entry.date,
+        &entry.flag,
+        &entry.payee,
+        &entry.narration,
+        &entry.change,
+        &entry.balance,
+    )
+        .hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        extract::{Query, State as Extract},
+        routing::get,
+        Router,
+    };
+    use serde_json::Value;
+    use std::{
+        collections::HashMap,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::{backend::Backend, config::Config, testing};
+
+    /// A journal page of fava 1.27 with `(date, flag, payee, change,
+    /// balance)` entries, newest first.
+    fn page(entries: &[(&str, &str, &str, &str, &str)]) -> String {
+        let lines: String = entries
+            .iter()
+            .map(|(date, flag, payee, change, balance)| {
+                format!(
+                    "<li class=\"transaction\"><p><span class=\"datecell\">{}</span>\
+                     <span class=\"flag\">{}</span><span class=\"description\">\
+                     <strong class=\"payee\">{}</strong></span><span class=\"indicators\">\
+                     </span><span class=\"num change\">{}</span><span class=\"num\">{}</span>\
+                     </p><ul class=\"postings\"><li class=\"posting\"><p>\
+                     <a class=\"account\">Assets:Bank</a></p></li></ul></li>",
+                    date, flag, payee, change, balance
+                )
+            })
+            .collect();
+        format!(
+            "<html><body><ol class=\"flex-table journal\">{}</ol></body></html>",
+            lines
+        )
+    }
+
+    /// Requests of account pages under way, and the most at the same time.
+    #[derive(Clone, Default)]
+    struct Concurrency(Arc<(AtomicUsize, AtomicUsize)>);
+
+    /// A fava with postings in 2022, 2023 and 2024, whose 2023 and 2024
+    /// pages both list the entry on their seam, and whose filtered pages
+    /// start with the summary of the years before.
+    async fn yearly(concurrency: &Concurrency) -> AppState {
+        let fava = Router::new()
+            .route(
+                "/api/query_result",
+                get(|| async {
+                    testing::table(
+                        &["year", "count(position)"],
+                        &[&["2022", "1"], &["2023", "2"], &["2024", "2"]],
+                    )
+                }),
+            )
+            .route(
+                "/account/*account",
+                get(
+                    |Query(query): Query<HashMap<String, String>>,
+                     Extract(concurrency): Extract<Concurrency>| async move {
+                        let (running, most) = &*concurrency.0;
+                        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        most.fetch_max(now, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        running.fetch_sub(1, Ordering::SeqCst);
+                        let seam = ("2023-12-31", "*", "Seam", "-5 CNY", "25 CNY");
+                        page(&match query.get("time").map(String::as_str) {
+                            Some("2024") => vec![
+                                ("2024-03-01", "*", "March", "-10 CNY", "10 CNY"),
+                                ("2024-01-02", "*", "January", "-5 CNY", "20 CNY"),
+                                seam,
+                                ("2023-12-31", "S", "Summary", "25 CNY", "25 CNY"),
+                            ],
+                            Some("2023") => vec![
+                                seam,
+                                ("2023-06-01", "*", "June", "-10 CNY", "30 CNY"),
+                                ("2022-12-31", "S", "Summary", "40 CNY", "40 CNY"),
+                            ],
+                            Some("2022") => {
+                                vec![("2022-01-01", "*", "Opening", "40 CNY", "40 CNY")]
+                            }
+                            _ => Vec::new(),
+                        })
+                    },
+                ),
+            )
+            .with_state(concurrency.clone());
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none")
+            .journal_by_year(true);
+        AppState::new(config)
+    }
+
+    #[tokio::test]
+    async fn joins_year_pages_without_losing_or_repeating_seam_entries() {
+        let concurrency = Concurrency::default();
+        let state = yearly(&concurrency).await;
+        let (status, body) = testing::get(&state, "/api/account/Assets:Bank").await;
+        assert_eq!(status, 200, "{}", body);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        let rows: Vec<(&str, &str)> = body["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|row| {
+                (
+                    row["date"].as_str().unwrap(),
+                    row["balance"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ("2022-01-01", "40 CNY"),
+                ("2023-06-01", "30 CNY"),
+                ("2023-12-31", "25 CNY"),
+                ("2024-01-02", "20 CNY"),
+                ("2024-03-01", "10 CNY"),
+            ]
+        );
+        assert_eq!(body["meta"]["upstream_pages"], 3);
+        assert_eq!(concurrency.0 .1.load(Ordering::SeqCst), CONCURRENCY);
+    }
+
+    #[tokio::test]
+    async fn fetches_the_whole_journal_at_once_without_journal_by_year() {
+        let concurrency = Concurrency::default();
+        let state = yearly(&concurrency).await;
+        let state = AppState::new(Config::new(state.config.url.clone()).refresh_path("none"));
+        let journal = account_journal(&state, "Assets:Bank", None, None)
+            .await
+            .unwrap();
+        assert_eq!((journal.pages, journal.entries.len()), (1, 0));
+    }
+}
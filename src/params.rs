@@ -0,0 +1,274 @@
+use axum::{
+    async_trait,
+    body::Body,
+    extract::{FromRequest, FromRequestParts, Query},
+    http::{request::Parts, Request},
+    Json,
+};
+use percent_encoding::percent_decode_str;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+
+use crate::{
+    capabilities,
+    i18n::{Lang, Message},
+    AppState, ErrorResult,
+};
+
+/// Parameters every endpoint accepts, read by other extractors.
+const COMMON: &[&str] = &["lang"];
+
+/// The query parameters an endpoint's parameter struct understands.
+pub trait QueryFields {
+    const FIELDS: &'static [&'static str];
+}
+
+/// Like `Query`, but rejects unknown parameters unless the config turns
+/// `strict_params` off, and reports bad parameters as a JSON `ErrorResult`.
+#[derive(Debug)]
+pub struct StrictQuery<T>(pub T);
+
+#[async_trait]
+impl<T> FromRequestParts<AppState> for StrictQuery<T>
+where
+    T: DeserializeOwned + QueryFields + Send,
+{
+    type Rejection = ErrorResult;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        registered::<T>();
+        if state.config.strict_params {
+            if let Err(message) = check_fields(parts.uri.query().unwrap_or_default(), T::FIELDS) {
+                let Ok(lang) = Lang::from_request_parts(parts, state).await;
+                return Err(ErrorResult::bad_request(message.localize(lang).to_string()));
+            }
+        }
+        match Query::<T>::from_request_parts(parts, state).await {
+            Ok(Query(params)) => Ok(StrictQuery(params)),
+            Err(rejection) => Err(ErrorResult::bad_request(rejection.to_string())),
+        }
+    }
+}
+
+/// Like `StrictQuery`, for the parameters as a JSON object in the request
+/// body. Numbers and booleans are read like their text in a query string.
+#[derive(Debug)]
+pub struct StrictJson<T>(pub T);
+
+#[async_trait]
+impl<T> FromRequest<AppState, Body> for StrictJson<T>
+where
+    T: DeserializeOwned + QueryFields + Send,
+{
+    type Rejection = ErrorResult;
+
+    async fn from_request(
+        request: Request<Body>,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let (mut parts, body) = request.into_parts();
+        let Ok(lang) = Lang::from_request_parts(&mut parts, state).await;
+        let request = Request::from_parts(parts, body);
+        let Json(object) = Json::<Map<String, Value>>::from_request(request, state)
+            .await
+            .map_err(|rejection| ErrorResult::bad_request(rejection.to_string()))?;
+        from_object(object, state, lang).map(StrictJson)
+    }
+}
+
+/// Reads the parameters of a JSON object like `StrictJson` does.
+pub fn from_object<T>(
+    object: Map<String, Value>,
+    state: &AppState,
+    lang: Lang,
+) -> Result<T, ErrorResult>
+where
+    T: DeserializeOwned + QueryFields,
+{
+    registered::<T>();
+    if state.config.strict_params {
+        if let Err(message) = check_keys(object.keys().cloned(), T::FIELDS) {
+            return Err(ErrorResult::bad_request(message.localize(lang).to_string()));
+        }
+    }
+    let mut fields = Map::new();
+    for (key, value) in object {
+        let value = match value {
+            Value::Null => continue,
+            Value::String(_) => value,
+            Value::Number(number) => Value::String(number.to_string()),
+            Value::Bool(flag) => Value::String(flag.to_string()),
+            _ => {
+                return Err(ErrorResult::bad_request(format!(
+                    "{} must be a string, a number or a boolean",
+                    key
+                )))
+            }
+        };
+        fields.insert(key, value);
+    }
+    serde_json::from_value(Value::Object(fields))
+        .map_err(|e| ErrorResult::bad_request(e.to_string()))
+}
+
+/// The parameter structs read so far, which the tests compare with those
+/// of the source.
+#[cfg(test)]
+pub static WALKED: std::sync::Mutex<std::collections::BTreeSet<&str>> =
+    std::sync::Mutex::new(std::collections::BTreeSet::new());
+
+/// Refuses, in debug builds, a parameter struct with fields that belong to
+/// no feature, so that clients learn about them from `/api/version`.
+fn registered<T: QueryFields>() {
+    debug_assert!(
+        capabilities::unregistered_param(T::FIELDS).is_none(),
+        "parameter {:?} belongs to no feature in capabilities::FEATURES",
+        capabilities::unregistered_param(T::FIELDS)
+    );
+    #[cfg(test)]
+    WALKED.lock().unwrap().insert(std::any::type_name::<T>());
+}
+
+fn check_fields(query: &str, fields: &[&str]) -> Result<(), Message> {
+    let keys = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| pair.split('=').next().unwrap_or_default().replace('+', " "))
+        .map(|key| percent_decode_str(&key).decode_utf8_lossy().into_owned());
+    check_keys(keys, fields)
+}
+
+pub(crate) fn check_keys(
+    keys: impl Iterator<Item = String>,
+    fields: &[&str],
+) -> Result<(), Message> {
+    for key in keys {
+        if fields.contains(&key.as_str()) || COMMON.contains(&key.as_str()) {
+            continue;
+        }
+        let closest = fields
+            .iter()
+            .chain(COMMON)
+            .map(|field| (distance(&key, field), field))
+            .filter(|(distance, _)| *distance <= 3)
+            .min();
+        return Err(match closest {
+            Some((_, field)) => Message::new("unknown_parameter", vec![key, field.to_string()]),
+            None => Message::new("unexpected_parameter", vec![key, fields.join(", ")]),
+        });
+    }
+    Ok(())
+}
+
+/// Levenshtein distance between two strings, by characters.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{http::StatusCode, routing::get, Router};
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{backend::Backend, config::Config, testing};
+
+    async fn state(strict_params: bool) -> AppState {
+        let fava = Router::new()
+            .route(
+                "/api/query_result",
+                get(|| async { testing::table(&["n"], &[&["1"]]) }),
+            )
+            .route(
+                "/account/*account",
+                get(|| async { include_str!("../tests/fixtures/1.27/account.html") }),
+            );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none")
+            .strict_params(strict_params);
+        AppState::new(config)
+    }
+
+    /// The status and error message of a GET of `uri`.
+    async fn rejected(state: &AppState, uri: &str) -> (StatusCode, String) {
+        let (status, body) = testing::get(state, uri).await;
+        let body: Value = serde_json::from_str(&body).unwrap();
+        (
+            status,
+            body["error"].as_str().unwrap_or_default().to_string(),
+        )
+    }
+
+    #[tokio::test]
+    async fn suggests_the_closest_parameter_for_a_typo() {
+        let state = state(true).await;
+        assert_eq!(
+            rejected(&state, "/api/query_result?querystring=SELECT%20n").await,
+            (
+                StatusCode::BAD_REQUEST,
+                "unknown parameter querystring, did you mean query_string?".into()
+            )
+        );
+        assert_eq!(
+            rejected(&state, "/api/account/Assets:Bank?negat=true").await,
+            (
+                StatusCode::BAD_REQUEST,
+                "unknown parameter negat, did you mean negate?".into()
+            )
+        );
+        let (_, message) = rejected(&state, "/api/account/Assets:Bank?negat=true&lang=zh").await;
+        assert_eq!(message, "未知参数 negat，是否想用 negate？");
+    }
+
+    #[tokio::test]
+    async fn lists_the_parameters_for_an_unknown_one() {
+        let state = state(true).await;
+        let (status, message) = rejected(
+            &state,
+            "/api/query_result?query_string=SELECT%20n&dashboard%5Fid=7",
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(
+            message.starts_with("unknown parameter dashboard_id, expected one of query_string, "),
+            "{}",
+            message
+        );
+    }
+
+    #[tokio::test]
+    async fn ignores_unknown_parameters_when_lenient() {
+        let state = state(false).await;
+        let (status, _) =
+            testing::get(&state, "/api/query_result?query_string=SELECT%20n&negat=1").await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = testing::get(&state, "/api/account/Assets:Bank?negat=true").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[test]
+    fn reads_json_keys_like_query_parameters() {
+        assert!(check_keys(["lang".to_string()].into_iter(), &["query_string"]).is_ok());
+        let message = check_keys(["query".to_string()].into_iter(), &["query_string"]);
+        assert_eq!(
+            message.unwrap_err().to_string(),
+            "unknown parameter query, expected one of query_string"
+        );
+        assert_eq!(distance("negat", "negate"), 1);
+        assert_eq!(distance("", "lang"), 4);
+    }
+}
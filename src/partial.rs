@@ -0,0 +1,202 @@
+/// The part of an upstream body that arrived before the request was dropped.
+#[derive(Debug, Default)]
+pub struct Received {
+    pub body: Vec<u8>,
+    pub content_length: Option<u64>,
+}
+
+/// Recovers the table html up to its last complete row from a truncated
+/// `query_result` JSON body.
+pub fn partial_table(body: &[u8]) -> Option<String> {
+    let start = value_start(body, b"\"table\"", b'"')?;
+    let rest = &body[start..];
+    let end = rfind(rest, b"</tr>")? + "</tr>".len();
+    decode(&rest[..end])
+}
+
+/// Cuts the HTML table of a `query_result` JSON body into tables of its
+/// complete rows as the body arrives, so that they can be parsed before
+/// the rest of it did.
+#[derive(Debug, Default)]
+pub struct TableChunks {
+    /// The body so far until the table started, then what is left of it.
+    pending: Vec<u8>,
+    started: bool,
+    /// The table up to the end of its `thead`.
+    head: Option<String>,
+}
+
+impl TableChunks {
+    /// Takes the next chunk of the body, and returns a table of the rows
+    /// it completed with the head of the whole table, if there are any.
+    pub fn push(&mut self, chunk: &[u8]) -> Option<String> {
+        self.pending.extend_from_slice(chunk);
+        if !self.started {
+            let start = value_start(&self.pending, b"\"table\"", b'"')?;
+            self.pending.drain(..start);
+            self.started = true;
+        }
+        if self.head.is_none() {
+            let end = find(&self.pending, b"</thead>")? + "</thead>".len();
+            self.head = Some(decode(&self.pending[..end])?);
+            self.pending.drain(..end);
+        }
+        let end = rfind(&self.pending, b"</tr>")? + "</tr>".len();
+        let rows = decode(&self.pending[..end]);
+        self.pending.drain(..end);
+        Some(format!(
+            "{}<tbody>{}</tbody></table>",
+            self.head.as_deref().unwrap_or_default(),
+            rows?
+        ))
+    }
+
+    /// Whether the body turned out to hold a table.
+    pub fn started(&self) -> bool {
+        self.started
+    }
+
+    /// The whole body, for one without a table, such as an error.
+    pub fn into_body(self) -> Vec<u8> {
+        self.pending
+    }
+}
+
+/// Recovers a truncated `/api/query` JSON body up to its last complete
+/// row, with the `rows` array and the objects around it closed again. Its
+/// `types` have to come before the rows, as fava sends them.
+pub fn partial_rows(body: &[u8]) -> Option<Vec<u8>> {
+    let start = value_start(body, b"\"rows\"", b'[')?;
+    let (mut depth, mut string, mut escaped) = (0, false, false);
+    let mut end = None;
+    for (offset, byte) in body[start..].iter().enumerate() {
+        match byte {
+            _ if escaped => escaped = false,
+            b'\\' if string => escaped = true,
+            b'"' => string = !string,
+            _ if string => {}
+            b'[' | b'{' => depth += 1,
+            b']' | b'}' if depth == 0 => break,
+            b']' | b'}' => {
+                depth -= 1;
+                if depth == 0 {
+                    end = Some(start + offset + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    let mut partial = body[..end?].to_vec();
+    partial.extend_from_slice(b"]}}");
+    Some(partial)
+}
+
+/// Where the value of `key` in a JSON body starts, right after its
+/// `opener`.
+fn value_start(body: &[u8], key: &[u8], opener: u8) -> Option<usize> {
+    let key = find(body, key)? + key.len();
+    let mut rest = body[key..].iter().enumerate();
+    let mut expect = |wanted: u8| {
+        rest.find(|(_, byte)| !byte.is_ascii_whitespace())
+            .filter(|(_, byte)| **byte == wanted)
+            .map(|(offset, _)| key + offset + 1)
+    };
+    expect(b':')?;
+    expect(opener)
+}
+
+/// The text of a JSON string without its quotes, which may not end within
+/// an escape.
+fn decode(text: &[u8]) -> Option<String> {
+    let text = std::str::from_utf8(text).ok()?;
+    serde_json::from_str(&format!("\"{}\"", text)).ok()
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn rfind(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .rposition(|window| window == needle)
+}
+
+impl Received {
+    /// Extrapolates the total row count from the share of the body received.
+    pub fn estimate_total(&self, parsed_rows: usize) -> Option<usize> {
+        let content_length = self.content_length? as usize;
+        if self.body.is_empty() {
+            return None;
+        }
+        Some(parsed_rows * content_length / self.body.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{backend::JsonQueryResult, each_table_row, get_table_data, QueryResult, Row};
+
+    const BODY: &str = r#"{"data": {"table": "<table class=\"queryresults\"><thead><tr><th>payee</th><th>n</th></tr></thead>\n<tbody><tr><td>Café \"A\"</td><td>1</td></tr>\n<tr><td>B</td><td>2</td></tr>\n<tr><td>C</td><td>3</td></tr></tbody></table>"}, "success": true}"#;
+
+    #[test]
+    fn cuts_the_same_rows_wherever_the_chunks_end() {
+        let whole: serde_json::Value = serde_json::from_str(BODY).unwrap();
+        let expected = get_table_data(whole["data"]["table"].as_str().unwrap().to_string()).rows;
+        for size in 1..BODY.len() {
+            let mut chunks = TableChunks::default();
+            let mut rows: Vec<Row> = Vec::new();
+            for chunk in BODY.as_bytes().chunks(size) {
+                if let Some(table) = chunks.push(chunk) {
+                    each_table_row(&table, &mut Vec::new(), |_, row| {
+                        rows.push(row);
+                        true
+                    });
+                }
+            }
+            assert!(chunks.started());
+            assert_eq!(rows, expected, "chunks of {} bytes", size);
+        }
+    }
+
+    #[test]
+    fn keeps_bodies_without_a_table() {
+        let body = br#"{"error": "Syntax error", "success": false}"#;
+        let mut chunks = TableChunks::default();
+        assert_eq!(chunks.push(body), None);
+        assert!(!chunks.started());
+        assert_eq!(chunks.into_body(), body);
+    }
+
+    const JSON_BODY: &str = r#"{"success": true, "data": {"t": "table", "types": [{"name": "payee", "dtype": "str"}, {"name": "position", "dtype": "Position"}], "rows": [["A \"]\" B", {"units": {"number": 1, "currency": "CNY"}, "cost": null}], ["C", {"units": {"number": 2, "currency": "CNY"}, "cost": null}]]}}"#;
+
+    #[test]
+    fn recovers_the_complete_rows_of_a_truncated_json_body() {
+        let first_row = JSON_BODY.find("], [").unwrap() + 1;
+        for cut in 0..JSON_BODY.len() {
+            let rows = partial_rows(&JSON_BODY.as_bytes()[..cut]).map(|body| {
+                let json: JsonQueryResult = serde_json::from_slice(&body).unwrap();
+                QueryResult::from(json).data.unwrap().parse().rows
+            });
+            let expected = match cut {
+                _ if cut < first_row => 0,
+                _ if cut < JSON_BODY.len() - "]}}".len() => 1,
+                _ => 2,
+            };
+            assert_eq!(
+                rows.map_or(0, |rows| rows.len()),
+                expected,
+                "cut at {}",
+                cut
+            );
+        }
+        let rows = partial_rows(JSON_BODY.as_bytes()).unwrap();
+        let json: JsonQueryResult = serde_json::from_slice(&rows).unwrap();
+        let rows = QueryResult::from(json).data.unwrap().parse().rows;
+        assert_eq!(rows[0]["payee"], "A \"]\" B");
+        assert_eq!(rows[1]["position"], "2 CNY");
+    }
+}
@@ -0,0 +1,445 @@
+/// Longest pattern a request may give, which also bounds the program it
+/// compiles to.
+const MAX_PATTERN: usize = 256;
+/// Most instructions a pattern may compile to, after `{n,m}` repeats, and
+/// most nodes compiling it may visit, which also bounds repeats of groups
+/// that compile to nothing.
+const MAX_PROGRAM: usize = 4096;
+/// Largest bound of a `{n,m}` repeat.
+const MAX_REPEAT: usize = 1000;
+
+/// A regular expression, matched like Python's `re.search` that fava's
+/// `~` uses: anywhere in the text, unless anchored with `^` or `$`.
+///
+/// It knows literals, `.`, classes such as `[a-z]` or `[^:]`, `\d`, `\w`,
+/// `\s` and their negations, groups, `|`, `*`, `+`, `?` and `{n,m}`.
+/// Matching runs every alternative at once, so its time grows with the
+/// text and the pattern but never explodes.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    program: Vec<Inst>,
+}
+
+#[derive(Debug, Clone)]
+enum Inst {
+    Char(Class),
+    /// Start of the text.
+    Start,
+    /// End of the text.
+    End,
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+#[derive(Debug, Clone)]
+struct Class {
+    items: Vec<Item>,
+    negated: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Item {
+    Any,
+    Range(char, char),
+    Digit,
+    Word,
+    Space,
+}
+
+impl Item {
+    fn matches(self, c: char) -> bool {
+        match self {
+            Item::Any => c != '\n',
+            Item::Range(low, high) => low <= c && c <= high,
+            Item::Digit => c.is_ascii_digit(),
+            Item::Word => c.is_alphanumeric() || c == '_',
+            Item::Space => c.is_whitespace(),
+        }
+    }
+}
+
+impl Class {
+    fn of(item: Item) -> Class {
+        Class {
+            items: vec![item],
+            negated: false,
+        }
+    }
+
+    fn matches(&self, c: char) -> bool {
+        self.items.iter().any(|item| item.matches(c)) != self.negated
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Char(Class),
+    Start,
+    End,
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: usize,
+        max: Option<usize>,
+    },
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl Parser<'_> {
+    fn alternate(&mut self) -> Result<Node, String> {
+        let mut branches = vec![self.concat()?];
+        while self.chars.next_if_eq(&'|').is_some() {
+            branches.push(self.concat()?);
+        }
+        Ok(match branches.len() {
+            1 => branches.remove(0),
+            _ => Node::Alternate(branches),
+        })
+    }
+
+    fn concat(&mut self) -> Result<Node, String> {
+        let mut nodes = Vec::new();
+        while let Some(&c) = self.chars.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.atom()?;
+            nodes.push(self.repeat(atom)?);
+        }
+        Ok(Node::Concat(nodes))
+    }
+
+    fn repeat(&mut self, mut node: Node) -> Result<Node, String> {
+        loop {
+            let (min, max) = match self.chars.next_if(|c| matches!(c, '*' | '+' | '?' | '{')) {
+                Some('*') => (0, None),
+                Some('+') => (1, None),
+                Some('?') => (0, Some(1)),
+                Some(_) => self.counts()?,
+                None => return Ok(node),
+            };
+            // Lazy repeats match the same texts.
+            self.chars.next_if_eq(&'?');
+            node = repeated(node, min, max)?;
+        }
+    }
+
+    /// The `n}`, `n,}` or `n,m}` after a `{`.
+    fn counts(&mut self) -> Result<(usize, Option<usize>), String> {
+        let mut text = String::new();
+        loop {
+            match self.chars.next() {
+                Some('}') => break,
+                Some(c) => text.push(c),
+                None => return Err("unclosed {".into()),
+            }
+        }
+        let invalid = || format!("invalid repeat {{{}}}", text);
+        let number = |text: &str| {
+            text.trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|n| *n <= MAX_REPEAT)
+                .ok_or_else(invalid)
+        };
+        match text.split_once(',') {
+            None => number(&text).map(|n| (n, Some(n))),
+            Some((min, "")) => Ok((number(min)?, None)),
+            Some((min, max)) => {
+                let (min, max) = (number(min)?, number(max)?);
+                match min <= max {
+                    true => Ok((min, Some(max))),
+                    false => Err(invalid()),
+                }
+            }
+        }
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        match self.chars.next() {
+            Some('(') => {
+                if self.chars.next_if_eq(&'?').is_some() && self.chars.next() != Some(':') {
+                    return Err("only (?:...) groups are supported".into());
+                }
+                let node = self.alternate()?;
+                match self.chars.next() {
+                    Some(')') => Ok(node),
+                    _ => Err("unclosed (".into()),
+                }
+            }
+            Some('[') => self.class().map(Node::Char),
+            Some('.') => Ok(Node::Char(Class::of(Item::Any))),
+            Some('^') => Ok(Node::Start),
+            Some('$') => Ok(Node::End),
+            Some('\\') => self.escape().map(Node::Char),
+            Some(c @ ('*' | '+' | '?' | '{')) => Err(format!("nothing to repeat before {}", c)),
+            Some(c) => Ok(Node::Char(Class::of(Item::Range(c, c)))),
+            None => Err("unexpected end".into()),
+        }
+    }
+
+    fn escape(&mut self) -> Result<Class, String> {
+        let item = |item| Ok(Class::of(item));
+        let negated = |item| {
+            Ok(Class {
+                items: vec![item],
+                negated: true,
+            })
+        };
+        match self.chars.next() {
+            Some('d') => item(Item::Digit),
+            Some('w') => item(Item::Word),
+            Some('s') => item(Item::Space),
+            Some('D') => negated(Item::Digit),
+            Some('W') => negated(Item::Word),
+            Some('S') => negated(Item::Space),
+            Some('n') => item(Item::Range('\n', '\n')),
+            Some('t') => item(Item::Range('\t', '\t')),
+            Some(c) if !c.is_alphanumeric() => item(Item::Range(c, c)),
+            Some(c) => Err(format!("unsupported escape \\{}", c)),
+            None => Err("unexpected end after \\".into()),
+        }
+    }
+
+    /// A class after its `[`.
+    fn class(&mut self) -> Result<Class, String> {
+        let negated = self.chars.next_if_eq(&'^').is_some();
+        let mut items = Vec::new();
+        let mut first = true;
+        loop {
+            let low = match self.chars.next() {
+                Some(']') if !first => break,
+                Some('\\') => match self.escape()? {
+                    Class {
+                        items: escaped,
+                        negated: false,
+                    } => match escaped[..] {
+                        [Item::Range(c, _)] => c,
+                        _ => {
+                            items.extend(escaped);
+                            first = false;
+                            continue;
+                        }
+                    },
+                    _ => return Err("negated escapes are not supported in a class".into()),
+                },
+                Some(c) => c,
+                None => return Err("unclosed [".into()),
+            };
+            first = false;
+            let mut ahead = self.chars.clone();
+            match (ahead.next(), ahead.next()) {
+                (Some('-'), Some(high)) if high != ']' && high != '\\' => {
+                    self.chars.next();
+                    self.chars.next();
+                    if high < low {
+                        return Err(format!("invalid range {}-{}", low, high));
+                    }
+                    items.push(Item::Range(low, high));
+                }
+                _ => items.push(Item::Range(low, low)),
+            }
+        }
+        Ok(Class { items, negated })
+    }
+}
+
+fn repeated(node: Node, min: usize, max: Option<usize>) -> Result<Node, String> {
+    match node {
+        Node::Repeat { .. } => Err("multiple repeats, group the pattern first".into()),
+        Node::Start | Node::End => Err("nothing to repeat".into()),
+        node => Ok(Node::Repeat {
+            node: Box::new(node),
+            min,
+            max,
+        }),
+    }
+}
+
+/// Appends the instructions of `node`, counting the nodes visited in
+/// `steps`.
+fn compile(node: &Node, program: &mut Vec<Inst>, steps: &mut usize) -> Result<(), String> {
+    *steps += 1;
+    if program.len() > MAX_PROGRAM || *steps > MAX_PROGRAM {
+        return Err("pattern too large".into());
+    }
+    match node {
+        Node::Char(class) => program.push(Inst::Char(class.clone())),
+        Node::Start => program.push(Inst::Start),
+        Node::End => program.push(Inst::End),
+        Node::Concat(nodes) => {
+            for node in nodes {
+                compile(node, program, steps)?;
+            }
+        }
+        Node::Alternate(branches) => {
+            let mut jumps = Vec::new();
+            for (i, branch) in branches.iter().enumerate() {
+                if i + 1 == branches.len() {
+                    compile(branch, program, steps)?;
+                    break;
+                }
+                let split = program.len();
+                program.push(Inst::Split(split + 1, 0));
+                compile(branch, program, steps)?;
+                jumps.push(program.len());
+                program.push(Inst::Jump(0));
+                program[split] = Inst::Split(split + 1, program.len());
+            }
+            let end = program.len();
+            for jump in jumps {
+                program[jump] = Inst::Jump(end);
+            }
+        }
+        Node::Repeat { node, min, max } => {
+            for _ in 0..*min {
+                compile(node, program, steps)?;
+            }
+            match max {
+                None => {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    compile(node, program, steps)?;
+                    program.push(Inst::Jump(split));
+                    program[split] = Inst::Split(split + 1, program.len());
+                }
+                Some(max) => {
+                    let mut splits = Vec::new();
+                    for _ in *min..*max {
+                        splits.push(program.len());
+                        program.push(Inst::Split(0, 0));
+                        compile(node, program, steps)?;
+                    }
+                    let end = program.len();
+                    for split in splits {
+                        program[split] = Inst::Split(split + 1, end);
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Result<Pattern, String> {
+        if pattern.chars().count() > MAX_PATTERN {
+            return Err(format!("pattern longer than {} characters", MAX_PATTERN));
+        }
+        let mut parser = Parser {
+            chars: pattern.chars().peekable(),
+        };
+        let node = parser.alternate()?;
+        if parser.chars.next().is_some() {
+            return Err("unmatched )".into());
+        }
+        let mut program = Vec::new();
+        compile(&node, &mut program, &mut 0)?;
+        if program.len() > MAX_PROGRAM {
+            return Err("pattern too large".into());
+        }
+        program.push(Inst::Match);
+        Ok(Pattern { program })
+    }
+
+    /// Whether the pattern matches anywhere in `text`.
+    pub fn is_match(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        let mut seen = vec![usize::MAX; self.program.len()];
+        let mut current = Vec::new();
+        let mut next = Vec::new();
+        for position in 0..=chars.len() {
+            // A match may also start here.
+            if self.add(&mut current, &mut seen, 0, position, chars.len()) {
+                return true;
+            }
+            let c = match chars.get(position) {
+                Some(c) => *c,
+                None => break,
+            };
+            next.clear();
+            for &pc in &current {
+                if let Inst::Char(class) = &self.program[pc] {
+                    if class.matches(c)
+                        && self.add(&mut next, &mut seen, pc + 1, position + 1, chars.len())
+                    {
+                        return true;
+                    }
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+        }
+        false
+    }
+
+    /// Adds the threads `pc` leads to without reading a character at
+    /// `position`, and tells whether one of them matched.
+    fn add(
+        &self,
+        threads: &mut Vec<usize>,
+        seen: &mut [usize],
+        pc: usize,
+        position: usize,
+        len: usize,
+    ) -> bool {
+        let mut stack = vec![pc];
+        while let Some(pc) = stack.pop() {
+            if seen[pc] == position {
+                continue;
+            }
+            seen[pc] = position;
+            match self.program[pc] {
+                Inst::Char(_) => threads.push(pc),
+                Inst::Start if position == 0 => stack.push(pc + 1),
+                Inst::End if position == len => stack.push(pc + 1),
+                Inst::Start | Inst::End => {}
+                Inst::Split(first, second) => {
+                    stack.push(second);
+                    stack.push(first);
+                }
+                Inst::Jump(target) => stack.push(target),
+                Inst::Match => return true,
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn matches_like_re_search() {
+        let pattern = Pattern::new("^Expenses:(Food|Rent)$").unwrap();
+        assert!(pattern.is_match("Expenses:Food"));
+        assert!(!pattern.is_match("Expenses:Food:Out"));
+        assert!(Pattern::new("a{2,3}").unwrap().is_match("xaax"));
+        assert!(!Pattern::new("^a{2,3}$").unwrap().is_match("aaaa"));
+        assert!(Pattern::new("[^:]+:\\d").unwrap().is_match("Assets:1"));
+    }
+
+    #[test]
+    fn rejects_repeat_bounds_above_the_cap() {
+        assert!(Pattern::new("a{1000}").is_ok());
+        assert!(Pattern::new("a{1001}").is_err());
+        assert!(Pattern::new("a{0,18446744073709551615}").is_err());
+        assert!(Pattern::new("a{99999999999999999999999}").is_err());
+    }
+
+    #[test]
+    fn repeats_of_empty_groups_fail_quickly() {
+        let started = Instant::now();
+        assert!(Pattern::new("(){18446744073709551615}").is_err());
+        assert!(Pattern::new("((){1000}){1000}").is_err());
+        assert!(Pattern::new("(((){1000}){1000}){1000}").is_err());
+        assert!(Pattern::new("(){1000}").unwrap().is_match(""));
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,111 @@
+/// A protobuf message written field by field, in the wire format of
+/// proto3.
+#[derive(Debug, Default)]
+pub struct Writer {
+    bytes: Vec<u8>,
+}
+
+/// The value of one field of a message being read.
+#[derive(Debug, Clone, Copy)]
+pub enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    /// A `fixed64`, `sfixed64` or `double`, which no request uses.
+    Fixed64,
+    /// A `fixed32`, `sfixed32` or `float`, which no request uses.
+    Fixed32,
+}
+
+const VARINT: u64 = 0;
+const FIXED64: u64 = 1;
+const BYTES: u64 = 2;
+const FIXED32: u64 = 5;
+
+fn varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+impl Writer {
+    fn tag(&mut self, field: u32, wire_type: u64) {
+        varint(&mut self.bytes, (u64::from(field) << 3) | wire_type);
+    }
+
+    pub fn bytes(&mut self, field: u32, value: &[u8]) -> &mut Writer {
+        self.tag(field, BYTES);
+        varint(&mut self.bytes, value.len() as u64);
+        self.bytes.extend_from_slice(value);
+        self
+    }
+
+    pub fn string(&mut self, field: u32, value: &str) -> &mut Writer {
+        self.bytes(field, value.as_bytes())
+    }
+
+    pub fn message(&mut self, field: u32, message: Writer) -> &mut Writer {
+        self.bytes(field, &message.bytes)
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// The fields of an encoded message by number, in the order they come,
+/// as repeated fields may come several times.
+pub fn fields(mut bytes: &[u8]) -> Result<Vec<(u32, Field<'_>)>, String> {
+    let mut fields = Vec::new();
+    while !bytes.is_empty() {
+        let tag = read_varint(&mut bytes)?;
+        let number = u32::try_from(tag >> 3)
+            .ok()
+            .filter(|number| *number > 0)
+            .ok_or_else(|| format!("invalid field number {}", tag >> 3))?;
+        let field = match tag & 7 {
+            VARINT => Field::Varint(read_varint(&mut bytes)?),
+            FIXED64 => {
+                take(&mut bytes, 8)?;
+                Field::Fixed64
+            }
+            BYTES => {
+                let len = usize::try_from(read_varint(&mut bytes)?)
+                    .map_err(|_| "field longer than the message".to_string())?;
+                Field::Bytes(take(&mut bytes, len)?)
+            }
+            FIXED32 => {
+                take(&mut bytes, 4)?;
+                Field::Fixed32
+            }
+            wire_type => return Err(format!("unsupported wire type {}", wire_type)),
+        };
+        fields.push((number, field));
+    }
+    Ok(fields)
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Result<u64, String> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes
+            .split_first()
+            .ok_or_else(|| "message ends inside a varint".to_string())?;
+        *bytes = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte < 0x80 {
+            return Ok(value);
+        }
+    }
+    Err("varint longer than 10 bytes".into())
+}
+
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8], String> {
+    if bytes.len() < len {
+        return Err("field longer than the message".into());
+    }
+    let (field, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(field)
+}
@@ -0,0 +1,346 @@
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
+use reqwest::StatusCode;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::{
+    amount::{self, Amount},
+    balance_sheet::{described, fava_filters, is_account},
+    empty_string_as_none,
+    i18n::{Lang, Message},
+    interval::{format_date, parse_date, shift_months, today},
+    params::{QueryFields, StrictQuery},
+    AppState, ErrorResult, GeneratedQuery, Meta,
+};
+
+/// Payments needed before a series counts as recurring.
+const MIN_OCCURRENCES: usize = 3;
+
+/// How often a recurring payment comes, and how many days early or late
+/// one may be.
+struct Cadence {
+    name: &'static str,
+    days: i64,
+    months: i64,
+    slack: i64,
+}
+
+impl Cadence {
+    fn after(&self, day: i64) -> i64 {
+        shift_months(day, self.months) + self.days
+    }
+}
+
+const CADENCES: &[Cadence] = &[
+    Cadence {
+        name: "weekly",
+        days: 7,
+        months: 0,
+        slack: 1,
+    },
+    Cadence {
+        name: "biweekly",
+        days: 14,
+        months: 0,
+        slack: 2,
+    },
+    Cadence {
+        name: "monthly",
+        days: 0,
+        months: 1,
+        slack: 3,
+    },
+    Cadence {
+        name: "quarterly",
+        days: 0,
+        months: 3,
+        slack: 7,
+    },
+    Cadence {
+        name: "yearly",
+        days: 0,
+        months: 12,
+        slack: 10,
+    },
+];
+
+/// Payments of the same amount to the same payee from the same account at
+/// a regular cadence, with the date the next one is due.
+///
+/// `account` defaults to `Expenses`. A payment without a payee goes by its
+/// narration. `overdue` marks series whose next payment is later than the
+/// cadence allows, which are often subscriptions that ended.
+pub async fn recurring(
+    State(state): State<AppState>,
+    StrictQuery(params): StrictQuery<RecurringParams>,
+    lang: Lang,
+) -> Result<RecurringResult, ErrorResult> {
+    let account = params.account.as_deref().unwrap_or("Expenses");
+    if !is_account(account) {
+        return Err(ErrorResult::bad_request(format!(
+            "invalid account {}",
+            account
+        )));
+    }
+    let min_occurrences = params.min_occurrences.unwrap_or(MIN_OCCURRENCES);
+    if min_occurrences < 2 {
+        return Err(ErrorResult::bad_request(
+            "min_occurrences must be at least 2".into(),
+        ));
+    }
+    let query = format!(
+        "SELECT date, payee, narration, account, position \
+         WHERE account ~ '^{}(:|$)' ORDER BY date",
+        account
+    );
+    let filters = fava_filters(params.time.as_deref(), params.filter.as_deref());
+    let parsed = state
+        .session()
+        .filtered(&filters)
+        .rows(&query)
+        .await
+        .map_err(|e| e.localize(lang))?;
+
+    let mut series: BTreeMap<(String, String, String, Decimal), Vec<i64>> = BTreeMap::new();
+    for row in &parsed.rows {
+        let field = |key: &str| row.get(key).map(|value| value.trim()).unwrap_or_default();
+        let (day, amount) = match (parse_date(field("date")), Amount::parse(field("position"))) {
+            (Some(day), Some(amount)) => (day, amount),
+            _ => continue,
+        };
+        let payee = match field("payee") {
+            "" => field("narration"),
+            payee => payee,
+        };
+        let key = (
+            payee.to_string(),
+            field("account").to_string(),
+            amount.currency,
+            amount.number.normalize(),
+        );
+        series.entry(key).or_default().push(day);
+    }
+
+    let today = today();
+    let mut payments: Vec<Recurring> = series
+        .into_iter()
+        .filter_map(|((payee, account, currency, number), mut days)| {
+            days.sort_unstable();
+            days.dedup();
+            if days.len() < min_occurrences {
+                return None;
+            }
+            let cadence = CADENCES.iter().find(|cadence| {
+                days.windows(2)
+                    .all(|pair| (pair[1] - cadence.after(pair[0])).abs() <= cadence.slack)
+            })?;
+            let last = days[days.len() - 1];
+            let next = cadence.after(last);
+            Some(Recurring {
+                payee,
+                account,
+                amount: amount::format_number(number),
+                currency,
+                cadence: cadence.name,
+                occurrences: days.len(),
+                first_seen: format_date(days[0]),
+                last_seen: format_date(last),
+                next_expected: format_date(next),
+                overdue: today > next + cadence.slack,
+            })
+        })
+        .collect();
+    payments.sort_by(|a, b| a.next_expected.cmp(&b.next_expected));
+
+    let generated = GeneratedQuery {
+        query,
+        filters: described(&filters),
+        feeds: "data".into(),
+    };
+    Ok(RecurringResult {
+        success: true,
+        data: payments,
+        warnings: parsed
+            .warnings
+            .into_iter()
+            .map(|warning| warning.localize(lang))
+            .collect(),
+        meta: Meta::explain(params.explain, vec![generated]),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RecurringParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    account: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    time: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    filter: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    min_occurrences: Option<usize>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    explain: Option<bool>,
+}
+
+impl QueryFields for RecurringParams {
+    const FIELDS: &[&str] = &["account", "time", "filter", "min_occurrences", "explain"];
+}
+
+#[derive(Debug, Serialize)]
+struct Recurring {
+    payee: String,
+    account: String,
+    amount: String,
+    currency: String,
+    cadence: &'static str,
+    occurrences: usize,
+    first_seen: String,
+    last_seen: String,
+    next_expected: String,
+    overdue: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecurringResult {
+    success: bool,
+    data: Vec<Recurring>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<Meta>,
+}
+
+impl IntoResponse for RecurringResult {
+    fn into_response(self) -> Response {
+        let body = Json(self);
+        (StatusCode::OK, body).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::get, Router};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{backend::Backend, config::Config, testing, testing::table};
+
+    const ROWS: &[&[&str]] = &[
+        &["2021-03-10", "Registrar", "", "Expenses:Web", "12 USD"],
+        &["2022-03-12", "Registrar", "", "Expenses:Web", "12.00 USD"],
+        &["2023-03-09", "Registrar", "", "Expenses:Web", "12 USD"],
+        &["2024-01-03", "Grocer", "", "Expenses:Food", "50 USD"],
+        &["2024-01-20", "Grocer", "", "Expenses:Food", "50 USD"],
+        &["2024-01-31", "Streaming", "", "Expenses:Media", "15.99 USD"],
+        &["2024-02-29", "Streaming", "", "Expenses:Media", "15.99 USD"],
+        &["2024-03-01", "Grocer", "", "Expenses:Food", "50 USD"],
+        &["2024-03-31", "Streaming", "", "Expenses:Media", "15.99 USD"],
+        &["2024-04-30", "Streaming", "", "Expenses:Media", "15.99 USD"],
+        &["2024-05-06", "", "Gym", "Expenses:Sport", "10 EUR"],
+        &["2024-05-13", "", "Gym", "Expenses:Sport", "10 EUR"],
+        &["2024-05-20", "", "Gym", "Expenses:Sport", "10 EUR"],
+        &["2024-05-31", "Streaming", "", "Expenses:Media", "15.99 USD"],
+        &["2024-06-30", "Streaming", "", "Expenses:Media", "17.99 USD"],
+    ];
+
+    async fn state() -> AppState {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|| async { table(&["date", "payee", "narration", "account", "position"], ROWS) }),
+        );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        AppState::new(config)
+    }
+
+    #[test]
+    fn keeps_month_end_payments_on_the_month_end() {
+        let monthly = &CADENCES[2];
+        let after = |date: &str| format_date(monthly.after(parse_date(date).unwrap()));
+        assert_eq!(after("2024-01-31"), "2024-02-29");
+        assert_eq!(after("2023-01-31"), "2023-02-28");
+        assert_eq!(after("2024-03-31"), "2024-04-30");
+        assert_eq!(after("2024-12-31"), "2025-01-31");
+        assert_eq!(
+            format_date(CADENCES[4].after(parse_date("2024-02-29").unwrap())),
+            "2025-02-28"
+        );
+    }
+
+    #[tokio::test]
+    async fn finds_monthly_yearly_and_weekly_payments() {
+        let (status, body) = testing::get(&state().await, "/api/recurring").await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["data"],
+            json!([
+                {
+                    "payee": "Registrar",
+                    "account": "Expenses:Web",
+                    "amount": "12",
+                    "currency": "USD",
+                    "cadence": "yearly",
+                    "occurrences": 3,
+                    "first_seen": "2021-03-10",
+                    "last_seen": "2023-03-09",
+                    "next_expected": "2024-03-09",
+                    "overdue": true,
+                },
+                {
+                    "payee": "Gym",
+                    "account": "Expenses:Sport",
+                    "amount": "10",
+                    "currency": "EUR",
+                    "cadence": "weekly",
+                    "occurrences": 3,
+                    "first_seen": "2024-05-06",
+                    "last_seen": "2024-05-20",
+                    "next_expected": "2024-05-27",
+                    "overdue": true,
+                },
+                {
+                    "payee": "Streaming",
+                    "account": "Expenses:Media",
+                    "amount": "15.99",
+                    "currency": "USD",
+                    "cadence": "monthly",
+                    "occurrences": 5,
+                    "first_seen": "2024-01-31",
+                    "last_seen": "2024-05-31",
+                    "next_expected": "2024-06-30",
+                    "overdue": true,
+                },
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn takes_fewer_occurrences_on_request() {
+        let state = state().await;
+        let (_, body) = testing::get(&state, "/api/recurring?min_occurrences=2").await;
+        let body: Value = serde_json::from_str(&body).unwrap();
+        let payees: Vec<&str> = body["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|payment| payment["payee"].as_str().unwrap())
+            .collect();
+        // The grocer's payments are irregular however few are asked for.
+        assert_eq!(payees, ["Registrar", "Gym", "Streaming"]);
+        for uri in [
+            "/api/recurring?min_occurrences=1",
+            "/api/recurring?account=expenses",
+        ] {
+            let (status, _) = testing::get(&state, uri).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+        }
+    }
+}
@@ -0,0 +1,489 @@
+use axum::{
+    extract::{
+        rejection::{JsonRejection, PathRejection},
+        Path, State,
+    },
+    response::{IntoResponse, Response},
+    Json,
+};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::{fs, sync::Mutex, time::SystemTime};
+
+use crate::{
+    empty_string_as_none,
+    i18n::Lang,
+    interval,
+    params::{QueryFields, StrictQuery},
+    query_rows, AppState, ErrorResult, Params, SuccessResult,
+};
+
+/// Serializes the changes of every ledger's store, as their states may
+/// share one file. It is only taken on blocking threads, which the file is
+/// read and written on.
+static FILE_LOCK: Mutex<()> = Mutex::new(());
+
+/// The saved queries of the `saved_queries` file, or of memory until the
+/// service stops if there is none.
+///
+/// The file is read for every request, so that the ledgers of one service
+/// share it and it may be edited by hand.
+#[derive(Debug, Default)]
+pub struct Store {
+    memory: Mutex<Saved>,
+}
+
+/// What a store keeps: the queries and the id the next one gets, so that
+/// the id of a deleted query is never given to another.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Saved {
+    #[serde(default)]
+    next_id: u64,
+    queries: Vec<SavedQuery>,
+}
+
+impl Saved {
+    /// Takes the next id, which is above the id of every query even if the
+    /// file was edited by hand.
+    fn take_id(&mut self) -> u64 {
+        let highest = self.queries.iter().map(|query| query.id).max();
+        let id = self.next_id.max(highest.map_or(1, |id| id + 1));
+        self.next_id = id + 1;
+        id
+    }
+}
+
+impl Store {
+    fn load(&self, state: &AppState) -> Result<Saved, ErrorResult> {
+        let path = match &state.config.saved_queries {
+            Some(path) => path,
+            None => return Ok(self.memory.lock().unwrap().clone()),
+        };
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Saved::default()),
+            Err(e) => {
+                return Err(storage_error(format!(
+                    "can not read {}: {}",
+                    path.display(),
+                    e
+                )))
+            }
+        };
+        serde_json::from_str(&text)
+            .map_err(|e| storage_error(format!("invalid {}: {}", path.display(), e)))
+    }
+
+    fn save(&self, state: &AppState, saved: Saved) -> Result<(), ErrorResult> {
+        let path = match &state.config.saved_queries {
+            Some(path) => path,
+            None => {
+                *self.memory.lock().unwrap() = saved;
+                return Ok(());
+            }
+        };
+        let text = serde_json::to_string_pretty(&saved).unwrap_or_default();
+        // A crash mid-write leaves the old file, not half of the new one.
+        let partial = path.with_extension("tmp");
+        fs::write(&partial, text)
+            .and_then(|_| fs::rename(&partial, path))
+            .map_err(|e| storage_error(format!("can not write {}: {}", path.display(), e)))
+    }
+}
+
+/// The stored queries of `state`.
+async fn queries(state: &AppState) -> Result<Vec<SavedQuery>, ErrorResult> {
+    let state = state.clone();
+    blocking(move || {
+        let _lock = FILE_LOCK.lock().unwrap();
+        Ok(state.saved_queries.load(&state)?.queries)
+    })
+    .await
+}
+
+/// Applies `change` to the stored queries of `state` and keeps the result,
+/// unless `change` fails.
+async fn update<T: Send + 'static>(
+    state: &AppState,
+    change: impl FnOnce(&mut Saved) -> Result<T, ErrorResult> + Send + 'static,
+) -> Result<T, ErrorResult> {
+    let state = state.clone();
+    blocking(move || {
+        let _lock = FILE_LOCK.lock().unwrap();
+        let mut saved = state.saved_queries.load(&state)?;
+        let result = change(&mut saved)?;
+        state.saved_queries.save(&state, saved)?;
+        Ok(result)
+    })
+    .await
+}
+
+/// Runs `work` on a blocking thread, as file access and the lock would
+/// stall the other requests of a runtime thread.
+async fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> Result<T, ErrorResult> + Send + 'static,
+) -> Result<T, ErrorResult> {
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(|e| storage_error(format!("saved queries failed: {}", e)))?
+}
+
+fn storage_error(error: String) -> ErrorResult {
+    ErrorResult {
+        error_code: Some("storage_error".into()),
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+        ..ErrorResult::new(error)
+    }
+}
+
+fn unknown(id: u64) -> ErrorResult {
+    ErrorResult {
+        error_code: Some("unknown_saved_query".into()),
+        status: StatusCode::NOT_FOUND,
+        ..ErrorResult::new(format!("unknown saved query {}", id))
+    }
+}
+
+/// The id of the path, or a `400` in the JSON envelope if it is not one.
+fn id_of(id: Result<Path<u64>, PathRejection>) -> Result<u64, ErrorResult> {
+    let Path(id) = id.map_err(|rejection| ErrorResult::bad_request(rejection.to_string()))?;
+    Ok(id)
+}
+
+fn input(
+    body: Result<Json<SavedQueryInput>, JsonRejection>,
+) -> Result<SavedQueryInput, ErrorResult> {
+    let Json(input) = body.map_err(|rejection| ErrorResult::bad_request(rejection.to_string()))?;
+    if input.name.trim().is_empty() || input.query.trim().is_empty() {
+        return Err(ErrorResult::bad_request(
+            "a saved query needs a name and a query".into(),
+        ));
+    }
+    Ok(input)
+}
+
+/// The current time in UTC as RFC 3339, such as `2024-01-02T03:04:05Z`.
+fn now() -> String {
+    let elapsed = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    timestamp(elapsed.as_secs())
+}
+
+fn timestamp(seconds: u64) -> String {
+    let time = seconds % 86400;
+    format!(
+        "{}T{:02}:{:02}:{:02}Z",
+        interval::format_date((seconds / 86400) as i64),
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// `GET /api/saved_queries`
+pub async fn list(
+    State(state): State<AppState>,
+) -> Result<SavedResult<Vec<SavedQuery>>, ErrorResult> {
+    Ok(SavedResult::ok(queries(&state).await?))
+}
+
+/// `POST /api/saved_queries`, with the `name`, `query` and optional
+/// `description` as JSON. Answers `201` with the id the query got.
+pub async fn create(
+    State(state): State<AppState>,
+    body: Result<Json<SavedQueryInput>, JsonRejection>,
+) -> Result<(StatusCode, SavedResult<SavedQuery>), ErrorResult> {
+    let input = input(body)?;
+    let saved = update(&state, move |saved| {
+        let query = SavedQuery {
+            id: saved.take_id(),
+            name: input.name,
+            query: input.query,
+            description: input.description,
+            created: now(),
+            updated: now(),
+        };
+        saved.queries.push(query.clone());
+        Ok(query)
+    })
+    .await?;
+    Ok((StatusCode::CREATED, SavedResult::ok(saved)))
+}
+
+/// `GET /api/saved_queries/:id`
+pub async fn get(
+    State(state): State<AppState>,
+    id: Result<Path<u64>, PathRejection>,
+) -> Result<SavedResult<SavedQuery>, ErrorResult> {
+    let id = id_of(id)?;
+    match queries(&state)
+        .await?
+        .into_iter()
+        .find(|query| query.id == id)
+    {
+        Some(query) => Ok(SavedResult::ok(query)),
+        None => Err(unknown(id)),
+    }
+}
+
+/// `PUT /api/saved_queries/:id`, replacing the name, query and description.
+pub async fn replace(
+    State(state): State<AppState>,
+    id: Result<Path<u64>, PathRejection>,
+    body: Result<Json<SavedQueryInput>, JsonRejection>,
+) -> Result<SavedResult<SavedQuery>, ErrorResult> {
+    let id = id_of(id)?;
+    let input = input(body)?;
+    let saved = update(&state, move |saved| {
+        let query = saved
+            .queries
+            .iter_mut()
+            .find(|query| query.id == id)
+            .ok_or_else(|| unknown(id))?;
+        query.name = input.name;
+        query.query = input.query;
+        query.description = input.description;
+        query.updated = now();
+        Ok(query.clone())
+    })
+    .await?;
+    Ok(SavedResult::ok(saved))
+}
+
+/// `DELETE /api/saved_queries/:id`, answering with the deleted query.
+pub async fn delete(
+    State(state): State<AppState>,
+    id: Result<Path<u64>, PathRejection>,
+) -> Result<SavedResult<SavedQuery>, ErrorResult> {
+    let id = id_of(id)?;
+    let deleted = update(&state, move |saved| {
+        let index = saved
+            .queries
+            .iter()
+            .position(|query| query.id == id)
+            .ok_or_else(|| unknown(id))?;
+        Ok(saved.queries.remove(index))
+    })
+    .await?;
+    Ok(SavedResult::ok(deleted))
+}
+
+/// `GET /api/saved_queries/:id/run`: runs a saved query like
+/// `/api/query_result` runs its `query_string`.
+pub async fn run(
+    State(state): State<AppState>,
+    id: Result<Path<u64>, PathRejection>,
+    StrictQuery(params): StrictQuery<RunParams>,
+    lang: Lang,
+) -> Result<SuccessResult, ErrorResult> {
+    let id = id_of(id)?;
+    let query = queries(&state)
+        .await?
+        .into_iter()
+        .find(|query| query.id == id)
+        .ok_or_else(|| unknown(id))?
+        .query;
+    let params = Params {
+        query_string: query,
+        account: params.account,
+        filter: params.filter,
+        time: params.time,
+        offset: params.offset,
+        limit: params.limit,
+        ..Params::default()
+    };
+    query_rows(&state, &params)
+        .await
+        .map(|result| result.localize(lang))
+        .map_err(|e| e.localize(lang))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedQuery {
+    id: u64,
+    name: String,
+    query: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    created: String,
+    updated: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SavedQueryInput {
+    name: String,
+    query: String,
+    description: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RunParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    account: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    filter: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    time: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    offset: Option<usize>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    limit: Option<usize>,
+}
+
+impl QueryFields for RunParams {
+    const FIELDS: &[&str] = &["account", "filter", "time", "offset", "limit"];
+}
+
+#[derive(Debug, Serialize)]
+pub struct SavedResult<T> {
+    success: bool,
+    data: T,
+}
+
+impl<T> SavedResult<T> {
+    fn ok(data: T) -> SavedResult<T> {
+        SavedResult {
+            success: true,
+            data,
+        }
+    }
+}
+
+impl<T: Serialize> IntoResponse for SavedResult<T> {
+    fn into_response(self) -> Response {
+        let body = Json(self);
+        (StatusCode::OK, body).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        extract::RawQuery,
+        http::{Method, Request},
+        routing::get as route_get,
+        Router,
+    };
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{
+        backend::Backend,
+        config::Config,
+        testing::{self, table},
+    };
+
+    async fn send(state: &AppState, method: Method, uri: &str, body: Value) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let (status, _, body) = testing::send(state, request).await;
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    /// A state whose fava answers a query with the query it got.
+    async fn state(saved_queries: Option<&std::path::Path>) -> AppState {
+        let fava = Router::new().route(
+            "/api/query_result",
+            route_get(|RawQuery(query): RawQuery| async move {
+                table(&["query"], &[&[&query.unwrap_or_default()]])
+            }),
+        );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        AppState::new(match saved_queries {
+            Some(path) => config.saved_queries(path),
+            None => config,
+        })
+    }
+
+    /// Creates, reads, replaces, runs and deletes queries through `state`.
+    async fn round_trip(state: &AppState) {
+        let input = json!({"name": "travel", "query": "SELECT payee"});
+        let (status, created) = send(state, Method::POST, "/api/saved_queries", input).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(created["data"]["id"], 1);
+        let stamp = created["data"]["created"].as_str().unwrap().to_string();
+        assert_eq!(stamp.len(), "2024-01-02T03:04:05Z".len(), "{}", stamp);
+        let input = json!({"name": "cash", "query": "SELECT account", "description": "d"});
+        let (_, second) = send(state, Method::POST, "/api/saved_queries", input).await;
+        assert_eq!(second["data"]["id"], 2);
+
+        let (status, got) = send(state, Method::GET, "/api/saved_queries/1", json!({})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(got["data"], created["data"]);
+        let input = json!({"name": "trips", "query": "SELECT date"});
+        let (status, replaced) = send(state, Method::PUT, "/api/saved_queries/1", input).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            (&replaced["data"]["name"], &replaced["data"]["created"]),
+            (&json!("trips"), &json!(stamp))
+        );
+        let (status, ran) = send(state, Method::GET, "/api/saved_queries/1/run", json!({})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(ran.to_string().contains("SELECT+date"), "{}", ran);
+
+        let (status, deleted) =
+            send(state, Method::DELETE, "/api/saved_queries/2", json!({})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(deleted["data"]["description"], "d");
+        let (_, list) = send(state, Method::GET, "/api/saved_queries", json!({})).await;
+        assert_eq!(list["data"].as_array().unwrap().len(), 1);
+        // The id of a deleted query is not given out again.
+        let input = json!({"name": "again", "query": "SELECT 1"});
+        let (_, third) = send(state, Method::POST, "/api/saved_queries", input).await;
+        assert_eq!(third["data"]["id"], 3);
+
+        let input = json!({"name": "x", "query": "SELECT 1"});
+        for (method, uri, body) in [
+            (Method::GET, "/api/saved_queries/2", json!({})),
+            (Method::GET, "/api/saved_queries/2/run", json!({})),
+            (Method::PUT, "/api/saved_queries/2", input),
+            (Method::DELETE, "/api/saved_queries/2", json!({})),
+        ] {
+            let (status, body) = send(state, method, uri, body).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{}", uri);
+            assert_eq!(body["code"], "unknown_saved_query");
+        }
+        let input = json!({"name": " ", "query": "SELECT 1"});
+        let (status, _) = send(state, Method::POST, "/api/saved_queries", input).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn keeps_queries_in_memory() {
+        round_trip(&state(None).await).await;
+    }
+
+    #[tokio::test]
+    async fn keeps_queries_in_the_file() {
+        let path = testing::temp_file("saved_queries.json", None);
+        round_trip(&state(Some(&path)).await).await;
+
+        let saved: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved["next_id"], 4);
+        assert_eq!(saved["queries"][0]["name"], "trips");
+        // Another state of the same file sees what the first one saved.
+        let (_, got) = send(
+            &state(Some(&path)).await,
+            Method::GET,
+            "/api/saved_queries/3",
+            json!({}),
+        )
+        .await;
+        assert_eq!(got["data"]["name"], "again");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn stamps_times_as_rfc_3339() {
+        assert_eq!(timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(timestamp(1_709_251_199), "2024-02-29T23:59:59Z");
+    }
+}
@@ -0,0 +1,36 @@
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use crate::AppState;
+
+/// How often background tasks check whether the ledger changed.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Spawns a task that runs `task` right away, and again whenever the ledger
+/// generation changes or `ttl` has passed since the previous run.
+pub fn on_change<F, Fut>(state: AppState, ttl: Duration, mut task: F)
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    let task_state = state.clone();
+    task_state.spawn_background(async move {
+        let mut last_run: Option<(Option<u64>, Instant)> = None;
+        loop {
+            let generation = state.version.generation(&state).await;
+            let due = match last_run {
+                None => true,
+                Some((last_generation, at)) => {
+                    (generation.is_some() && generation != last_generation) || at.elapsed() >= ttl
+                }
+            };
+            if due {
+                task().await;
+                last_run = Some((generation, Instant::now()));
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    });
+}
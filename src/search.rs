@@ -0,0 +1,102 @@
+use crate::{pattern::Pattern, Row};
+
+/// Keeps the rows in which any of `columns` (all of them if not given)
+/// contains `term`. Both sides are lowercased with Unicode's case mapping,
+/// so accented Latin, Greek or Cyrillic text matches regardless of case,
+/// and text without case such as Chinese matches as is.
+pub fn filter(rows: Vec<Row>, term: &str, columns: Option<&str>) -> Vec<Row> {
+    let term = term.to_lowercase();
+    let columns: Option<Vec<&str>> = columns.map(|columns| {
+        columns
+            .split(',')
+            .map(str::trim)
+            .filter(|column| !column.is_empty())
+            .collect()
+    });
+    rows.into_iter()
+        .filter(|row| {
+            row.iter()
+                .filter(|(column, _)| match &columns {
+                    Some(columns) => columns.contains(&column.as_str()),
+                    None => true,
+                })
+                .any(|(_, value)| value.to_lowercase().contains(&term))
+        })
+        .collect()
+}
+
+/// Keeps the rows whose `column` matches `pattern`, given together as
+/// `row_filter=column:pattern` and matched like fava's `~`, see
+/// [`Pattern`].
+pub fn row_filter(rows: Vec<Row>, row_filter: &str) -> Result<Vec<Row>, String> {
+    let (column, pattern) = row_filter
+        .split_once(':')
+        .filter(|(column, _)| !column.trim().is_empty())
+        .ok_or_else(|| format!("invalid row_filter {}, expected column:pattern", row_filter))?;
+    let column = column.trim();
+    if !rows.is_empty() && !rows.iter().any(|row| row.contains_key(column)) {
+        return Err(format!("unknown row_filter column {}", column));
+    }
+    let pattern =
+        Pattern::new(pattern).map_err(|e| format!("invalid row_filter pattern: {}", e))?;
+    Ok(rows
+        .into_iter()
+        .filter(|row| pattern.is_match(row.get(column).map_or("", String::as_str)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows() -> Vec<Row> {
+        [
+            ("Expenses:Food", "CAFÉ Olé"),
+            ("Expenses:Travel", "Σπίτι"),
+            ("Expenses:Food", "超市"),
+        ]
+        .iter()
+        .map(|(account, payee)| {
+            Row::from([
+                ("account".to_string(), account.to_string()),
+                ("payee".to_string(), payee.to_string()),
+            ])
+        })
+        .collect()
+    }
+
+    fn payees(rows: &[Row]) -> Vec<&str> {
+        rows.iter().map(|row| row["payee"].as_str()).collect()
+    }
+
+    #[test]
+    fn matches_any_column_regardless_of_case() {
+        assert_eq!(payees(&filter(rows(), "café", None)), ["CAFÉ Olé"]);
+        assert_eq!(payees(&filter(rows(), "σπίτι", None)), ["Σπίτι"]);
+        assert_eq!(payees(&filter(rows(), "超", None)), ["超市"]);
+        assert_eq!(payees(&filter(rows(), "food", None)), ["CAFÉ Olé", "超市"]);
+    }
+
+    #[test]
+    fn matches_only_the_given_columns() {
+        assert!(filter(rows(), "food", Some("payee")).is_empty());
+        assert_eq!(filter(rows(), "food", Some(" payee, account")).len(), 2);
+    }
+
+    #[test]
+    fn keeps_the_rows_a_pattern_matches() {
+        let rows = row_filter(rows(), "account:^Expenses:F").unwrap();
+        assert_eq!(payees(&rows), ["CAFÉ Olé", "超市"]);
+        assert_eq!(
+            row_filter(rows.clone(), "payee").unwrap_err(),
+            "invalid row_filter payee, expected column:pattern"
+        );
+        assert_eq!(
+            row_filter(rows, "narration:x").unwrap_err(),
+            "unknown row_filter column narration"
+        );
+        assert!(row_filter(Vec::new(), "narration:(")
+            .unwrap_err()
+            .starts_with("invalid row_filter pattern: "));
+    }
+}
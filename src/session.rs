@@ -0,0 +1,754 @@
+use reqwest::{header::RETRY_AFTER, StatusCode};
+use std::{
+    collections::hash_map::RandomState,
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    sync::Mutex,
+    time::Duration,
+};
+
+use crate::{
+    availability,
+    backend::{Backend, JsonQueryResult},
+    cache,
+    cache::VersionedCache,
+    config::RetryConfig,
+    fingerprint, interval,
+    paging::{self, Journal},
+    partial, table_rows, AppState, ErrorResult, ParsedRows, QueryResult, UpstreamError,
+};
+
+/// The way every endpoint talks to fava: fail fast during announced
+/// maintenance, serve from the per-generation caches, refresh fava before a
+/// real fetch if its `changed` API could not, and fall back to the last good
+/// result when configured.
+///
+/// Sessions are cheap and short-lived; the builder methods pick the options
+/// of a single call, e.g. `state.session().refresh_path(path).query(q)`.
+#[derive(Clone, Copy)]
+pub struct UpstreamSession<'a> {
+    state: &'a AppState,
+    refresh_path: Option<&'a str>,
+    refresh: Option<bool>,
+    /// Fava's own filter parameters, such as `time` and `filter`.
+    filters: &'a [(&'a str, &'a str)],
+    received: Option<&'a Mutex<partial::Received>>,
+}
+
+/// What [`UpstreamSession::lookup`] decided: a cached result, or a fetch
+/// whose result may be cached under `generation`.
+enum Lookup<T> {
+    Cached(T),
+    Fetch { generation: Option<u64> },
+}
+
+impl AppState {
+    pub(crate) fn session(&self) -> UpstreamSession<'_> {
+        UpstreamSession {
+            state: self,
+            refresh_path: None,
+            refresh: None,
+            filters: &[],
+            received: None,
+        }
+    }
+}
+
+impl<'a> UpstreamSession<'a> {
+    /// Refreshes `path` instead of the configured page, or nothing for `none`.
+    pub fn refresh_path(self, refresh_path: Option<&'a str>) -> Self {
+        UpstreamSession {
+            refresh_path: refresh_path.or(self.refresh_path),
+            ..self
+        }
+    }
+
+    /// With `true`, bypasses the caches and requests the refresh page even
+    /// though fava's `changed` API already reloaded the ledger; with `false`,
+    /// never requests the refresh page.
+    pub fn refresh(self, refresh: Option<bool>) -> Self {
+        UpstreamSession {
+            refresh: refresh.or(self.refresh),
+            ..self
+        }
+    }
+
+    /// Has fava filter the ledger before running queries, with the `time`,
+    /// `filter` and `account` parameters of its web interface.
+    pub fn filtered(self, filters: &'a [(&'a str, &'a str)]) -> Self {
+        UpstreamSession { filters, ..self }
+    }
+
+    /// Streams query results into `received` chunk by chunk, so that a
+    /// caller giving up early can still use what arrived.
+    pub fn streaming_into(self, received: &'a Mutex<partial::Received>) -> Self {
+        UpstreamSession {
+            received: Some(received),
+            ..self
+        }
+    }
+
+    /// Runs a BQL query and parses the result table into rows.
+    pub async fn rows(&self, query_string: &str) -> Result<ParsedRows, ErrorResult> {
+        let parsed = table_rows(self.query(query_string).await)?;
+        self.state.metrics.rows_parsed(parsed.rows.len());
+        Ok(parsed)
+    }
+
+    /// The filters as fava gets them, with a relative `time` such as `ytd`
+    /// turned into dates, see [`interval::relative_time`].
+    fn fava_filters(&self) -> Vec<(&'a str, String)> {
+        let today = interval::today();
+        self.filters
+            .iter()
+            .map(|(name, value)| match *name {
+                "time" => (*name, fava_time(value, today)),
+                _ => (*name, value.to_string()),
+            })
+            .collect()
+    }
+
+    /// Fetches a query result. When fava can not be reached, the last good
+    /// result may stand in if the config allows it.
+    pub async fn query(&self, query_string: &str) -> Result<QueryResult, UpstreamError> {
+        let state = self.state;
+        let key = self.key(query_string);
+        let result = self
+            .cached(&state.queries, &key, || self.fetch_query(query_string))
+            .await;
+        match &result {
+            Err(e) if e.is_unreachable() => state.availability.record_failure(e.to_string()),
+            _ => {}
+        }
+        let max_age = match state.config.stale_fallback {
+            Some(max_age) => max_age,
+            None => return result,
+        };
+        match result {
+            Ok(result) => {
+                if result.success {
+                    state.fallback.insert(key, result.clone(), max_age);
+                }
+                Ok(result)
+            }
+            Err(e) if e.is_unreachable() => match state.fallback.get(&key, max_age) {
+                Some((result, age)) => {
+                    println!(
+                        "serving a {}s old result, fava failed: {}",
+                        age.as_secs(),
+                        e
+                    );
+                    Ok(QueryResult {
+                        stale_for: Some(age),
+                        ..result
+                    })
+                }
+                None => Err(e),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Starts a query on fava's HTML table API for the caller to read the
+    /// body of as it arrives. `None` if the result is cached, or fava's JSON
+    /// API answers queries, for [`query`](Self::query) to fetch it instead.
+    ///
+    /// Streamed results are not cached, as they are never complete here.
+    pub async fn query_stream(
+        &self,
+        query_string: &str,
+    ) -> Result<Option<reqwest::Response>, UpstreamError> {
+        let state = self.state;
+        if state.backend.use_json(state.config.backend) {
+            return Ok(None);
+        }
+        let key = self.key(query_string);
+        let cached = |generation| state.queries.contains(&key, generation).then_some(());
+        if let Lookup::Cached(()) = self.lookup(cached).await? {
+            return Ok(None);
+        }
+        let filters = self.fava_filters();
+        let mut query = vec![("query_string", query_string)];
+        query.extend(filters.iter().map(|(name, value)| (*name, value.as_str())));
+        match self.get("/api/query_result", &query).await {
+            Ok(response) => Ok(Some(response)),
+            Err(e) => {
+                if e.is_unreachable() {
+                    state.availability.record_failure(e.to_string());
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// The cache key of a query, with the filters it runs under.
+    fn key(&self, query_string: &str) -> String {
+        let mut key = cache::normalize_query(query_string);
+        for (name, value) in self.fava_filters() {
+            key.push_str(&format!("\n{}={}", name, value));
+        }
+        key
+    }
+
+    /// The journal of an account, see [`paging::account_journal`].
+    pub async fn account_journal(&self, account: &str) -> Result<Journal, ErrorResult> {
+        paging::account_journal(self.state, account, self.refresh_path, self.refresh).await
+    }
+
+    /// Fetches the journal page of an account, limited to fava's `time`
+    /// filter if given.
+    pub async fn account_page(
+        &self,
+        account: &str,
+        time: Option<&str>,
+    ) -> Result<String, UpstreamError> {
+        let time = time.map(|time| fava_time(time, interval::today()));
+        let key = match &time {
+            Some(time) => format!("{}?time={}", account, time),
+            None => account.to_string(),
+        };
+        let path = format!("/account/{}", account);
+        let query: Vec<(&str, &str)> = time.iter().map(|time| ("time", time.as_str())).collect();
+        self.cached(&self.state.accounts, &key, || async {
+            let response = self.get(&path, &query).await?;
+            if response.status() == StatusCode::NOT_FOUND {
+                return Err(UpstreamError::UnknownAccount(account.to_string()));
+            }
+            Ok((response.text().await?, true))
+        })
+        .await
+    }
+
+    /// Fetches any other page, without refreshing or caching it.
+    pub async fn page(&self, path: &str, query: &[(&str, &str)]) -> Result<String, UpstreamError> {
+        check_available(self.state)?;
+        admit(self.state)?;
+        Ok(self.get(path, query).await?.text().await?)
+    }
+
+    /// Requests a file from fava, for the caller to stream its body.
+    pub async fn download(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<reqwest::Response, UpstreamError> {
+        check_available(self.state)?;
+        admit(self.state)?;
+        self.get(path, query).await
+    }
+
+    /// Serves `key` from `cache` if the ledger did not change, or else runs
+    /// `fetch`, which also tells whether its result may be cached.
+    ///
+    /// Asking fava's `changed` API for the generation makes fava reload
+    /// changed beancount files, so the refresh page is only requested when
+    /// that API did not answer.
+    async fn cached<T, F, Fut>(
+        &self,
+        cache: &VersionedCache<T>,
+        key: &str,
+        fetch: F,
+    ) -> Result<T, UpstreamError>
+    where
+        T: Clone,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<(T, bool), UpstreamError>>,
+    {
+        let generation = match self.lookup(|generation| cache.get(key, generation)).await? {
+            Lookup::Cached(result) => return Ok(result),
+            Lookup::Fetch { generation } => generation,
+        };
+        let (result, cacheable) = fetch().await?;
+        if let (Some(generation), true) = (generation, cacheable) {
+            cache.insert(key.to_string(), generation, result.clone());
+        }
+        Ok(result)
+    }
+
+    /// Whether a result can be served from a cache, by `cached` for the
+    /// current generation, or fava has to be asked. Before fava is asked,
+    /// the request is admitted and the refresh page requested if it was
+    /// forced or the generation is unknown.
+    async fn lookup<T>(
+        &self,
+        cached: impl FnOnce(u64) -> Option<T>,
+    ) -> Result<Lookup<T>, UpstreamError> {
+        let state = self.state;
+        check_available(state)?;
+        let generation = state.version.generation(state).await;
+        let forced = self.refresh == Some(true);
+        if let Some(result) = generation.filter(|_| !forced).and_then(cached) {
+            return Ok(Lookup::Cached(result));
+        }
+        admit(state)?;
+        // 先请求页面以刷新数据
+        match self.refresh {
+            Some(true) => self.refresh_page().await,
+            None if generation.is_none() => self.refresh_page().await,
+            _ => {}
+        }
+        Ok(Lookup::Fetch { generation })
+    }
+
+    /// Asks fava's JSON API when it has one, or else its HTML table API.
+    async fn fetch_query(&self, query_string: &str) -> Result<(QueryResult, bool), UpstreamError> {
+        let state = self.state;
+        let backend = state.config.backend;
+        let filters = self.fava_filters();
+        let mut query = vec![("query_string", query_string)];
+        query.extend(filters.iter().map(|(name, value)| (*name, value.as_str())));
+        let mut result = None;
+        if state.backend.use_json(backend) {
+            let response = self.get("/api/query", &query).await?;
+            if response.status() == StatusCode::NOT_FOUND && backend == Backend::Auto {
+                state.backend.set(false);
+            } else {
+                let body = self.body(response).await?;
+                match serde_json::from_slice::<JsonQueryResult>(&body) {
+                    Ok(json) => {
+                        state.backend.set(true);
+                        result = Some(Ok(QueryResult::from(json)));
+                    }
+                    // Not fava's JSON, maybe a catch-all page of an old
+                    // version or of a proxy.
+                    Err(_) if backend == Backend::Auto && state.backend.known().is_none() => {
+                        state.backend.set(false)
+                    }
+                    Err(e) => result = Some(Err(UpstreamError::from(e))),
+                }
+            }
+        }
+        let result = match result {
+            Some(result) => result,
+            None => {
+                let response = self.get("/api/query_result", &query).await?;
+                let body = self.body(response).await?;
+                serde_json::from_slice::<QueryResult>(&body).map_err(UpstreamError::from)
+            }
+        };
+        let result = fingerprint::check(state, result).await?;
+        let success = result.success;
+        Ok((result, success))
+    }
+
+    /// Reads a response, into `received` if streaming.
+    async fn body(&self, mut response: reqwest::Response) -> Result<Vec<u8>, UpstreamError> {
+        match self.received {
+            Some(received) => {
+                received.lock().unwrap().content_length = response.content_length();
+                while let Some(chunk) = response.chunk().await? {
+                    received.lock().unwrap().body.extend_from_slice(&chunk);
+                }
+                Ok(std::mem::take(&mut received.lock().unwrap().body))
+            }
+            None => Ok(response.bytes().await?.to_vec()),
+        }
+    }
+
+    /// Requests a fava report page so fava notices changed beancount files.
+    /// A failing refresh is only logged, the actual request may still
+    /// succeed.
+    async fn refresh_page(&self) {
+        let state = self.state;
+        let refresh_path = self.refresh_path.unwrap_or(&state.config.refresh_path);
+        if refresh_path == "none" {
+            return;
+        }
+        let refresh_url = format!(
+            "{}/{}",
+            state.client.url(),
+            refresh_path.trim_start_matches('/')
+        );
+        match state.client.fetch(refresh_path, &[]).await {
+            Ok(response) if response.status().is_success() => {
+                let _ = response.text().await;
+            }
+            Ok(response) => println!("refresh {} returned {}", refresh_url, response.status()),
+            Err(e) => println!("refresh {} failed: {}", refresh_url, e),
+        }
+    }
+
+    /// GETs `path`, retrying transient failures as configured before they
+    /// count against fava's availability.
+    async fn get(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<reqwest::Response, UpstreamError> {
+        let retry = self.state.config.retry.as_ref();
+        let attempts = retry.map_or(1, |retry| retry.attempts.max(1));
+        let mut attempt = 1;
+        loop {
+            let result = self.state.client.fetch(path, query).await;
+            let transient = match &result {
+                Ok(response) => is_maintenance(response.status()),
+                Err(e) => e.is_connect() || e.is_timeout(),
+            };
+            // Fava announcing how long it is down ends the retries, the
+            // back-off window of `check_response` holds the requests off.
+            let announced =
+                matches!(&result, Ok(response) if response.headers().contains_key(RETRY_AFTER));
+            match retry {
+                Some(retry) if transient && !announced && attempt < attempts => {
+                    let wait = backoff(retry, attempt);
+                    println!(
+                        "fava fetch {} failed, retry {} of {} in {:?}",
+                        path,
+                        attempt,
+                        attempts - 1,
+                        wait
+                    );
+                    tokio::time::sleep(wait).await;
+                    attempt += 1;
+                }
+                _ => {
+                    if let Some(breaker) = &self.state.config.circuit_breaker {
+                        self.state.availability.record_outcome(breaker, !transient);
+                    }
+                    let result = result
+                        .map_err(UpstreamError::from)
+                        .and_then(|response| check_response(self.state, response));
+                    self.state.metrics.upstream(result.is_err());
+                    return result;
+                }
+            }
+        }
+    }
+}
+
+/// The wait before retry number `attempt`: the configured backoff, doubled
+/// per earlier retry and shifted by a random share of the jitter.
+fn backoff(retry: &RetryConfig, attempt: u32) -> Duration {
+    let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+    let factor = 1.0 + retry.jitter * (2.0 * random - 1.0);
+    let wait = retry.backoff * 2f64.powi(attempt as i32 - 1) * factor;
+    Duration::from_secs_f64(wait.clamp(0.0, 60.0))
+}
+
+/// Fava's `time` filter for `time`: a relative one such as `ytd` as the
+/// dates it covers counted from `today`, anything else as it is.
+fn fava_time(time: &str, today: i64) -> String {
+    interval::relative_time(time, today).unwrap_or_else(|| time.to_string())
+}
+
+fn is_maintenance(status: StatusCode) -> bool {
+    status == StatusCode::BAD_GATEWAY || status == StatusCode::SERVICE_UNAVAILABLE
+}
+
+/// Fails fast while fava is inside a maintenance window it announced earlier.
+fn check_available(state: &AppState) -> Result<(), UpstreamError> {
+    match state.availability.remaining() {
+        Some(retry_after) => Err(UpstreamError::Unavailable(retry_after)),
+        None => Ok(()),
+    }
+}
+
+/// Fails fast while the circuit breaker is open.
+fn admit(state: &AppState) -> Result<(), UpstreamError> {
+    match &state.config.circuit_breaker {
+        Some(breaker) => state
+            .availability
+            .admit(breaker)
+            .map_err(UpstreamError::Unavailable),
+        None => Ok(()),
+    }
+}
+
+/// Turns fava's 502/503 maintenance responses into `Unavailable` errors and
+/// starts the matching back-off window.
+fn check_response(
+    state: &AppState,
+    response: reqwest::Response,
+) -> Result<reqwest::Response, UpstreamError> {
+    let status = response.status();
+    if !is_maintenance(status) {
+        return Ok(response);
+    }
+    let retry_after = response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(availability::parse_retry_after)
+        .unwrap_or(Duration::from_secs(state.config.retry_after));
+    println!(
+        "fava answered {}, backing off for {:?}",
+        status, retry_after
+    );
+    state.availability.mark_unavailable(retry_after);
+    Err(UpstreamError::Unavailable(retry_after))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        extract::{Query, State as Extract},
+        http::HeaderMap,
+        routing::get,
+        Router,
+    };
+    use serde_json::{json, Value};
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+    };
+
+    use super::*;
+    use crate::{backend::Backend, config::Config, testing, testing::Hits};
+
+    /// A fava in maintenance, announcing how long with `Retry-After` if
+    /// one is given.
+    async fn maintenance(retry_after: Option<&'static str>, hits: &Hits) -> AppState {
+        let hits = hits.clone();
+        let fava = Router::new().route(
+            "/report",
+            get(move || async move {
+                hits.hit();
+                let mut headers = HeaderMap::new();
+                if let Some(retry_after) = retry_after {
+                    headers.insert(RETRY_AFTER, retry_after.parse().unwrap());
+                }
+                (StatusCode::SERVICE_UNAVAILABLE, headers, "down")
+            }),
+        );
+        let config = Config::new(testing::fava(fava).await).retry(RetryConfig {
+            attempts: 3,
+            backoff: 0.01,
+            jitter: 0.0,
+        });
+        AppState::new(config)
+    }
+
+    #[tokio::test]
+    async fn stops_retrying_when_fava_announces_its_downtime() {
+        let hits = Hits::default();
+        let state = maintenance(Some("7"), &hits).await;
+        let result = state.session().page("/report", &[]).await;
+        assert!(
+            matches!(result, Err(UpstreamError::Unavailable(wait)) if wait == Duration::from_secs(7))
+        );
+        assert_eq!(hits.count(), 1);
+        // The window holds further requests off without asking fava.
+        let result = state.session().page("/report", &[]).await;
+        assert!(matches!(result, Err(UpstreamError::Unavailable(_))));
+        assert_eq!(hits.count(), 1);
+    }
+
+    #[tokio::test]
+    async fn retries_unannounced_maintenance() {
+        let hits = Hits::default();
+        let state = maintenance(None, &hits).await;
+        let result = state.session().page("/report", &[]).await;
+        assert!(matches!(result, Err(UpstreamError::Unavailable(_))));
+        assert_eq!(hits.count(), 3);
+    }
+
+    /// A fava without a `changed` API, whose refresh page `/income_statement/`
+    /// is not found, counting the requests for it.
+    async fn without_refresh_page(refreshes: &Hits) -> String {
+        let refreshes = refreshes.clone();
+        let fava = Router::new()
+            .route(
+                "/income_statement/",
+                get(move || async move {
+                    refreshes.hit();
+                    StatusCode::NOT_FOUND
+                }),
+            )
+            .route(
+                "/api/query_result",
+                get(|| async { testing::table(&["n"], &[&["1"]]) }),
+            );
+        testing::fava(fava).await
+    }
+
+    #[tokio::test]
+    async fn answers_queries_although_the_refresh_page_is_not_found() {
+        let refreshes = Hits::default();
+        let state = testing::state(&without_refresh_page(&refreshes).await);
+        let (status, body) =
+            testing::get(&state, "/api/query_result?query_string=SELECT%20n").await;
+        assert_eq!(
+            (status, body.as_str()),
+            (
+                StatusCode::OK,
+                "{\"success\":true,\"data\":[{\"n\":\"1\"}]}"
+            )
+        );
+        assert_eq!(refreshes.count(), 1);
+    }
+
+    #[tokio::test]
+    async fn skips_the_refresh_with_refresh_path_none() {
+        let refreshes = Hits::default();
+        let url = without_refresh_page(&refreshes).await;
+        let state = testing::state(&url);
+        let uri = "/api/query_result?query_string=SELECT%20n&refresh_path=none";
+        assert_eq!(testing::get(&state, uri).await.0, StatusCode::OK);
+        let state = AppState::new(Config::new(&url).refresh_path("none"));
+        let uri = "/api/query_result?query_string=SELECT%20n";
+        assert_eq!(testing::get(&state, uri).await.0, StatusCode::OK);
+        assert_eq!(refreshes.count(), 0);
+    }
+
+    #[tokio::test]
+    async fn hands_the_filters_to_fava_and_skips_refreshes_when_told() {
+        let refreshes = Hits::default();
+        let asked = Arc::new(Mutex::new(Vec::new()));
+        let (hits, seen) = (refreshes.clone(), asked.clone());
+        let fava = Router::new()
+            .route("/income_statement/", get(move || async move { hits.hit() }))
+            .route(
+                "/api/query_result",
+                get(move |uri: axum::http::Uri| async move {
+                    seen.lock()
+                        .unwrap()
+                        .push(uri.query().unwrap_or_default().to_string());
+                    testing::table(&["n"], &[&["1"]])
+                }),
+            );
+        let state = testing::state(&testing::fava(fava).await);
+        let filters = [("time", "2024"), ("account", "Assets")];
+        let rows = state
+            .session()
+            .filtered(&filters)
+            .rows("SELECT n")
+            .await
+            .unwrap();
+        assert_eq!(rows.rows.len(), 1);
+        assert_eq!(refreshes.count(), 1);
+        state
+            .session()
+            .refresh(Some(false))
+            .rows("SELECT n")
+            .await
+            .unwrap();
+        assert_eq!(refreshes.count(), 1);
+        assert_eq!(
+            *asked.lock().unwrap(),
+            [
+                "query_string=SELECT+n&time=2024&account=Assets",
+                "query_string=SELECT+n",
+            ]
+        );
+    }
+
+    /// A state for a fava in maintenance while the flag is set, serving
+    /// results up to `max_staleness` old then.
+    async fn failing(max_staleness: Duration) -> (AppState, Arc<AtomicBool>) {
+        let down = Arc::new(AtomicBool::new(false));
+        let fava = Router::new()
+            .route(
+                "/api/query_result",
+                get(|Extract(down): Extract<Arc<AtomicBool>>| async move {
+                    match down.load(Ordering::SeqCst) {
+                        true => (StatusCode::SERVICE_UNAVAILABLE, "down".to_string()),
+                        false => (StatusCode::OK, testing::table(&["n"], &[&["1"]])),
+                    }
+                }),
+            )
+            .with_state(down.clone());
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none")
+            .serve_stale_on_error(max_staleness);
+        (AppState::new(config), down)
+    }
+
+    /// Bypasses the cache, so that each query asks fava.
+    const QUERY: &str = "/api/query_result?query_string=SELECT%20n&refresh=true";
+
+    #[tokio::test]
+    async fn serves_the_last_good_result_while_fava_fails() {
+        let (state, down) = failing(Duration::from_secs(60)).await;
+        assert_eq!(testing::get(&state, QUERY).await.0, StatusCode::OK);
+        down.store(true, Ordering::SeqCst);
+        let (status, body) = testing::get(&state, QUERY).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["data"], json!([{ "n": "1" }]));
+        assert_eq!(body["meta"]["cached"], "stale_error_fallback");
+        assert_eq!(body["warnings"].as_array().unwrap().len(), 1);
+        // The failure still counts.
+        let (_, status) = testing::get(&state, "/api/status").await;
+        let status: Value = serde_json::from_str(&status).unwrap();
+        assert!(status["data"]["last_upstream_failure"]["error"]
+            .as_str()
+            .is_some_and(|error| error.contains("unavailable")));
+        let (_, metrics) = testing::get(&state, "/metrics").await;
+        assert!(
+            metrics.contains("fava_query_upstream_errors_total 1\n"),
+            "{}",
+            metrics
+        );
+    }
+
+    #[tokio::test]
+    async fn refuses_results_older_than_max_staleness() {
+        let (state, down) = failing(Duration::from_millis(50)).await;
+        assert_eq!(testing::get(&state, QUERY).await.0, StatusCode::OK);
+        down.store(true, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let (status, body) = testing::get(&state, QUERY).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE, "{}", body);
+        assert!(!body.contains("stale_error_fallback"));
+    }
+
+    #[tokio::test]
+    async fn fails_as_usual_without_a_good_result() {
+        let (state, down) = failing(Duration::from_secs(60)).await;
+        down.store(true, Ordering::SeqCst);
+        let (status, body) = testing::get(&state, QUERY).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE, "{}", body);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["success"], false);
+        assert!(body.get("meta").is_none());
+    }
+
+    #[test]
+    fn resolves_relative_times_for_fava() {
+        let today = interval::parse_date("2024-05-15").unwrap();
+        assert_eq!(fava_time("ytd", today), "2024-01-01 - 2024-05-15");
+        assert_eq!(fava_time("last_30_days", today), "2024-04-16 - 2024-05-15");
+        assert_eq!(fava_time("last_quarter", today), "2024-Q1");
+        assert_eq!(fava_time("2023-01 - 2023-06", today), "2023-01 - 2023-06");
+    }
+
+    #[tokio::test]
+    async fn refreshes_only_when_changed_can_not_answer_or_when_forced() {
+        let (refreshes, queries) = (Hits::default(), Hits::default());
+        let (refreshed, queried) = (refreshes.clone(), queries.clone());
+        let fava = Router::new()
+            .route(
+                "/income_statement/",
+                get(move || async move { refreshed.hit() }),
+            )
+            .route(
+                "/api/changed",
+                get(|| async { "{\"success\": true, \"data\": false}" }),
+            )
+            .route(
+                "/api/query_result",
+                get(
+                    move |Query(query): Query<HashMap<String, String>>| async move {
+                        if query["query_string"] == "SELECT n" {
+                            queried.hit();
+                        }
+                        testing::table(&["n"], &[&["1"]])
+                    },
+                ),
+            );
+        let config = Config::new(testing::fava(fava).await).backend(Backend::Html);
+        let state = AppState::new(config);
+        let uri = "/api/query_result?query_string=SELECT%20n";
+        for _ in 0..2 {
+            assert_eq!(testing::get(&state, uri).await.0, StatusCode::OK);
+        }
+        assert_eq!((refreshes.count(), queries.count()), (0, 1));
+        let forced = format!("{}&refresh=true", uri);
+        assert_eq!(testing::get(&state, &forced).await.0, StatusCode::OK);
+        assert_eq!((refreshes.count(), queries.count()), (1, 2));
+    }
+}
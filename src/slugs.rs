@@ -0,0 +1,131 @@
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{Request, Uri},
+    response::{IntoResponse, Response},
+    Router,
+};
+use reqwest::StatusCode;
+use std::collections::BTreeMap;
+use tower::ServiceExt;
+
+use crate::{config, routes, AppState, ErrorResult};
+
+/// Ledgers served at once below `/slugs/<slug>`, so that requests for made
+/// up slugs can not pile up states without end.
+const MAX_SLUGS: usize = 32;
+
+/// The other ledgers of the fava instance, keyed by their url slug, each
+/// with a state of its own that is created on its first request.
+#[derive(Default)]
+pub struct Slugs {
+    states: BTreeMap<String, (Router, AppState)>,
+}
+
+impl Slugs {
+    /// The states of the slugs served so far.
+    pub fn states(&self) -> impl Iterator<Item = &AppState> {
+        self.states.values().map(|(_, state)| state)
+    }
+}
+
+/// Serves `/slugs/<slug>/<route>` with the routes of the fava ledger at
+/// `<slug>`, a sibling of the configured ledger such as `/company` next to
+/// `/personal`.
+pub async fn forward(
+    State(state): State<AppState>,
+    Path((slug, _)): Path<(String, String)>,
+    request: Request<Body>,
+) -> Response {
+    let router = match ledger(&state, &slug) {
+        Ok(router) => router,
+        Err(e) => return e.into_response(),
+    };
+    // The raw path, as the captured route is percent decoded already.
+    let prefix = format!("/slugs/{}", slug);
+    let path = request
+        .uri()
+        .path()
+        .strip_prefix(&prefix)
+        .unwrap_or_default()
+        .to_string();
+    let uri = match request.uri().query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path,
+    };
+    let (mut parts, body) = request.into_parts();
+    parts.uri = match uri.parse::<Uri>() {
+        Ok(uri) => uri,
+        Err(e) => return ErrorResult::bad_request(e.to_string()).into_response(),
+    };
+    // Drop the captures of this route, the ledger's routes have their own.
+    parts.extensions = Default::default();
+    match router.oneshot(Request::from_parts(parts, body)).await {
+        Ok(response) => response,
+        Err(never) => match never {},
+    }
+}
+
+fn ledger(state: &AppState, slug: &str) -> Result<Router, ErrorResult> {
+    if !config::is_ledger_name(slug) {
+        return Err(ErrorResult::bad_request(format!(
+            "invalid ledger slug {}, use letters, digits, - and _",
+            slug
+        )));
+    }
+    let mut slugs = state.slugs.lock().unwrap();
+    if let Some((router, _)) = slugs.states.get(slug) {
+        return Ok(router.clone());
+    }
+    if slugs.states.len() >= MAX_SLUGS {
+        return Err(ErrorResult {
+            error_code: Some("too_many_slugs".into()),
+            status: StatusCode::NOT_FOUND,
+            ..ErrorResult::new(format!("more than {} ledger slugs requested", MAX_SLUGS))
+        });
+    }
+    let config = state
+        .config
+        .for_slug(slug)
+        .ok_or_else(|| ErrorResult::bad_request(format!("can not derive the url of {}", slug)))?;
+    let slug_state = AppState::new(config);
+    let router = routes(slug_state.clone());
+    slugs
+        .states
+        .insert(slug.to_string(), (router.clone(), slug_state));
+    Ok(router)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::routing::get;
+
+    use super::*;
+    use crate::{backend::Backend, config::Config, testing};
+
+    #[tokio::test]
+    async fn serves_the_sibling_ledgers_of_the_fava() {
+        let table = |ledger| move || async move { testing::table(&["ledger"], &[&[ledger]]) };
+        let fava = Router::new()
+            .route("/personal/api/query_result", get(table("personal")))
+            .route("/company/api/query_result", get(table("company")));
+        let url = format!("{}/personal/", testing::fava(fava).await);
+        let config = Config::new(url)
+            .backend(Backend::Html)
+            .refresh_path("none")
+            .slugs(true);
+        let state = AppState::new(config);
+        let query = "api/query_result?query_string=SELECT%20ledger";
+        for (path, ledger) in [("", "personal"), ("/slugs/company", "company")] {
+            let (status, body) = testing::get(&state, &format!("{}/{}", path, query)).await;
+            assert_eq!(status, StatusCode::OK, "{}", body);
+            let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(body["data"][0]["ledger"], ledger);
+        }
+        testing::get(&state, &format!("/slugs/company/{}", query)).await;
+        assert_eq!(state.slugs.lock().unwrap().states().count(), 1);
+
+        let (status, _) = testing::get(&state, &format!("/slugs/com.pany/{}", query)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,283 @@
+use serde::Serialize;
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{cache, config::PollSmoothingConfig, AppState, QueryResult};
+
+/// How often the scheduler looks for due refreshes.
+const TICK: Duration = Duration::from_millis(250);
+
+/// Keeps a refresh schedule for every polled query, so that upstream load
+/// depends on the number of distinct queries rather than on the number of
+/// clients polling them.
+#[derive(Debug, Default)]
+pub struct PollSmoother {
+    entries: Mutex<HashMap<String, Scheduled>>,
+    /// Held while fetching an unscheduled query, so that clients starting to
+    /// poll at the same time share one upstream request.
+    first_fetch: tokio::sync::Mutex<()>,
+}
+
+#[derive(Debug)]
+struct Scheduled {
+    query_string: String,
+    result: QueryResult,
+    last_requested: Instant,
+    refreshed_at: Instant,
+    next_refresh: Instant,
+    refreshes: u64,
+    last_duration: Duration,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScheduleStatus {
+    query: String,
+    refreshes: u64,
+    seconds_since_refresh: f64,
+    seconds_until_refresh: f64,
+    seconds_since_request: f64,
+    last_refresh_ms: u128,
+}
+
+impl PollSmoother {
+    /// Returns the scheduled result for a query and records the poll.
+    pub fn serve(&self, key: &str) -> Option<QueryResult> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get_mut(key)?;
+        entry.last_requested = Instant::now();
+        Some(entry.result.clone())
+    }
+
+    /// Adds a freshly fetched query to the schedule.
+    pub fn schedule(
+        &self,
+        key: String,
+        query_string: &str,
+        result: QueryResult,
+        duration: Duration,
+        config: &PollSmoothingConfig,
+    ) {
+        let now = Instant::now();
+        self.entries.lock().unwrap().insert(
+            key,
+            Scheduled {
+                query_string: query_string.to_string(),
+                result,
+                last_requested: now,
+                refreshed_at: now,
+                next_refresh: now + next_interval(config),
+                refreshes: 1,
+                last_duration: duration,
+            },
+        );
+    }
+
+    pub fn status(&self) -> Vec<ScheduleStatus> {
+        let now = Instant::now();
+        let entries = self.entries.lock().unwrap();
+        let mut status: Vec<ScheduleStatus> = entries
+            .values()
+            .map(|entry| ScheduleStatus {
+                query: entry.query_string.clone(),
+                refreshes: entry.refreshes,
+                seconds_since_refresh: (now - entry.refreshed_at).as_secs_f64(),
+                seconds_until_refresh: entry
+                    .next_refresh
+                    .saturating_duration_since(now)
+                    .as_secs_f64(),
+                seconds_since_request: (now - entry.last_requested).as_secs_f64(),
+                last_refresh_ms: entry.last_duration.as_millis(),
+            })
+            .collect();
+        status.sort_by(|a, b| a.query.cmp(&b.query));
+        status
+    }
+
+    /// Drops idle queries and returns the ones due for a refresh.
+    fn due(&self, config: &PollSmoothingConfig) -> Vec<(String, String)> {
+        let now = Instant::now();
+        let idle = Duration::from_secs(config.idle);
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| now - entry.last_requested < idle);
+        entries
+            .iter()
+            .filter(|(_, entry)| entry.next_refresh <= now)
+            .map(|(key, entry)| (key.clone(), entry.query_string.clone()))
+            .collect()
+    }
+
+    fn refreshed(
+        &self,
+        key: &str,
+        result: Option<QueryResult>,
+        duration: Duration,
+        config: &PollSmoothingConfig,
+    ) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.get_mut(key) {
+            let now = Instant::now();
+            if let Some(result) = result {
+                entry.result = result;
+                entry.refreshed_at = now;
+                entry.refreshes += 1;
+            }
+            entry.last_duration = duration;
+            entry.next_refresh = now + next_interval(config);
+        }
+    }
+}
+
+/// The configured interval, shifted by a random share of the jitter so that
+/// refreshes of different queries do not line up.
+fn next_interval(config: &PollSmoothingConfig) -> Duration {
+    let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+    let factor = 1.0 + config.jitter * (2.0 * random - 1.0);
+    Duration::from_secs(config.interval).mul_f64(factor.max(0.0))
+}
+
+/// Serves a query from the schedule, fetching and scheduling it on its
+/// first poll.
+pub async fn query(
+    state: &AppState,
+    config: &PollSmoothingConfig,
+    query_string: &str,
+) -> Result<QueryResult, crate::UpstreamError> {
+    let key = cache::normalize_query(query_string);
+    if let Some(result) = state.smoother.serve(&key) {
+        return Ok(result);
+    }
+    let _first_fetch = state.smoother.first_fetch.lock().await;
+    if let Some(result) = state.smoother.serve(&key) {
+        return Ok(result);
+    }
+    let started = Instant::now();
+    let result = state.session().query(query_string).await?;
+    if result.success {
+        state
+            .smoother
+            .schedule(key, query_string, result.clone(), started.elapsed(), config);
+    }
+    Ok(result)
+}
+
+/// Starts the scheduler refreshing polled queries, if smoothing is enabled.
+pub fn spawn(state: &AppState) {
+    let config = match &state.config.poll_smoothing {
+        Some(config) => config.clone(),
+        None => return,
+    };
+    let task_state = state.clone();
+    state.spawn_background(async move {
+        let state = task_state;
+        loop {
+            for (key, query_string) in state.smoother.due(&config) {
+                let started = Instant::now();
+                let result = match state.session().query(&query_string).await {
+                    Ok(result) if result.success => Some(result),
+                    Ok(result) => {
+                        println!(
+                            "scheduled refresh of {} failed: {}",
+                            query_string,
+                            result.error.unwrap_or_default()
+                        );
+                        None
+                    }
+                    Err(e) => {
+                        println!("scheduled refresh of {} failed: {}", query_string, e);
+                        None
+                    }
+                };
+                state
+                    .smoother
+                    .refreshed(&key, result, started.elapsed(), &config);
+            }
+            tokio::time::sleep(TICK).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::State as Extract, routing::get, Router};
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{backend::Backend, config::Config, testing, testing::Hits};
+
+    const POLL: &str = "/api/query_result?query_string=SELECT%20n";
+
+    /// A state whose fava counts its queries, smoothing polls every second.
+    async fn smoothed(hits: &Hits, idle: u64) -> AppState {
+        let fava = Router::new()
+            .route(
+                "/api/query_result",
+                get(|Extract(hits): Extract<Hits>| async move {
+                    hits.hit();
+                    testing::table(&["n"], &[&[&hits.count().to_string()]])
+                }),
+            )
+            .with_state(hits.clone());
+        let mut config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        config.poll_smoothing = Some(PollSmoothingConfig {
+            interval: 1,
+            idle,
+            jitter: 0.0,
+        });
+        let state = AppState::new(config);
+        spawn(&state);
+        state
+    }
+
+    async fn schedule(state: &AppState) -> Vec<Value> {
+        let (_, body) = testing::get(state, "/api/cache/status").await;
+        let status: Value = serde_json::from_str(&body).unwrap();
+        status["schedule"].as_array().unwrap().clone()
+    }
+
+    #[tokio::test]
+    async fn queries_fava_at_the_scheduled_rate_for_any_number_of_pollers() {
+        let hits = Hits::default();
+        let state = smoothed(&hits, 60).await;
+        let pollers: Vec<_> = (0..20)
+            .map(|_| {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    for _ in 0..25 {
+                        let (status, _) = testing::get(&state, POLL).await;
+                        assert_eq!(status, 200);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                    }
+                })
+            })
+            .collect();
+        for poller in pollers {
+            poller.await.unwrap();
+        }
+        // 500 polls over some 2.5 seconds: the first fetch, then one
+        // refresh a second.
+        assert!((2..=3).contains(&hits.count()), "{}", hits.count());
+        let schedule = schedule(&state).await;
+        assert_eq!(schedule.len(), 1);
+        assert_eq!(schedule[0]["query"], "SELECT n");
+        assert_eq!(schedule[0]["refreshes"], hits.count() as u64);
+        state.stop();
+    }
+
+    #[tokio::test]
+    async fn drops_queries_nobody_polled_for_the_idle_period() {
+        let hits = Hits::default();
+        let state = smoothed(&hits, 1).await;
+        testing::get(&state, POLL).await;
+        assert_eq!(schedule(&state).await.len(), 1);
+        tokio::time::sleep(Duration::from_millis(1600)).await;
+        assert!(schedule(&state).await.is_empty());
+        assert_eq!(hits.count(), 1);
+        state.stop();
+    }
+}
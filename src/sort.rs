@@ -0,0 +1,110 @@
+use std::cmp::Ordering;
+
+use crate::{amount::Amount, Row};
+
+/// Sorts rows by `sort`, comma separated `column:asc` or `column:desc`
+/// keys, ascending if the direction is left out. Values that read as
+/// numbers or amounts compare by their number, then by their commodity,
+/// and come before other text, which compares as is. Rows that tie keep
+/// their order.
+pub fn sort(mut rows: Vec<Row>, sort: &str) -> Result<Vec<Row>, String> {
+    let mut keys = Vec::new();
+    for key in sort.split(',').map(str::trim).filter(|key| !key.is_empty()) {
+        let (column, descending) = match key.rsplit_once(':') {
+            Some((column, "asc")) => (column, false),
+            Some((column, "desc")) => (column, true),
+            Some((_, direction)) => {
+                return Err(format!(
+                    "invalid sort direction {}, expected asc or desc",
+                    direction
+                ))
+            }
+            None => (key, false),
+        };
+        if !rows.is_empty() && !rows.iter().any(|row| row.contains_key(column)) {
+            return Err(format!("unknown sort column {}", column));
+        }
+        keys.push((column, descending));
+    }
+    rows.sort_by(|a, b| {
+        keys.iter()
+            .map(|(column, descending)| {
+                let ordering = compare(
+                    a.get(*column).map_or("", String::as_str),
+                    b.get(*column).map_or("", String::as_str),
+                );
+                match descending {
+                    true => ordering.reverse(),
+                    false => ordering,
+                }
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    });
+    Ok(rows)
+}
+
+fn compare(a: &str, b: &str) -> Ordering {
+    match (Amount::parse_number(a), Amount::parse_number(b)) {
+        (Some(a), Some(b)) => a.0.cmp(&b.0).then_with(|| a.1.cmp(&b.1)),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => a.cmp(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows() -> Vec<Row> {
+        [
+            ("Expenses:Rent", "3000.00 CNY"),
+            ("Expenses:Food", "-12.50 CNY"),
+            ("Expenses:Books", "n/a"),
+            ("Expenses:Travel", "20 USD"),
+            ("Expenses:Fees", "20 CNY"),
+        ]
+        .iter()
+        .map(|(account, position)| {
+            Row::from([
+                ("account".to_string(), account.to_string()),
+                ("position".to_string(), position.to_string()),
+            ])
+        })
+        .collect()
+    }
+
+    fn accounts(rows: &[Row]) -> Vec<&str> {
+        rows.iter().map(|row| row["account"].as_str()).collect()
+    }
+
+    #[test]
+    fn orders_amounts_by_their_number() {
+        let rows = sort(rows(), "position").unwrap();
+        assert_eq!(
+            accounts(&rows),
+            [
+                "Expenses:Food",
+                "Expenses:Fees",
+                "Expenses:Travel",
+                "Expenses:Rent",
+                "Expenses:Books"
+            ]
+        );
+        let rows = sort(rows, "position:desc, account:asc").unwrap();
+        assert_eq!(accounts(&rows)[..2], ["Expenses:Books", "Expenses:Rent"]);
+    }
+
+    #[test]
+    fn refuses_unknown_columns_and_directions() {
+        assert_eq!(
+            sort(rows(), "payee").unwrap_err(),
+            "unknown sort column payee"
+        );
+        assert_eq!(
+            sort(rows(), "account:up").unwrap_err(),
+            "invalid sort direction up, expected asc or desc"
+        );
+    }
+}
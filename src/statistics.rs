@@ -0,0 +1,204 @@
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    empty_string_as_none,
+    i18n::{Lang, Message},
+    params::{QueryFields, StrictQuery},
+    AppState, ErrorResult, GeneratedQuery, Meta, ParsedRows,
+};
+
+const TRANSACTIONS_QUERY: &str = "SELECT DISTINCT id, date";
+const ACCOUNTS_QUERY: &str = "SELECT DISTINCT account";
+const COMMODITIES_QUERY: &str = "SELECT DISTINCT currency";
+const POSTINGS_QUERY: &str =
+    "SELECT count(position) AS postings, max(date) AS last_activity WHERE date <= today()";
+
+/// Counts of the ledger's transactions, postings, accounts and commodities,
+/// the dates of its first and last transaction, and of the last one that is
+/// not in the future.
+pub async fn statistics(
+    State(state): State<AppState>,
+    StrictQuery(params): StrictQuery<StatisticsParams>,
+    lang: Lang,
+) -> Result<StatisticsResult, ErrorResult> {
+    let session = state.session();
+    let (transactions, accounts, commodities, postings) = tokio::join!(
+        session.rows(TRANSACTIONS_QUERY),
+        session.rows(ACCOUNTS_QUERY),
+        session.rows(COMMODITIES_QUERY),
+        session.rows(POSTINGS_QUERY),
+    );
+    let transactions = transactions.map_err(|e| e.localize(lang))?;
+    let accounts = accounts.map_err(|e| e.localize(lang))?;
+    let commodities = commodities.map_err(|e| e.localize(lang))?;
+    let postings = postings.map_err(|e| e.localize(lang))?;
+
+    let mut dates: Vec<&str> = transactions
+        .rows
+        .iter()
+        .filter_map(|row| row.get("date"))
+        .map(String::as_str)
+        .filter(|date| !date.is_empty())
+        .collect();
+    dates.sort_unstable();
+    let totals = postings.rows.first();
+    let total = |column: &str| totals.and_then(|row| row.get(column));
+    let data = Statistics {
+        transactions: transactions.rows.len(),
+        postings: total("postings")
+            .and_then(|count| count.trim().parse().ok())
+            .unwrap_or(0),
+        accounts: distinct(&accounts, "account"),
+        commodities: distinct(&commodities, "currency"),
+        first_date: dates.first().map(|date| date.to_string()),
+        last_date: dates.last().map(|date| date.to_string()),
+        last_activity: total("last_activity")
+            .filter(|date| !date.is_empty())
+            .cloned(),
+    };
+
+    let generated = [
+        (
+            TRANSACTIONS_QUERY,
+            "data.transactions, data.first_date, data.last_date",
+        ),
+        (ACCOUNTS_QUERY, "data.accounts"),
+        (COMMODITIES_QUERY, "data.commodities"),
+        (POSTINGS_QUERY, "data.postings, data.last_activity"),
+    ]
+    .map(|(query, feeds)| GeneratedQuery {
+        query: query.into(),
+        filters: Vec::new(),
+        feeds: feeds.into(),
+    });
+    let warnings = [transactions, accounts, commodities, postings]
+        .into_iter()
+        .flat_map(|parsed| parsed.warnings)
+        .map(|warning| warning.localize(lang))
+        .collect();
+    Ok(StatisticsResult {
+        success: true,
+        data,
+        warnings,
+        meta: Meta::explain(params.explain, generated.into()),
+    })
+}
+
+fn distinct(parsed: &ParsedRows, column: &str) -> usize {
+    parsed
+        .rows
+        .iter()
+        .filter(|row| row.get(column).is_some_and(|value| !value.is_empty()))
+        .count()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatisticsParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    explain: Option<bool>,
+}
+
+impl QueryFields for StatisticsParams {
+    const FIELDS: &[&str] = &["explain"];
+}
+
+#[derive(Debug, Serialize)]
+struct Statistics {
+    transactions: usize,
+    postings: usize,
+    /// Accounts with postings.
+    accounts: usize,
+    /// Commodities posted.
+    commodities: usize,
+    first_date: Option<String>,
+    last_date: Option<String>,
+    last_activity: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatisticsResult {
+    success: bool,
+    data: Statistics,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<Meta>,
+}
+
+impl IntoResponse for StatisticsResult {
+    fn into_response(self) -> Response {
+        let body = Json(self);
+        (StatusCode::OK, body).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::Query, routing::get, Router};
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{backend::Backend, config::Config, testing, testing::table};
+
+    #[tokio::test]
+    async fn counts_the_ledger() {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|Query(params): Query<HashMap<String, String>>| async move {
+                match params["query_string"].as_str() {
+                    TRANSACTIONS_QUERY => table(
+                        &["id", "date"],
+                        &[
+                            &["a", "2024-03-01"],
+                            &["b", "2023-01-05"],
+                            &["c", "2025-01-01"],
+                        ],
+                    ),
+                    ACCOUNTS_QUERY => {
+                        table(&["account"], &[&["Assets:Bank"], &["Expenses:Food"], &[""]])
+                    }
+                    COMMODITIES_QUERY => table(&["currency"], &[&["CNY"]]),
+                    POSTINGS_QUERY => {
+                        table(&["postings", "last_activity"], &[&["5", "2024-03-01"]])
+                    }
+                    _ => table(&["n"], &[]),
+                }
+            }),
+        );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        let state = AppState::new(config);
+        let (status, body) = testing::get(&state, "/api/statistics?explain=true").await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["data"],
+            json!({
+                "transactions": 3,
+                "postings": 5,
+                "accounts": 2,
+                "commodities": 1,
+                "first_date": "2023-01-05",
+                "last_date": "2025-01-01",
+                "last_activity": "2024-03-01",
+            })
+        );
+        let generated = &body["meta"]["generated_queries"];
+        assert_eq!(generated[3]["query"], POSTINGS_QUERY);
+        assert_eq!(generated[3]["feeds"], "data.postings, data.last_activity");
+        assert_eq!(
+            testing::get(&state, "/api/statistics?account=Assets")
+                .await
+                .0,
+            StatusCode::BAD_REQUEST
+        );
+    }
+}
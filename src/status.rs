@@ -0,0 +1,138 @@
+use axum::{extract::State, http::StatusCode, Json};
+use serde::Serialize;
+use std::time::Duration;
+
+use crate::{groups::EndpointGroup, AppState};
+
+/// How long `/readyz` waits for fava before it reports the service as not
+/// ready.
+const READY_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub async fn status(State(state): State<AppState>) -> Json<StatusResult> {
+    Json(StatusResult {
+        success: true,
+        data: Status {
+            endpoint_groups: state.config.endpoints.iter().copied().collect(),
+            upstream_error: state.fingerprint.mismatch(),
+            last_upstream_failure: state
+                .availability
+                .last_failure()
+                .map(|(at, error)| Failure { at, error }),
+            circuit: state
+                .config
+                .circuit_breaker
+                .as_ref()
+                .map(|_| state.availability.circuit()),
+        },
+    })
+}
+
+/// `GET /healthz`: answers as long as the process serves requests, without
+/// asking fava.
+pub async fn healthz() -> Json<HealthResult> {
+    Json(HealthResult {
+        success: true,
+        error: None,
+    })
+}
+
+/// `GET /readyz`: `200` while fava answers and looks like fava, `503`
+/// otherwise, so that an orchestrator stops routing to the service while
+/// its upstream is gone.
+pub async fn readyz(State(state): State<AppState>) -> (StatusCode, Json<HealthResult>) {
+    let error = match state.fingerprint.mismatch() {
+        Some(mismatch) => Some(mismatch),
+        None if state.availability.remaining().is_some() => Some("fava is unavailable".into()),
+        None => {
+            match tokio::time::timeout(READY_TIMEOUT, state.client.fetch("/help/", &[])).await {
+                Ok(Ok(response)) if response.status().is_success() => None,
+                Ok(Ok(response)) => Some(format!("fava answered {}", response.status())),
+                Ok(Err(e)) => Some(e.to_string()),
+                Err(_) => Some("fava did not answer in time".into()),
+            }
+        }
+    };
+    let status = match error {
+        Some(_) => StatusCode::SERVICE_UNAVAILABLE,
+        None => StatusCode::OK,
+    };
+    (
+        status,
+        Json(HealthResult {
+            success: error.is_none(),
+            error,
+        }),
+    )
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthResult {
+    success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatusResult {
+    success: bool,
+    data: Status,
+}
+
+#[derive(Debug, Serialize)]
+struct Status {
+    /// Endpoint groups enabled in this deployment.
+    endpoint_groups: Vec<EndpointGroup>,
+    /// Set while the configured url does not look like a fava server.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upstream_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_upstream_failure: Option<Failure>,
+    /// State of the circuit breaker, if configured.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    circuit: Option<&'static str>,
+}
+
+#[derive(Debug, Serialize)]
+struct Failure {
+    at: String,
+    error: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::get, Router};
+
+    use super::*;
+    use crate::{config::Config, testing};
+
+    #[tokio::test]
+    async fn is_ready_only_while_fava_answers() {
+        let up = Router::new().route("/help/", get(|| async { "Fava v1.27.3" }));
+        let down = Router::new().route(
+            "/help/",
+            get(|| async { (StatusCode::BAD_GATEWAY, "down") }),
+        );
+        let up = testing::fava(up).await;
+        let cases = [
+            (up.as_str(), 200, "{\"success\":true}"),
+            (
+                &testing::fava(down).await,
+                503,
+                "{\"success\":false,\"error\":\"fava answered 502 Bad Gateway\"}",
+            ),
+        ];
+        for (url, status, body) in cases {
+            let state = AppState::new(Config::new(url).refresh_path("none"));
+            let (answered, text) = testing::get(&state, "/readyz").await;
+            assert_eq!((answered.as_u16(), text.as_str()), (status, body));
+            assert_eq!(
+                testing::get(&state, "/healthz").await,
+                (StatusCode::OK, "{\"success\":true}".to_string())
+            );
+        }
+        let state = AppState::new(Config::new("http://127.0.0.1:9").refresh_path("none"));
+        let (status, text) = testing::get(&state, "/readyz").await;
+        assert_eq!(status, 503, "{}", text);
+        assert!(text.contains("\"success\":false"));
+    }
+}
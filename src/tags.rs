@@ -0,0 +1,586 @@
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Response},
+    Json,
+};
+use reqwest::StatusCode;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    amount::{self, Amount},
+    balance_sheet::{described, fava_filters},
+    empty_string_as_none,
+    i18n::{Lang, Message},
+    params::{QueryFields, StrictQuery},
+    AppState, ErrorResult, GeneratedQuery, Meta, Row, SuccessResult,
+};
+
+/// Characters beancount accepts in a tag name.
+fn is_tag_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '-' | '_' | '/' | '.')
+}
+
+/// Strips an optional leading `#` and checks the remaining name is a valid tag.
+fn normalize_tag(tag: &str) -> Option<&str> {
+    let tag = tag.strip_prefix('#').unwrap_or(tag);
+    if tag.is_empty() || !tag.chars().all(is_tag_char) {
+        return None;
+    }
+    Some(tag)
+}
+
+fn tag_filter(tag: &str) -> String {
+    format!("'{}' IN tags", tag)
+}
+
+fn tag_query(tag: &str) -> String {
+    format!(
+        "SELECT id, date, flag, payee, narration, account, position WHERE {} ORDER BY date",
+        tag_filter(tag)
+    )
+}
+
+fn tags_query(column: &str) -> String {
+    format!("SELECT DISTINCT id, {}", column)
+}
+
+const PAYEES_QUERY: &str = "SELECT DISTINCT id, date, payee";
+
+/// Splits fava's rendering of a tag set (`a, b` or `#a #b`) or a link set
+/// (`^a ^b`) into names.
+pub(crate) fn split_tags(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| c == ',' || c.is_whitespace())
+        .map(|tag| tag.trim_start_matches(['#', '^']))
+        .filter(|tag| !tag.is_empty())
+}
+
+pub async fn tag(
+    State(state): State<AppState>,
+    Path(tag): Path<String>,
+    StrictQuery(params): StrictQuery<TagParams>,
+    lang: Lang,
+) -> Result<TagResult, ErrorResult> {
+    let tag = match normalize_tag(&tag) {
+        Some(tag) => tag,
+        None => {
+            let message = Message::new("invalid_tag", vec![tag.to_string()]);
+            return Err(ErrorResult {
+                status: StatusCode::BAD_REQUEST,
+                ..ErrorResult::message(message)
+            }
+            .localize(lang));
+        }
+    };
+    let query = tag_query(tag);
+    let parsed = state
+        .session()
+        .rows(&query)
+        .await
+        .map_err(|e| e.localize(lang))?;
+    let warnings = parsed
+        .warnings
+        .into_iter()
+        .map(|warning| warning.localize(lang))
+        .collect();
+    let generated = GeneratedQuery {
+        query,
+        filters: vec![tag_filter(tag)],
+        feeds: "data.transactions, data.summary".into(),
+    };
+    Ok(TagResult {
+        meta: Meta::explain(params.explain, vec![generated]),
+        ..TagResult::new(get_tag_data(parsed.rows), warnings)
+    })
+}
+
+pub async fn tags(
+    State(state): State<AppState>,
+    StrictQuery(params): StrictQuery<TagsParams>,
+    lang: Lang,
+) -> Result<SuccessResult, ErrorResult> {
+    names(&state, "tags", "tag", &params, lang).await
+}
+
+/// The links of the ledger, like `/api/tags` lists the tags.
+pub async fn links(
+    State(state): State<AppState>,
+    StrictQuery(params): StrictQuery<TagsParams>,
+    lang: Lang,
+) -> Result<SuccessResult, ErrorResult> {
+    names(&state, "links", "link", &params, lang).await
+}
+
+/// The names in the set `column` of the transactions, each as `key`, with
+/// the number of transactions using it given `counts=true`.
+async fn names(
+    state: &AppState,
+    column: &str,
+    key: &str,
+    params: &TagsParams,
+    lang: Lang,
+) -> Result<SuccessResult, ErrorResult> {
+    let query = tags_query(column);
+    let filters = fava_filters(params.time.as_deref(), None);
+    let parsed = state
+        .session()
+        .filtered(&filters)
+        .rows(&query)
+        .await
+        .map_err(|e| e.localize(lang))?;
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    parsed
+        .rows
+        .iter()
+        .filter_map(|row| row.get(column))
+        .flat_map(|names| split_tags(names))
+        .for_each(|name| *counts.entry(name.to_string()).or_default() += 1);
+
+    let data = counts
+        .into_iter()
+        .map(|(name, count)| {
+            let mut item = Row::new();
+            item.insert(key.into(), name);
+            if Some(true) == params.counts {
+                item.insert("count".into(), count.to_string());
+            }
+            item
+        })
+        .collect();
+    let generated = GeneratedQuery {
+        query,
+        filters: described(&filters),
+        feeds: "data".into(),
+    };
+    Ok(SuccessResult {
+        warnings: parsed.warnings,
+        meta: Meta::explain(params.explain, vec![generated]),
+        ..SuccessResult::new(data)
+    }
+    .localize(lang))
+}
+
+/// Every payee with the number of its transactions and the date of the
+/// last one, by name. `prefix` keeps the payees starting with it, ignoring
+/// case.
+pub async fn payees(
+    State(state): State<AppState>,
+    StrictQuery(params): StrictQuery<PayeesParams>,
+    lang: Lang,
+) -> Result<PayeesResult, ErrorResult> {
+    let parsed = state
+        .session()
+        .rows(PAYEES_QUERY)
+        .await
+        .map_err(|e| e.localize(lang))?;
+    let prefix = params.prefix.as_deref().map(str::to_lowercase);
+    let mut payees: BTreeMap<String, Payee> = BTreeMap::new();
+    for row in &parsed.rows {
+        let (payee, date) = match (row.get("payee"), row.get("date")) {
+            (Some(payee), Some(date)) if !payee.is_empty() => (payee, date),
+            _ => continue,
+        };
+        if let Some(prefix) = &prefix {
+            if !payee.to_lowercase().starts_with(prefix.as_str()) {
+                continue;
+            }
+        }
+        let entry = payees.entry(payee.clone()).or_insert_with(|| Payee {
+            payee: payee.clone(),
+            count: 0,
+            last_seen: date.clone(),
+        });
+        entry.count += 1;
+        if *date > entry.last_seen {
+            entry.last_seen = date.clone();
+        }
+    }
+
+    let generated = GeneratedQuery {
+        query: PAYEES_QUERY.into(),
+        filters: Vec::new(),
+        feeds: "data".into(),
+    };
+    Ok(PayeesResult {
+        success: true,
+        data: payees.into_values().collect(),
+        warnings: parsed
+            .warnings
+            .into_iter()
+            .map(|warning| warning.localize(lang))
+            .collect(),
+        meta: Meta::explain(params.explain, vec![generated]),
+    })
+}
+
+/// Groups the per-posting query rows into transactions and sums them per
+/// account and currency. The total only covers income and expense postings,
+/// since the postings of a balanced transaction always sum up to zero.
+fn get_tag_data(rows: Vec<Row>) -> TagData {
+    let mut transactions: Vec<TagTransaction> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    let mut accounts: BTreeMap<String, BTreeMap<String, Decimal>> = BTreeMap::new();
+    let mut total: BTreeMap<String, Decimal> = BTreeMap::new();
+
+    for mut row in rows {
+        let mut take = |key: &str| row.remove(key).unwrap_or_default();
+        let id = take("id");
+        let account = take("account");
+        let position = take("position");
+        let i = *index.entry(id).or_insert_with(|| {
+            transactions.push(TagTransaction {
+                date: take("date"),
+                flag: take("flag"),
+                payee: take("payee"),
+                narration: take("narration"),
+                postings: Vec::new(),
+            });
+            transactions.len() - 1
+        });
+
+        let amount = Amount::parse(&position);
+        if let Some(amount) = &amount {
+            *accounts
+                .entry(account.clone())
+                .or_default()
+                .entry(amount.currency.clone())
+                .or_default() += amount.number;
+            if account.starts_with("Income") || account.starts_with("Expenses") {
+                *total.entry(amount.currency.clone()).or_default() += amount.number;
+            }
+        }
+        let (amount, currency) = amount
+            .map(|amount| (amount::format_number(amount.number), amount.currency))
+            .unwrap_or_default();
+        transactions[i].postings.push(TagPosting {
+            account,
+            amount,
+            currency,
+        });
+    }
+
+    let to_strings = |sums: BTreeMap<String, Decimal>| {
+        sums.into_iter()
+            .map(|(currency, number)| (currency, amount::format_number(number)))
+            .collect()
+    };
+    TagData {
+        transactions,
+        summary: TagSummary {
+            accounts: accounts
+                .into_iter()
+                .map(|(account, sums)| (account, to_strings(sums)))
+                .collect(),
+            total: to_strings(total),
+        },
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TagParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    explain: Option<bool>,
+}
+
+impl QueryFields for TagParams {
+    const FIELDS: &[&str] = &["explain"];
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TagsParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    counts: Option<bool>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    time: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    explain: Option<bool>,
+}
+
+impl QueryFields for TagsParams {
+    const FIELDS: &[&str] = &["counts", "time", "explain"];
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PayeesParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    prefix: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    explain: Option<bool>,
+}
+
+impl QueryFields for PayeesParams {
+    const FIELDS: &[&str] = &["prefix", "explain"];
+}
+
+#[derive(Debug, Serialize)]
+struct Payee {
+    payee: String,
+    /// Transactions with this payee.
+    count: usize,
+    last_seen: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PayeesResult {
+    success: bool,
+    data: Vec<Payee>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<Meta>,
+}
+
+impl IntoResponse for PayeesResult {
+    fn into_response(self) -> Response {
+        let body = Json(self);
+        (StatusCode::OK, body).into_response()
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct TagPosting {
+    account: String,
+    amount: String,
+    currency: String,
+}
+
+#[derive(Debug, Serialize)]
+struct TagTransaction {
+    date: String,
+    flag: String,
+    payee: String,
+    narration: String,
+    postings: Vec<TagPosting>,
+}
+
+#[derive(Debug, Serialize)]
+struct TagSummary {
+    accounts: BTreeMap<String, BTreeMap<String, String>>,
+    total: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize)]
+struct TagData {
+    transactions: Vec<TagTransaction>,
+    summary: TagSummary,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagResult {
+    success: bool,
+    data: TagData,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<Meta>,
+}
+
+impl TagResult {
+    fn new(data: TagData, warnings: Vec<Message>) -> TagResult {
+        TagResult {
+            success: true,
+            data,
+            warnings,
+            meta: None,
+        }
+    }
+}
+
+impl IntoResponse for TagResult {
+    fn into_response(self) -> Response {
+        let body = Json(self);
+        (StatusCode::OK, body).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::Query, routing::get, Router};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{backend::Backend, config::Config, testing, testing::table};
+
+    async fn state() -> AppState {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|Query(params): Query<HashMap<String, String>>| async move {
+                match params["query_string"].as_str() {
+                    "SELECT DISTINCT id, tags" => table(
+                        &["id", "tags"],
+                        &[&["1", "trip, food"], &["2", "#trip"], &["3", ""]],
+                    ),
+                    "SELECT DISTINCT id, links" => {
+                        assert_eq!(params.get("time").map(String::as_str), Some("2024"));
+                        table(&["id", "links"], &[&["1", "^trip-2024 ^hotel"], &["2", ""]])
+                    }
+                    PAYEES_QUERY => table(
+                        &["id", "date", "payee"],
+                        &[
+                            &["1", "2024-01-02", "Hotel"],
+                            &["2", "2024-02-01", "Cafe"],
+                            &["3", "2024-01-05", "cafe bar"],
+                            &["4", "2024-01-03", "Cafe"],
+                            &["5", "2024-01-04", ""],
+                        ],
+                    ),
+                    _ => table(
+                        &[
+                            "id",
+                            "date",
+                            "flag",
+                            "payee",
+                            "narration",
+                            "account",
+                            "position",
+                        ],
+                        &[
+                            &[
+                                "1",
+                                "2024-01-02",
+                                "*",
+                                "Hotel",
+                                "Two nights",
+                                "Expenses:Travel",
+                                "300.00 CNY",
+                            ],
+                            &["1", "", "", "", "", "Assets:Bank", "-300.00 CNY"],
+                            &[
+                                "2",
+                                "2024-01-03",
+                                "*",
+                                "Cafe",
+                                "",
+                                "Expenses:Food",
+                                "12.5 CNY",
+                            ],
+                            &["2", "", "", "", "", "Assets:Bank", "-12.5 CNY"],
+                        ],
+                    ),
+                }
+            }),
+        );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        AppState::new(config)
+    }
+
+    #[tokio::test]
+    async fn groups_the_postings_of_a_tag() {
+        let (status, body) = testing::get(&state().await, "/api/tag/%23trip?explain=true").await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["data"]["transactions"][0],
+            json!({
+                "date": "2024-01-02",
+                "flag": "*",
+                "payee": "Hotel",
+                "narration": "Two nights",
+                "postings": [
+                    {"account": "Expenses:Travel", "amount": "300.00", "currency": "CNY"},
+                    {"account": "Assets:Bank", "amount": "-300.00", "currency": "CNY"},
+                ],
+            })
+        );
+        assert_eq!(body["data"]["transactions"].as_array().unwrap().len(), 2);
+        assert_eq!(
+            body["data"]["summary"],
+            json!({
+                "accounts": {
+                    "Assets:Bank": {"CNY": "-312.50"},
+                    "Expenses:Food": {"CNY": "12.5"},
+                    "Expenses:Travel": {"CNY": "300.00"},
+                },
+                "total": {"CNY": "312.50"},
+            })
+        );
+        assert_eq!(
+            body["meta"]["generated_queries"][0]["query"],
+            tag_query("trip")
+        );
+    }
+
+    #[tokio::test]
+    async fn refuses_an_invalid_tag() {
+        let (status, _) = testing::get(&state().await, "/api/tag/a%20b").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn lists_the_tags_with_their_counts() {
+        let state = state().await;
+        let (status, body) = testing::get(&state, "/api/tags?counts=true").await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["data"],
+            json!([{"tag": "food", "count": "1"}, {"tag": "trip", "count": "2"}])
+        );
+        let (_, body) = testing::get(&state, "/api/tags").await;
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["data"], json!([{"tag": "food"}, {"tag": "trip"}]));
+    }
+
+    #[tokio::test]
+    async fn counts_the_payees_by_name() {
+        let state = state().await;
+        let (status, body) = testing::get(&state, "/api/payees?prefix=CA").await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["data"],
+            json!([
+                {"payee": "Cafe", "count": 2, "last_seen": "2024-02-01"},
+                {"payee": "cafe bar", "count": 1, "last_seen": "2024-01-05"},
+            ])
+        );
+        let (_, body) = testing::get(&state, "/api/payees").await;
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["data"].as_array().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn lists_the_links_of_a_time() {
+        let (status, body) =
+            testing::get(&state().await, "/api/links?time=2024&explain=true").await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["data"],
+            json!([{"link": "hotel"}, {"link": "trip-2024"}])
+        );
+        assert_eq!(
+            body["meta"]["generated_queries"][0]["filters"],
+            json!(["time=2024"])
+        );
+    }
+
+    #[tokio::test]
+    async fn explains_the_tag_queries_only_when_asked() {
+        let state = state().await;
+        let (_, body) = testing::get(&state, "/api/tag/trip").await;
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert!(body.get("meta").is_none());
+
+        let (_, body) = testing::get(&state, "/api/tag/trip?explain=true").await;
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["meta"]["generated_queries"],
+            json!([{
+                "query": "SELECT id, date, flag, payee, narration, account, position \
+                          WHERE 'trip' IN tags ORDER BY date",
+                "filters": ["'trip' IN tags"],
+                "feeds": "data.transactions, data.summary",
+            }])
+        );
+        let (_, body) = testing::get(&state, "/api/tags?explain=true").await;
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["meta"]["generated_queries"][0]["query"],
+            "SELECT DISTINCT id, tags"
+        );
+    }
+}
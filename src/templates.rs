@@ -0,0 +1,350 @@
+use axum::{
+    extract::{rejection::QueryRejection, Path, Query, State},
+    Json,
+};
+use reqwest::StatusCode;
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::{i18n::Lang, params, query_rows, AppState, ErrorResult, Params, SuccessResult};
+
+/// A piece of a query template: text, or a `{name}` to fill in, which is
+/// `quoted` inside a BQL string literal.
+#[derive(Debug, PartialEq, Eq)]
+enum Part<'a> {
+    Text(&'a str),
+    Placeholder { name: &'a str, quoted: bool },
+}
+
+/// Splits a template into its parts. `{{` and `}}` stand for literal
+/// braces, a lone `}` or an unclosed string is an error.
+fn parse(template: &str) -> Result<Vec<Part<'_>>, String> {
+    let mut parts = Vec::new();
+    let mut quote = None;
+    let mut start = 0;
+    let mut chars = template.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (Some(_), '\\') => {
+                chars.next();
+            }
+            (Some(open), c) if c == open => quote = None,
+            (None, '\'' | '"') => quote = Some(c),
+            (_, '{' | '}') if chars.peek().map(|(_, next)| *next) == Some(c) => {
+                parts.push(Part::Text(&template[start..=i]));
+                chars.next();
+                start = i + 2;
+            }
+            (_, '{') => {
+                let end = template[i..]
+                    .find('}')
+                    .map(|end| i + end)
+                    .ok_or_else(|| format!("unclosed placeholder at {}", i))?;
+                let name = &template[i + 1..end];
+                if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                    return Err(format!("invalid placeholder {{{}}}", name));
+                }
+                parts.push(Part::Text(&template[start..i]));
+                parts.push(Part::Placeholder {
+                    name,
+                    quoted: quote.is_some(),
+                });
+                while chars.peek().is_some_and(|(j, _)| *j <= end) {
+                    chars.next();
+                }
+                start = end + 1;
+            }
+            (_, '}') => return Err(format!("unmatched }} at {}", i)),
+            _ => {}
+        }
+    }
+    if let Some(open) = quote {
+        return Err(format!("unclosed {} string", open));
+    }
+    parts.push(Part::Text(&template[start..]));
+    Ok(parts)
+}
+
+/// The names of a template's placeholders, each once, in order.
+pub fn placeholders(template: &str) -> Result<Vec<&str>, String> {
+    let mut names = Vec::new();
+    for part in parse(template)? {
+        if let Part::Placeholder { name, .. } = part {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Fills in a template. Values inside a string literal may hold anything
+/// but quotes and backslashes, other values only a number or a date, so
+/// that no value can change the shape of the query.
+fn render(template: &str, values: &HashMap<String, String>) -> Result<String, ErrorResult> {
+    let mut query = String::new();
+    for part in parse(template).map_err(ErrorResult::new)? {
+        let (name, quoted) = match part {
+            Part::Text(text) => {
+                query.push_str(text);
+                continue;
+            }
+            Part::Placeholder { name, quoted } => (name, quoted),
+        };
+        let value = values
+            .get(name)
+            .ok_or_else(|| ErrorResult::bad_request(format!("missing parameter {}", name)))?;
+        let safe = match quoted {
+            true => !value
+                .chars()
+                .any(|c| matches!(c, '\'' | '"' | '\\') || c.is_control()),
+            false => is_number(value) || is_date(value),
+        };
+        if !safe {
+            return Err(ErrorResult::bad_request(match quoted {
+                true => format!("parameter {} can not hold quotes or backslashes", name),
+                false => format!("parameter {} must be a number or a YYYY-MM-DD date", name),
+            }));
+        }
+        query.push_str(value);
+    }
+    Ok(query)
+}
+
+fn is_number(text: &str) -> bool {
+    let digits = text.strip_prefix('-').unwrap_or(text);
+    let (whole, fraction) = digits.split_once('.').unwrap_or((digits, "0"));
+    [whole, fraction]
+        .iter()
+        .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+}
+
+fn is_date(text: &str) -> bool {
+    let parts: Vec<&str> = text.split('-').collect();
+    parts.len() == 3
+        && parts
+            .iter()
+            .zip([4, 2, 2])
+            .all(|(part, width)| part.len() == width && part.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// `GET /api/template/:name`: runs the query template `name` of the config
+/// with the query parameters filled into its placeholders.
+pub async fn template(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    values: Result<Query<HashMap<String, String>>, QueryRejection>,
+    lang: Lang,
+) -> Result<SuccessResult, ErrorResult> {
+    let Query(values) =
+        values.map_err(|rejection| ErrorResult::bad_request(rejection.to_string()))?;
+    let template = state
+        .config
+        .templates
+        .get(&name)
+        .ok_or_else(|| ErrorResult {
+            error_code: Some("unknown_template".into()),
+            status: StatusCode::NOT_FOUND,
+            ..ErrorResult::new(format!("unknown template {}", name))
+        })?;
+    if state.config.strict_params {
+        let names = placeholders(template).map_err(ErrorResult::new)?;
+        params::check_keys(values.keys().cloned(), &names)
+            .map_err(|message| ErrorResult::bad_request(message.localize(lang).to_string()))?;
+    }
+    let params = Params {
+        query_string: render(template, &values)?,
+        ..Params::default()
+    };
+    query_rows(&state, &params)
+        .await
+        .map(|result| result.localize(lang))
+        .map_err(|e| e.localize(lang))
+}
+
+/// `GET /api/templates`: the query templates of the config with their
+/// parameters.
+pub async fn templates(State(state): State<AppState>) -> Json<TemplatesResult> {
+    Json(TemplatesResult {
+        success: true,
+        data: state
+            .config
+            .templates
+            .iter()
+            .map(|(name, template)| Template {
+                name: name.clone(),
+                params: placeholders(template)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(str::to_string)
+                    .collect(),
+            })
+            .collect(),
+    })
+}
+
+#[derive(Debug, Serialize)]
+pub struct TemplatesResult {
+    success: bool,
+    data: Vec<Template>,
+}
+
+#[derive(Debug, Serialize)]
+struct Template {
+    name: String,
+    params: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::RawQuery, routing::get, Router};
+
+    use super::*;
+    use crate::{backend::Backend, config::Config, testing, testing::table};
+
+    fn values(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn error(template: &str, pairs: &[(&str, &str)]) -> (StatusCode, String) {
+        let error = render(template, &values(pairs)).unwrap_err();
+        (error.status, error.error)
+    }
+
+    #[test]
+    fn splits_text_escapes_and_placeholders() {
+        assert_eq!(
+            parse("SELECT '{{x}}', {limit} WHERE payee = \"{payee}\"").unwrap(),
+            [
+                Part::Text("SELECT '{"),
+                Part::Text("x}"),
+                Part::Text("', "),
+                Part::Placeholder {
+                    name: "limit",
+                    quoted: false
+                },
+                Part::Text(" WHERE payee = \""),
+                Part::Placeholder {
+                    name: "payee",
+                    quoted: true
+                },
+                Part::Text("\""),
+            ]
+        );
+        // A quote escaped inside a string does not end it.
+        assert_eq!(
+            placeholders("SELECT 'it\\'s {a}' WHERE x = {b} AND y = {a}").unwrap(),
+            ["a", "b"]
+        );
+    }
+
+    #[test]
+    fn rejects_broken_templates() {
+        for (template, message) in [
+            ("SELECT {year", "unclosed placeholder at 7"),
+            ("SELECT {}", "invalid placeholder {}"),
+            ("SELECT {a b}", "invalid placeholder {a b}"),
+            ("SELECT 1 }", "unmatched } at 9"),
+            ("SELECT 'x", "unclosed ' string"),
+            ("SELECT \"{a}", "unclosed \" string"),
+        ] {
+            assert_eq!(parse(template).unwrap_err(), message, "{}", template);
+        }
+    }
+
+    #[test]
+    fn fills_in_numbers_dates_and_quoted_text() {
+        let query = render(
+            "SELECT {{a}} WHERE date >= {from} AND number > {min} AND payee = \"{payee}\"",
+            &values(&[
+                ("from", "2024-01-01"),
+                ("min", "-1.5"),
+                ("payee", "Café {x} 1"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            query,
+            "SELECT {a} WHERE date >= 2024-01-01 AND number > -1.5 AND payee = \"Café {x} 1\""
+        );
+    }
+
+    #[test]
+    fn refuses_values_that_change_the_query() {
+        let quoted = "SELECT * WHERE payee = '{payee}'";
+        for value in ["x' OR 'a'='a", "x\" OR 1", "x\\", "x\ny", "x\u{7}"] {
+            assert_eq!(
+                error(quoted, &[("payee", value)]),
+                (
+                    StatusCode::BAD_REQUEST,
+                    "parameter payee can not hold quotes or backslashes".into()
+                ),
+                "{:?}",
+                value
+            );
+        }
+        let bare = "SELECT * LIMIT {limit}";
+        for value in ["1 OR 1=1", "1;", "", "1.", "-", "2024-1-01", "x"] {
+            assert_eq!(
+                error(bare, &[("limit", value)]),
+                (
+                    StatusCode::BAD_REQUEST,
+                    "parameter limit must be a number or a YYYY-MM-DD date".into()
+                ),
+                "{:?}",
+                value
+            );
+        }
+        assert_eq!(
+            error(bare, &[]),
+            (StatusCode::BAD_REQUEST, "missing parameter limit".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn runs_a_template_of_the_config() {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|RawQuery(query): RawQuery| async move {
+                table(&["query"], &[&[&query.unwrap_or_default()]])
+            }),
+        );
+        let mut config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        config
+            .templates
+            .insert("top".into(), "SELECT payee LIMIT {limit}".into());
+        let state = AppState::new(config);
+
+        let (status, body) = testing::get(&state, "/api/template/top?limit=3").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("SELECT+payee+LIMIT+3"), "{}", body);
+        for (uri, status, code) in [
+            ("/api/template/top", StatusCode::BAD_REQUEST, "bad_request"),
+            (
+                "/api/template/top?limit=1%20OR%201",
+                StatusCode::BAD_REQUEST,
+                "bad_request",
+            ),
+            (
+                "/api/template/nope",
+                StatusCode::NOT_FOUND,
+                "unknown_template",
+            ),
+        ] {
+            let (answered, body) = testing::get(&state, uri).await;
+            assert_eq!(answered, status, "{}", uri);
+            let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(body["code"], code, "{}", uri);
+        }
+        let (_, body) = testing::get(&state, "/api/templates").await;
+        assert_eq!(
+            body,
+            r#"{"success":true,"data":[{"name":"top","params":["limit"]}]}"#
+        );
+    }
+}
@@ -0,0 +1,203 @@
+//! What the tests of the modules share: a fava played by an axum router on
+//! a local port, the fixtures captured from real favas, and requests to the
+//! service's own routes.
+//!
+//! The fixtures are written by `examples/capture_fixtures.rs`, one directory
+//! per fava version below `tests/fixtures`, and listed in the manifest
+//! there. Tests run against each of them, so regenerating the fixtures for
+//! a new fava version is one run of the example plus a review of its diff.
+//!
+//! Snapshots pin the exact responses for the fixtures of each version below
+//! `tests/snapshots`. A change of a response shape is made on purpose by
+//! running the tests with `UPDATE_SNAPSHOTS=1`, which rewrites the
+//! snapshots, and committing them once their diff has been reviewed.
+
+use axum::{
+    body::Body,
+    http::{header::CONTENT_TYPE, HeaderMap, Request, StatusCode},
+    routing::get as route_get,
+    Router,
+};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fs,
+    net::TcpListener,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use tower::ServiceExt;
+
+use crate::{config::Config, routes, AppState};
+
+/// Serves `fava` on a free local port and returns its url.
+pub async fn fava(fava: Router) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let server = axum::Server::from_tcp(listener)
+        .unwrap()
+        .serve(fava.into_make_service());
+    tokio::spawn(server);
+    url
+}
+
+/// A state for the fava at `url` with the default config.
+pub fn state(url: &str) -> AppState {
+    AppState::new(Config::new(url))
+}
+
+/// Sends `request` through the routes of `state`, and returns the status,
+/// headers and body of the response.
+pub async fn send(state: &AppState, request: Request<Body>) -> (StatusCode, HeaderMap, Vec<u8>) {
+    let response = routes(state.clone()).oneshot(request).await.unwrap();
+    let (parts, body) = response.into_parts();
+    let body = hyper::body::to_bytes(body).await.unwrap();
+    (parts.status, parts.headers, body.to_vec())
+}
+
+/// GETs `uri` from the routes of `state`, with its body as text.
+pub async fn get(state: &AppState, uri: &str) -> (StatusCode, String) {
+    let request = Request::get(uri).body(Body::empty()).unwrap();
+    let (status, _, body) = send(state, request).await;
+    (status, String::from_utf8(body).unwrap())
+}
+
+/// A result table as fava's `/api/query_result` renders it.
+pub fn table(columns: &[&str], rows: &[&[&str]]) -> String {
+    let head: String = columns
+        .iter()
+        .map(|column| format!("<th>{}</th>", column))
+        .collect();
+    let body: String = rows
+        .iter()
+        .map(|row| {
+            let cells: String = row
+                .iter()
+                .map(|cell| format!("<td>{}</td>", cell))
+                .collect();
+            format!("<tr>{}</tr>", cells)
+        })
+        .collect();
+    let table = format!(
+        "<table class=\"queryresults\"><thead><tr>{}</tr></thead><tbody>{}</tbody></table>",
+        head, body
+    );
+    serde_json::json!({ "success": true, "data": { "table": table } }).to_string()
+}
+
+/// A path below the temporary directory that no other test uses, holding
+/// `contents` if there are any.
+pub fn temp_file(name: &str, contents: Option<&str>) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "fava-query-{}-{}-{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::SeqCst),
+        name
+    ));
+    let _ = fs::remove_file(&path);
+    if let Some(contents) = contents {
+        fs::write(&path, contents).unwrap();
+    }
+    path
+}
+
+/// Counts the requests of a mock route.
+#[derive(Clone, Default)]
+pub struct Hits(Arc<AtomicUsize>);
+
+impl Hits {
+    pub fn hit(&self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// The fixtures of one fava version, as listed in the manifest.
+#[derive(Debug, Deserialize)]
+pub struct FixtureSet {
+    pub fava_version: String,
+    dir: String,
+    /// The query whose result `query_result` holds.
+    pub query: String,
+    /// The account whose journal `account` holds.
+    pub account: String,
+    files: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct Manifest {
+    fixtures: Vec<FixtureSet>,
+}
+
+fn fixtures_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+fn snapshots_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots")
+}
+
+impl FixtureSet {
+    /// Every set of the manifest.
+    pub fn all() -> Vec<FixtureSet> {
+        let manifest = fs::read_to_string(fixtures_dir().join("manifest.toml")).unwrap();
+        let manifest: Manifest = toml::from_str(&manifest).unwrap();
+        assert!(
+            !manifest.fixtures.is_empty(),
+            "the manifest lists no fixtures"
+        );
+        manifest.fixtures
+    }
+
+    /// The fixture of `kind`: `query_result`, `account`, `changed` or
+    /// `errors`.
+    pub fn file(&self, kind: &str) -> String {
+        let name = &self.files[kind];
+        fs::read_to_string(fixtures_dir().join(&self.dir).join(name)).unwrap()
+    }
+
+    /// Serves the fixtures where fava serves what they were captured from,
+    /// and returns its url.
+    pub async fn fava(&self) -> String {
+        let respond = |kind: &str, content_type: &'static str| {
+            let body = self.file(kind);
+            route_get(move || async move { ([(CONTENT_TYPE, content_type)], body) })
+        };
+        fava(
+            Router::new()
+                .route(
+                    "/api/query_result",
+                    respond("query_result", "application/json"),
+                )
+                .route("/api/changed", respond("changed", "application/json"))
+                .route("/account/*account", respond("account", "text/html"))
+                .route("/errors/", respond("errors", "text/html")),
+        )
+        .await
+    }
+
+    /// Whether `body` is the snapshot `name` of this version, see
+    /// [`matches_snapshot`].
+    pub fn matches_snapshot(&self, name: &str, body: &[u8]) -> bool {
+        matches_snapshot(&format!("{}/{}", self.dir, name), body)
+    }
+}
+
+/// Whether `body` is the snapshot at `path` below `tests/snapshots`,
+/// rewriting the snapshot instead with `UPDATE_SNAPSHOTS` set.
+pub fn matches_snapshot(path: &str, body: &[u8]) -> bool {
+    let path = snapshots_dir().join(path);
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, body).unwrap();
+        return true;
+    }
+    fs::read(&path).is_ok_and(|snapshot| snapshot == body)
+}
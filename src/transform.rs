@@ -0,0 +1,297 @@
+use evalexpr::{build_operator_tree, ContextWithMutableVariables, HashMapContext, Node, Value};
+use rust_decimal::prelude::ToPrimitive;
+
+use crate::{
+    amount::{self, Amount},
+    config::TransformConfig,
+    Row,
+};
+
+/// Applies a configured transform to parsed rows: columns are renamed first,
+/// then computed columns are added, then rows failing the filter are dropped.
+///
+/// Expressions are evaluated by `evalexpr`, which has no I/O and no loops.
+/// Columns are exposed as variables, with characters that are not valid in
+/// an identifier replaced by `_`; numeric cells (including amounts such as
+/// `12.00 CNY`) are numbers, everything else is a string.
+pub fn apply(name: &str, transform: &TransformConfig, rows: Vec<Row>) -> Result<Vec<Row>, String> {
+    let compile = |expression: &str| {
+        build_operator_tree(expression).map_err(|e| {
+            format!(
+                "transform {}: invalid expression '{}': {}",
+                name, expression, e
+            )
+        })
+    };
+    let columns = transform
+        .columns
+        .iter()
+        .map(|(column, expression)| Ok((column, expression, compile(expression)?)))
+        .collect::<Result<Vec<(&String, &String, Node)>, String>>()?;
+    let filter = match &transform.filter {
+        Some(expression) => Some((expression, compile(expression)?)),
+        None => None,
+    };
+
+    let mut result = Vec::new();
+    for row in rows {
+        let mut row: Row = row
+            .into_iter()
+            .map(|(key, value)| match transform.rename.get(&key) {
+                Some(renamed) => (renamed.clone(), value),
+                None => (key, value),
+            })
+            .collect();
+        let mut context = HashMapContext::new();
+        for (key, value) in &row {
+            set_variable(&mut context, key, cell_value(value));
+        }
+        for (column, expression, node) in &columns {
+            let value = node
+                .eval_with_context(&context)
+                .map_err(|e| format!("transform {}: '{}' failed: {}", name, expression, e))?;
+            row.insert(column.to_string(), cell_text(&value));
+            set_variable(&mut context, column, value);
+        }
+        if let Some((expression, node)) = &filter {
+            let keep = node.eval_boolean_with_context(&context).map_err(|e| {
+                format!("transform {}: filter '{}' failed: {}", name, expression, e)
+            })?;
+            if !keep {
+                continue;
+            }
+        }
+        result.push(row);
+    }
+    Ok(result)
+}
+
+/// The `columns` of a result as `apply` renames them, each in its place.
+pub fn rename_columns(transform: &TransformConfig, columns: Vec<String>) -> Vec<String> {
+    columns
+        .into_iter()
+        .map(|column| transform.rename.get(&column).cloned().unwrap_or(column))
+        .collect()
+}
+
+/// Flips the sign of the comma separated `columns` of every row, such as
+/// `invert_columns=changed,balance`. Numbers, amounts and inventories keep
+/// their scale; cells that hold none of them are left as they are.
+pub fn invert(rows: Vec<Row>, columns: &str) -> Result<Vec<Row>, String> {
+    let columns: Vec<&str> = columns
+        .split(',')
+        .map(str::trim)
+        .filter(|column| !column.is_empty())
+        .collect();
+    if let Some(unknown) = columns
+        .iter()
+        .find(|column| !rows.is_empty() && !rows.iter().any(|row| row.contains_key(**column)))
+    {
+        return Err(format!("unknown invert_columns column {}", unknown));
+    }
+    Ok(rows
+        .into_iter()
+        .map(|mut row| {
+            for column in &columns {
+                if let Some(text) = row.get_mut(*column) {
+                    if let Some(inverted) = inverted(text) {
+                        *text = inverted;
+                    }
+                }
+            }
+            row
+        })
+        .collect())
+}
+
+fn inverted(text: &str) -> Option<String> {
+    let inventory = amount::parse_inventory(text);
+    if !inventory.is_empty() {
+        let positions: Vec<String> = inventory
+            .into_iter()
+            .map(|(number, currency)| format!("{} {}", amount::format_number(-number), currency))
+            .collect();
+        return Some(positions.join(", "));
+    }
+    match Amount::parse_number(text)? {
+        (number, None) => Some(amount::format_number(-number)),
+        (_, Some(_)) => None,
+    }
+}
+
+fn set_variable(context: &mut HashMapContext, column: &str, value: Value) {
+    let name: String = column
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect();
+    // Only fails when a variable would change its type, which a fresh
+    // context per row never asks for.
+    let _ = context.set_value(name, value);
+}
+
+fn cell_value(text: &str) -> Value {
+    let number = Amount::parse(text)
+        .and_then(|amount| amount.number.to_f64())
+        .or_else(|| text.trim().parse::<f64>().ok());
+    match number {
+        Some(number) => Value::Float(number),
+        None => Value::String(text.to_string()),
+    }
+}
+
+fn cell_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Empty => String::new(),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::get, Router};
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::{backend::Backend, config::Config, testing, AppState};
+
+    fn rows() -> Vec<Row> {
+        [
+            ("Food", "120.00 CNY", "400"),
+            ("Rent", "3000.00 CNY", "3000"),
+        ]
+        .iter()
+        .map(|(account, spent, budget)| {
+            Row::from([
+                ("account".to_string(), account.to_string()),
+                ("sum(position)".to_string(), spent.to_string()),
+                ("budget".to_string(), budget.to_string()),
+            ])
+        })
+        .collect()
+    }
+
+    fn usage() -> TransformConfig {
+        TransformConfig {
+            rename: BTreeMap::from([("account".to_string(), "name".to_string())]),
+            columns: BTreeMap::from([("ratio".to_string(), "sum_position_ / budget".to_string())]),
+            filter: Some("ratio < 1".to_string()),
+        }
+    }
+
+    #[test]
+    fn computes_a_ratio_column_and_filters_by_it() {
+        let rows = apply("usage", &usage(), rows()).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["name"], "Food");
+        assert_eq!(rows[0]["ratio"], "0.3");
+        assert!(!rows[0].contains_key("account"));
+    }
+
+    #[test]
+    fn names_the_expression_that_does_not_compile_or_run() {
+        let broken = TransformConfig {
+            columns: BTreeMap::from([("ratio".to_string(), "budget / (".to_string())]),
+            ..TransformConfig::default()
+        };
+        let error = apply("broken", &broken, rows()).unwrap_err();
+        assert!(
+            error.starts_with("transform broken: invalid expression 'budget / ('"),
+            "{}",
+            error
+        );
+        let failing = TransformConfig {
+            filter: Some("name > 1".to_string()),
+            ..usage()
+        };
+        let error = apply("failing", &failing, rows()).unwrap_err();
+        assert!(
+            error.starts_with("transform failing: filter 'name > 1' failed"),
+            "{}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn transforms_every_format_alike() {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|| async {
+                testing::table(
+                    &["account", "sum(position)", "budget"],
+                    &[
+                        &["Food", "120.00 CNY", "400"],
+                        &["Rent", "3000.00 CNY", "3000"],
+                    ],
+                )
+            }),
+        );
+        let mut config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        config.transforms.insert("usage".to_string(), usage());
+        let state = AppState::new(config);
+        let uri = "/api/query_result?query_string=SELECT%201&transform=usage&format=";
+        let (_, json) = testing::get(&state, &format!("{}json", uri)).await;
+        let (_, csv) = testing::get(&state, &format!("{}csv", uri)).await;
+        let (_, ndjson) = testing::get(&state, &format!("{}ndjson", uri)).await;
+        assert_eq!(
+            json,
+            r#"{"success":true,"data":[{"name":"Food","sum(position)":"120.00 CNY","budget":"400","ratio":"0.3"}]}"#
+        );
+        assert_eq!(
+            csv,
+            "name,sum(position),budget,ratio\r\nFood,120.00 CNY,400,0.3\r\n"
+        );
+        assert_eq!(
+            ndjson,
+            "{\"name\":\"Food\",\"sum(position)\":\"120.00 CNY\",\"budget\":\"400\",\"ratio\":\"0.3\"}\n"
+        );
+    }
+
+    #[test]
+    fn flips_the_sign_of_the_named_columns() {
+        let row = Row::from([
+            ("account".to_string(), "Income:Salary".to_string()),
+            (
+                "sum(position)".to_string(),
+                "-120.50 CNY, 3 USD".to_string(),
+            ),
+            ("budget".to_string(), "400".to_string()),
+        ]);
+        let rows = invert(vec![row.clone()], "sum(position), budget,account").unwrap();
+        assert_eq!(rows[0]["sum(position)"], "120.50 CNY, -3 USD");
+        assert_eq!(rows[0]["budget"], "-400");
+        assert_eq!(rows[0]["account"], "Income:Salary");
+        assert_eq!(
+            invert(vec![row], "balance").unwrap_err(),
+            "unknown invert_columns column balance"
+        );
+        assert!(invert(Vec::new(), "balance").unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn inverts_the_columns_of_a_query() {
+        let fava = Router::new().route(
+            "/api/query_result",
+            get(|| async {
+                testing::table(
+                    &["account", "balance"],
+                    &[&["Income:Salary", "-3000.00 CNY"]],
+                )
+            }),
+        );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Html)
+            .refresh_path("none");
+        let state = AppState::new(config);
+        let uri = "/api/query_result?query_string=SELECT%201&invert_columns=balance";
+        let (_, json) = testing::get(&state, uri).await;
+        assert_eq!(
+            json,
+            r#"{"success":true,"data":[{"account":"Income:Salary","balance":"3000.00 CNY"}]}"#
+        );
+        let (status, _) = testing::get(&state, &format!("{}%2Cchanged", uri)).await;
+        assert_eq!(status, axum::http::StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,125 @@
+use rust_decimal::Decimal;
+use serde::{ser::Error as _, ser::SerializeMap, Serialize, Serializer};
+use serde_json::value::RawValue;
+use std::str::FromStr;
+
+use crate::amount;
+
+/// A cell with `typed=true`: numbers become JSON numbers, amounts such as
+/// `12.00 CNY` `{number, currency}` objects, inventories arrays of them
+/// and empty cells `null`. Dates stay strings, which fava already renders
+/// as `YYYY-MM-DD`, as does any other text.
+#[derive(Debug, PartialEq)]
+pub enum Cell {
+    Null,
+    Text(String),
+    Number(Decimal),
+    Amount(Decimal, String),
+    Inventory(Vec<(Decimal, String)>),
+}
+
+/// Reads `text` as a [`Cell`].
+pub fn cell(text: &str) -> Cell {
+    let text = text.trim();
+    if text.is_empty() {
+        return Cell::Null;
+    }
+    if let Some(number) = number(text) {
+        return Cell::Number(number);
+    }
+    // Only cells that are nothing but amounts, so that a position with a
+    // cost or a price keeps its text.
+    let tokens = text.split_whitespace().count();
+    let mut positions = amount::parse_inventory(text);
+    if positions.is_empty() || tokens != positions.len() * 2 {
+        return Cell::Text(text.to_string());
+    }
+    match positions.len() {
+        1 => {
+            let (number, currency) = positions.remove(0);
+            Cell::Amount(number, currency)
+        }
+        _ => Cell::Inventory(positions),
+    }
+}
+
+/// A bare number, without thousands separators, which would be ambiguous
+/// next to the commas between the positions of an inventory.
+fn number(text: &str) -> Option<Decimal> {
+    if !text.starts_with(|c: char| c.is_ascii_digit() || c == '-') {
+        return None;
+    }
+    Decimal::from_str(text).ok()
+}
+
+impl Serialize for Cell {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Cell::Null => serializer.serialize_none(),
+            Cell::Text(text) => serializer.serialize_str(text),
+            Cell::Number(number) => Number(*number).serialize(serializer),
+            Cell::Amount(number, currency) => Position(*number, currency).serialize(serializer),
+            Cell::Inventory(positions) => serializer.collect_seq(
+                positions
+                    .iter()
+                    .map(|(number, currency)| Position(*number, currency)),
+            ),
+        }
+    }
+}
+
+/// A number written with every digit of its scale, `1000.10` as it is
+/// rather than as the nearest `f64`.
+struct Number(Decimal);
+
+impl Serialize for Number {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        RawValue::from_string(amount::format_number(self.0))
+            .map_err(S::Error::custom)?
+            .serialize(serializer)
+    }
+}
+
+struct Position<'a>(Decimal, &'a str);
+
+impl Serialize for Position<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("number", &Number(self.0))?;
+        map.serialize_entry("currency", self.1)?;
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json(text: &str) -> String {
+        serde_json::to_string(&cell(text)).unwrap()
+    }
+
+    #[test]
+    fn writes_numbers_with_every_digit() {
+        assert_eq!(json("12345678901234567.89"), "12345678901234567.89");
+        assert_eq!(json("0.10"), "0.10");
+        assert_eq!(json("-0.00"), "0.00");
+        assert_eq!(json("42"), "42");
+        assert_eq!(
+            json("9007199254740993.1 CNY"),
+            r#"{"number":9007199254740993.1,"currency":"CNY"}"#
+        );
+        assert_eq!(
+            json("1000.00 CNY, 3 USD"),
+            r#"[{"number":1000.00,"currency":"CNY"},{"number":3,"currency":"USD"}]"#
+        );
+    }
+
+    #[test]
+    fn keeps_other_cells_as_text() {
+        assert_eq!(json(" "), "null");
+        assert_eq!(json("2024-01-02"), r#""2024-01-02""#);
+        assert_eq!(json("1,000.00"), r#""1,000.00""#);
+        assert_eq!(json("10 VT {100.00 USD}"), r#""10 VT {100.00 USD}""#);
+    }
+}
@@ -0,0 +1,174 @@
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    i18n::Lang,
+    params::{QueryFields, StrictQuery},
+    AppState, ErrorResult,
+};
+
+/// `GET /api/query_validate`: has fava compile a query and answers with
+/// its error or its columns. A `SELECT` runs with `LIMIT 0`, replacing
+/// any limit of its own, so fava returns no rows; other statements such as
+/// `BALANCES` run as they are and their rows are dropped.
+///
+/// Column types come from fava's JSON API and are `null` with the HTML
+/// tables of older versions.
+pub async fn query_validate(
+    State(state): State<AppState>,
+    StrictQuery(params): StrictQuery<ValidateParams>,
+    lang: Lang,
+) -> Result<ValidateResult, ErrorResult> {
+    let query = without_rows(&params.query_string);
+    let result = state
+        .session()
+        .query(&query)
+        .await
+        .map_err(|e| ErrorResult::from(e).localize(lang))?;
+    let data = match result.success {
+        true => Validation {
+            valid: true,
+            error: None,
+            columns: result
+                .data
+                .map(|data| data.schema())
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(name, column_type)| Column { name, column_type })
+                .collect(),
+        },
+        false => Validation {
+            valid: false,
+            error: result.error,
+            columns: Vec::new(),
+        },
+    };
+    Ok(ValidateResult {
+        success: true,
+        data,
+    })
+}
+
+/// `query` as a `SELECT` with `LIMIT 0`, or as is if it is another
+/// statement.
+fn without_rows(query: &str) -> String {
+    let query = query.trim().trim_end_matches(';').trim_end();
+    let is_select = query
+        .split_whitespace()
+        .next()
+        .is_some_and(|keyword| keyword.eq_ignore_ascii_case("select"));
+    if !is_select {
+        return query.to_string();
+    }
+    let without_limit = query
+        .rsplit_once(char::is_whitespace)
+        .filter(|(_, count)| !count.is_empty() && count.chars().all(|c| c.is_ascii_digit()))
+        .and_then(|(rest, _)| rest.trim_end().rsplit_once(char::is_whitespace))
+        .filter(|(_, keyword)| keyword.eq_ignore_ascii_case("limit"))
+        .map(|(rest, _)| rest.trim_end());
+    format!("{} LIMIT 0", without_limit.unwrap_or(query))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ValidateParams {
+    query_string: String,
+}
+
+impl QueryFields for ValidateParams {
+    const FIELDS: &[&str] = &["query_string"];
+}
+
+#[derive(Debug, Serialize)]
+struct Column {
+    name: String,
+    #[serde(rename = "type")]
+    column_type: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Validation {
+    valid: bool,
+    /// fava's error message, for an invalid query.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    columns: Vec<Column>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ValidateResult {
+    success: bool,
+    data: Validation,
+}
+
+impl IntoResponse for ValidateResult {
+    fn into_response(self) -> Response {
+        let body = Json(self);
+        (StatusCode::OK, body).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::Query, routing::get, Router};
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{backend::Backend, config::Config, testing};
+
+    #[test]
+    fn asks_for_no_rows() {
+        for (query, asked) in [
+            ("SELECT account", "SELECT account LIMIT 0"),
+            ("select account limit 10;", "select account LIMIT 0"),
+            (
+                "SELECT account WHERE payee = 'limit 5'",
+                "SELECT account WHERE payee = 'limit 5' LIMIT 0",
+            ),
+            ("BALANCES", "BALANCES"),
+        ] {
+            assert_eq!(without_rows(query), asked);
+        }
+    }
+
+    #[tokio::test]
+    async fn answers_the_columns_or_the_error() {
+        let fava = Router::new().route(
+            "/api/query",
+            get(|Query(params): Query<HashMap<String, String>>| async move {
+                match params["query_string"].as_str() {
+                    "SELECT account, position LIMIT 0" => r#"{"success": true, "data": {"t": "table", "types": [{"name": "account", "dtype": "str"}, {"name": "position", "dtype": "Position"}], "rows": []}}"#,
+                    _ => r#"{"success": false, "error": "syntax error"}"#,
+                }
+            }),
+        );
+        let config = Config::new(testing::fava(fava).await)
+            .backend(Backend::Json)
+            .refresh_path("none");
+        let state = AppState::new(config);
+        let uri = "/api/query_validate?query_string=";
+        let (status, body) = testing::get(
+            &state,
+            &format!("{}SELECT%20account,%20position%20LIMIT%203", uri),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["data"],
+            json!({"valid": true, "columns": [{"name": "account", "type": "str"}, {"name": "position", "type": "Position"}]})
+        );
+        let (status, body) = testing::get(&state, &format!("{}SELECT%20FROM", uri)).await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["data"],
+            json!({"valid": false, "error": "syntax error", "columns": []})
+        );
+    }
+}
@@ -0,0 +1,283 @@
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Response},
+    Json,
+};
+use reqwest::StatusCode;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::RwLock,
+    time::Duration,
+};
+
+use crate::{
+    apply_transform,
+    config::{ComponentConfig, ViewComponentConfig, ViewConfig},
+    get_account_data, schedule, AccountParams, AppState, ErrorResult, Row,
+};
+
+/// Recompute interval for views without a `ttl`, as a backstop for ledger
+/// changes that fava's changed API did not report.
+const DEFAULT_TTL: Duration = Duration::from_secs(300);
+
+/// The last computed document of every configured view.
+#[derive(Debug, Default)]
+pub struct ViewStore {
+    documents: RwLock<HashMap<String, BTreeMap<String, ViewComponent>>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ViewComponent {
+    data: Option<Vec<Row>>,
+    /// Set when the last recompute failed and `data` is an older result.
+    stale: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Starts one background task per configured view that keeps its document
+/// up to date.
+pub fn spawn(state: &AppState) {
+    for (name, view) in state.config.views.clone() {
+        let ttl = view.ttl.map(Duration::from_secs).unwrap_or(DEFAULT_TTL);
+        let task_state = state.clone();
+        schedule::on_change(state.clone(), ttl, move || {
+            let state = task_state.clone();
+            let name = name.clone();
+            let view = view.clone();
+            async move { compute(&state, &name, &view).await }
+        });
+    }
+}
+
+async fn compute(state: &AppState, name: &str, view: &ViewConfig) {
+    let mut document = BTreeMap::new();
+    for (key, component) in &view.components {
+        let component = match compute_component(state, component).await {
+            Ok(rows) => ViewComponent {
+                data: Some(rows),
+                stale: false,
+                error: None,
+            },
+            Err(error) => {
+                println!("view {} component {} failed: {}", name, key, error);
+                let previous =
+                    state
+                        .views
+                        .documents
+                        .read()
+                        .unwrap()
+                        .get(name)
+                        .and_then(|document| {
+                            document
+                                .get(key)
+                                .and_then(|component| component.data.clone())
+                        });
+                ViewComponent {
+                    data: previous,
+                    stale: true,
+                    error: Some(error),
+                }
+            }
+        };
+        document.insert(key.clone(), component);
+    }
+    state
+        .views
+        .documents
+        .write()
+        .unwrap()
+        .insert(name.to_string(), document);
+}
+
+async fn compute_component(
+    state: &AppState,
+    component: &ViewComponentConfig,
+) -> Result<Vec<Row>, String> {
+    let rows = compute_rows(state, &component.source).await?;
+    apply_transform(state, component.transform.as_deref(), rows).map_err(|e| e.error)
+}
+
+async fn compute_rows(state: &AppState, component: &ComponentConfig) -> Result<Vec<Row>, String> {
+    match component {
+        ComponentConfig::Query { query } => state
+            .session()
+            .rows(query)
+            .await
+            .map(|parsed| parsed.rows)
+            .map_err(|e| e.error),
+        ComponentConfig::Account { account, negate } => {
+            let journal = state
+                .session()
+                .account_journal(account)
+                .await
+                .map_err(|e| e.error)?;
+            let params = AccountParams {
+                negate: Some(*negate),
+                ..Default::default()
+            };
+            Ok(get_account_data(&journal.entries, &params).rows)
+        }
+    }
+}
+
+pub async fn view(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<ViewResult, ErrorResult> {
+    if !state.config.views.contains_key(&name) {
+        return Err(ErrorResult {
+            error_code: Some("unknown_view".into()),
+            status: StatusCode::NOT_FOUND,
+            ..ErrorResult::new(format!("unknown view: {}", name))
+        });
+    }
+    match state.views.documents.read().unwrap().get(&name) {
+        Some(document) => Ok(ViewResult {
+            success: true,
+            data: document.clone(),
+        }),
+        None => Err(ErrorResult {
+            error_code: Some("view_not_ready".into()),
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            ..ErrorResult::new(format!("view {} is not ready yet", name))
+        }),
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ViewResult {
+    success: bool,
+    data: BTreeMap<String, ViewComponent>,
+}
+
+impl IntoResponse for ViewResult {
+    fn into_response(self) -> Response {
+        let body = Json(self);
+        (StatusCode::OK, body).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::State as Extract, routing::get, Router};
+    use serde_json::{json, Value};
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    use super::*;
+    use crate::{backend::Backend, config::Config, testing, testing::Hits};
+
+    /// A fava answering each query with the number of queries so far, and
+    /// failing them while `failing` is set.
+    async fn counting(hits: &Hits, failing: &Arc<AtomicBool>) -> String {
+        let fava = Router::new()
+            .route(
+                "/api/query_result",
+                get(
+                    |Extract((hits, failing)): Extract<(Hits, Arc<AtomicBool>)>| async move {
+                        if failing.load(Ordering::SeqCst) {
+                            return (StatusCode::INTERNAL_SERVER_ERROR, "down".to_string());
+                        }
+                        hits.hit();
+                        let count = hits.count().to_string();
+                        (StatusCode::OK, testing::table(&["n"], &[&[&count]]))
+                    },
+                ),
+            )
+            .route(
+                "/account/*account",
+                get(|| async { include_str!("../tests/fixtures/1.27/account.html") }),
+            )
+            .with_state((hits.clone(), failing.clone()));
+        testing::fava(fava).await
+    }
+
+    fn state(url: &str, ttl: Option<u64>) -> AppState {
+        let mut config = Config::new(url).backend(Backend::Html).refresh_path("none");
+        let view: ViewConfig = serde_json::from_value(json!({
+            "ttl": ttl,
+            "components": {
+                "count": { "query": "SELECT n" },
+                "bank": { "account": "Assets:Bank", "negate": true },
+            },
+        }))
+        .unwrap();
+        config.views.insert("wall".to_string(), view);
+        AppState::new(config)
+    }
+
+    async fn document(state: &AppState) -> (StatusCode, Value) {
+        let (status, body) = testing::get(state, "/api/view/wall").await;
+        (status, serde_json::from_str(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn merges_the_components_under_their_keys() {
+        let hits = Hits::default();
+        let state = state(&counting(&hits, &Default::default()).await, None);
+        assert_eq!(document(&state).await.0, StatusCode::SERVICE_UNAVAILABLE);
+        compute(&state, "wall", &state.config.views["wall"]).await;
+        let (status, body) = document(&state).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body["data"],
+            json!({
+                "bank": {
+                    "data": [
+                        { "date": "2024-01-02", "changed": "-1000.00 CNY", "balance": "-1000.00 CNY" },
+                        { "date": "2024-01-03", "changed": "300.00 CNY", "balance": "-700.00 CNY" },
+                    ],
+                    "stale": false,
+                },
+                "count": { "data": [{ "n": "1" }], "stale": false },
+            })
+        );
+        let (status, _) = testing::get(&state, "/api/view/other").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn keeps_the_last_good_component_when_fava_fails() {
+        let hits = Hits::default();
+        let failing = Arc::new(AtomicBool::new(false));
+        let state = state(&counting(&hits, &failing).await, None);
+        let view = &state.config.views["wall"];
+        compute(&state, "wall", view).await;
+        failing.store(true, Ordering::SeqCst);
+        compute(&state, "wall", view).await;
+        let (status, body) = document(&state).await;
+        assert_eq!(status, StatusCode::OK);
+        let count = &body["data"]["count"];
+        assert_eq!(count["data"], json!([{ "n": "1" }]));
+        assert_eq!(count["stale"], true);
+        assert!(count["error"].is_string());
+        // The account pages still answer.
+        assert_eq!(body["data"]["bank"]["stale"], false);
+    }
+
+    #[tokio::test]
+    async fn recomputes_in_the_background_once_the_ttl_passed() {
+        let hits = Hits::default();
+        let state = state(&counting(&hits, &Default::default()).await, Some(0));
+        spawn(&state);
+        let mut counts = Vec::new();
+        for _ in 0..60 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            if let (StatusCode::OK, body) = document(&state).await {
+                let count = body["data"]["count"]["data"][0]["n"].clone();
+                if counts.last() != Some(&count) {
+                    counts.push(count);
+                }
+            }
+            if counts.len() == 2 {
+                break;
+            }
+        }
+        state.stop();
+        assert_eq!(counts, [json!("1"), json!("2")]);
+    }
+}
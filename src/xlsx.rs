@@ -0,0 +1,301 @@
+use rust_decimal::Decimal;
+use std::str::FromStr;
+
+use crate::{amount::Amount, config::LocaleConfig, interval, zip::ZipWriter, Row};
+
+/// Days from Excel's day zero, 1899-12-30, to 1970-01-01.
+const EXCEL_EPOCH: i64 = 25569;
+
+/// The header's bold style among the cell formats of [`styles`].
+const HEADER_STYLE: usize = 1;
+
+/// The number format of a cell: a date, or a number with its scale and
+/// the commodity shown after it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Format {
+    Date,
+    Number {
+        scale: u32,
+        currency: Option<String>,
+    },
+}
+
+impl Format {
+    /// The number format code, dates in the `date` one.
+    fn code(&self, date: &str) -> String {
+        match self {
+            Format::Date => date.into(),
+            Format::Number { scale, currency } => {
+                // Bare numbers may be years or ids, which read badly with
+                // thousands separators.
+                let mut code = match currency {
+                    Some(_) => "#,##0".to_string(),
+                    None => "0".to_string(),
+                };
+                if *scale > 0 {
+                    code.push('.');
+                    code.push_str(&"0".repeat(*scale as usize));
+                }
+                if let Some(currency) = currency {
+                    code.push_str(&format!(" \"{}\"", currency));
+                }
+                code
+            }
+        }
+    }
+}
+
+enum Cell<'a> {
+    Text(&'a str),
+    Number(String, Format),
+}
+
+/// Reads dates as `YYYY-MM-DD`, and numbers and single amounts such as
+/// `12.00 CNY`, which keep their commodity in the cell's number format.
+/// Anything else, inventories of several commodities included, is text.
+fn cell(text: &str) -> Cell<'_> {
+    let trimmed = text.trim();
+    let is_date = trimmed.len() == 10
+        && trimmed.char_indices().all(|(i, c)| {
+            matches!((i, c), (4 | 7, '-')) || (i != 4 && i != 7 && c.is_ascii_digit())
+        });
+    if let Some(day) = interval::parse_date(trimmed).filter(|_| is_date) {
+        return Cell::Number((day + EXCEL_EPOCH).to_string(), Format::Date);
+    }
+    let number = match trimmed.split_whitespace().count() {
+        1 => Decimal::from_str(&trimmed.replace(',', ""))
+            .ok()
+            .map(|number| (number, None)),
+        2 => Amount::parse(trimmed).map(|amount| (amount.number, Some(amount.currency))),
+        _ => None,
+    };
+    match number {
+        Some((number, currency)) => Cell::Number(
+            number.to_string(),
+            Format::Number {
+                scale: number.scale(),
+                currency,
+            },
+        ),
+        None => Cell::Text(text),
+    }
+}
+
+/// An Excel workbook of one sheet with a header row of `columns`, frozen
+/// so that it stays in view. Dates show in the pattern of `locale`, numbers
+/// with the separators of the reader's Excel, as workbooks can not choose
+/// them.
+pub fn write(columns: &[&str], rows: &[Row], locale: Option<&LocaleConfig>) -> Vec<u8> {
+    let mut formats: Vec<Format> = Vec::new();
+    let mut sheet = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+         <worksheet xmlns=\"http://schemas.openxmlformats.org/spreadsheetml/2006/main\">\
+         <sheetViews><sheetView workbookViewId=\"0\">\
+         <pane ySplit=\"1\" topLeftCell=\"A2\" activePane=\"bottomLeft\" state=\"frozen\"/>\
+         </sheetView></sheetViews><sheetData>",
+    );
+    sheet.push_str("<row r=\"1\">");
+    for (i, column) in columns.iter().enumerate() {
+        sheet.push_str(&text_cell(&reference(i, 1), column, Some(HEADER_STYLE)));
+    }
+    sheet.push_str("</row>");
+    for (number, row) in rows.iter().enumerate() {
+        let line = number + 2;
+        sheet.push_str(&format!("<row r=\"{}\">", line));
+        for (i, column) in columns.iter().enumerate() {
+            let text = row.get(*column).map_or("", String::as_str);
+            let reference = reference(i, line);
+            match cell(text) {
+                Cell::Text("") => {}
+                Cell::Text(text) => sheet.push_str(&text_cell(&reference, text, None)),
+                Cell::Number(value, format) => {
+                    let index = match formats.iter().position(|known| *known == format) {
+                        Some(index) => index,
+                        None => {
+                            formats.push(format);
+                            formats.len() - 1
+                        }
+                    };
+                    sheet.push_str(&format!(
+                        "<c r=\"{}\" s=\"{}\"><v>{}</v></c>",
+                        reference,
+                        HEADER_STYLE + 1 + index,
+                        value
+                    ));
+                }
+            }
+        }
+        sheet.push_str("</row>");
+    }
+    sheet.push_str("</sheetData></worksheet>");
+
+    let mut zip = ZipWriter::default();
+    let mut output = Vec::new();
+    for (name, data) in [
+        ("[Content_Types].xml", CONTENT_TYPES.to_string()),
+        ("_rels/.rels", ROOT_RELS.to_string()),
+        ("xl/workbook.xml", WORKBOOK.to_string()),
+        ("xl/_rels/workbook.xml.rels", WORKBOOK_RELS.to_string()),
+        ("xl/styles.xml", styles(&formats, locale)),
+        ("xl/worksheets/sheet1.xml", sheet),
+    ] {
+        output.extend(zip.entry(name, data.as_bytes()));
+    }
+    output.extend(zip.finish());
+    output
+}
+
+/// The default style, the header style and one style per number format,
+/// in the order of `formats`.
+fn styles(formats: &[Format], locale: Option<&LocaleConfig>) -> String {
+    let date = locale.map_or_else(|| "yyyy-mm-dd".to_string(), LocaleConfig::excel_date);
+    let mut number_formats = String::new();
+    let mut cell_formats = String::from(
+        "<xf numFmtId=\"0\" fontId=\"0\" fillId=\"0\" borderId=\"0\" xfId=\"0\"/>\
+         <xf numFmtId=\"0\" fontId=\"1\" fillId=\"0\" borderId=\"0\" xfId=\"0\" applyFont=\"1\"/>",
+    );
+    for (i, format) in formats.iter().enumerate() {
+        // Ids below 164 are Excel's builtin formats.
+        let id = 164 + i;
+        number_formats.push_str(&format!(
+            "<numFmt numFmtId=\"{}\" formatCode=\"{}\"/>",
+            id,
+            escape(&format.code(&date))
+        ));
+        cell_formats.push_str(&format!(
+            "<xf numFmtId=\"{}\" fontId=\"0\" fillId=\"0\" borderId=\"0\" xfId=\"0\" applyNumberFormat=\"1\"/>",
+            id
+        ));
+    }
+    if !formats.is_empty() {
+        number_formats = format!(
+            "<numFmts count=\"{}\">{}</numFmts>",
+            formats.len(),
+            number_formats
+        );
+    }
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+         <styleSheet xmlns=\"http://schemas.openxmlformats.org/spreadsheetml/2006/main\">\
+         {}\
+         <fonts count=\"2\"><font><sz val=\"11\"/><name val=\"Calibri\"/></font>\
+         <font><b/><sz val=\"11\"/><name val=\"Calibri\"/></font></fonts>\
+         <fills count=\"2\"><fill><patternFill patternType=\"none\"/></fill>\
+         <fill><patternFill patternType=\"gray125\"/></fill></fills>\
+         <borders count=\"1\"><border><left/><right/><top/><bottom/><diagonal/></border></borders>\
+         <cellStyleXfs count=\"1\"><xf numFmtId=\"0\" fontId=\"0\" fillId=\"0\" borderId=\"0\"/></cellStyleXfs>\
+         <cellXfs count=\"{}\">{}</cellXfs>\
+         </styleSheet>",
+        number_formats,
+        formats.len() + 2,
+        cell_formats
+    )
+}
+
+fn text_cell(reference: &str, text: &str, style: Option<usize>) -> String {
+    let style = style
+        .map(|style| format!(" s=\"{}\"", style))
+        .unwrap_or_default();
+    format!(
+        "<c r=\"{}\"{} t=\"inlineStr\"><is><t xml:space=\"preserve\">{}</t></is></c>",
+        reference,
+        style,
+        escape(text)
+    )
+}
+
+/// A cell reference such as `A1` or `AB12`, from a column index from 0.
+fn reference(column: usize, row: usize) -> String {
+    let mut letters = Vec::new();
+    let mut column = column + 1;
+    while column > 0 {
+        column -= 1;
+        letters.push(char::from(b'A' + (column % 26) as u8));
+        column /= 26;
+    }
+    letters.iter().rev().collect::<String>() + &row.to_string()
+}
+
+/// Escapes text for XML, dropping the control characters XML can not hold.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+const CONTENT_TYPES: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+<Types xmlns=\"http://schemas.openxmlformats.org/package/2006/content-types\">\
+<Default Extension=\"rels\" ContentType=\"application/vnd.openxmlformats-package.relationships+xml\"/>\
+<Default Extension=\"xml\" ContentType=\"application/xml\"/>\
+<Override PartName=\"/xl/workbook.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml\"/>\
+<Override PartName=\"/xl/worksheets/sheet1.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml\"/>\
+<Override PartName=\"/xl/styles.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.spreadsheetml.styles+xml\"/>\
+</Types>";
+
+const ROOT_RELS: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+<Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\">\
+<Relationship Id=\"rId1\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument\" Target=\"xl/workbook.xml\"/>\
+</Relationships>";
+
+const WORKBOOK: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+<workbook xmlns=\"http://schemas.openxmlformats.org/spreadsheetml/2006/main\" \
+xmlns:r=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships\">\
+<sheets><sheet name=\"Query\" sheetId=\"1\" r:id=\"rId1\"/></sheets></workbook>";
+
+const WORKBOOK_RELS: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+<Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\">\
+<Relationship Id=\"rId1\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet\" Target=\"worksheets/sheet1.xml\"/>\
+<Relationship Id=\"rId2\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles\" Target=\"styles.xml\"/>\
+</Relationships>";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zip;
+
+    #[test]
+    fn types_the_cells_under_a_frozen_header() {
+        let row = Row::from([
+            ("date".to_string(), "2024-01-02".to_string()),
+            ("payee".to_string(), "Shop & Co".to_string()),
+            ("position".to_string(), "-1,234.50 CNY".to_string()),
+            ("year".to_string(), "2024".to_string()),
+        ]);
+        let columns = ["date", "payee", "position", "year", "missing"];
+        let workbook = write(&columns, &[row], None);
+        let entries = zip::read(&workbook, 0);
+        let file = |name: &str| {
+            let (_, data) = entries.iter().find(|(entry, _)| entry == name).unwrap();
+            String::from_utf8(data.clone()).unwrap()
+        };
+        let sheet = file("xl/worksheets/sheet1.xml");
+        assert!(sheet.contains("state=\"frozen\""));
+        assert!(sheet.contains(
+            "<c r=\"A1\" s=\"1\" t=\"inlineStr\"><is><t xml:space=\"preserve\">date</t></is></c>"
+        ));
+        assert!(
+            sheet.contains(
+                "<row r=\"2\"><c r=\"A2\" s=\"2\"><v>45293</v></c>\
+             <c r=\"B2\" t=\"inlineStr\"><is><t xml:space=\"preserve\">Shop &amp; Co</t></is></c>\
+             <c r=\"C2\" s=\"3\"><v>-1234.50</v></c><c r=\"D2\" s=\"4\"><v>2024</v></c></row>"
+            ),
+            "{}",
+            sheet
+        );
+        let styles = file("xl/styles.xml");
+        assert!(styles.contains("formatCode=\"yyyy-mm-dd\""));
+        assert!(styles.contains("formatCode=\"#,##0.00 &quot;CNY&quot;\""));
+        assert!(styles.contains("formatCode=\"0\""));
+        assert_eq!(reference(27, 12), "AB12");
+    }
+}
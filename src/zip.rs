@@ -0,0 +1,277 @@
+/// Writes a zip archive entry by entry, so that a streamed archive only ever
+/// holds the entry being written. Entries are stored uncompressed.
+///
+/// Sizes and offsets past 4 GiB, and more than 65535 entries, are written
+/// as Zip64 records, which only the entries and the end of the archive that
+/// need them carry.
+#[derive(Debug, Default)]
+pub struct ZipWriter {
+    offset: u64,
+    central: Vec<u8>,
+    count: u64,
+}
+
+/// 1980-01-01 00:00, the earliest timestamp zip can express.
+const DOS_DATE: u16 = (1 << 5) | 1;
+
+/// What a field of a plain record holds when its Zip64 record has the value.
+const ZIP64_U32: u32 = u32::MAX;
+const ZIP64_U16: u16 = u16::MAX;
+
+/// The version extracting an entry needs, 4.5 for Zip64 records.
+const VERSION: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+
+impl ZipWriter {
+    /// Returns the bytes of a complete entry named `name` holding `data`.
+    pub fn entry(&mut self, name: &str, data: &[u8]) -> Vec<u8> {
+        let crc = crc32fast::hash(data);
+        let size = data.len() as u64;
+        let large = size >= u64::from(ZIP64_U32);
+        let far = self.offset >= u64::from(ZIP64_U32);
+        let version = match large || far {
+            true => VERSION_ZIP64,
+            false => VERSION,
+        };
+        let size32 = match large {
+            true => ZIP64_U32,
+            false => size as u32,
+        };
+
+        // The local extra field has both sizes, the central one just the
+        // fields it leaves out.
+        let mut local_extra = Vec::new();
+        if large {
+            zip64_extra(&mut local_extra, &[size, size]);
+        }
+        let mut central_extra = Vec::new();
+        let mut central_fields = Vec::new();
+        if large {
+            central_fields.extend([size, size]);
+        }
+        if far {
+            central_fields.push(self.offset);
+        }
+        if !central_fields.is_empty() {
+            zip64_extra(&mut central_extra, &central_fields);
+        }
+
+        // Version needed, flags (utf-8 names), method (stored), time, date,
+        // crc, both sizes and name length; the extra length follows.
+        let mut fields = Vec::new();
+        fields.extend_from_slice(&version.to_le_bytes());
+        fields.extend_from_slice(&(1u16 << 11).to_le_bytes());
+        fields.extend_from_slice(&0u16.to_le_bytes());
+        fields.extend_from_slice(&0u16.to_le_bytes());
+        fields.extend_from_slice(&DOS_DATE.to_le_bytes());
+        fields.extend_from_slice(&crc.to_le_bytes());
+        fields.extend_from_slice(&size32.to_le_bytes());
+        fields.extend_from_slice(&size32.to_le_bytes());
+        fields.extend_from_slice(&(name.len() as u16).to_le_bytes());
+
+        let mut local = Vec::with_capacity(30 + name.len() + local_extra.len() + data.len());
+        local.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        local.extend_from_slice(&fields);
+        local.extend_from_slice(&(local_extra.len() as u16).to_le_bytes());
+        local.extend_from_slice(name.as_bytes());
+        local.extend_from_slice(&local_extra);
+        local.extend_from_slice(data);
+
+        // Version made by, then the shared fields, extra and comment length,
+        // disk number, attributes and the offset of the local header.
+        let offset32 = match far {
+            true => ZIP64_U32,
+            false => self.offset as u32,
+        };
+        self.central.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        self.central.extend_from_slice(&version.to_le_bytes());
+        self.central.extend_from_slice(&fields);
+        self.central
+            .extend_from_slice(&(central_extra.len() as u16).to_le_bytes());
+        self.central.extend_from_slice(&0u16.to_le_bytes());
+        self.central.extend_from_slice(&0u16.to_le_bytes());
+        self.central.extend_from_slice(&0u16.to_le_bytes());
+        self.central.extend_from_slice(&0u32.to_le_bytes());
+        self.central.extend_from_slice(&offset32.to_le_bytes());
+        self.central.extend_from_slice(name.as_bytes());
+        self.central.extend_from_slice(&central_extra);
+
+        self.offset += local.len() as u64;
+        self.count += 1;
+        local
+    }
+
+    /// Returns the central directory that ends the archive, followed by the
+    /// Zip64 end records if its size, offset or entry count need them.
+    pub fn finish(self) -> Vec<u8> {
+        let mut end = self.central;
+        let size = end.len() as u64;
+        let zip64 = self.count >= u64::from(ZIP64_U16)
+            || size >= u64::from(ZIP64_U32)
+            || self.offset >= u64::from(ZIP64_U32);
+        if zip64 {
+            let record = self.offset + size;
+            // The Zip64 end record: its size after this field, versions, disk
+            // numbers, entry counts, then the size and offset of the central
+            // directory.
+            end.extend_from_slice(&0x06064b50u32.to_le_bytes());
+            end.extend_from_slice(&44u64.to_le_bytes());
+            end.extend_from_slice(&VERSION_ZIP64.to_le_bytes());
+            end.extend_from_slice(&VERSION_ZIP64.to_le_bytes());
+            end.extend_from_slice(&0u32.to_le_bytes());
+            end.extend_from_slice(&0u32.to_le_bytes());
+            end.extend_from_slice(&self.count.to_le_bytes());
+            end.extend_from_slice(&self.count.to_le_bytes());
+            end.extend_from_slice(&size.to_le_bytes());
+            end.extend_from_slice(&self.offset.to_le_bytes());
+            // Its locator: disk, offset and number of disks.
+            end.extend_from_slice(&0x07064b50u32.to_le_bytes());
+            end.extend_from_slice(&0u32.to_le_bytes());
+            end.extend_from_slice(&record.to_le_bytes());
+            end.extend_from_slice(&1u32.to_le_bytes());
+        }
+        let count = self.count.min(u64::from(ZIP64_U16)) as u16;
+        let size = size.min(u64::from(ZIP64_U32)) as u32;
+        let offset = self.offset.min(u64::from(ZIP64_U32)) as u32;
+        end.extend_from_slice(&0x06054b50u32.to_le_bytes());
+        end.extend_from_slice(&0u16.to_le_bytes());
+        end.extend_from_slice(&0u16.to_le_bytes());
+        end.extend_from_slice(&count.to_le_bytes());
+        end.extend_from_slice(&count.to_le_bytes());
+        end.extend_from_slice(&size.to_le_bytes());
+        end.extend_from_slice(&offset.to_le_bytes());
+        end.extend_from_slice(&0u16.to_le_bytes());
+        end
+    }
+}
+
+/// Appends the Zip64 extended information field holding `values`.
+fn zip64_extra(extra: &mut Vec<u8>, values: &[u64]) {
+    extra.extend_from_slice(&1u16.to_le_bytes());
+    extra.extend_from_slice(&(values.len() as u16 * 8).to_le_bytes());
+    for value in values {
+        extra.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// The names and data of the entries of an archive whose first byte is at
+/// offset `base`, read through its central directory, for the tests.
+#[cfg(test)]
+pub fn read(archive: &[u8], base: u64) -> Vec<(String, Vec<u8>)> {
+    let u16_at = |at: usize| u16::from_le_bytes(archive[at..at + 2].try_into().unwrap());
+    let u32_at = |at: usize| u32::from_le_bytes(archive[at..at + 4].try_into().unwrap());
+    let u64_at = |at: usize| u64::from_le_bytes(archive[at..at + 8].try_into().unwrap());
+    let at = |offset: u64| (offset - base) as usize;
+
+    let end = archive.len() - 22;
+    assert_eq!(u32_at(end), 0x06054b50);
+    let (mut count, mut directory) = (u64::from(u16_at(end + 10)), u64::from(u32_at(end + 16)));
+    if count == u64::from(ZIP64_U16) || directory == u64::from(ZIP64_U32) {
+        let locator = end - 20;
+        assert_eq!(u32_at(locator), 0x07064b50);
+        let record = at(u64_at(locator + 8));
+        assert_eq!(u32_at(record), 0x06064b50);
+        count = u64_at(record + 32);
+        directory = u64_at(record + 48);
+    }
+
+    let mut entries = Vec::new();
+    let mut header = at(directory);
+    for _ in 0..count {
+        assert_eq!(u32_at(header), 0x02014b50);
+        let mut size = u64::from(u32_at(header + 24));
+        let name_length = usize::from(u16_at(header + 28));
+        let extra_length = usize::from(u16_at(header + 30));
+        let mut offset = u64::from(u32_at(header + 42));
+        let name = &archive[header + 46..header + 46 + name_length];
+        let mut extra = header + 46 + name_length;
+        if extra_length > 0 {
+            assert_eq!(u16_at(extra), 1);
+            extra += 4;
+            if size == u64::from(ZIP64_U32) {
+                size = u64_at(extra + 8);
+                extra += 16;
+            }
+            if offset == u64::from(ZIP64_U32) {
+                offset = u64_at(extra);
+            }
+        }
+
+        let local = at(offset);
+        assert_eq!(u32_at(local), 0x04034b50);
+        assert_eq!(&archive[local + 30..local + 30 + name_length], name);
+        let data = local + 30 + name_length + usize::from(u16_at(local + 28));
+        let data = archive[data..data + size as usize].to_vec();
+        assert_eq!(crc32fast::hash(&data), u32_at(header + 16));
+        entries.push((String::from_utf8(name.to_vec()).unwrap(), data));
+        header += 46 + name_length + extra_length;
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The first of `signature` in `archive`.
+    fn find(archive: &[u8], signature: u32) -> Option<usize> {
+        archive
+            .windows(4)
+            .position(|window| window == signature.to_le_bytes())
+    }
+
+    #[test]
+    fn reads_back_what_it_wrote() {
+        let mut zip = ZipWriter::default();
+        let mut archive = zip.entry("Assets/Bank.csv", b"date\r\n2024-01-02\r\n");
+        archive.extend(zip.entry("Ausgaben/Café.csv", b""));
+        archive.extend(zip.finish());
+        assert_eq!(
+            read(&archive, 0),
+            [
+                (
+                    "Assets/Bank.csv".to_string(),
+                    b"date\r\n2024-01-02\r\n".to_vec()
+                ),
+                ("Ausgaben/Café.csv".to_string(), Vec::new()),
+            ]
+        );
+        assert_eq!(find(&archive, 0x06064b50), None);
+    }
+
+    #[test]
+    fn counts_more_than_65535_entries_in_zip64_records() {
+        let mut zip = ZipWriter::default();
+        let mut archive = Vec::new();
+        for i in 0..70_000 {
+            archive.extend(zip.entry(&format!("{}.csv", i), i.to_string().as_bytes()));
+        }
+        archive.extend(zip.finish());
+        let entries = read(&archive, 0);
+        assert_eq!(entries.len(), 70_000);
+        assert_eq!(
+            entries[69_999],
+            ("69999.csv".to_string(), b"69999".to_vec())
+        );
+    }
+
+    #[test]
+    fn points_past_4_gib_through_zip64_records() {
+        // As if 5 GiB of entries came before.
+        let base = 5 << 30;
+        let mut zip = ZipWriter {
+            offset: base,
+            ..ZipWriter::default()
+        };
+        let mut archive = zip.entry("Expenses/Food.csv", b"date\r\n");
+        archive.extend(zip.finish());
+        assert_eq!(
+            read(&archive, base),
+            [("Expenses/Food.csv".to_string(), b"date\r\n".to_vec())]
+        );
+        let header = find(&archive, 0x02014b50).unwrap();
+        assert_eq!(archive[header + 6..header + 8], VERSION_ZIP64.to_le_bytes());
+        assert_eq!(archive[header + 42..header + 46], ZIP64_U32.to_le_bytes());
+        assert!(find(&archive, 0x06064b50).is_some());
+    }
+}